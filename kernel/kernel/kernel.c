@@ -20,6 +20,7 @@
 #include <kernel/syscall/syscall.h>
 #include <kernel/util/elf/elf.h>
 #include <kernel/multitasking/tasks/task_small.h>
+#include <kernel/util/amc/amc_internal.h>
 #include <kernel/smp.h>
 
 #include "kernel.h"
@@ -32,7 +33,9 @@ void FS_SERVER_EXEC_TRAMPOLINE_NAME(uint32_t arg1, uint32_t arg2, uint32_t arg3)
     boot_info_t* boot_info = boot_info_get();
     const char* program_name = "fs_server";
     char* argv[] = {program_name, NULL};
-    elf_load_buffer(program_name, argv, PMA_TO_VMA(boot_info->file_server_elf_start), boot_info->file_server_elf_size, false);
+    // The file server launches everything else, and hands out capabilities according to its manifest
+    amc_capabilities_grant(getpid(), AMC_CAPABILITIES_ALL);
+    elf_load_buffer(program_name, argv, NULL, PMA_TO_VMA(boot_info->file_server_elf_start), boot_info->file_server_elf_size, false);
 }
 
 void draw(axle_boot_info_t* bi, int color) {
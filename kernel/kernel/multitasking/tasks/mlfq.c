@@ -225,6 +225,9 @@ void mlfq_print(void) {
                 case (IRQ_WAIT | AMC_AWAIT_MESSAGE):
                     blocked_reason = "adi";
                     break;
+                case (AMC_AWAIT_INBOX_SPACE | AMC_AWAIT_TIMESTAMP):
+                    blocked_reason = "amc_inbox";
+                    break;
                 case ZOMBIE:
                     blocked_reason = "zombie";
                     break;
@@ -152,6 +152,7 @@ void _thread_destroy(task_small_t* thread) {
     if (!thread->is_thread) {
         // Free AMC service if there is one
         amc_teardown_service_for_task(thread);
+        amc_capabilities_forget(thread->id);
 
         // Free virtual memory space
         vas_teardown(thread->vas_state);
@@ -25,6 +25,8 @@ typedef enum task_state {
 	VMM_MODIFY = 		(1 << 10),
 	// AMC service sleeping until a timestamp has been reached
 	AMC_AWAIT_TIMESTAMP = (1 << 11),
+	// AMC service waiting for room in the inbox of the service it's sending to
+	AMC_AWAIT_INBOX_SPACE = (1 << 12),
 } task_state_t;
 
 typedef struct task_context {
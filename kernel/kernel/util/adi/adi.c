@@ -56,16 +56,21 @@ static void _adi_interrupt_handler(register_state_t* regs) {
     mlfq_goto_task(task);
 }
 
-void adi_register_driver(const char* name, uint32_t irq) {
-    task_assert(irq > 0 && irq < MAX_INT_VECTOR, "Invalid IRQ provided", NULL);
+bool adi_register_driver(const char* name, uint32_t irq) {
+    task_small_t* current_task = tasking_get_current_task();
+    if (irq == 0 || irq >= MAX_INT_VECTOR) {
+        printf("adi_register_driver() rejected for %s: invalid IRQ %d\n", current_task->name, irq);
+        return false;
+    }
+    if (!(amc_capabilities_of_task(current_task->id) & AMC_CAPABILITY_REGISTER_DRIVER)) {
+        printf("adi_register_driver() rejected for %s: it wasn't granted the register-driver capability\n", current_task->name);
+        return false;
+    }
     if (_adi_drivers[irq].task) {
-        printf("invalid adi_register_driver() will kill %s. IRQ %d already mapped to a driver task\n", name, irq);
-        task_assert(false, "IRQ already mapped to a driver task", NULL);
-        return;
+        printf("adi_register_driver() rejected for %s: IRQ %d already mapped to a driver task\n", current_task->name, irq);
+        return false;
     }
 
-    task_small_t* current_task = tasking_get_current_task();
-
     // We're modifying interrupt handling - clear interrupts
     static spinlock_t int_spinlock = {0};
     if (!int_spinlock.name) int_spinlock.name = "adi_register_driver interrupt clear";
@@ -83,6 +88,7 @@ void adi_register_driver(const char* name, uint32_t irq) {
     interrupt_setup_callback(irq, _adi_interrupt_handler);
 
     spinlock_release(&int_spinlock);
+    return true;
 }
 
 bool adi_event_await(uint32_t irq) {
@@ -21,7 +21,9 @@ typedef struct adi_driver {
 // Register the running process as the provided driver name
 // This driver will be responsible for handling the provided IRQ
 // The process's priority will be elevated to PRIORITY_DRIVER
-void adi_register_driver(const char* name, uint32_t irq);
+// Returns false if the IRQ is invalid or already taken, or if the process wasn't granted the
+// register-driver capability
+bool adi_register_driver(const char* name, uint32_t irq);
 
 // Block until an event is received
 // An event will be either an interrupt that must be serviced, or an amc message
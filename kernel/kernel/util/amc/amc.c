@@ -59,13 +59,16 @@ static hash_map_t* _amc_services_by_task = 0;
 
 static void _amc_message_add_to_delivery_queue(amc_service_t* dest_service, amc_message_t* message);
 static void _amc_core_shared_memory_destroy(amc_service_t* local_service, uint32_t shmem_descriptor);
+static void _amc_wake_senders_awaiting_inbox_space(amc_service_t* service);
 void _amc_remove_service_from_sleep_list(amc_service_t* service);
 void _amc_remove_service_from_sleep_list__with_held_lock(amc_service_t* service);
 
-void amc_append_message_to_service_inbox(
+amc_message_t* amc_append_message_to_service_inbox(
     amc_service_t* service,
-    amc_message_t* message
+    amc_message_t* message,
+    bool* out_evicted_was_coalesced
 );
+bool amc_service_inbox_should_block_sender(amc_service_t* service);
 amc_message_t* amc_select_message_to_deliver(
     amc_service_t* service,
     uint32_t desired_source_service_count,
@@ -77,6 +80,14 @@ bool amc_has_message_from_int(
     const char* src_service_name
 );
 bool amc_service_has_message(amc_service_t* service);
+void amc_forget_service_inbox(amc_service_t* service);
+void amc_trace_forget_subscriber(const char* service_name);
+void amc_trace_record_message(
+    const char* source_service,
+    const char* destination_service,
+    void* buf,
+    uint32_t buf_size
+);
 
 array_m* amc_services(void) {
     return _amc_services;
@@ -230,6 +241,7 @@ void amc_register_service(const char* name) {
     service->shmem_regions = array_m_create(32);
     service->services_to_notify_upon_death = array_m_create(32);
     service->delivery_enabled = true;
+    service->senders_awaiting_inbox_space = array_m_create(256);
 
     // Create the message delivery pool in the task's address space
     /*
@@ -286,6 +298,24 @@ void amc_teardown_service_for_task(task_small_t* task) {
         amc_message_free(msg);
     }
     array_m_destroy(service->message_queue);
+    amc_forget_service_inbox(service);
+    amc_trace_forget_subscriber(service->name);
+
+    // Wake up anyone waiting to send to this service. They'll find that it's gone.
+    _amc_wake_senders_awaiting_inbox_space(service);
+    array_m_destroy(service->senders_awaiting_inbox_space);
+    // And stop waiting on other services' inboxes, if we were
+    int32_t sleep_idx = array_m_index(_asleep_procs, service);
+    if (sleep_idx >= 0) {
+        array_m_remove(_asleep_procs, sleep_idx);
+    }
+    for (int32_t i = 0; i < _amc_services->size; i++) {
+        amc_service_t* other = _array_m_lookup_unlocked(_amc_services, i);
+        int32_t waiter_idx = array_m_index(other->senders_awaiting_inbox_space, service);
+        if (waiter_idx >= 0) {
+            array_m_remove(other->senders_awaiting_inbox_space, waiter_idx);
+        }
+    }
 
     // Free shared memory regions
     printf("Shared memory region size: %d\n", service->shmem_regions->size);
@@ -355,11 +385,51 @@ typedef struct amc_flow_control_msg_t {
 
 bool _memory_scanner_is_waiting = false;
 
+static void _amc_wake_senders_awaiting_inbox_space(amc_service_t* service) {
+    spinlock_acquire(&service->senders_awaiting_inbox_space->lock);
+    while (service->senders_awaiting_inbox_space->size) {
+        amc_service_t* sender = _array_m_lookup_unlocked(service->senders_awaiting_inbox_space, 0);
+        _array_m_remove_unlocked(service->senders_awaiting_inbox_space, 0);
+        // The sender might have been woken by its timeout already
+        spinlock_acquire(&_asleep_procs->lock);
+        if ((sender->task->blocked_info.status & AMC_AWAIT_INBOX_SPACE) != 0) {
+            int32_t sleep_idx = _array_m_index_unlocked(_asleep_procs, sender);
+            if (sleep_idx >= 0) {
+                _array_m_remove_unlocked(_asleep_procs, sleep_idx);
+            }
+            tasking_unblock_task_with_reason(sender->task, AMC_AWAIT_INBOX_SPACE);
+        }
+        spinlock_release(&_asleep_procs->lock);
+    }
+    spinlock_release(&service->senders_awaiting_inbox_space->lock);
+}
+
+static void _amc_notify_sender_of_dropped_message(amc_message_t* dropped) {
+    // There's nobody to tell if the kernel sent the message, or if the sender has since gone away
+    if (!strncmp(dropped->source, AXLE_CORE_SERVICE_NAME, AMC_MAX_SERVICE_NAME_LEN)) {
+        return;
+    }
+    amc_service_t* sender = amc_service_with_name(dropped->source);
+    if (sender == NULL || !sender->delivery_enabled) {
+        return;
+    }
+
+    amc_message_dropped_notification_t notif = {0};
+    notif.event = AMC_MESSAGE_DROPPED_NOTIFICATION;
+    snprintf(notif.dest, sizeof(notif.dest), "%s", dropped->dest);
+    if (dropped->len >= sizeof(uint32_t)) {
+        memcpy(&notif.dropped_event, dropped->body, sizeof(uint32_t));
+    }
+    amc_message_send__from_core(dropped->source, &notif, sizeof(notif));
+}
+
 static void _amc_message_add_to_delivery_queue(amc_service_t* dest_service, amc_message_t* message) {
     // We're modifying some state of the destination service - hold a spinlock
     spinlock_acquire(&dest_service->spinlock);
 
-    amc_append_message_to_service_inbox(dest_service, message);
+    // The inbox may have been full, in which case another message was evicted to make room
+    bool evicted_was_coalesced = false;
+    amc_message_t* evicted = amc_append_message_to_service_inbox(dest_service, message, &evicted_was_coalesced);
 
     // And unblock the task if it was waiting for a message
     if ((dest_service->task->blocked_info.status & AMC_AWAIT_MESSAGE) != 0) {
@@ -373,6 +443,73 @@ static void _amc_message_add_to_delivery_queue(amc_service_t* dest_service, amc_
 
     // Release our exclusive access
     spinlock_release(&dest_service->spinlock);
+
+    if (evicted != NULL) {
+        // Notify the sender only after releasing the lock, as the notification is itself a message
+        if (!evicted_was_coalesced) {
+            _amc_notify_sender_of_dropped_message(evicted);
+        }
+        amc_message_free(evicted);
+    }
+}
+
+// A sender stops waiting for inbox space after this long, and its message evicts the oldest one
+// instead. This breaks wait cycles between more than two services, which aren't detected directly.
+#define AMC_INBOX_SPACE_MAX_WAIT_MS 2000
+
+// Blocks the sending service until the destination's inbox has room, if the destination asked for
+// backpressure. Returns the destination, which may have gone away while we waited.
+// If waiting could deadlock, or takes too long, this returns early and the message is appended
+// to the full inbox, evicting the oldest message and notifying its sender.
+static amc_service_t* _amc_await_space_in_inbox(amc_service_t* dest_service, const char* destination_service) {
+    amc_service_t* sender = amc_service_of_active_task();
+    // Waiting on ourselves would never finish
+    if (sender == NULL || sender == dest_service) {
+        return dest_service;
+    }
+
+    uintptr_t give_up_at = ms_since_boot() + AMC_INBOX_SPACE_MAX_WAIT_MS;
+    while (true) {
+        spinlock_acquire(&dest_service->spinlock);
+        if (!amc_service_inbox_should_block_sender(dest_service)) {
+            spinlock_release(&dest_service->spinlock);
+            break;
+        }
+        // If the destination is waiting for room in our inbox, neither of us would ever drain
+        // our own inbox again
+        if (array_m_index(sender->senders_awaiting_inbox_space, dest_service) >= 0) {
+            spinlock_release(&dest_service->spinlock);
+            printf("[AMC] %s and %s are waiting on each other's inboxes, dropping from %s's inbox instead\n", sender->name, dest_service->name, dest_service->name);
+            break;
+        }
+        if (ms_since_boot() >= give_up_at) {
+            spinlock_release(&dest_service->spinlock);
+            printf("[AMC] %s gave up waiting for room in %s's inbox, dropping from it instead\n", sender->name, dest_service->name);
+            break;
+        }
+        if (array_m_index(dest_service->senders_awaiting_inbox_space, sender) < 0) {
+            array_m_insert(dest_service->senders_awaiting_inbox_space, sender);
+        }
+        spinlock_release(&dest_service->spinlock);
+
+        // Also wake up periodically to check again, in case the wakeup raced with us blocking
+        sender->task->blocked_info.wake_timestamp = ms_since_boot() + 50;
+        array_m_insert(_asleep_procs, sender);
+        tasking_block_task(sender->task, AMC_AWAIT_INBOX_SPACE | AMC_AWAIT_TIMESTAMP);
+
+        dest_service = amc_service_with_name(destination_service);
+        if (dest_service == NULL || !dest_service->delivery_enabled) {
+            return dest_service;
+        }
+    }
+
+    // We're still listed as a waiter if a timeout woke us, rather than the destination. Stop
+    // waiting, so the destination isn't mistaken for being in a cycle with us later.
+    int32_t waiter_idx = array_m_index(dest_service->senders_awaiting_inbox_space, sender);
+    if (waiter_idx >= 0) {
+        array_m_remove(dest_service->senders_awaiting_inbox_space, waiter_idx);
+    }
+    return dest_service;
 }
 
 void _amc_remove_service_from_sleep_list__with_held_lock(amc_service_t* service) {
@@ -561,12 +698,15 @@ static bool _amc_message_send_from_service_name(
     const char* source_service,
     const char* destination_service,
     void* buf,
-    uint32_t buf_size
+    uint32_t buf_size,
+    bool sender_can_block
 ) {
     if (buf_size >= AMC_MAX_MESSAGE_SIZE) printf("Large message size: %d\n", buf_size);
     assert(buf_size < AMC_MAX_MESSAGE_SIZE, "Message exceeded max size");
     assert(destination_service != NULL, "NULL destination service provided");
 
+    amc_trace_record_message(source_service, destination_service, buf, buf_size);
+
     // If this is a message to com.axle.core, provide special handling
     if (!strncmp(destination_service, AXLE_CORE_SERVICE_NAME, AMC_MAX_SERVICE_NAME_LEN)) {
         amc_core_handle_message(source_service, buf, buf_size);
@@ -583,6 +723,12 @@ static bool _amc_message_send_from_service_name(
 
     // Find the destination service
     amc_service_t* dest_service = amc_service_with_name(destination_service);
+    if (dest_service != NULL && dest_service->delivery_enabled && sender_can_block) {
+        // Respect the destination's backpressure, if it asked for it.
+        // Another sender might fill the inbox again before we append, in which case the
+        // overflow falls back to dropping the oldest message.
+        dest_service = _amc_await_space_in_inbox(dest_service, destination_service);
+    }
 
     if (dest_service == NULL || !dest_service->delivery_enabled) {
         if (dest_service == NULL) {
@@ -617,7 +763,8 @@ bool amc_message_send(const char* destination_service, void* buf, uint32_t buf_s
         current_service->name,
         destination_service,
         buf,
-        buf_size
+        buf_size,
+        true
     );
 }
 
@@ -626,7 +773,9 @@ bool amc_message_send__from_core(const char* destination_service, void* buf, uin
         AXLE_CORE_SERVICE_NAME,
         destination_service,
         buf,
-        buf_size
+        buf_size,
+        // The kernel may be sending from an interrupt handler
+        false
     );
 }
 
@@ -668,6 +817,8 @@ void _amc_message_await_from_services_ex(int source_service_count, const char**
             // Copy the message into the receiver's storage, and free the internal storage
             _amc_message_deliver(service, available_message_matching_criteria, out);
             spinlock_release(&service->spinlock);
+            // There's now room in our inbox
+            _amc_wake_senders_awaiting_inbox_space(service);
             return;
         }
         else {
@@ -36,6 +36,9 @@ typedef struct amc_service {
 
     // Whether the service is able to receive messages. This is unset when a service crashes.
     bool delivery_enabled;
+
+    // Services blocked until this service's inbox has room for their message
+    array_m* senders_awaiting_inbox_space;
 } amc_service_t;
 
 array_m* amc_services(void);
@@ -67,6 +70,12 @@ array_m* amc_messages_to_unknown_services_pool();
 
 void amc_disable_delivery(amc_service_t* service);
 
+// Capabilities are tracked by PID, so a program can be granted them before it registers a service
+void amc_capabilities_grant(uint32_t pid, uint32_t capabilities);
+uint32_t amc_capabilities_of_task(uint32_t pid);
+void amc_capabilities_forget(uint32_t pid);
+bool amc_service_has_capability(amc_service_t* service, uint32_t capability);
+
 void task_inform_supervisor__process_create__with_task(task_small_t* task, uint64_t pid);
 void task_inform_supervisor__process_start(uint64_t entry_point);
 void task_inform_supervisor__process_exit(uint64_t exit_code);
@@ -122,23 +122,119 @@ static void _amc_core_file_server_map_initrd(const char* source_service) {
     amc_message_send__from_core(source_service, &msg, sizeof(amc_initrd_info_t));
 }
 
-static void AMC_EXEC_TRAMPOLINE_NAME(const char* program_name, void* buf, uint32_t buf_size) {
-    char* argv[] = {program_name, NULL};
-    elf_load_buffer(program_name, argv, buf, buf_size, true);
+bool amc_service_has_capability(amc_service_t* service, uint32_t capability) {
+    return (amc_capabilities_of_task(service->task->id) & capability) == capability;
+}
+
+static bool _amc_core_check_capability(amc_service_t* service, uint32_t capability) {
+    if (amc_service_has_capability(service, capability)) {
+        return true;
+    }
+    printf("[AMC] Denying request from %s, which lacks capability 0x%x\n", service->name, capability);
+    return false;
+}
+
+#define AMC_EXEC_MAX_ARGS 16
+#define AMC_EXEC_MAX_ENVIRONMENT_ENTRIES 32
+
+typedef struct amc_exec_trampoline_args {
+    // Holds the NUL-terminated program name, followed by the NUL-terminated arguments, ending
+    // with an empty entry
+    char* command_line;
+    // Holds NUL-terminated KEY=VALUE entries, ending with an empty entry. May be NULL.
+    char* environment;
+    uint32_t capabilities;
+} amc_exec_trampoline_args_t;
+
+static void AMC_EXEC_TRAMPOLINE_NAME(amc_exec_trampoline_args_t* args, void* buf, uint32_t buf_size) {
+    // Grant capabilities before the program runs, so it never observes itself without them
+    amc_capabilities_grant(getpid(), args->capabilities);
+
+    char* command_line = args->command_line;
+    char* environment = args->environment;
+    kfree(args);
+
+    char* argv[AMC_EXEC_MAX_ARGS + 1] = {0};
+    int argc = 0;
+    argv[argc++] = command_line;
+    char* cursor;
+    for (cursor = command_line + strlen(command_line) + 1; *cursor && argc < AMC_EXEC_MAX_ARGS; cursor += strlen(cursor) + 1) {
+        argv[argc++] = cursor;
+    }
+
+    char* envp[AMC_EXEC_MAX_ENVIRONMENT_ENTRIES + 1] = {0};
+    int envc = 0;
+    for (cursor = environment; cursor != NULL && *cursor && envc < AMC_EXEC_MAX_ENVIRONMENT_ENTRIES; cursor += strlen(cursor) + 1) {
+        envp[envc++] = cursor;
+    }
+
+    // The loader copies argv and envp onto the program's stack
+    elf_load_buffer(argv[0], argv, envp, buf, buf_size, true);
 	panic("noreturn");
 }
 
+// Checks that a block of NUL-terminated, non-empty entries fits within `available_len` bytes of the
+// message, and holds at most `max_entries` entries
+static bool _amc_exec_block_is_valid(const char* block, uint32_t block_len, uint32_t available_len, uint32_t max_block_len, uint32_t max_entries) {
+    if (block_len > available_len || block_len > max_block_len) {
+        return false;
+    }
+    if (block_len > 0 && block[block_len - 1] != '\0') {
+        return false;
+    }
+    uint32_t entry_count = 0;
+    for (uint32_t i = 0; i < block_len; i++) {
+        if (block[i] != '\0') {
+            continue;
+        }
+        // An empty entry would end the list early
+        if (i == 0 || block[i - 1] == '\0') {
+            return false;
+        }
+        entry_count += 1;
+    }
+    return entry_count <= max_entries;
+}
+
 static void _amc_core_file_server_exec_buffer(const char* source_service, void* buf, uint32_t buf_size) {
-    //assert(!strncmp(source_service, "com.axle.file_server", AMC_MAX_SERVICE_NAME_LEN), "Only File Server may use this syscall");
+    amc_service_t* source = amc_service_with_name(source_service);
+    assert(source != NULL, "Failed to find service that sent the message...");
     // This syscall is heavily restricted
-    bool is_allowed_service = (
-        !strncmp(source_service, "com.axle.file_server", AMC_MAX_SERVICE_NAME_LEN) || 
-        !strncmp(source_service, "com.axle.linker", AMC_MAX_SERVICE_NAME_LEN) ||
-        !strncmp(source_service, "com.axle.ide", AMC_MAX_SERVICE_NAME_LEN)
-    );
-    assert(is_allowed_service, "Only certain services may use this syscall");
+    if (!_amc_core_check_capability(source, AMC_CAPABILITY_EXEC_BUFFER)) {
+        amc_exec_buffer_response_t resp = {
+            .event = AMC_FILE_MANAGER_EXEC_BUFFER_RESPONSE,
+            .missing_capability = AMC_CAPABILITY_EXEC_BUFFER,
+        };
+        amc_message_send__from_core(source_service, &resp, sizeof(resp));
+        return;
+    }
 
     amc_exec_buffer_cmd_t* cmd = (amc_exec_buffer_cmd_t*)buf;
+
+    // The arguments and environment are carried in the message itself, so they can be
+    // bounds-checked
+    const char* arguments = (const char*)buf + sizeof(amc_exec_buffer_cmd_t);
+    uint32_t arguments_len = 0;
+    const char* environment = NULL;
+    uint32_t environment_len = 0;
+    if (buf_size >= sizeof(amc_exec_buffer_cmd_t)) {
+        uint32_t available_len = buf_size - sizeof(amc_exec_buffer_cmd_t);
+        arguments_len = cmd->arguments_len;
+        environment = arguments + arguments_len;
+        environment_len = cmd->environment_len;
+        bool arguments_valid = _amc_exec_block_is_valid(arguments, arguments_len, available_len, AMC_EXEC_MAX_ARGUMENTS_LEN, AMC_EXEC_MAX_ARGS - 1);
+        // Only look for the environment once we know that the arguments fit in the message
+        if (!arguments_valid || !_amc_exec_block_is_valid(environment, environment_len, available_len - arguments_len, AMC_EXEC_MAX_ENVIRONMENT_LEN, AMC_EXEC_MAX_ENVIRONMENT_ENTRIES)) {
+            printf("[AMC] Rejecting exec_buffer from %s with a malformed argument or environment block\n", source_service);
+            amc_exec_buffer_response_t resp = {
+                .event = AMC_FILE_MANAGER_EXEC_BUFFER_RESPONSE,
+                .missing_capability = 0,
+            };
+            amc_message_send__from_core(source_service, &resp, sizeof(resp));
+            return;
+        }
+    }
+
     printf("exec buffer(program_name: %s, buffer_addr: 0x%p, buffer_size: %p)\n", cmd->program_name, cmd->buffer_addr, cmd->buffer_size);
 	printf("[%d ms] exec_buffer (buffer size %d)\n", ms_since_boot(), cmd->buffer_size);
 
@@ -149,11 +245,34 @@ static void _amc_core_file_server_exec_buffer(const char* source_service, void*
     // TODO(PT): Where should this be freed?
     char* name_copy = strdup(cmd->program_name);
 
+    // And build the command line that the trampoline turns into argv
+    uint32_t name_len = strlen(name_copy);
+    char* command_line = kmalloc(name_len + 1 + arguments_len + 1);
+    memcpy(command_line, name_copy, name_len + 1);
+    memcpy(command_line + name_len + 1, arguments, arguments_len);
+    // Terminate the list with an empty entry
+    command_line[name_len + 1 + arguments_len] = '\0';
+
+    amc_exec_trampoline_args_t* trampoline_args = kmalloc(sizeof(amc_exec_trampoline_args_t));
+    trampoline_args->command_line = command_line;
+    trampoline_args->environment = NULL;
+    trampoline_args->capabilities = 0;
+    if (buf_size >= offsetof(amc_exec_buffer_cmd_t, capabilities) + sizeof(cmd->capabilities)) {
+        // A program can't be granted anything that its launcher doesn't hold
+        trampoline_args->capabilities = cmd->capabilities & amc_capabilities_of_task(source->task->id);
+    }
+    if (environment_len > 0) {
+        // Copy the entries, and end the block with an empty entry
+        trampoline_args->environment = kmalloc(environment_len + 1);
+        memcpy(trampoline_args->environment, environment, environment_len);
+        trampoline_args->environment[environment_len] = '\0';
+    }
+
     if (cmd->with_supervisor) {
         task_small_t* child = task_spawn__managed__with_args(
             name_copy,
             AMC_EXEC_TRAMPOLINE_NAME, 
-            (uintptr_t)name_copy, 
+            (uintptr_t)trampoline_args, 
             (uintptr_t)copy, 
             cmd->buffer_size
         );
@@ -162,7 +281,7 @@ static void _amc_core_file_server_exec_buffer(const char* source_service, void*
         task_spawn__with_args(
             name_copy,
             AMC_EXEC_TRAMPOLINE_NAME, 
-            (uintptr_t)name_copy, 
+            (uintptr_t)trampoline_args, 
             (uintptr_t)copy, 
             cmd->buffer_size
         );
@@ -291,6 +410,14 @@ static void _amc_core_map_physical_range(const char* source_service, void* buf,
     amc_service_t* source = amc_service_with_name(source_service);
     assert(source != NULL, "Failed to find service that sent the message...");
 
+    amc_map_physical_range_response_t resp = {0};
+    resp.event = AMC_MAP_PHYSICAL_RANGE_RESPONSE;
+    if (!_amc_core_check_capability(source, AMC_CAPABILITY_MAP_PHYSICAL)) {
+        resp.missing_capability = AMC_CAPABILITY_MAP_PHYSICAL;
+        amc_message_send__from_core(source_service, &resp, sizeof(resp));
+        return;
+    }
+
     amc_map_physical_range_request_t* req = (amc_map_physical_range_request_t*)buf;
 
     printf("[AMC] %s mapping physical range [0x%p - 0x%p]\n", source->name, req->phys_base, req->phys_base + req->size);
@@ -298,8 +425,6 @@ static void _amc_core_map_physical_range(const char* source_service, void* buf,
     uintptr_t virt_base = vas_map_range(vas_get_active_state(), 0x7d0000000000, req->size, req->phys_base, VAS_RANGE_ACCESS_LEVEL_READ_WRITE, VAS_RANGE_PRIVILEGE_LEVEL_USER);
     printf("\tMapped physical range to virt [0x%p - 0x%p]\n", virt_base, virt_base + req->size);
 
-    amc_map_physical_range_response_t resp = {0};
-    resp.event = AMC_MAP_PHYSICAL_RANGE_RESPONSE;
     resp.virt_base = virt_base;
     amc_message_send__from_core(source_service, &resp, sizeof(resp));
 }
@@ -308,6 +433,14 @@ static void _amc_core_alloc_physical_range(const char* source_service, void* buf
     amc_service_t* source = amc_service_with_name(source_service);
     assert(source != NULL, "Failed to find service that sent the message...");
 
+    amc_alloc_physical_range_response_t resp = {0};
+    resp.event = AMC_ALLOC_PHYSICAL_RANGE_RESPONSE;
+    if (!_amc_core_check_capability(source, AMC_CAPABILITY_ALLOC_PHYSICAL)) {
+        resp.missing_capability = AMC_CAPABILITY_ALLOC_PHYSICAL;
+        amc_message_send__from_core(source_service, &resp, sizeof(resp));
+        return;
+    }
+
     amc_alloc_physical_range_request_t* req = (amc_alloc_physical_range_request_t*)buf;
 
     uintptr_t range_size = addr_space_page_ceil(req->size);
@@ -319,8 +452,6 @@ static void _amc_core_alloc_physical_range(const char* source_service, void* buf
     uintptr_t virt_base = vas_map_range(vas_get_active_state(), 0x7d0000000000, range_size, phys_base, VAS_RANGE_ACCESS_LEVEL_READ_WRITE, VAS_RANGE_PRIVILEGE_LEVEL_USER);
     printf("\tAllocated Phys [0x%p - 0x%p], Virt [0x%p - 0x%p]\n", phys_base, phys_base + range_size, virt_base, virt_base + range_size);
 
-    amc_alloc_physical_range_response_t resp = {0};
-    resp.event = AMC_ALLOC_PHYSICAL_RANGE_RESPONSE;
     resp.phys_base = phys_base;
     resp.virt_base = virt_base;
     amc_message_send__from_core(source_service, &resp, sizeof(resp));
@@ -403,6 +534,65 @@ static void _amc_core_grant_pml1_entry(const char* source_service) {
 #include <kernel/multitasking/tasks/task_small_int.h>
 #include <kernel/util/amc/amc_internal.h>
 
+amc_trace_stream_response_t* amc_trace_populate_stream_response(const char* subscriber, uint64_t after_sequence, uint32_t* out_size);
+
+static void _amc_core_stream_trace(const char* source_service, void* buf, uint32_t buf_size) {
+    amc_trace_stream_request_t* req = (amc_trace_stream_request_t*)buf;
+    if (buf_size < sizeof(amc_trace_stream_request_t)) {
+        printf("[%s] Ignoring truncated trace stream request (%d bytes)\n", source_service, buf_size);
+        return;
+    }
+
+    amc_service_t* source = amc_service_with_name(source_service);
+    if (!_amc_core_check_capability(source, AMC_CAPABILITY_TRACE_MESSAGES)) {
+        amc_trace_stream_response_t resp = {
+            .event = AMC_TRACE_STREAM_RESPONSE,
+            .status = AMC_TRACE_STREAM_MISSING_CAPABILITY,
+        };
+        amc_message_send__from_core(source_service, &resp, sizeof(resp));
+        return;
+    }
+
+    uint32_t response_size = 0;
+    amc_trace_stream_response_t* response = amc_trace_populate_stream_response(source_service, req->after_sequence, &response_size);
+    amc_message_send__from_core(source_service, response, response_size);
+    kfree(response);
+}
+
+bool amc_service_inbox_configure(
+    amc_service_t* service,
+    uint32_t limit,
+    uint32_t overflow_policy,
+    uint32_t* coalesced_events,
+    uint32_t coalesced_event_count
+);
+
+static void _amc_core_configure_inbox(const char* source_service, void* buf, uint32_t buf_size) {
+    amc_inbox_configure_cmd_t* cmd = (amc_inbox_configure_cmd_t*)buf;
+    if (buf_size < sizeof(amc_inbox_configure_cmd_t)) {
+        printf("[%s] Ignoring truncated inbox configuration (%d bytes)\n", source_service, buf_size);
+        return;
+    }
+    if (cmd->limit == 0 || cmd->limit > AMC_INBOX_MAX_LIMIT || cmd->coalesced_event_count > AMC_INBOX_MAX_COALESCED_EVENTS) {
+        printf("[%s] Ignoring invalid inbox configuration (limit %d, %d coalesced events)\n", source_service, cmd->limit, cmd->coalesced_event_count);
+        return;
+    }
+
+    amc_service_t* service = amc_service_with_name(source_service);
+    spinlock_acquire(&service->spinlock);
+    bool configured = amc_service_inbox_configure(
+        service,
+        cmd->limit,
+        cmd->overflow_policy,
+        cmd->coalesced_events,
+        cmd->coalesced_event_count
+    );
+    spinlock_release(&service->spinlock);
+    if (!configured) {
+        printf("[%s] Ignoring inbox configuration with unknown overflow policy %d\n", source_service, cmd->overflow_policy);
+    }
+}
+
 task_viewer_get_task_info_response_t* tasking_populate_tasks_info(void);
 
 static void _amc_core_send_task_info(const char* source_service) {
@@ -576,6 +766,12 @@ void amc_core_handle_message(const char* source_service, void* buf, uint32_t buf
     else if (u32buf[0] == TASK_VIEWER_GET_TASK_INFO) {
         _amc_core_send_task_info(source_service);
     }
+    else if (u32buf[0] == AMC_TRACE_STREAM_REQUEST) {
+        _amc_core_stream_trace(source_service, buf, buf_size);
+    }
+    else if (u32buf[0] == AMC_INBOX_CONFIGURE) {
+        _amc_core_configure_inbox(source_service, buf, buf_size);
+    }
     else {
         printf("Unknown message: %d\n", u32buf[0]);
         assert(0, "Unknown message to core");
@@ -6,6 +6,15 @@
 #define AXLE_CORE_SERVICE_NAME "com.axle.core"
 #define AMC_MAX_SERVICE_NAME_LEN 64
 
+// Privileged core commands that a service may only use once it's been granted the capability.
+// Each launcher grants its programs a subset of its own capabilities, as listed in its manifest.
+#define AMC_CAPABILITY_MAP_PHYSICAL (1 << 0)
+#define AMC_CAPABILITY_ALLOC_PHYSICAL (1 << 1)
+#define AMC_CAPABILITY_EXEC_BUFFER (1 << 2)
+#define AMC_CAPABILITY_REGISTER_DRIVER (1 << 3)
+#define AMC_CAPABILITY_TRACE_MESSAGES (1 << 4)
+#define AMC_CAPABILITIES_ALL (AMC_CAPABILITY_MAP_PHYSICAL | AMC_CAPABILITY_ALLOC_PHYSICAL | AMC_CAPABILITY_EXEC_BUFFER | AMC_CAPABILITY_REGISTER_DRIVER | AMC_CAPABILITY_TRACE_MESSAGES)
+
 #define AMC_COPY_SERVICES 200
 #define AMC_COPY_SERVICES_RESPONSE 200
 
@@ -57,8 +66,32 @@ typedef struct amc_exec_buffer_cmd {
     bool with_supervisor;
     void* buffer_addr;
     uint32_t buffer_size;
+    // AMC_CAPABILITY_* to grant to the new program, limited to those held by the sender.
+    // Older senders omit the fields from here on, so check the message size before reading them.
+    // Their programs are granted nothing.
+    uint32_t capabilities;
+    // The size of the argument block, which directly follows this struct in the message.
+    // The block holds the arguments that follow the program name in argv, each NUL-terminated
+    // and non-empty.
+    uint32_t arguments_len;
+    // The size of the environment block, which directly follows the argument block.
+    // The block holds the KEY=VALUE entries that become the program's envp, each NUL-terminated
+    // and non-empty.
+    uint32_t environment_len;
 } amc_exec_buffer_cmd_t;
 
+// At most this many bytes of arguments are accepted
+#define AMC_EXEC_MAX_ARGUMENTS_LEN 4096
+// At most this many bytes of environment entries are accepted
+#define AMC_EXEC_MAX_ENVIRONMENT_LEN 4096
+
+// Only sent when the request is denied, or is malformed
+typedef struct amc_exec_buffer_response {
+    uint32_t event; // AMC_FILE_MANAGER_EXEC_BUFFER_RESPONSE
+    // Zero if the request was malformed
+    uint32_t missing_capability;
+} amc_exec_buffer_response_t;
+
 #define AMC_SHARED_MEMORY_DESTROY 205
 
 typedef struct amc_shared_memory_destroy_cmd {
@@ -151,6 +184,8 @@ typedef struct amc_map_physical_range_request {
 typedef struct amc_map_physical_range_response {
     uint32_t event;
     uintptr_t virt_base;
+    // The AMC_CAPABILITY_* that the sender lacks when the request is denied, or 0 on success
+    uint32_t missing_capability;
 } amc_map_physical_range_response_t;
 
 /*
@@ -171,6 +206,8 @@ typedef struct amc_alloc_physical_range_response {
     uint32_t event;
     uintptr_t phys_base;
     uintptr_t virt_base;
+    // The AMC_CAPABILITY_* that the sender lacks when the request is denied, or 0 on success
+    uint32_t missing_capability;
 } amc_alloc_physical_range_response_t;
 
 /*
@@ -240,6 +277,94 @@ typedef struct amc_supervised_process_event {
     amc_supervised_process_event_payload_t payload;
 } amc_supervised_process_event_t;
 
+// Streams recent AMC traffic and the depth of each service's inbox, for debugging stuck services.
+// The kernel records every message into a ring buffer. The requester becomes the trace's subscriber,
+// and its own traffic stops being recorded, so polling doesn't drown out everything else.
+// Streaming requires AMC_CAPABILITY_TRACE_MESSAGES. There's only one subscriber at a time, and the
+// subscription is released when the subscriber's service is torn down.
+#define AMC_TRACE_STREAM_REQUEST 216
+#define AMC_TRACE_STREAM_RESPONSE 216
+
+#define AMC_TRACE_STREAM_OK 0
+// The requester lacks AMC_CAPABILITY_TRACE_MESSAGES
+#define AMC_TRACE_STREAM_MISSING_CAPABILITY 1
+// Another service is already subscribed to the trace
+#define AMC_TRACE_STREAM_SUBSCRIBER_EXISTS 2
+
+typedef struct amc_trace_stream_request {
+    uint32_t event; // AMC_TRACE_STREAM_REQUEST
+    // Only records newer than this are sent. Pass the last response's last_sequence, or 0.
+    uint64_t after_sequence;
+} amc_trace_stream_request_t;
+
+typedef struct amc_trace_record {
+    char source[AMC_MAX_SERVICE_NAME_LEN];
+    char dest[AMC_MAX_SERVICE_NAME_LEN];
+    // The first word of the body, or 0 if the body is too short to hold one
+    uint32_t event;
+    uint32_t size;
+    uint64_t timestamp;
+} amc_trace_record_t;
+
+typedef struct amc_inbox_depth {
+    char service[AMC_MAX_SERVICE_NAME_LEN];
+    uint32_t depth;
+} amc_inbox_depth_t;
+
+typedef struct amc_trace_stream_response {
+    uint32_t event; // AMC_TRACE_STREAM_RESPONSE
+    // AMC_TRACE_STREAM_*. The remaining fields are zero unless this is AMC_TRACE_STREAM_OK.
+    uint32_t status;
+    // Sequence number of the newest record included
+    uint64_t last_sequence;
+    // Records that were overwritten before they could be streamed
+    uint64_t dropped_count;
+    uint32_t record_count;
+    uint32_t inbox_count;
+    // record_count amc_trace_record_t, followed by inbox_count amc_inbox_depth_t
+    uint8_t data[];
+} amc_trace_stream_response_t;
+
+// Configures the sender's own inbox: how many messages it holds, and what happens when a message
+// arrives while it's full. Every inbox starts out holding AMC_INBOX_DEFAULT_LIMIT messages and
+// dropping the oldest on overflow.
+#define AMC_INBOX_CONFIGURE 217
+
+#define AMC_INBOX_DEFAULT_LIMIT 1024
+#define AMC_INBOX_MAX_LIMIT 8192
+#define AMC_INBOX_MAX_COALESCED_EVENTS 8
+
+typedef enum amc_inbox_overflow_policy {
+    // Evict the oldest queued message to make room
+    AMC_INBOX_OVERFLOW_DROP_OLDEST = 0,
+    // Block the sender until the inbox has room. Senders that can't block (such as the kernel)
+    // fall back to dropping the oldest message, as do senders whose own inbox the destination is
+    // waiting on, and senders that have waited for too long.
+    AMC_INBOX_OVERFLOW_BLOCK_SENDER = 1,
+    // Replace the newest queued message with the same source and event, if the event is one of
+    // coalesced_events. Otherwise, drop the oldest message.
+    AMC_INBOX_OVERFLOW_COALESCE = 2,
+} amc_inbox_overflow_policy_t;
+
+typedef struct amc_inbox_configure_cmd {
+    uint32_t event; // AMC_INBOX_CONFIGURE
+    uint32_t limit;
+    uint32_t overflow_policy;
+    uint32_t coalesced_event_count;
+    uint32_t coalesced_events[AMC_INBOX_MAX_COALESCED_EVENTS];
+} amc_inbox_configure_cmd_t;
+
+// Sent to a service when a message it sent was evicted from a full inbox before being delivered.
+// Messages replaced by coalescing aren't reported, as a newer message from the sender superseded them.
+#define AMC_MESSAGE_DROPPED_NOTIFICATION 218
+
+typedef struct amc_message_dropped_notification {
+    uint32_t event; // AMC_MESSAGE_DROPPED_NOTIFICATION
+    char dest[AMC_MAX_SERVICE_NAME_LEN];
+    // The first word of the dropped message's body, or 0 if the body was too short to hold one
+    uint32_t dropped_event;
+} amc_message_dropped_notification_t;
+
 void amc_core_handle_message(const char* source_service, void* buf, uint32_t buf_size);
 
 #endif
\ No newline at end of file
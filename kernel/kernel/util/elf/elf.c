@@ -9,7 +9,7 @@
 #include <kernel/assert.h>
 #include <kernel/util/amc/amc_internal.h>
 
-void user_mode(uintptr_t stack_top, uintptr_t entry_point);
+void user_mode(uintptr_t stack_top, uintptr_t entry_point, uintptr_t argc, uintptr_t argv);
 
 static bool elf_check_magic(elf_header* hdr) {
 	if (!hdr) return false;
@@ -165,7 +165,54 @@ static void _record_elf_symbol_table(void* buf, elf_t* elf) {
 // TODO(PT): Ensure this is in the sysroot
 //#include <sys/axle/syscalls.h>
 
-void elf_load_buffer(char* program_name, char** argv, uint8_t* buf, uint32_t buf_size, bool free_buffer) {
+static int _elf_count_strings(char** strings) {
+	int count = 0;
+	while (strings != NULL && strings[count] != NULL) {
+		count++;
+	}
+	return count;
+}
+
+// Copies argv and envp to the top of the user stack, laid out as
+// argv[0..argc], NULL, envp[0..envc], NULL, followed by the strings they point to.
+// Returns the address of the pointer array, which is 16-byte aligned.
+static uintptr_t* _elf_copy_arguments_to_user_stack(uintptr_t* stack_top, uint32_t stack_size, char** argv, char** envp) {
+	int argc = _elf_count_strings(argv);
+	int envc = _elf_count_strings(envp);
+
+	uint32_t strings_size = 0;
+	for (int i = 0; i < argc; i++) {
+		strings_size += strlen(argv[i]) + 1;
+	}
+	for (int i = 0; i < envc; i++) {
+		strings_size += strlen(envp[i]) + 1;
+	}
+	uint32_t pointer_count = argc + 1 + envc + 1;
+	task_assert(strings_size + (pointer_count * sizeof(uintptr_t)) < stack_size / 2, "Arguments and environment are too large for the user stack", NULL);
+
+	char* string_cursor = (char*)stack_top - strings_size;
+	// Keep the pointer array 16-byte aligned
+	uintptr_t* pointers = (uintptr_t*)(((uintptr_t)string_cursor - (pointer_count * sizeof(uintptr_t))) & ~0xf);
+
+	int pointer_idx = 0;
+	for (int i = 0; i < argc; i++) {
+		uint32_t len = strlen(argv[i]) + 1;
+		memcpy(string_cursor, argv[i], len);
+		pointers[pointer_idx++] = (uintptr_t)string_cursor;
+		string_cursor += len;
+	}
+	pointers[pointer_idx++] = 0;
+	for (int i = 0; i < envc; i++) {
+		uint32_t len = strlen(envp[i]) + 1;
+		memcpy(string_cursor, envp[i], len);
+		pointers[pointer_idx++] = (uintptr_t)string_cursor;
+		string_cursor += len;
+	}
+	pointers[pointer_idx++] = 0;
+	return pointers;
+}
+
+void elf_load_buffer(char* program_name, char** argv, char** envp, uint8_t* buf, uint32_t buf_size, bool free_buffer) {
 	printf("ELF loading %s for PID %d\n", program_name, getpid());
 	elf_header* hdr = (elf_header*)buf;
 
@@ -230,7 +277,11 @@ void elf_load_buffer(char* program_name, char** argv, uint8_t* buf, uint32_t buf
 	);
 	printf("[%d] allocated ELF stack at 0x%08x\n", getpid(), stack_bottom);
     uintptr_t *stack_top = (uintptr_t *)(stack_bottom + stack_size); // point to top of malloc'd stack
-	uintptr_t* stack_top_orig = stack_top;
+	// Hand the program its arguments and environment on its own stack, since it can't read the
+	// kernel heap copies
+	int argc = _elf_count_strings(argv);
+	uintptr_t* user_argv = _elf_copy_arguments_to_user_stack(stack_top, stack_size, argv, envp);
+	stack_top = user_argv;
 	printf("[%d] Set ESP to 0x%08x\n", getpid(), stack_top);
     *(--stack_top)= 0xaa;   //address of task's entry point
     *(--stack_top)= 0xbb;   //address of task's entry point
@@ -242,12 +293,6 @@ void elf_load_buffer(char* program_name, char** argv, uint8_t* buf, uint32_t buf
     *(--stack_top)   = 0x4;             //ebp
     *(--stack_top)   = 0x5;             //ebp
 
-	//calculate argc count
-	int argc = 0;
-	while (argv[argc] != NULL) {
-		argc++;
-	}
-
 	//vas_active_unmap_temp(sizeof(vmm_page_directory_t));
 	// Ensure the task won't be scheduled while modifying its critical state
 	//spinlock_acquire(&elf->priority_lock);
@@ -271,7 +316,7 @@ void elf_load_buffer(char* program_name, char** argv, uint8_t* buf, uint32_t buf
 	snprintf(msg_buf, 512, "Jump to user mode for %s\n", program_name);
 	//draw_string_oneshot(msg_buf);
 	//spinlock_release(&elf->priority_lock);
-	user_mode(stack_top, entry_point);
+	user_mode((uintptr_t)stack_top, entry_point, argc, (uintptr_t)user_argv);
 
 	// Binary should terminate via _exit()
 	task_assert(false, "ELF returned execution to loader", NULL);
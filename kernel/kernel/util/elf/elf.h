@@ -164,6 +164,6 @@ enum elf_rt_types {
 #define PT_DYNAMIC	2
 #define PT_INTERP	3
 
-void elf_load_buffer(char* program_name, char** argv, uint8_t* buf, uint32_t buf_size, bool free_buffer);
+void elf_load_buffer(char* program_name, char** argv, char** envp, uint8_t* buf, uint32_t buf_size, bool free_buffer);
 
 #endif
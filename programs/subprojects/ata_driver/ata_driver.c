@@ -302,7 +302,10 @@ static void _message_received(amc_message_t* msg) {
 
 int main(int argc, char** argv) {
 	amc_register_service(ATA_DRIVER_SERVICE_NAME);
-	adi_register_driver(ATA_DRIVER_SERVICE_NAME, INT_VECTOR_APIC_14);
+	if (!adi_register_driver(ATA_DRIVER_SERVICE_NAME, INT_VECTOR_APIC_14)) {
+		printf("%s failed to register as a driver\n", ATA_DRIVER_SERVICE_NAME);
+		return 1;
+	}
 
     printf("[ATA] init\n");
 
@@ -126,7 +126,7 @@ bool libamc_handle_message(amc_message_t* msg) {
     return false;
 }
 
-void amc_alloc_physical_range(uintptr_t buffer_size, uintptr_t* out_phys_base, uintptr_t* out_virt_base) {
+bool amc_alloc_physical_range(uintptr_t buffer_size, uintptr_t* out_phys_base, uintptr_t* out_virt_base) {
     printf("amc_alloc_physical_range(buffer_size=%p, out_phys=%p, out_virt=%p)\n", (void*)buffer_size, out_phys_base, out_virt_base);
     assert(out_phys_base && out_virt_base, "out-parameters must be provided");
     amc_alloc_physical_range_request_t req = {
@@ -137,6 +137,11 @@ void amc_alloc_physical_range(uintptr_t buffer_size, uintptr_t* out_phys_base, u
     amc_message_t* out_resp;
     amc_message_await__u32_event(AXLE_CORE_SERVICE_NAME, AMC_ALLOC_PHYSICAL_RANGE_RESPONSE, &out_resp);
     amc_alloc_physical_range_response_t* phys_range_info = (amc_alloc_physical_range_response_t*)out_resp->body;
+    if (phys_range_info->missing_capability) {
+        printf("amc_alloc_physical_range denied, missing capability 0x%x\n", phys_range_info->missing_capability);
+        return false;
+    }
     *out_phys_base = phys_range_info->phys_base;
     *out_virt_base = phys_range_info->virt_base;
+    return true;
 }
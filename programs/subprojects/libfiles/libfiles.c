@@ -202,6 +202,14 @@ ssize_t read(int fildes, void* buf, size_t nbyte) {
     amc_message_t* response_msg;
     amc_message_await__u32_event(FILE_SERVER_SERVICE_NAME, FILE_SERVER_READ_FILE__PARTIAL_RESPONSE, &response_msg);
     file_server_read_file_partial_response_t* resp = (file_server_read_file_partial_response_t*)&response_msg->body;
+    if (resp->status == FILE_SERVER_STATUS_OUT_OF_RANGE) {
+        // Reading past the end of the file
+        return 0;
+    }
+    else if (resp->status != FILE_SERVER_STATUS_SUCCESS) {
+        errno = EIO;
+        return -1;
+    }
 
     //printf("Read got response! Length: %ld\n", resp->data_length);
     entry->offset += resp->data_length;
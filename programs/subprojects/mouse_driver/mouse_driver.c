@@ -35,7 +35,10 @@ static _handle_amc_messages(void) {
 int main(int argc, char** argv) {
 	amc_register_service(MOUSE_DRIVER_SERVICE_NAME);
 	// This process will handle PS/2 mouse IRQ's (IRQ 12)
-	adi_register_driver(MOUSE_DRIVER_SERVICE_NAME, INT_VECTOR_APIC_12);
+	if (!adi_register_driver(MOUSE_DRIVER_SERVICE_NAME, INT_VECTOR_APIC_12)) {
+		printf("%s failed to register as a driver\n", MOUSE_DRIVER_SERVICE_NAME);
+		return 1;
+	}
 
 	ps2_mouse_state_t state = {0, 0};
 	while (true) {
@@ -239,7 +239,8 @@ void realtek_8139_init(uint32_t bus, uint32_t device_slot, uint32_t function, ui
 	//amc_physical_memory_region_create(rx_buffer_size, &virt_memory_rx_addr, &phys_memory_rx_addr);
     uintptr_t phys_rx_base = 0;
     uintptr_t virt_rx_base = 0;
-    amc_alloc_physical_range(rx_buffer_size, &phys_rx_base, &virt_rx_base);
+    bool allocated_rx = amc_alloc_physical_range(rx_buffer_size, &phys_rx_base, &virt_rx_base);
+    assert(allocated_rx, "Failed to allocate RX buffer");
     printf("Got RX buffer [Phys 0x%p] [Virt 0x%p]\n", (void*)phys_rx_base, (void*)virt_rx_base);
 
 	out_state->receive_buffer_virt = virt_rx_base;
@@ -248,7 +249,8 @@ void realtek_8139_init(uint32_t bus, uint32_t device_slot, uint32_t function, ui
 
     uintptr_t phys_tx_base = 0;
 	uintptr_t virt_tx_base = 0;
-    amc_alloc_physical_range((1024 * 8) + 16, &phys_tx_base, &virt_tx_base);
+    bool allocated_tx = amc_alloc_physical_range((1024 * 8) + 16, &phys_tx_base, &virt_tx_base);
+    assert(allocated_tx, "Failed to allocate TX buffer");
     printf("Got TX buffer [Phys 0x%p] [Virt 0x%p]\n", (void*)phys_tx_base, (void*)virt_tx_base);
 	out_state->transmit_buffer_virt = virt_tx_base;
 	out_state->transmit_buffer_phys = phys_tx_base;
@@ -401,7 +403,10 @@ int main(int argc, char** argv) {
 	// This process will handle interrupts from the Realtek 8159 NIC (IRQ11)
 	// TODO(PT): The interrupt number is read from the PCI bus
 	// It should be communicated to this process
-	adi_register_driver(RTL8139_SERVICE_NAME, INT_VECTOR_APIC_11);
+	if (!adi_register_driver(RTL8139_SERVICE_NAME, INT_VECTOR_APIC_11)) {
+		printf("%s failed to register as a driver\n", RTL8139_SERVICE_NAME);
+		return 1;
+	}
 
 	// TODO(PT): This should be read from the PCI bus
 	int io_base = 0xc000;
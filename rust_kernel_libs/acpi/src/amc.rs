@@ -1,3 +1,4 @@
+use crate::amc_trace::{service_name_from_str, AmcInboxDepth};
 use crate::apic::cpu_core_private_info;
 use alloc::collections::{BTreeMap, VecDeque};
 use alloc::string::ToString;
@@ -8,33 +9,151 @@ use ffi_bindings::{cpu_id, println, AmcMessage, AmcService};
 use lazy_static::lazy_static;
 use spin::Mutex;
 
+/// Represents AMC_INBOX_DEFAULT_LIMIT
+const AMC_INBOX_DEFAULT_LIMIT: usize = 1024;
+
+/// Represents amc_inbox_overflow_policy_t
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum InboxOverflowPolicy {
+    DropOldest,
+    BlockSender,
+    Coalesce,
+}
+
+impl InboxOverflowPolicy {
+    fn from_raw(raw: u32) -> Option<Self> {
+        match raw {
+            0 => Some(Self::DropOldest),
+            1 => Some(Self::BlockSender),
+            2 => Some(Self::Coalesce),
+            _ => None,
+        }
+    }
+}
+
+struct Inbox {
+    messages: VecDeque<&'static AmcMessage>,
+    limit: usize,
+    overflow_policy: InboxOverflowPolicy,
+    /// Events that may be coalesced when the overflow policy is Coalesce
+    coalesced_events: Vec<u32>,
+}
+
+impl Inbox {
+    fn new() -> Self {
+        Self {
+            messages: VecDeque::new(),
+            limit: AMC_INBOX_DEFAULT_LIMIT,
+            overflow_policy: InboxOverflowPolicy::DropOldest,
+            coalesced_events: Vec::new(),
+        }
+    }
+
+    fn is_full(&self) -> bool {
+        self.messages.len() >= self.limit
+    }
+}
+
+unsafe fn message_event(message: &AmcMessage) -> Option<u32> {
+    if (message.len as usize) < core::mem::size_of::<u32>() {
+        return None;
+    }
+    Some(core::ptr::read_unaligned(
+        core::ptr::addr_of!(message.body) as *const u32
+    ))
+}
+
 lazy_static! {
-    static ref SERVICES_TO_INBOXES: spin::Mutex<BTreeMap<&'static AmcService, VecDeque<&'static AmcMessage>>> =
+    static ref SERVICES_TO_INBOXES: spin::Mutex<BTreeMap<&'static AmcService, Inbox>> =
         Mutex::new(BTreeMap::new());
+    /// The privileged core commands (AMC_CAPABILITY_*) that each task may use, by PID.
+    /// Tasks without an entry have no capabilities.
+    static ref TASKS_TO_CAPABILITIES: spin::Mutex<BTreeMap<u32, u32>> = Mutex::new(BTreeMap::new());
 }
 
 unsafe fn track_inbox_for_service_if_necessary(service: &'static AmcService) {
     let mut services_to_inboxes = SERVICES_TO_INBOXES.lock();
     if !services_to_inboxes.contains_key(service) {
-        services_to_inboxes.insert(service, VecDeque::new());
+        services_to_inboxes.insert(service, Inbox::new());
     }
 }
 
+/// Returns a message that was evicted to make room, if the inbox was full. The caller owns the evicted message.
 #[no_mangle]
 pub unsafe fn amc_append_message_to_service_inbox(
     service_raw: *const AmcService,
     message: *const AmcMessage,
-) {
+    out_evicted_was_coalesced: *mut bool,
+) -> *const AmcMessage {
     // Lifetimes are managed by the C bits of the kernel
     let service: &'static AmcService = &*service_raw;
     track_inbox_for_service_if_necessary(service);
 
     let mut services_to_inboxes = SERVICES_TO_INBOXES.lock();
+    let inbox = services_to_inboxes.get_mut(service).unwrap();
     let message: &'static AmcMessage = &*message;
-    services_to_inboxes
-        .get_mut(service)
-        .unwrap()
-        .push_back(message);
+    *out_evicted_was_coalesced = false;
+    if !inbox.is_full() {
+        inbox.messages.push_back(message);
+        return core::ptr::null();
+    }
+
+    if inbox.overflow_policy == InboxOverflowPolicy::Coalesce {
+        if let Some(event) = message_event(message) {
+            if inbox.coalesced_events.contains(&event) {
+                // Replace the newest message that this one supersedes, keeping its place in the queue
+                let superseded = inbox.messages.iter_mut().rev().find(|queued| {
+                    queued.source() == message.source() && message_event(queued) == Some(event)
+                });
+                if let Some(superseded) = superseded {
+                    *out_evicted_was_coalesced = true;
+                    let replaced: &AmcMessage = core::mem::replace(superseded, message);
+                    return replaced;
+                }
+            }
+        }
+    }
+
+    // Senders that could block have already waited for room, so everything else drops the oldest message
+    let evicted = inbox.messages.pop_front().unwrap();
+    inbox.messages.push_back(message);
+    evicted
+}
+
+#[no_mangle]
+pub unsafe fn amc_service_inbox_should_block_sender(service_raw: *const AmcService) -> bool {
+    let service: &'static AmcService = &*service_raw;
+    track_inbox_for_service_if_necessary(service);
+    let services_to_inboxes = SERVICES_TO_INBOXES.lock();
+    let inbox = services_to_inboxes.get(service).unwrap();
+    inbox.overflow_policy == InboxOverflowPolicy::BlockSender && inbox.is_full()
+}
+
+/// Returns false if the overflow policy is unknown. Messages that are already queued beyond the
+/// new limit stay queued.
+#[no_mangle]
+pub unsafe fn amc_service_inbox_configure(
+    service_raw: *const AmcService,
+    limit: u32,
+    overflow_policy_raw: u32,
+    coalesced_events_raw: *const u32,
+    coalesced_event_count: u32,
+) -> bool {
+    let overflow_policy = match InboxOverflowPolicy::from_raw(overflow_policy_raw) {
+        Some(overflow_policy) => overflow_policy,
+        None => return false,
+    };
+    let service: &'static AmcService = &*service_raw;
+    track_inbox_for_service_if_necessary(service);
+
+    let coalesced_events =
+        core::slice::from_raw_parts(coalesced_events_raw, coalesced_event_count as usize);
+    let mut services_to_inboxes = SERVICES_TO_INBOXES.lock();
+    let inbox = services_to_inboxes.get_mut(service).unwrap();
+    inbox.limit = limit as usize;
+    inbox.overflow_policy = overflow_policy;
+    inbox.coalesced_events = coalesced_events.to_vec();
+    true
 }
 
 #[no_mangle]
@@ -47,7 +166,7 @@ pub unsafe fn amc_select_message_to_deliver(
     let service = &*service_raw;
     track_inbox_for_service_if_necessary(service);
     let mut services_to_inboxes = SERVICES_TO_INBOXES.lock();
-    let available_messages = services_to_inboxes.get_mut(service).unwrap();
+    let available_messages = &mut services_to_inboxes.get_mut(service).unwrap().messages;
 
     let should_match_any_service =
         desired_source_services_raw == core::ptr::null() || desired_source_service_count == 0;
@@ -110,8 +229,8 @@ pub unsafe fn amc_has_message_from_int(
     let service: &'static AmcService = &*this_service_raw;
     track_inbox_for_service_if_necessary(service);
 
-    let mut services_to_inboxes = SERVICES_TO_INBOXES.lock();
-    let available_messages = services_to_inboxes.get_mut(service).unwrap();
+    let services_to_inboxes = SERVICES_TO_INBOXES.lock();
+    let available_messages = &services_to_inboxes.get(service).unwrap().messages;
 
     let service_name = CStr::from_ptr(service_name_raw).to_str().unwrap();
     available_messages
@@ -128,6 +247,7 @@ pub unsafe fn amc_service_has_message(service_raw: *const AmcService) -> bool {
         .lock()
         .get(service)
         .expect(&format!("No queue found for {}", service.name()))
+        .messages
         .len()
         > 0
 }
@@ -140,5 +260,41 @@ pub unsafe fn amc_service_inbox_len(service_raw: *const AmcService) -> usize {
         .lock()
         .get(service)
         .expect(&format!("No queue found for {}", service.name()))
+        .messages
         .len()
 }
+
+#[no_mangle]
+pub fn amc_capabilities_grant(pid: u32, capabilities: u32) {
+    let mut tasks_to_capabilities = TASKS_TO_CAPABILITIES.lock();
+    *tasks_to_capabilities.entry(pid).or_insert(0) |= capabilities;
+}
+
+#[no_mangle]
+pub fn amc_capabilities_of_task(pid: u32) -> u32 {
+    TASKS_TO_CAPABILITIES.lock().get(&pid).copied().unwrap_or(0)
+}
+
+#[no_mangle]
+pub fn amc_capabilities_forget(pid: u32) {
+    TASKS_TO_CAPABILITIES.lock().remove(&pid);
+}
+
+/// Called when a service is torn down, so its inbox doesn't outlive it
+#[no_mangle]
+pub unsafe fn amc_forget_service_inbox(service_raw: *const AmcService) {
+    SERVICES_TO_INBOXES
+        .lock()
+        .retain(|&service, _| !core::ptr::eq(service, service_raw));
+}
+
+pub(crate) unsafe fn amc_inbox_depths() -> Vec<AmcInboxDepth> {
+    SERVICES_TO_INBOXES
+        .lock()
+        .iter()
+        .map(|(service, inbox)| AmcInboxDepth {
+            service: service_name_from_str(service.name()),
+            depth: inbox.messages.len() as u32,
+        })
+        .collect()
+}
@@ -0,0 +1,288 @@
+use crate::amc::amc_inbox_depths;
+use alloc::alloc::alloc;
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use core::alloc::Layout;
+use core::ffi::{c_char, CStr};
+use core::mem::{align_of, size_of};
+use ffi_bindings::{ms_since_boot, AmcMessage};
+use lazy_static::lazy_static;
+use spin::Mutex;
+
+/// How many of the most recent messages are kept around
+const TRACE_CAPACITY: usize = 1024;
+/// Keeps each response to a modest size. Subscribers ask again for the rest.
+const MAX_RECORDS_PER_RESPONSE: usize = 256;
+
+const AMC_TRACE_STREAM_RESPONSE: u32 = 216;
+const AMC_TRACE_STREAM_OK: u32 = 0;
+const AMC_TRACE_STREAM_SUBSCRIBER_EXISTS: u32 = 2;
+
+pub(crate) type ServiceName = [u8; AmcMessage::MAX_SERVICE_NAME_LEN];
+
+/// Represents amc_trace_record_t
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+struct AmcTraceRecord {
+    source: ServiceName,
+    dest: ServiceName,
+    event: u32,
+    size: u32,
+    timestamp: u64,
+}
+
+/// Represents amc_inbox_depth_t
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct AmcInboxDepth {
+    pub service: ServiceName,
+    pub depth: u32,
+}
+
+/// Represents amc_trace_stream_response_t
+#[repr(C)]
+pub struct AmcTraceStreamResponse {
+    event: u32,
+    status: u32,
+    last_sequence: u64,
+    dropped_count: u64,
+    record_count: u32,
+    inbox_count: u32,
+    // VLA of records, followed by inbox depths
+    data: [u8; 0],
+}
+
+pub(crate) fn service_name_from_str(name: &str) -> ServiceName {
+    service_name_from_bytes(name.as_bytes())
+}
+
+/// Service names come straight from senders, so they aren't necessarily UTF-8
+fn service_name_from_bytes(name: &[u8]) -> ServiceName {
+    let mut buf = [0; AmcMessage::MAX_SERVICE_NAME_LEN];
+    // Leave room for the NUL terminator
+    let len = name.len().min(buf.len() - 1);
+    buf[..len].copy_from_slice(&name[..len]);
+    buf
+}
+
+struct MessageTrace {
+    records: VecDeque<AmcTraceRecord>,
+    /// Sequence number of the newest record. Records are numbered from 1.
+    last_sequence: u64,
+    /// Traffic to and from the subscriber isn't recorded, as it'd mostly be the trace itself
+    subscriber: Option<Vec<u8>>,
+}
+
+impl MessageTrace {
+    fn first_sequence(&self) -> u64 {
+        self.last_sequence + 1 - self.records.len() as u64
+    }
+
+    /// Returns false if another service is already subscribed
+    fn subscribe(&mut self, subscriber: &[u8]) -> bool {
+        match &self.subscriber {
+            Some(existing) => existing == subscriber,
+            None => {
+                self.subscriber = Some(subscriber.to_vec());
+                true
+            }
+        }
+    }
+
+    fn unsubscribe(&mut self, service: &[u8]) {
+        if self.subscriber.as_deref() == Some(service) {
+            self.subscriber = None;
+        }
+    }
+
+    fn record(&mut self, source: &[u8], dest: &[u8], event: u32, size: u32, timestamp: u64) {
+        if let Some(subscriber) = &self.subscriber {
+            if subscriber == source || subscriber == dest {
+                return;
+            }
+        }
+        if self.records.len() == TRACE_CAPACITY {
+            self.records.pop_front();
+        }
+        self.records.push_back(AmcTraceRecord {
+            source: service_name_from_bytes(source),
+            dest: service_name_from_bytes(dest),
+            event,
+            size,
+            timestamp,
+        });
+        self.last_sequence += 1;
+    }
+}
+
+lazy_static! {
+    static ref MESSAGE_TRACE: spin::Mutex<MessageTrace> = Mutex::new(MessageTrace {
+        records: VecDeque::with_capacity(TRACE_CAPACITY),
+        last_sequence: 0,
+        subscriber: None,
+    });
+}
+
+/// Called for every message that's sent, including messages to the core
+#[no_mangle]
+pub unsafe fn amc_trace_record_message(
+    source_service_raw: *const c_char,
+    dest_service_raw: *const c_char,
+    body: *const u8,
+    len: u32,
+) {
+    let source_service = CStr::from_ptr(source_service_raw).to_bytes();
+    let dest_service = CStr::from_ptr(dest_service_raw).to_bytes();
+    let event = if len as usize >= size_of::<u32>() {
+        core::ptr::read_unaligned(body as *const u32)
+    } else {
+        0
+    };
+
+    MESSAGE_TRACE.lock().record(
+        source_service,
+        dest_service,
+        event,
+        len,
+        ms_since_boot() as u64,
+    );
+}
+
+/// Builds the response to AMC_TRACE_STREAM_REQUEST, and makes the requester the trace's
+/// subscriber unless another service already is. The caller sends the response and frees it.
+#[no_mangle]
+pub unsafe fn amc_trace_populate_stream_response(
+    subscriber_raw: *const c_char,
+    after_sequence: u64,
+    out_size: *mut u32,
+) -> *mut AmcTraceStreamResponse {
+    let subscriber = CStr::from_ptr(subscriber_raw).to_bytes();
+    // Read the inboxes first so the trace and inbox locks are never held together
+    let inbox_depths = amc_inbox_depths();
+
+    let mut trace = MESSAGE_TRACE.lock();
+    if !trace.subscribe(subscriber) {
+        drop(trace);
+        return alloc_stream_response(AMC_TRACE_STREAM_SUBSCRIBER_EXISTS, 0, 0, &[], &[], out_size);
+    }
+
+    let first_sequence = trace.first_sequence();
+    // Anything between the requested sequence and the oldest record has been overwritten
+    let dropped_count = first_sequence.saturating_sub(after_sequence + 1);
+    let skip =
+        (after_sequence.saturating_sub(first_sequence - 1) as usize).min(trace.records.len());
+    let records: Vec<AmcTraceRecord> = trace
+        .records
+        .iter()
+        .skip(skip)
+        .take(MAX_RECORDS_PER_RESPONSE)
+        .copied()
+        .collect();
+    let last_sequence = (first_sequence - 1) + (skip + records.len()) as u64;
+    drop(trace);
+
+    alloc_stream_response(
+        AMC_TRACE_STREAM_OK,
+        last_sequence,
+        dropped_count,
+        &records,
+        &inbox_depths,
+        out_size,
+    )
+}
+
+unsafe fn alloc_stream_response(
+    status: u32,
+    last_sequence: u64,
+    dropped_count: u64,
+    records: &[AmcTraceRecord],
+    inbox_depths: &[AmcInboxDepth],
+    out_size: *mut u32,
+) -> *mut AmcTraceStreamResponse {
+    let records_size = size_of::<AmcTraceRecord>() * records.len();
+    let inboxes_size = size_of::<AmcInboxDepth>() * inbox_depths.len();
+    let total_size = size_of::<AmcTraceStreamResponse>() + records_size + inboxes_size;
+    let layout = Layout::from_size_align(total_size, align_of::<u64>()).unwrap();
+    let response = alloc(layout) as *mut AmcTraceStreamResponse;
+    (*response).event = AMC_TRACE_STREAM_RESPONSE;
+    (*response).status = status;
+    (*response).last_sequence = last_sequence;
+    (*response).dropped_count = dropped_count;
+    (*response).record_count = records.len() as u32;
+    (*response).inbox_count = inbox_depths.len() as u32;
+
+    let data = core::ptr::addr_of_mut!((*response).data) as *mut u8;
+    core::ptr::copy_nonoverlapping(records.as_ptr() as *const u8, data, records_size);
+    core::ptr::copy_nonoverlapping(
+        inbox_depths.as_ptr() as *const u8,
+        data.add(records_size),
+        inboxes_size,
+    );
+
+    *out_size = total_size as u32;
+    response
+}
+
+/// Called when a service is torn down, so that another service can subscribe to the trace
+#[no_mangle]
+pub unsafe fn amc_trace_forget_subscriber(service_raw: *const c_char) {
+    let service = CStr::from_ptr(service_raw).to_bytes();
+    MESSAGE_TRACE.lock().unsubscribe(service);
+}
+
+#[cfg(test)]
+mod test {
+    use crate::amc_trace::{MessageTrace, TRACE_CAPACITY};
+    use alloc::collections::VecDeque;
+    use alloc::vec;
+
+    fn empty_trace() -> MessageTrace {
+        MessageTrace {
+            records: VecDeque::with_capacity(TRACE_CAPACITY),
+            last_sequence: 0,
+            subscriber: None,
+        }
+    }
+
+    #[test]
+    fn record_non_utf8_names() {
+        // Given a sender whose name isn't valid UTF-8
+        let mut trace = empty_trace();
+        let source = b"com.axle.\xff\xfe";
+        // When it sends a message
+        trace.record(source, b"com.axle.dest", 7, 4, 100);
+        // Then the message is recorded with the name's bytes intact
+        assert_eq!(trace.last_sequence, 1);
+        let record = trace.records[0];
+        assert_eq!(&record.source[..source.len()], source);
+        assert_eq!(record.source[source.len()], 0);
+        assert_eq!(&record.dest[..13], b"com.axle.dest");
+
+        // And a subscriber with a non-UTF-8 name has its own traffic left out
+        trace.subscriber = Some(vec![0xff, 0xfe]);
+        trace.record(b"\xff\xfe", b"com.axle.dest", 7, 4, 101);
+        assert_eq!(trace.last_sequence, 1);
+    }
+
+    #[test]
+    fn single_subscriber() {
+        // Given a service that's subscribed to the trace
+        let mut trace = empty_trace();
+        assert!(trace.subscribe(b"com.axle.amc_trace_viewer"));
+        // Then it may keep polling
+        assert!(trace.subscribe(b"com.axle.amc_trace_viewer"));
+        // But another service can't take over the subscription
+        assert!(!trace.subscribe(b"com.axle.snooper"));
+        assert_eq!(
+            trace.subscriber.as_deref(),
+            Some(&b"com.axle.amc_trace_viewer"[..])
+        );
+
+        // Tearing down an unrelated service leaves the subscription alone
+        trace.unsubscribe(b"com.axle.snooper");
+        assert!(!trace.subscribe(b"com.axle.snooper"));
+        // Once the subscriber is torn down, another service may subscribe
+        trace.unsubscribe(b"com.axle.amc_trace_viewer");
+        assert!(trace.subscribe(b"com.axle.snooper"));
+    }
+}
@@ -35,6 +35,7 @@ use ffi_bindings::{
 };
 
 mod amc;
+mod amc_trace;
 mod apic;
 mod interrupts;
 mod scheduler;
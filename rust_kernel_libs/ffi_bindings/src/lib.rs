@@ -51,6 +51,9 @@ extern "C" {
     pub fn vas_load_state(state: *const VasState);
     pub fn vas_is_page_present(state: *const VasState, page_addr: u64) -> bool;
 
+    // pit.h
+    pub fn ms_since_boot() -> usize;
+
     // boot_info.h
     pub fn boot_info_get() -> *const BootInfo;
 
@@ -0,0 +1,204 @@
+#![no_std]
+#![feature(start)]
+#![feature(format_args_nl)]
+#![feature(default_alloc_error_handler)]
+
+extern crate alloc;
+extern crate libc;
+
+use alloc::collections::VecDeque;
+use alloc::{format, rc::Rc, string::String};
+use core::cell::RefCell;
+
+use libgui::label::Label;
+use libgui::text_view::TextView;
+use libgui::ui_elements::UIElement;
+use libgui::AwmWindow;
+use libgui::{Timer, TimerMode};
+
+use axle_rt::core_commands::{
+    AmcTraceStream, AmcTraceStreamRequest, AmcTraceStreamStatus, AMC_CORE_SERVICE_NAME,
+};
+use axle_rt::{amc_message_send, amc_register_service, println, AmcMessage};
+
+use agx_definitions::{Color, Point, Rect, RectInsets, Size};
+
+const AMC_TRACE_VIEWER_SERVICE_NAME: &str = "com.axle.amc_trace_viewer";
+
+/// How often the kernel is asked for new trace records
+const POLL_INTERVAL_MS: usize = 250;
+/// How many lines of traffic are shown at once
+const MAX_TRAFFIC_LINES: usize = 48;
+
+const SUMMARY_HEIGHT: isize = 32;
+
+struct TraceViewer {
+    summary_label: Rc<Label>,
+    traffic_view: Rc<TextView>,
+    inboxes_view: Rc<TextView>,
+    /// Sequence number of the newest record we've displayed
+    last_sequence: RefCell<u64>,
+    dropped_count: RefCell<u64>,
+    /// Set while we're waiting for the core to respond, so slow responses don't pile up requests
+    request_in_flight: RefCell<bool>,
+    traffic_lines: RefCell<VecDeque<String>>,
+}
+
+impl TraceViewer {
+    fn new(window: &Rc<AwmWindow>) -> Rc<Self> {
+        let summary_label = Rc::new(Label::new(
+            "Waiting for trace...",
+            Color::black(),
+            |_label, superview_size| {
+                Rect::new(8, 8, superview_size.width - 16, SUMMARY_HEIGHT - 16)
+            },
+        ));
+        Rc::clone(window).add_component(Rc::clone(&summary_label) as Rc<dyn UIElement>);
+
+        let traffic_view = TextView::new(
+            Color::white(),
+            None,
+            Size::new(8, 10),
+            RectInsets::new(8, 8, 8, 8),
+            |_view, superview_size| {
+                Rect::from_parts(
+                    Point::new(0, SUMMARY_HEIGHT),
+                    Size::new(
+                        (superview_size.width * 2) / 3,
+                        superview_size.height - SUMMARY_HEIGHT,
+                    ),
+                )
+            },
+        );
+        Rc::clone(window).add_component(Rc::clone(&traffic_view) as Rc<dyn UIElement>);
+
+        let inboxes_view = TextView::new(
+            Color::white(),
+            None,
+            Size::new(8, 10),
+            RectInsets::new(8, 8, 8, 8),
+            |_view, superview_size| {
+                let traffic_width = (superview_size.width * 2) / 3;
+                Rect::from_parts(
+                    Point::new(traffic_width, SUMMARY_HEIGHT),
+                    Size::new(
+                        superview_size.width - traffic_width,
+                        superview_size.height - SUMMARY_HEIGHT,
+                    ),
+                )
+            },
+        );
+        Rc::clone(window).add_component(Rc::clone(&inboxes_view) as Rc<dyn UIElement>);
+
+        Rc::new(Self {
+            summary_label,
+            traffic_view,
+            inboxes_view,
+            last_sequence: RefCell::new(0),
+            dropped_count: RefCell::new(0),
+            request_in_flight: RefCell::new(false),
+            traffic_lines: RefCell::new(VecDeque::new()),
+        })
+    }
+
+    fn request_trace(&self) {
+        if *self.request_in_flight.borrow() {
+            return;
+        }
+        *self.request_in_flight.borrow_mut() = true;
+        amc_message_send(
+            AMC_CORE_SERVICE_NAME,
+            AmcTraceStreamRequest::new(*self.last_sequence.borrow()),
+        );
+    }
+
+    fn handle_trace_stream(&self, stream: AmcTraceStream) {
+        if stream.status != AmcTraceStreamStatus::Streaming {
+            // Leave the request marked as in flight, so there's no more polling
+            self.summary_label
+                .set_text(&format!("Trace unavailable: {:?}", stream.status));
+            return;
+        }
+        *self.request_in_flight.borrow_mut() = false;
+        *self.last_sequence.borrow_mut() = stream.last_sequence;
+        *self.dropped_count.borrow_mut() += stream.dropped_count;
+
+        {
+            let mut traffic_lines = self.traffic_lines.borrow_mut();
+            for record in stream.records.iter() {
+                traffic_lines.push_back(format!(
+                    "{:>8}ms {} -> {} event {} ({} bytes)\n",
+                    record.timestamp,
+                    record.source(),
+                    record.dest(),
+                    record.event,
+                    record.size
+                ));
+            }
+            while traffic_lines.len() > MAX_TRAFFIC_LINES {
+                traffic_lines.pop_front();
+            }
+        }
+
+        self.summary_label.set_text(&format!(
+            "Messages seen: {}, dropped: {}",
+            stream.last_sequence,
+            *self.dropped_count.borrow()
+        ));
+
+        // Only redraw the traffic if something new arrived, so the view doesn't flicker
+        if !stream.records.is_empty() {
+            self.traffic_view.clear();
+            for line in self.traffic_lines.borrow().iter() {
+                self.traffic_view.draw_string(line, Color::black());
+            }
+        }
+
+        self.inboxes_view.clear();
+        self.inboxes_view
+            .draw_string("Messages waiting in inboxes:\n", Color::black());
+        for inbox in stream.inbox_depths.iter().filter(|i| i.depth > 0) {
+            // Highlight services that are falling behind
+            let color = if inbox.depth >= 16 {
+                Color::new(200, 40, 40)
+            } else {
+                Color::black()
+            };
+            self.inboxes_view
+                .draw_string(&format!("{:>4} {}\n", inbox.depth, inbox.service()), color);
+        }
+    }
+}
+
+#[start]
+#[allow(unreachable_code)]
+fn start(_argc: isize, _argv: *const *const u8) -> isize {
+    amc_register_service(AMC_TRACE_VIEWER_SERVICE_NAME);
+
+    let window = Rc::new(AwmWindow::new("AMC Traffic", Size::new(900, 600)));
+    let viewer = TraceViewer::new(&window);
+
+    let viewer_clone = Rc::clone(&viewer);
+    window.add_message_handler(move |window, msg_unparsed: AmcMessage<[u8]>| {
+        if msg_unparsed.source() != AMC_CORE_SERVICE_NAME {
+            println!("Dropping unhandled message from {}", msg_unparsed.source());
+            return;
+        }
+        match AmcTraceStream::parse(msg_unparsed.body()) {
+            Some(stream) => {
+                viewer_clone.handle_trace_stream(stream);
+                window.draw();
+            }
+            None => println!("Dropping unhandled message from core"),
+        }
+    });
+
+    window.add_timer(Timer::new(
+        POLL_INTERVAL_MS,
+        TimerMode::Periodic,
+        move |_window| viewer.request_trace(),
+    ));
+
+    window.enter_event_loop();
+    0
+}
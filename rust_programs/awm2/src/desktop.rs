@@ -17,7 +17,6 @@ use awm_messages::{AwmCreateWindow, AwmWindowPartialRedraw, AwmWindowUpdateTitle
 use core::cell::RefCell;
 use core::cmp::{max, min};
 use core::mem;
-use core::ptr;
 use mouse_driver_messages::MousePacket;
 
 use crate::animations::{Animation, ShortcutSnapAnimationParams, WindowTransformAnimationParams};
@@ -27,9 +26,7 @@ use axle_rt::core_commands::{
     AmcRegisterServiceDiedNotif, AmcServiceDiedNotif, AmcSharedMemoryCreateRequest,
 };
 use dock_messages::{AwmDockTaskViewClicked, AwmDockWindowMinimizeWithInfo, AWM_DOCK_HEIGHT};
-use file_manager_messages::{
-    str_from_u8_nul_utf8_unchecked, ReadFile, ReadFileResponse, FILE_SERVER_SERVICE_NAME,
-};
+use file_manager_messages::{str_from_u8_nul_utf8_unchecked, FileType};
 use kb_driver_messages::{KeyEventType, KeyIdentifier, KeyboardPacket};
 use menu_bar_messages::AWM_MENU_BAR_HEIGHT;
 use preferences_messages::PreferencesUpdated;
@@ -40,7 +37,8 @@ pub extern crate libc;
 #[cfg(target_os = "axle")]
 mod conditional_imports {
     pub use awm_messages::AwmCreateWindowResponse;
-    pub use axle_rt::{amc_message_await__u32_event, amc_message_send, AmcMessage};
+    pub use axle_rt::amc_message_send;
+    pub use file_manager_messages::{read_file, stat_path};
 }
 #[cfg(not(target_os = "axle"))]
 mod conditional_imports {}
@@ -131,7 +129,8 @@ pub struct Desktop {
     ongoing_animations: Vec<Animation>,
     window_images: WindowDecorationImages,
     desktop_shortcuts_state: DesktopShortcutsState,
-    desktop_shortcut_image: BitmapImage,
+    // Shortcut icons are chosen by the type of the file the shortcut points to
+    desktop_shortcut_images: BTreeMap<FileType, BitmapImage>,
 }
 
 impl Desktop {
@@ -179,27 +178,45 @@ impl Desktop {
             ongoing_animations: vec![],
             window_images,
             desktop_shortcuts_state,
-            desktop_shortcut_image: BitmapImage::read_bmp_from_path("/images/executable_icon.bmp"),
+            desktop_shortcut_images: BTreeMap::from([
+                (
+                    FileType::Executable,
+                    BitmapImage::read_bmp_from_path("/images/executable_icon.bmp"),
+                ),
+                (
+                    FileType::Directory,
+                    BitmapImage::read_bmp_from_path("/images/folder_icon.bmp"),
+                ),
+                (
+                    FileType::Image,
+                    BitmapImage::read_bmp_from_path("/images/image_icon.bmp"),
+                ),
+                (
+                    FileType::Text,
+                    BitmapImage::read_bmp_from_path("/images/text_icon.bmp"),
+                ),
+            ]),
         }
     }
 
     pub fn load_shortcuts(&mut self) {
         #[cfg(target_os = "axle")]
         {
-            let file_read_request = ReadFile::new("/config/desktop_shortcuts.txt");
-            amc_message_send(FILE_SERVER_SERVICE_NAME, file_read_request);
-            let file_data_msg: AmcMessage<ReadFileResponse> =
-                amc_message_await__u32_event(FILE_SERVER_SERVICE_NAME);
-            let file_data_body = file_data_msg.body();
-            let file_bytes = unsafe {
-                let bmp_data_slice = ptr::slice_from_raw_parts(
-                    (&file_data_body.data) as *const u8,
-                    file_data_body.len,
-                );
-                let bmp_data: &[u8] = &*(bmp_data_slice as *const [u8]);
-                bmp_data.to_vec()
+            let shortcuts_path = "/config/desktop_shortcuts.txt";
+            let file_bytes = match read_file(shortcuts_path) {
+                Ok(file_bytes) => file_bytes,
+                Err(e) => {
+                    println!("Failed to read {shortcuts_path}, no shortcuts will be shown: {e:?}");
+                    return;
+                }
+            };
+            let file_data = match String::from_utf8(file_bytes) {
+                Ok(file_data) => file_data,
+                Err(e) => {
+                    println!("Failed to read {shortcuts_path}, invalid UTF-8: {e}");
+                    return;
+                }
             };
-            let file_data = String::from_utf8(file_bytes).unwrap();
             for line in file_data.split("\n") {
                 let components: Vec<&str> = line.split(", ").collect();
                 if components.len() != 2 && components.len() != 4 {
@@ -209,6 +226,12 @@ impl Desktop {
                 let path = components[0];
                 let title = components[1];
                 let id = self.next_desktop_element_id();
+                // Most shortcuts launch programs, so fall back to the executable icon
+                let file_type = stat_path(path).map_or(FileType::Executable, |m| m.file_type);
+                let icon = self
+                    .desktop_shortcut_images
+                    .get(&file_type)
+                    .unwrap_or(&self.desktop_shortcut_images[&FileType::Executable]);
                 let shortcut = if components.len() == 4 {
                     let (x, y) = (components[2], components[3]);
                     let coordinates = (
@@ -219,7 +242,7 @@ impl Desktop {
                         &mut self.desktop_background_layer,
                         self.background_gradient_outer_color,
                         id,
-                        &self.desktop_shortcut_image,
+                        icon,
                         path,
                         title,
                         coordinates,
@@ -229,7 +252,7 @@ impl Desktop {
                         &mut self.desktop_background_layer,
                         self.background_gradient_outer_color,
                         id,
-                        &self.desktop_shortcut_image,
+                        icon,
                         path,
                         title,
                     )
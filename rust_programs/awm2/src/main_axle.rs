@@ -35,7 +35,7 @@ use mouse_driver_messages::{MousePacket, MOUSE_DRIVER_SERVICE_NAME};
 use preferences_messages::PREFERENCES_SERVICE_NAME;
 
 use axle_rt::core_commands::{
-    AmcAwmMapFramebuffer, AmcAwmMapFramebufferResponse, AmcServiceDiedNotif,
+    AmcAwmMapFramebuffer, AmcAwmMapFramebufferResponse, AmcMessageDropped, AmcServiceDiedNotif,
     AmcSharedMemoryCreateRequest, AmcSharedMemoryCreateResponse, AmcSleepUntilDelayOrMessage,
     AMC_CORE_SERVICE_NAME,
 };
@@ -170,6 +170,16 @@ fn process_next_amc_message(desktop: &mut Desktop) {
                     desktop.handle_amc_service_died_notif(body_as_type_unchecked(raw_body));
                     true
                 }
+                AmcMessageDropped::EXPECTED_EVENT => {
+                    // A window isn't keeping up with its events. It'll catch up with the latest ones.
+                    let dropped: &AmcMessageDropped = body_as_type_unchecked(raw_body);
+                    println!(
+                        "Dropped event {} to unresponsive {}",
+                        dropped.dropped_event,
+                        dropped.dest()
+                    );
+                    true
+                }
                 _ => false,
             },
             _ => false,
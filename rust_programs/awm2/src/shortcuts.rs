@@ -15,17 +15,15 @@ use alloc::string::String;
 use alloc::string::ToString;
 use alloc::vec;
 use alloc::vec::Vec;
-use axle_rt::AmcMessage;
-use axle_rt::{amc_message_await__u32_event, amc_message_send};
 use core::cell::RefCell;
-use core::ptr;
 use dock_messages::AWM_DOCK_HEIGHT;
-use file_manager_messages::{ReadFile, ReadFileResponse, FILE_SERVER_SERVICE_NAME};
 use lazy_static::lazy_static;
 use menu_bar_messages::AWM_MENU_BAR_HEIGHT;
 use spin::Mutex;
 use ttf_renderer::{render_char_onto, Font};
 
+#[cfg(target_os = "axle")]
+use file_manager_messages::read_file;
 #[cfg(not(target_os = "axle"))]
 use std::fs;
 
@@ -38,19 +36,8 @@ pub fn load_font(path: &str) -> Font {
     let font_bytes = {
         #[cfg(target_os = "axle")]
         {
-            let file_read_request = ReadFile::new(path);
-            amc_message_send(FILE_SERVER_SERVICE_NAME, file_read_request);
-            let file_data_msg: AmcMessage<ReadFileResponse> =
-                amc_message_await__u32_event(FILE_SERVER_SERVICE_NAME);
-            let file_data_body = file_data_msg.body();
-            unsafe {
-                let data_slice = ptr::slice_from_raw_parts(
-                    (&file_data_body.data) as *const u8,
-                    file_data_body.len,
-                );
-                let data: &[u8] = &*(data_slice as *const [u8]);
-                data.to_vec()
-            }
+            // Nothing can be drawn without a font
+            read_file(path).unwrap_or_else(|e| panic!("Failed to read font {path}: {e:?}"))
         }
         #[cfg(not(target_os = "axle"))]
         {
@@ -0,0 +1,648 @@
+//! A host implementation of AMC, for testing services without booting axle.
+//!
+//! Each simulated process is a thread, and every thread spawned by the same `AmcSimulator`
+//! shares one message router. The usual `axle_rt` API (`amc_register_service`,
+//! `amc_message_send`, `amc_message_await*`, `amc_has_message`) works unchanged inside a
+//! simulated process, as it calls into the `libc` shim below rather than into axle's libc.
+//!
+//! The simulated core service answers the same requests the kernel does for queries,
+//! service-died notifications, sleeping until a message arrives, and shared memory. When a
+//! process's closure returns or panics, its service dies, and everyone who asked to be told
+//! about it receives an `AmcServiceDiedNotif`. Inboxes are bounded and follow the overflow
+//! policy their service configured, just like the kernel's.
+//!
+//! ```ignore
+//! let sim = AmcSimulator::new();
+//! sim.spawn(|| {
+//!     amc_register_service("com.axle.echo");
+//!     let msg: AmcMessage<Ping> = amc_message_await(None);
+//!     amc_message_send(msg.source(), *msg.body());
+//! });
+//! sim.run(|| {
+//!     amc_register_service("com.axle.test");
+//!     amc_message_send("com.axle.echo", Ping::new(7));
+//!     let echo: AmcMessage<Ping> = amc_message_await(Some("com.axle.echo"));
+//! });
+//! ```
+
+extern crate std;
+
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+use core::mem::size_of;
+use core::time::Duration;
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::Instant;
+
+use crate::core_commands::{
+    AmcInboxConfigure, AmcInboxOverflowPolicy, AmcMessageDropped, AmcQueryServiceRequest,
+    AmcQueryServiceResponse, AmcRegisterServiceDiedNotif, AmcServiceDiedNotif,
+    AmcSharedMemoryCreateRequest, AmcSharedMemoryCreateResponse, AmcSleepUntilDelayOrMessage,
+    AMC_CORE_SERVICE_NAME, AMC_INBOX_DEFAULT_LIMIT, AMC_INBOX_MAX_COALESCED_EVENTS,
+    AMC_INBOX_MAX_LIMIT, AMC_MAX_SERVICE_NAME_LEN,
+};
+use crate::ExpectsEventField;
+
+/// How long a sender waits for room in a full inbox, like `AMC_INBOX_SPACE_MAX_WAIT_MS` in the
+/// kernel
+const INBOX_SPACE_MAX_WAIT: Duration = Duration::from_millis(2000);
+
+#[derive(Debug)]
+struct QueuedMessage {
+    source: String,
+    body: Vec<u8>,
+}
+
+impl QueuedMessage {
+    fn event(&self) -> Option<u32> {
+        let bytes = self.body.get(..size_of::<u32>())?;
+        Some(u32::from_ne_bytes(bytes.try_into().unwrap()))
+    }
+}
+
+#[derive(Debug)]
+struct Service {
+    inbox: VecDeque<QueuedMessage>,
+    inbox_limit: usize,
+    overflow_policy: AmcInboxOverflowPolicy,
+    coalesced_events: Vec<u32>,
+    /// Services that asked to be told when this one dies
+    notify_upon_death: Vec<String>,
+}
+
+impl Default for Service {
+    fn default() -> Self {
+        Self {
+            inbox: VecDeque::new(),
+            inbox_limit: AMC_INBOX_DEFAULT_LIMIT as usize,
+            overflow_policy: AmcInboxOverflowPolicy::DropOldest,
+            coalesced_events: Vec::new(),
+            notify_upon_death: Vec::new(),
+        }
+    }
+}
+
+impl Service {
+    fn inbox_is_full(&self) -> bool {
+        self.inbox.len() >= self.inbox_limit
+    }
+
+    fn should_block_sender(&self) -> bool {
+        self.overflow_policy == AmcInboxOverflowPolicy::BlockSender && self.inbox_is_full()
+    }
+
+    /// Returns a message that was evicted to make room, and whether it was coalesced away
+    fn push(&mut self, message: QueuedMessage) -> Option<(QueuedMessage, bool)> {
+        if !self.inbox_is_full() {
+            self.inbox.push_back(message);
+            return None;
+        }
+        if self.overflow_policy == AmcInboxOverflowPolicy::Coalesce {
+            if let Some(event) = message
+                .event()
+                .filter(|e| self.coalesced_events.contains(e))
+            {
+                let superseded = self.inbox.iter_mut().rev().find(|queued| {
+                    queued.source == message.source && queued.event() == Some(event)
+                });
+                if let Some(superseded) = superseded {
+                    return Some((core::mem::replace(superseded, message), true));
+                }
+            }
+        }
+        let evicted = self.inbox.pop_front().unwrap();
+        self.inbox.push_back(message);
+        Some((evicted, false))
+    }
+}
+
+#[derive(Debug, Default)]
+struct SimState {
+    services: BTreeMap<String, Service>,
+    /// Messages sent to services that haven't registered yet, in the order they were sent
+    undelivered: Vec<(String, QueuedMessage)>,
+    /// Shared memory regions live as long as the simulator
+    shared_memory: Vec<Vec<u64>>,
+    /// Services blocked until another service's inbox has room, mapped to that service
+    awaiting_inbox_space: BTreeMap<String, String>,
+}
+
+struct SimKernel {
+    state: Mutex<SimState>,
+    /// Signalled whenever an inbox changes or a service registers
+    message_arrived: Condvar,
+    boot_time: Instant,
+}
+
+impl SimKernel {
+    fn lock(&self) -> MutexGuard<'_, SimState> {
+        // A simulated process that panics while holding the lock shouldn't take down the others
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn wait<'a>(&self, guard: MutexGuard<'a, SimState>) -> MutexGuard<'a, SimState> {
+        self.message_arrived
+            .wait(guard)
+            .unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn deliver(&self, state: &mut SimState, to_service: &str, message: QueuedMessage) {
+        let evicted = match state.services.get_mut(to_service) {
+            Some(service) => service.push(message),
+            // Like the kernel, hold onto messages for services that don't exist yet
+            None => {
+                state.undelivered.push((to_service.to_string(), message));
+                None
+            }
+        };
+        self.message_arrived.notify_all();
+
+        // Tell the sender that its message was dropped, unless a newer one superseded it
+        if let Some((evicted, false)) = evicted {
+            if evicted.source != AMC_CORE_SERVICE_NAME
+                && state.services.contains_key(&evicted.source)
+            {
+                let notif = AmcMessageDropped::new(to_service, evicted.event().unwrap_or(0));
+                self.deliver(state, &evicted.source, core_message(&notif));
+            }
+        }
+    }
+
+    fn register_service(&self, name: &str) {
+        let mut state = self.lock();
+        assert!(
+            !state.services.contains_key(name),
+            "Service {name} is already registered"
+        );
+        let mut service = Service::default();
+        let (pending, undelivered) = core::mem::take(&mut state.undelivered)
+            .into_iter()
+            .partition(|(to_service, _)| to_service == name);
+        state.undelivered = undelivered;
+        service
+            .inbox
+            .extend(pending.into_iter().map(|(_, message)| message));
+        state.services.insert(name.to_string(), service);
+        self.message_arrived.notify_all();
+    }
+
+    fn service_died(&self, name: &str) {
+        let mut state = self.lock();
+        let service = match state.services.remove(name) {
+            Some(service) => service,
+            None => return,
+        };
+        for to_notify in service.notify_upon_death.iter() {
+            let notif = AmcServiceDiedNotif::new(name);
+            self.deliver(&mut state, to_notify, core_message(&notif));
+        }
+    }
+
+    /// Handles a message sent to the core service, as the kernel would
+    fn handle_core_message(&self, from_service: &str, body: &[u8]) {
+        let mut state = self.lock();
+        match read_u32(body, 0) {
+            AmcQueryServiceRequest::EXPECTED_EVENT => {
+                let remote_service_name = read_service_name(body, 4);
+                let mut response = AmcQueryServiceResponse {
+                    event: AmcQueryServiceResponse::EXPECTED_EVENT,
+                    remote_service_name: [0; AMC_MAX_SERVICE_NAME_LEN],
+                    service_exists: state.services.contains_key(&remote_service_name),
+                };
+                crate::copy_str_into_sized_slice(
+                    &mut response.remote_service_name,
+                    &remote_service_name,
+                );
+                self.deliver(&mut state, from_service, core_message(&response));
+            }
+            AmcRegisterServiceDiedNotif::EXPECTED_EVENT => {
+                let remote_service_name = read_service_name(body, 4);
+                match state.services.get_mut(&remote_service_name) {
+                    Some(remote) => remote.notify_upon_death.push(from_service.to_string()),
+                    None => std::println!(
+                        "[amc_sim] Dropping death notification request from {from_service} \
+                         for {remote_service_name}, which doesn't exist"
+                    ),
+                }
+            }
+            AmcSleepUntilDelayOrMessage::EXPECTED_EVENT => {
+                let deadline = Instant::now() + Duration::from_millis(read_u32(body, 4) as u64);
+                loop {
+                    let has_message = state
+                        .services
+                        .get(from_service)
+                        .is_some_and(|s| !s.inbox.is_empty());
+                    let now = Instant::now();
+                    if has_message || now >= deadline {
+                        break;
+                    }
+                    state = self
+                        .message_arrived
+                        .wait_timeout(state, deadline - now)
+                        .unwrap_or_else(|e| e.into_inner())
+                        .0;
+                }
+            }
+            AmcSharedMemoryCreateRequest::EXPECTED_EVENT => {
+                let buffer_size = read_u32(body, 4 + AMC_MAX_SERVICE_NAME_LEN) as usize;
+                let mut buffer = vec![0_u64; buffer_size.div_ceil(size_of::<u64>())];
+                // Both services share an address space, so they see the buffer at the same place
+                let buffer_start = buffer.as_mut_ptr() as usize;
+                state.shared_memory.push(buffer);
+                let response = AmcSharedMemoryCreateResponse::new(buffer_start, buffer_start);
+                self.deliver(&mut state, from_service, core_message(&response));
+            }
+            AmcInboxConfigure::EXPECTED_EVENT => {
+                let limit = read_u32(body, 4);
+                let overflow_policy = match read_u32(body, 8) {
+                    0 => AmcInboxOverflowPolicy::DropOldest,
+                    1 => AmcInboxOverflowPolicy::BlockSender,
+                    2 => AmcInboxOverflowPolicy::Coalesce,
+                    policy => {
+                        panic!("[amc_sim] {from_service} sent unknown overflow policy {policy}")
+                    }
+                };
+                let coalesced_event_count = read_u32(body, 12) as usize;
+                assert!(
+                    limit > 0
+                        && limit <= AMC_INBOX_MAX_LIMIT
+                        && coalesced_event_count <= AMC_INBOX_MAX_COALESCED_EVENTS,
+                    "[amc_sim] {from_service} sent an invalid inbox configuration"
+                );
+                let service = state.services.get_mut(from_service).unwrap();
+                service.inbox_limit = limit as usize;
+                service.overflow_policy = overflow_policy;
+                service.coalesced_events = (0..coalesced_event_count)
+                    .map(|i| read_u32(body, 16 + i * size_of::<u32>()))
+                    .collect();
+            }
+            event => panic!("[amc_sim] {from_service} sent unsupported core event {event}"),
+        }
+    }
+}
+
+fn read_u32(body: &[u8], offset: usize) -> u32 {
+    let bytes = body
+        .get(offset..offset + size_of::<u32>())
+        .expect("[amc_sim] Core message was too short");
+    u32::from_ne_bytes(bytes.try_into().unwrap())
+}
+
+fn read_service_name(body: &[u8], offset: usize) -> String {
+    let name = body
+        .get(offset..offset + AMC_MAX_SERVICE_NAME_LEN)
+        .expect("[amc_sim] Core message was too short");
+    let len = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+    String::from_utf8_lossy(&name[..len]).into_owned()
+}
+
+fn core_message<T>(message: &T) -> QueuedMessage {
+    let body =
+        unsafe { core::slice::from_raw_parts(message as *const T as *const u8, size_of::<T>()) };
+    QueuedMessage {
+        source: AMC_CORE_SERVICE_NAME.to_string(),
+        body: body.to_vec(),
+    }
+}
+
+/// The simulated process running on this thread
+struct Process {
+    kernel: Arc<SimKernel>,
+    service: Option<String>,
+    /// Messages handed out to this process. They're kept until the process exits, as the AMC
+    /// API lets callers hold onto delivered messages.
+    delivered: Vec<Vec<u64>>,
+}
+
+std::thread_local! {
+    static PROCESS: RefCell<Option<Process>> = const { RefCell::new(None) };
+}
+
+fn with_process<R>(f: impl FnOnce(&mut Process) -> R) -> R {
+    PROCESS.with(|process| {
+        let mut process = process.borrow_mut();
+        let process = process
+            .as_mut()
+            .expect("[amc_sim] AMC was used outside of a simulated process");
+        f(process)
+    })
+}
+
+/// Tears down the simulated process on this thread when it exits, even if it panicked
+struct ProcessGuard;
+
+impl Drop for ProcessGuard {
+    fn drop(&mut self) {
+        let process = PROCESS.with(|process| process.borrow_mut().take());
+        if let Some(Process {
+            kernel,
+            service: Some(service),
+            ..
+        }) = process
+        {
+            kernel.service_died(&service);
+        }
+    }
+}
+
+/// Runs services as threads that talk to each other over a simulated AMC
+pub struct AmcSimulator {
+    kernel: Arc<SimKernel>,
+}
+
+impl AmcSimulator {
+    pub fn new() -> Self {
+        Self {
+            kernel: Arc::new(SimKernel {
+                state: Mutex::new(SimState::default()),
+                message_arrived: Condvar::new(),
+                boot_time: Instant::now(),
+            }),
+        }
+    }
+
+    /// Runs `f` as a new simulated process on its own thread
+    pub fn spawn<F, R>(&self, f: F) -> JoinHandle<R>
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let kernel = Arc::clone(&self.kernel);
+        thread::spawn(move || Self::run_process(kernel, f))
+    }
+
+    /// Runs `f` as a simulated process on the current thread, such as a test's own thread
+    pub fn run<R>(&self, f: impl FnOnce() -> R) -> R {
+        Self::run_process(Arc::clone(&self.kernel), f)
+    }
+
+    fn run_process<R>(kernel: Arc<SimKernel>, f: impl FnOnce() -> R) -> R {
+        PROCESS.with(|process| {
+            let mut process = process.borrow_mut();
+            assert!(
+                process.is_none(),
+                "[amc_sim] This thread is already running a simulated process"
+            );
+            *process = Some(Process {
+                kernel,
+                service: None,
+                delivered: Vec::new(),
+            });
+        });
+        let _guard = ProcessGuard;
+        f()
+    }
+
+    pub fn service_exists(&self, service_name: &str) -> bool {
+        self.kernel.lock().services.contains_key(service_name)
+    }
+
+    /// Blocks until `service_name` registers, and returns false if it doesn't within `timeout`
+    pub fn wait_for_service(&self, service_name: &str, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.kernel.lock();
+        while !state.services.contains_key(service_name) {
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+            state = self
+                .kernel
+                .message_arrived
+                .wait_timeout(state, deadline - now)
+                .unwrap_or_else(|e| e.into_inner())
+                .0;
+        }
+        true
+    }
+}
+
+impl Default for AmcSimulator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Stands in for the parts of axle's libc that `axle_rt` uses to talk to AMC
+#[allow(non_camel_case_types, non_snake_case)]
+pub mod libc {
+    use super::*;
+    use std::ffi::CStr;
+
+    pub type c_void = core::ffi::c_void;
+    pub type c_char = u8;
+
+    #[repr(C)]
+    pub struct amc_message_t {
+        pub source: [u8; AMC_MAX_SERVICE_NAME_LEN],
+        pub dest: [u8; AMC_MAX_SERVICE_NAME_LEN],
+        pub len: u32,
+        pub body: [u8; 0],
+    }
+
+    unsafe fn service_name(name: *const u8) -> String {
+        CStr::from_ptr(name as *const _)
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    /// Hands a message to the calling process, and returns where it was copied to
+    fn hand_out(
+        process: &mut Process,
+        to_service: &str,
+        message: QueuedMessage,
+    ) -> *mut amc_message_t {
+        // Place the header so that the body starts on an 8-byte boundary, like the messages
+        // that are cast to structs holding pointers or u64s expect
+        let header_offset = size_of::<u64>() - size_of::<amc_message_t>() % size_of::<u64>();
+        let len = header_offset + size_of::<amc_message_t>() + message.body.len();
+        let mut storage = vec![0_u64; len.div_ceil(size_of::<u64>())];
+        unsafe {
+            let msg = (storage.as_mut_ptr() as *mut u8).add(header_offset) as *mut amc_message_t;
+            crate::copy_str_into_sized_slice(&mut (*msg).source, &message.source);
+            crate::copy_str_into_sized_slice(&mut (*msg).dest, to_service);
+            (*msg).len = message.body.len() as u32;
+            core::ptr::copy_nonoverlapping(
+                message.body.as_ptr(),
+                core::ptr::addr_of_mut!((*msg).body) as *mut u8,
+                message.body.len(),
+            );
+            process.delivered.push(storage);
+            msg
+        }
+    }
+
+    /// Blocks until a message that `filter` accepts arrives in the calling process's inbox
+    fn await_message(out: *mut *mut amc_message_t, filter: impl Fn(&QueuedMessage) -> bool) {
+        with_process(|process| {
+            let this_service = process
+                .service
+                .clone()
+                .expect("[amc_sim] Awaited a message before registering a service");
+            let kernel = Arc::clone(&process.kernel);
+            let mut state = kernel.lock();
+            let message = loop {
+                let inbox = &mut state.services.get_mut(&this_service).unwrap().inbox;
+                if let Some(index) = inbox.iter().position(&filter) {
+                    // Senders may be waiting for room in the inbox
+                    kernel.message_arrived.notify_all();
+                    break inbox.remove(index).unwrap();
+                }
+                state = kernel.wait(state);
+            };
+            drop(state);
+            unsafe { *out = hand_out(process, &this_service, message) };
+        })
+    }
+
+    fn has_message(filter: impl Fn(&QueuedMessage) -> bool) -> bool {
+        with_process(|process| {
+            let this_service = match &process.service {
+                Some(service) => service,
+                None => return false,
+            };
+            process
+                .kernel
+                .lock()
+                .services
+                .get(this_service)
+                .is_some_and(|s| s.inbox.iter().any(&filter))
+        })
+    }
+
+    /// # Safety
+    ///
+    /// `s` must point to a NUL-terminated string.
+    pub unsafe fn printf(s: *const u8) -> i32 {
+        let s = CStr::from_ptr(s as *const _).to_string_lossy();
+        std::print!("{s}");
+        s.len() as i32
+    }
+
+    /// # Safety
+    ///
+    /// Always safe to call. It's only unsafe to match axle's libc.
+    pub unsafe fn ms_since_boot() -> u32 {
+        with_process(|process| process.kernel.boot_time.elapsed().as_millis() as u32)
+    }
+
+    /// # Safety
+    ///
+    /// `name` must point to a NUL-terminated string.
+    pub unsafe fn amc_register_service(name: *const u8) {
+        let name = service_name(name);
+        with_process(|process| {
+            assert!(
+                process.service.is_none(),
+                "[amc_sim] A process can only register one service"
+            );
+            process.kernel.register_service(&name);
+            process.service = Some(name);
+        })
+    }
+
+    /// # Safety
+    ///
+    /// `to_service` must point to a NUL-terminated string, and `buf` must be valid for reads of
+    /// `buf_size` bytes.
+    pub unsafe fn amc_message_send(to_service: *const u8, buf: *const c_void, buf_size: u32) {
+        let to_service = service_name(to_service);
+        let body = core::slice::from_raw_parts(buf as *const u8, buf_size as usize).to_vec();
+        let (kernel, this_service) = with_process(|process| {
+            let this_service = process
+                .service
+                .clone()
+                .expect("[amc_sim] Sent a message before registering a service");
+            (Arc::clone(&process.kernel), this_service)
+        });
+        if to_service == AMC_CORE_SERVICE_NAME {
+            kernel.handle_core_message(&this_service, &body);
+        } else {
+            let mut state = kernel.lock();
+            // Respect the destination's backpressure. Waiting on ourselves would never finish.
+            // Like the kernel, give up and drop the oldest message if the destination is waiting
+            // on our inbox, or if we've waited too long.
+            let give_up_at = Instant::now() + INBOX_SPACE_MAX_WAIT;
+            while to_service != this_service
+                && state
+                    .services
+                    .get(&to_service)
+                    .is_some_and(|s| s.should_block_sender())
+            {
+                let now = Instant::now();
+                if now >= give_up_at
+                    || state.awaiting_inbox_space.get(&to_service) == Some(&this_service)
+                {
+                    break;
+                }
+                state
+                    .awaiting_inbox_space
+                    .insert(this_service.clone(), to_service.clone());
+                state = kernel
+                    .message_arrived
+                    .wait_timeout(state, give_up_at - now)
+                    .unwrap_or_else(|e| e.into_inner())
+                    .0;
+            }
+            state.awaiting_inbox_space.remove(&this_service);
+            let message = QueuedMessage {
+                source: this_service,
+                body,
+            };
+            kernel.deliver(&mut state, &to_service, message);
+        }
+    }
+
+    /// # Safety
+    ///
+    /// `from_service` must point to a NUL-terminated string, and `out` must be valid for writes.
+    /// The message handed out lives until the calling process exits.
+    pub unsafe fn amc_message_await(from_service: *const u8, out: *mut *mut amc_message_t) {
+        let from_service = service_name(from_service);
+        await_message(out, |message| message.source == from_service)
+    }
+
+    /// # Safety
+    ///
+    /// `out` must be valid for writes. The message handed out lives until the calling process
+    /// exits.
+    pub unsafe fn amc_message_await_any(out: *mut *mut amc_message_t) {
+        await_message(out, |_| true)
+    }
+
+    /// # Safety
+    ///
+    /// `from_service` must point to a NUL-terminated string, and `out` must be valid for writes.
+    /// The message handed out lives until the calling process exits.
+    pub unsafe fn amc_message_await__u32_event(
+        from_service: *const u8,
+        event: u32,
+        out: *mut *mut amc_message_t,
+    ) {
+        let from_service = service_name(from_service);
+        await_message(out, |message| {
+            message.source == from_service
+                && message.body.get(..size_of::<u32>()) == Some(&event.to_ne_bytes()[..])
+        })
+    }
+
+    /// # Safety
+    ///
+    /// `from_service` must point to a NUL-terminated string.
+    pub unsafe fn amc_has_message_from(from_service: *const u8) -> bool {
+        let from_service = service_name(from_service);
+        has_message(|message| message.source == from_service)
+    }
+
+    /// # Safety
+    ///
+    /// Always safe to call. It's only unsafe to match axle's libc.
+    pub unsafe fn amc_has_message() -> bool {
+        has_message(|_| true)
+    }
+}
@@ -1,19 +1,107 @@
-#[cfg(target_os = "axle")]
+#[cfg(any(target_os = "axle", feature = "amc_sim"))]
 mod conditional_imports {
-    pub use crate::{amc_message_await, amc_message_send, copy_str_into_sized_slice, AmcMessage};
+    // The physical range helpers only exist on axle
+    #[cfg(target_os = "axle")]
+    pub use crate::amc_message_await;
+    #[cfg(target_os = "axle")]
+    pub use crate::amc_message_send_untyped;
+    pub use crate::{amc_message_send, copy_str_into_sized_slice, AmcMessage};
 }
-#[cfg(not(target_os = "axle"))]
+#[cfg(not(any(target_os = "axle", feature = "amc_sim")))]
 mod conditional_imports {}
 
 use crate::core_commands::conditional_imports::*;
 
+use crate::process::{SpawnError, MAX_ARGUMENTS, MAX_ARGUMENTS_LEN, MAX_ENVIRONMENT_LEN};
 use crate::{ContainsEventField, ExpectsEventField};
 use alloc::vec::Vec;
 use axle_rt_derive::ContainsEventField;
-use cstr_core::CString;
+use core::ops::{BitAnd, BitOr, BitOrAssign};
+use cstr_core::{CStr, CString};
 
 pub const AMC_CORE_SERVICE_NAME: &str = "com.axle.core";
-const AMC_MAX_SERVICE_NAME_LEN: usize = 64;
+pub(crate) const AMC_MAX_SERVICE_NAME_LEN: usize = 64;
+
+/// The privileged core commands that a service may use, mirroring `AMC_CAPABILITY_*` in the
+/// kernel. Programs are granted a subset of their launcher's capabilities when they're launched.
+#[repr(transparent)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct AmcCapabilities(u32);
+
+impl AmcCapabilities {
+    pub const NONE: Self = Self(0);
+    pub const MAP_PHYSICAL: Self = Self(1 << 0);
+    pub const ALLOC_PHYSICAL: Self = Self(1 << 1);
+    pub const EXEC_BUFFER: Self = Self(1 << 2);
+    pub const REGISTER_DRIVER: Self = Self(1 << 3);
+    pub const TRACE_MESSAGES: Self = Self(1 << 4);
+
+    const NAMES: [(&'static str, Self); 5] = [
+        ("map_physical", Self::MAP_PHYSICAL),
+        ("alloc_physical", Self::ALLOC_PHYSICAL),
+        ("exec_buffer", Self::EXEC_BUFFER),
+        ("register_driver", Self::REGISTER_DRIVER),
+        ("trace_messages", Self::TRACE_MESSAGES),
+    ];
+
+    /// Looks up a single capability by the name used in the startup manifest, like
+    /// `map_physical`
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::NAMES
+            .iter()
+            .find(|(capability_name, _)| *capability_name == name)
+            .map(|(_, capability)| *capability)
+    }
+
+    pub fn bits(self) -> u32 {
+        self.0
+    }
+
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl BitOr for AmcCapabilities {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for AmcCapabilities {
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.0 |= rhs.0
+    }
+}
+
+impl BitAnd for AmcCapabilities {
+    type Output = Self;
+
+    fn bitand(self, rhs: Self) -> Self {
+        Self(self.0 & rhs.0)
+    }
+}
+
+/// The kernel refused a privileged core command because the service wasn't granted the
+/// capability it needs
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct CapabilityDenied(pub AmcCapabilities);
+
+impl CapabilityDenied {
+    /// Responses to privileged commands report the missing capability, or 0 on success
+    fn check(missing_capability: u32) -> Result<(), Self> {
+        match missing_capability {
+            0 => Ok(()),
+            missing => Err(Self(AmcCapabilities(missing))),
+        }
+    }
+}
 
 #[derive(Debug)]
 pub struct PhysVirtPair {
@@ -76,6 +164,7 @@ impl ExpectsEventField for AmcMapPhysicalRangeRequest {
 pub struct AmcMapPhysicalRangeResponse {
     event: u32,
     virt_base: usize,
+    missing_capability: u32,
 }
 
 // Never constructed directly, sent from amc
@@ -86,12 +175,13 @@ impl ExpectsEventField for AmcMapPhysicalRangeResponse {
 }
 
 #[cfg(target_os = "axle")]
-pub fn amc_map_physical_range(phys_base: usize, size: usize) -> usize {
+pub fn amc_map_physical_range(phys_base: usize, size: usize) -> Result<usize, CapabilityDenied> {
     let req = AmcMapPhysicalRangeRequest::new(phys_base, size);
     amc_message_send(AMC_CORE_SERVICE_NAME, req);
     let resp: AmcMessage<AmcMapPhysicalRangeResponse> =
         amc_message_await(Some(AMC_CORE_SERVICE_NAME));
-    resp.body().virt_base
+    CapabilityDenied::check(resp.body().missing_capability)?;
+    Ok(resp.body().virt_base)
 }
 
 /* Alloc virtual memory mapping */
@@ -122,6 +212,7 @@ pub struct AmcAllocPhysicalRangeResponse {
     event: u32,
     phys_base: usize,
     virt_base: usize,
+    missing_capability: u32,
 }
 
 // Never constructed directly, sent from amc
@@ -132,13 +223,14 @@ impl ExpectsEventField for AmcAllocPhysicalRangeResponse {
 }
 
 #[cfg(target_os = "axle")]
-pub fn amc_alloc_physical_range(size: usize) -> PhysRangeMapping {
+pub fn amc_alloc_physical_range(size: usize) -> Result<PhysRangeMapping, CapabilityDenied> {
     let req = AmcAllocPhysicalRangeRequest::new(size);
     amc_message_send(AMC_CORE_SERVICE_NAME, req);
     let resp: AmcMessage<AmcAllocPhysicalRangeResponse> =
         amc_message_await(Some(AMC_CORE_SERVICE_NAME));
+    CapabilityDenied::check(resp.body().missing_capability)?;
     let addr = PhysVirtPair::new(resp.body().phys_base, resp.body().virt_base);
-    PhysRangeMapping::new(addr, size)
+    Ok(PhysRangeMapping::new(addr, size))
 }
 
 /* Free virtual memory mapping */
@@ -198,7 +290,7 @@ impl ExpectsEventField for AmcQueryServiceRequest {
 }
 
 impl AmcQueryServiceRequest {
-    #[cfg(target_os = "axle")]
+    #[cfg(any(target_os = "axle", feature = "amc_sim"))]
     pub fn send(remote_service_name: &str) -> AmcQueryServiceResponse {
         let mut name_buf = [0; AMC_MAX_SERVICE_NAME_LEN];
         let _name_len = copy_str_into_sized_slice(&mut name_buf, remote_service_name);
@@ -227,6 +319,10 @@ impl ExpectsEventField for AmcQueryServiceResponse {
 }
 
 // Start/control processes
+/// The header of an exec request. The program's arguments follow it in the same message, as
+/// `arguments_len` bytes of NUL-terminated entries, and then its environment, as
+/// `environment_len` bytes of NUL-terminated KEY=VALUE entries. Carrying them in the message
+/// lets the kernel bounds-check them.
 #[repr(C)]
 #[derive(Debug, ContainsEventField)]
 pub struct AmcExecBuffer {
@@ -235,6 +331,10 @@ pub struct AmcExecBuffer {
     with_supervisor: bool,
     buffer_addr: *const u8,
     buffer_size: u32,
+    // Limited by the kernel to the capabilities that the sender holds
+    capabilities: AmcCapabilities,
+    arguments_len: u32,
+    environment_len: u32,
 }
 
 impl AmcExecBuffer {
@@ -249,14 +349,132 @@ impl AmcExecBuffer {
             with_supervisor,
             buffer_addr,
             buffer_size: buf.len() as _,
+            capabilities: AmcCapabilities::NONE,
+            arguments_len: 0,
+            environment_len: 0,
         }
     }
+
+    /// Encodes `arguments` as the block of NUL-terminated entries that follows the header.
+    /// Arguments may contain spaces, but can't be empty or contain a NUL.
+    pub fn encode_arguments(arguments: &[&str]) -> Result<Vec<u8>, SpawnError> {
+        if arguments.len() > MAX_ARGUMENTS {
+            return Err(SpawnError::TooManyArguments);
+        }
+        let mut block = Vec::new();
+        for argument in arguments {
+            if argument.is_empty() || argument.contains('\0') {
+                return Err(SpawnError::InvalidArgument);
+            }
+            block.extend_from_slice(argument.as_bytes());
+            block.push(0);
+        }
+        if block.len() > MAX_ARGUMENTS_LEN {
+            return Err(SpawnError::TooManyArguments);
+        }
+        Ok(block)
+    }
+
+    /// The message to send: this header, followed by the encoded `arguments` and `environment`
+    fn into_message(mut self, arguments: &[u8], environment: &[u8]) -> Vec<u8> {
+        self.arguments_len = arguments.len() as u32;
+        self.environment_len = environment.len() as u32;
+        let header = unsafe {
+            core::slice::from_raw_parts(
+                &self as *const Self as *const u8,
+                core::mem::size_of::<Self>(),
+            )
+        };
+        let mut message = Vec::with_capacity(header.len() + arguments.len() + environment.len());
+        message.extend_from_slice(header);
+        message.extend_from_slice(arguments);
+        message.extend_from_slice(environment);
+        message
+    }
+
+    /// Launches a program, passing it `arguments` after its name in argv, and granting it
+    /// whichever of `capabilities` the sender holds.
+    /// The kernel copies the program name while it handles the message, so it only needs to
+    /// live until it's sent.
+    #[cfg(target_os = "axle")]
+    pub fn send_with_arguments(
+        program_name: &str,
+        arguments: &[&str],
+        buf: &[u8],
+        with_supervisor: bool,
+        capabilities: AmcCapabilities,
+    ) -> Result<(), SpawnError> {
+        let program_name = CString::new(program_name).map_err(|_| SpawnError::InvalidArgument)?;
+        let arguments = Self::encode_arguments(arguments)?;
+        let header = AmcExecBuffer {
+            event: Self::EXPECTED_EVENT,
+            program_name: program_name.as_ptr() as *const u8,
+            with_supervisor,
+            buffer_addr: buf.as_ptr(),
+            buffer_size: buf.len() as _,
+            capabilities,
+            arguments_len: 0,
+            environment_len: 0,
+        };
+        let message = header.into_message(&arguments, &[]);
+        unsafe { amc_message_send_untyped(AMC_CORE_SERVICE_NAME, message.as_ptr(), message.len()) };
+        Ok(())
+    }
+
+    /// Builds the message that launches a program with `arguments` and an `environment` block.
+    /// The kernel copies the program name while it handles the message, so it only needs to live
+    /// until the message is sent.
+    /// `environment` holds non-empty NUL-terminated KEY=VALUE entries, and is limited to
+    /// `MAX_ENVIRONMENT_LEN` bytes.
+    pub fn with_environment(
+        program_name: &CStr,
+        arguments: &[&str],
+        environment: &[u8],
+        buf: &[u8],
+        with_supervisor: bool,
+        capabilities: AmcCapabilities,
+    ) -> Result<Vec<u8>, SpawnError> {
+        if environment.len() > MAX_ENVIRONMENT_LEN {
+            return Err(SpawnError::TooManyEnvironmentEntries);
+        }
+        let arguments = Self::encode_arguments(arguments)?;
+        let header = AmcExecBuffer {
+            event: Self::EXPECTED_EVENT,
+            program_name: program_name.as_ptr() as *const u8,
+            with_supervisor,
+            buffer_addr: buf.as_ptr(),
+            buffer_size: buf.len() as _,
+            capabilities,
+            arguments_len: 0,
+            environment_len: 0,
+        };
+        Ok(header.into_message(&arguments, environment))
+    }
 }
 
 impl ExpectsEventField for AmcExecBuffer {
     const EXPECTED_EVENT: u32 = 204;
 }
 
+/// Only sent when the sender isn't allowed to launch programs, or the request was malformed
+#[repr(C)]
+#[derive(Debug, ContainsEventField)]
+pub struct AmcExecBufferDenied {
+    event: u32,
+    missing_capability: u32,
+}
+
+impl AmcExecBufferDenied {
+    /// Empty if the kernel rejected the request as malformed
+    pub fn missing_capability(&self) -> AmcCapabilities {
+        AmcCapabilities(self.missing_capability)
+    }
+}
+
+impl ExpectsEventField for AmcExecBufferDenied {
+    const EXPECTED_EVENT: u32 = 204;
+}
+
 #[repr(C)]
 #[derive(Debug)]
 pub enum SupervisedProcessEvent {
@@ -273,6 +491,16 @@ pub struct AmcSupervisedProcessEventMsg {
     pub supervised_process_event: SupervisedProcessEvent,
 }
 
+impl AmcSupervisedProcessEventMsg {
+    #[cfg(test)]
+    pub(crate) fn new(supervised_process_event: SupervisedProcessEvent) -> Self {
+        Self {
+            event: Self::EXPECTED_EVENT,
+            supervised_process_event,
+        }
+    }
+}
+
 impl ExpectsEventField for AmcSupervisedProcessEventMsg {
     const EXPECTED_EVENT: u32 = 215;
 }
@@ -322,7 +550,7 @@ pub struct AmcSharedMemoryCreateRequest {
 }
 
 impl AmcSharedMemoryCreateRequest {
-    #[cfg(target_os = "axle")]
+    #[cfg(any(target_os = "axle", feature = "amc_sim"))]
     pub fn send(remote_service_name: &str, buffer_size: u32) -> AmcSharedMemoryCreateResponse {
         let mut name_buf = [0; AMC_MAX_SERVICE_NAME_LEN];
         let _name_len = copy_str_into_sized_slice(&mut name_buf, remote_service_name);
@@ -351,6 +579,17 @@ pub struct AmcSharedMemoryCreateResponse {
     pub remote_buffer_start: usize,
 }
 
+impl AmcSharedMemoryCreateResponse {
+    #[cfg(feature = "amc_sim")]
+    pub(crate) fn new(local_buffer_start: usize, remote_buffer_start: usize) -> Self {
+        Self {
+            event: Self::EXPECTED_EVENT,
+            local_buffer_start,
+            remote_buffer_start,
+        }
+    }
+}
+
 impl ExpectsEventField for AmcSharedMemoryCreateResponse {
     const EXPECTED_EVENT: u32 = 210;
 }
@@ -383,7 +622,7 @@ pub struct AmcRegisterServiceDiedNotif {
 }
 
 impl AmcRegisterServiceDiedNotif {
-    #[cfg(target_os = "axle")]
+    #[cfg(any(target_os = "axle", feature = "amc_sim"))]
     pub fn send(service_name: &str) {
         let mut name_buf = [0; AMC_MAX_SERVICE_NAME_LEN];
         let _name_len = copy_str_into_sized_slice(&mut name_buf, service_name);
@@ -406,8 +645,366 @@ pub struct AmcServiceDiedNotif {
     pub dead_service: [u8; AMC_MAX_SERVICE_NAME_LEN],
 }
 
+impl AmcServiceDiedNotif {
+    #[cfg(feature = "amc_sim")]
+    pub(crate) fn new(dead_service: &str) -> Self {
+        let mut dead_service_buf = [0; AMC_MAX_SERVICE_NAME_LEN];
+        let _name_len = copy_str_into_sized_slice(&mut dead_service_buf, dead_service);
+        Self {
+            event: Self::EXPECTED_EVENT,
+            dead_service: dead_service_buf,
+        }
+    }
+}
+
 impl ExpectsEventField for AmcServiceDiedNotif {
     const EXPECTED_EVENT: u32 = 208;
 }
 
+/* Trace AMC traffic */
+
+#[repr(C)]
+#[derive(Debug, ContainsEventField)]
+pub struct AmcTraceStreamRequest {
+    event: u32,
+    /// Only records newer than this are sent back
+    after_sequence: u64,
+}
+
+impl AmcTraceStreamRequest {
+    pub fn new(after_sequence: u64) -> Self {
+        Self {
+            event: Self::EXPECTED_EVENT,
+            after_sequence,
+        }
+    }
+}
+
+impl ExpectsEventField for AmcTraceStreamRequest {
+    const EXPECTED_EVENT: u32 = 216;
+}
+
+fn str_from_service_name(name: &[u8; AMC_MAX_SERVICE_NAME_LEN]) -> &str {
+    let len = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+    core::str::from_utf8(&name[..len]).unwrap_or("<invalid name>")
+}
+
+/// A message that the kernel saw being sent
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct AmcTraceRecord {
+    source: [u8; AMC_MAX_SERVICE_NAME_LEN],
+    dest: [u8; AMC_MAX_SERVICE_NAME_LEN],
+    /// The first word of the body, or 0 if the body was too short to hold one
+    pub event: u32,
+    pub size: u32,
+    /// Milliseconds since boot
+    pub timestamp: u64,
+}
+
+impl AmcTraceRecord {
+    pub fn source(&self) -> &str {
+        str_from_service_name(&self.source)
+    }
+
+    pub fn dest(&self) -> &str {
+        str_from_service_name(&self.dest)
+    }
+}
+
+/// How many messages are waiting in a service's inbox
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct AmcInboxDepth {
+    service: [u8; AMC_MAX_SERVICE_NAME_LEN],
+    pub depth: u32,
+}
+
+impl AmcInboxDepth {
+    pub fn service(&self) -> &str {
+        str_from_service_name(&self.service)
+    }
+}
+
+/// Fixed-size start of the response, which is followed by the records and inbox depths
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+struct AmcTraceStreamResponseHeader {
+    event: u32,
+    status: u32,
+    last_sequence: u64,
+    dropped_count: u64,
+    record_count: u32,
+    inbox_count: u32,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AmcTraceStreamStatus {
+    Streaming,
+    /// The requester wasn't granted `AmcCapabilities::TRACE_MESSAGES`
+    MissingCapability,
+    /// Another service is already subscribed to the trace
+    SubscriberExists,
+}
+
+impl AmcTraceStreamStatus {
+    fn from_raw(raw: u32) -> Option<Self> {
+        match raw {
+            0 => Some(Self::Streaming),
+            1 => Some(Self::MissingCapability),
+            2 => Some(Self::SubscriberExists),
+            _ => None,
+        }
+    }
+}
+
+/// The response to `AmcTraceStreamRequest`.
+/// Only one service may stream the trace at a time, and a refused request carries no records.
+#[derive(Debug, Clone)]
+pub struct AmcTraceStream {
+    pub status: AmcTraceStreamStatus,
+    /// Pass this in the next request to pick up where this response left off
+    pub last_sequence: u64,
+    /// Records that were overwritten before they could be streamed
+    pub dropped_count: u64,
+    pub records: Vec<AmcTraceRecord>,
+    pub inbox_depths: Vec<AmcInboxDepth>,
+}
+
+impl ExpectsEventField for AmcTraceStream {
+    const EXPECTED_EVENT: u32 = 216;
+}
+
+/// Only used for the plain-old-data structs above, for which any bytes are a valid value.
+/// Message bodies aren't necessarily aligned for their fields, so the value is copied out.
+fn read_from_body<T: Copy>(body: &[u8], offset: usize) -> Option<T> {
+    let bytes = body.get(offset..offset.checked_add(core::mem::size_of::<T>())?)?;
+    Some(unsafe { core::ptr::read_unaligned(bytes.as_ptr() as *const T) })
+}
+
+impl AmcTraceStream {
+    /// Returns None if the body isn't a well-formed trace stream response
+    pub fn parse(body: &[u8]) -> Option<Self> {
+        let header: AmcTraceStreamResponseHeader = read_from_body(body, 0)?;
+        if header.event != Self::EXPECTED_EVENT {
+            return None;
+        }
+        let status = AmcTraceStreamStatus::from_raw(header.status)?;
+        let records_start = core::mem::size_of::<AmcTraceStreamResponseHeader>();
+        let records = (0..header.record_count as usize)
+            .map(|i| {
+                read_from_body(
+                    body,
+                    records_start + i * core::mem::size_of::<AmcTraceRecord>(),
+                )
+            })
+            .collect::<Option<Vec<AmcTraceRecord>>>()?;
+        let inboxes_start = records_start + records.len() * core::mem::size_of::<AmcTraceRecord>();
+        let inbox_depths = (0..header.inbox_count as usize)
+            .map(|i| {
+                read_from_body(
+                    body,
+                    inboxes_start + i * core::mem::size_of::<AmcInboxDepth>(),
+                )
+            })
+            .collect::<Option<Vec<AmcInboxDepth>>>()?;
+        Some(Self {
+            status,
+            last_sequence: header.last_sequence,
+            dropped_count: header.dropped_count,
+            records,
+            inbox_depths,
+        })
+    }
+}
+
+/* Configure the inbox */
+
+/// How many messages an inbox holds until its service configures it otherwise
+pub const AMC_INBOX_DEFAULT_LIMIT: u32 = 1024;
+pub const AMC_INBOX_MAX_LIMIT: u32 = 8192;
+pub const AMC_INBOX_MAX_COALESCED_EVENTS: usize = 8;
+
+/// What happens when a message arrives at a full inbox
+#[repr(u32)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AmcInboxOverflowPolicy {
+    /// Evict the oldest queued message. Its sender receives an `AmcMessageDropped`.
+    DropOldest = 0,
+    /// Block the sender until there's room. The kernel can't block, so messages it sends drop
+    /// the oldest message instead. So do senders whose own inbox the destination is waiting on,
+    /// and senders that have waited for too long.
+    BlockSender = 1,
+    /// Replace the newest queued message with the same source and event, if the event is one of
+    /// the coalesced events. Otherwise, drop the oldest message.
+    Coalesce = 2,
+}
+
+#[repr(C)]
+#[derive(Debug, ContainsEventField)]
+pub struct AmcInboxConfigure {
+    event: u32,
+    limit: u32,
+    overflow_policy: u32,
+    coalesced_event_count: u32,
+    coalesced_events: [u32; AMC_INBOX_MAX_COALESCED_EVENTS],
+}
+
+impl AmcInboxConfigure {
+    pub fn new(
+        limit: u32,
+        overflow_policy: AmcInboxOverflowPolicy,
+        coalesced_events: &[u32],
+    ) -> Self {
+        assert!(
+            coalesced_events.len() <= AMC_INBOX_MAX_COALESCED_EVENTS,
+            "At most {AMC_INBOX_MAX_COALESCED_EVENTS} events can be coalesced"
+        );
+        let mut coalesced_events_buf = [0; AMC_INBOX_MAX_COALESCED_EVENTS];
+        coalesced_events_buf[..coalesced_events.len()].copy_from_slice(coalesced_events);
+        Self {
+            event: Self::EXPECTED_EVENT,
+            limit,
+            overflow_policy: overflow_policy as u32,
+            coalesced_event_count: coalesced_events.len() as u32,
+            coalesced_events: coalesced_events_buf,
+        }
+    }
+}
+
+impl ExpectsEventField for AmcInboxConfigure {
+    const EXPECTED_EVENT: u32 = 217;
+}
+
+/// Configures the calling service's inbox. The kernel ignores limits of 0 or above
+/// `AMC_INBOX_MAX_LIMIT`.
+#[cfg(any(target_os = "axle", feature = "amc_sim"))]
+pub fn amc_configure_inbox(
+    limit: u32,
+    overflow_policy: AmcInboxOverflowPolicy,
+    coalesced_events: &[u32],
+) {
+    amc_message_send(
+        AMC_CORE_SERVICE_NAME,
+        AmcInboxConfigure::new(limit, overflow_policy, coalesced_events),
+    );
+}
+
+/// Sent by the core when a message we sent was evicted from a full inbox before it was
+/// delivered. Messages superseded by coalescing aren't reported.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, ContainsEventField)]
+pub struct AmcMessageDropped {
+    event: u32,
+    dest: [u8; AMC_MAX_SERVICE_NAME_LEN],
+    /// The event of the dropped message, or 0 if its body was too short to hold one
+    pub dropped_event: u32,
+}
+
+impl AmcMessageDropped {
+    #[cfg(feature = "amc_sim")]
+    pub(crate) fn new(dest: &str, dropped_event: u32) -> Self {
+        let mut dest_buf = [0; AMC_MAX_SERVICE_NAME_LEN];
+        let _name_len = copy_str_into_sized_slice(&mut dest_buf, dest);
+        Self {
+            event: Self::EXPECTED_EVENT,
+            dest: dest_buf,
+            dropped_event,
+        }
+    }
+
+    /// The service the dropped message was sent to
+    pub fn dest(&self) -> &str {
+        str_from_service_name(&self.dest)
+    }
+}
+
+impl ExpectsEventField for AmcMessageDropped {
+    const EXPECTED_EVENT: u32 = 218;
+}
+
 /* End of event modeling */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    fn service_name(name: &str) -> [u8; AMC_MAX_SERVICE_NAME_LEN] {
+        let mut buf = [0; AMC_MAX_SERVICE_NAME_LEN];
+        buf[..name.len()].copy_from_slice(name.as_bytes());
+        buf
+    }
+
+    fn as_bytes<T>(value: &T) -> &[u8] {
+        unsafe {
+            core::slice::from_raw_parts(value as *const T as *const u8, core::mem::size_of::<T>())
+        }
+    }
+
+    #[test]
+    fn test_parse_trace_stream() {
+        let record = AmcTraceRecord {
+            source: service_name("com.axle.awm"),
+            dest: service_name("com.axle.awm_dock"),
+            event: 42,
+            size: 16,
+            timestamp: 1234,
+        };
+        let inbox = AmcInboxDepth {
+            service: service_name("com.axle.awm_dock"),
+            depth: 3,
+        };
+        let header = AmcTraceStreamResponseHeader {
+            event: AmcTraceStream::EXPECTED_EVENT,
+            status: 0,
+            last_sequence: 7,
+            dropped_count: 2,
+            record_count: 2,
+            inbox_count: 1,
+        };
+        // Start at an odd offset, as message bodies aren't aligned for the 64-bit fields
+        let mut buf = vec![0xff];
+        buf.extend_from_slice(as_bytes(&header));
+        buf.extend_from_slice(as_bytes(&record));
+        buf.extend_from_slice(as_bytes(&record));
+        buf.extend_from_slice(as_bytes(&inbox));
+        let body = &buf[1..];
+
+        let stream = AmcTraceStream::parse(body).unwrap();
+        assert_eq!(stream.status, AmcTraceStreamStatus::Streaming);
+        assert_eq!(stream.last_sequence, 7);
+        assert_eq!(stream.dropped_count, 2);
+        assert_eq!(stream.records.len(), 2);
+        assert_eq!(stream.records[1].source(), "com.axle.awm");
+        assert_eq!(stream.records[1].dest(), "com.axle.awm_dock");
+        assert_eq!(stream.records[1].event, 42);
+        assert_eq!(stream.records[1].timestamp, 1234);
+        assert_eq!(stream.inbox_depths.len(), 1);
+        assert_eq!(stream.inbox_depths[0].service(), "com.axle.awm_dock");
+        assert_eq!(stream.inbox_depths[0].depth, 3);
+
+        // The counts say there's more data than the body holds
+        assert!(AmcTraceStream::parse(&body[..body.len() - 1]).is_none());
+        assert!(AmcTraceStream::parse(&body[..8]).is_none());
+    }
+
+    #[test]
+    fn test_parse_refused_trace_stream() {
+        let mut header = AmcTraceStreamResponseHeader {
+            event: AmcTraceStream::EXPECTED_EVENT,
+            status: 2,
+            last_sequence: 0,
+            dropped_count: 0,
+            record_count: 0,
+            inbox_count: 0,
+        };
+        let stream = AmcTraceStream::parse(as_bytes(&header)).unwrap();
+        assert_eq!(stream.status, AmcTraceStreamStatus::SubscriberExists);
+        assert!(stream.records.is_empty());
+
+        // Statuses this build doesn't know about are rejected
+        header.status = 3;
+        assert!(AmcTraceStream::parse(as_bytes(&header)).is_none());
+    }
+}
@@ -0,0 +1,442 @@
+//! A single-threaded executor for writing event loops as async tasks.
+//!
+//! Tasks wait on AMC messages, IRQs and timers through an `Events` handle:
+//!
+//! ```ignore
+//! let mut executor = Executor::new();
+//! let events = executor.events();
+//! executor.spawn(async move {
+//!     loop {
+//!         events.next_irq(AHCI_INTERRUPT_VECTOR).await;
+//!         handle_interrupt();
+//!         adi_send_eoi(AHCI_INTERRUPT_VECTOR);
+//!     }
+//! });
+//! let events = executor.events();
+//! executor.spawn(async move {
+//!     loop {
+//!         let msg = events.next_message(None).await;
+//!         handle_message(msg);
+//!     }
+//! });
+//! executor.run();
+//! ```
+//!
+//! Once no task can make progress, the executor blocks in the kernel until something that a
+//! task is waiting on could have happened, and then polls every waiting task again. Messages
+//! that no task is waiting for are held until one asks for them.
+
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::rc::Rc;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::task::Wake;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::{Context, Poll, Waker};
+
+#[cfg(any(target_os = "axle", feature = "amc_sim"))]
+use crate::rpc::AmcKernelTransport;
+use crate::rpc::{AmcTransport, OwnedAmcMessage};
+
+/// Tracks the events that tasks are waiting on, and blocks until one of them might be ready
+struct Reactor<T: AmcTransport> {
+    transport: T,
+    /// Messages that have been received, but not yet claimed by a task
+    messages: VecDeque<OwnedAmcMessage>,
+    /// IRQs that have fired, but not yet been claimed by a task
+    pending_irqs: BTreeMap<u32, u32>,
+    // Registered by pending futures, and cleared whenever they're woken
+    waiters: Vec<Waker>,
+    awaited_irqs: Vec<u32>,
+    next_deadline: Option<u64>,
+    /// Set when messages arrive that the waiting tasks haven't seen yet
+    received_since_wake: bool,
+}
+
+impl<T: AmcTransport> Reactor<T> {
+    fn new(transport: T) -> Self {
+        Self {
+            transport,
+            messages: VecDeque::new(),
+            pending_irqs: BTreeMap::new(),
+            waiters: Vec::new(),
+            awaited_irqs: Vec::new(),
+            next_deadline: None,
+            received_since_wake: false,
+        }
+    }
+
+    fn receive_pending_messages(&mut self) {
+        while self.transport.has_message() {
+            let msg = self.transport.receive();
+            self.messages.push_back(msg);
+            self.received_since_wake = true;
+        }
+    }
+
+    /// Wakes every waiting task, so that each can check whether its event has happened
+    fn wake_all(&mut self) {
+        for waker in self.waiters.drain(..) {
+            waker.wake();
+        }
+        self.awaited_irqs.clear();
+        self.next_deadline = None;
+        self.received_since_wake = false;
+    }
+
+    fn wait_for_event(&mut self) {
+        if self.waiters.is_empty() {
+            panic!("Every task is waiting on something other than messages, IRQs or timers");
+        }
+        self.receive_pending_messages();
+        let now = self.transport.ms_since_boot();
+        let timer_expired = matches!(self.next_deadline, Some(deadline) if deadline <= now);
+        if !self.received_since_wake && !timer_expired {
+            if let Some(irq) = self.awaited_irqs.first().copied() {
+                // The kernel can't wake us for a timer while we're waiting on an IRQ, so timers
+                // that expire in the meantime are noticed once an IRQ or a message arrives
+                if self.transport.await_irq_or_message(irq) {
+                    *self.pending_irqs.entry(irq).or_insert(0) += 1;
+                }
+            } else if let Some(deadline) = self.next_deadline {
+                self.transport
+                    .sleep_until_message_or_delay((deadline - now) as u32);
+            } else {
+                let msg = self.transport.receive();
+                self.messages.push_back(msg);
+            }
+            self.receive_pending_messages();
+        }
+        self.wake_all();
+    }
+
+    fn register(&mut self, cx: &Context<'_>) {
+        self.waiters.push(cx.waker().clone());
+    }
+}
+
+/// A handle that tasks use to wait on events
+pub struct Events<T: AmcTransport> {
+    reactor: Rc<RefCell<Reactor<T>>>,
+}
+
+impl<T: AmcTransport> Clone for Events<T> {
+    fn clone(&self) -> Self {
+        Self {
+            reactor: Rc::clone(&self.reactor),
+        }
+    }
+}
+
+impl<T: AmcTransport> Events<T> {
+    /// Resolves to the next message from `from_service`, or from any service if None
+    pub fn next_message(&self, from_service: Option<&str>) -> NextMessage<T> {
+        NextMessage {
+            reactor: Rc::clone(&self.reactor),
+            from_service: from_service.map(String::from),
+        }
+    }
+
+    /// Resolves when `irq` fires. The task must call `adi_send_eoi` before it waits for the
+    /// next one.
+    pub fn next_irq(&self, irq: u32) -> NextIrq<T> {
+        NextIrq {
+            reactor: Rc::clone(&self.reactor),
+            irq,
+        }
+    }
+
+    /// Resolves once `ms` have passed
+    pub fn sleep(&self, ms: u32) -> Sleep<T> {
+        let deadline = self.reactor.borrow_mut().transport.ms_since_boot() + ms as u64;
+        Sleep {
+            reactor: Rc::clone(&self.reactor),
+            deadline,
+        }
+    }
+
+    /// Sends a message through the executor's transport
+    pub fn send(&self, to_service: &str, message: &[u8]) {
+        self.reactor
+            .borrow_mut()
+            .transport
+            .send(to_service, message);
+    }
+
+    pub fn ms_since_boot(&self) -> u64 {
+        self.reactor.borrow_mut().transport.ms_since_boot()
+    }
+}
+
+pub struct NextMessage<T: AmcTransport> {
+    reactor: Rc<RefCell<Reactor<T>>>,
+    from_service: Option<String>,
+}
+
+impl<T: AmcTransport> Future for NextMessage<T> {
+    type Output = OwnedAmcMessage;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<OwnedAmcMessage> {
+        let mut reactor = self.reactor.borrow_mut();
+        reactor.receive_pending_messages();
+        let position = reactor
+            .messages
+            .iter()
+            .position(|msg| match &self.from_service {
+                Some(from_service) => msg.source == *from_service,
+                None => true,
+            });
+        match position {
+            Some(position) => Poll::Ready(reactor.messages.remove(position).unwrap()),
+            None => {
+                reactor.register(cx);
+                Poll::Pending
+            }
+        }
+    }
+}
+
+pub struct NextIrq<T: AmcTransport> {
+    reactor: Rc<RefCell<Reactor<T>>>,
+    irq: u32,
+}
+
+impl<T: AmcTransport> Future for NextIrq<T> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let mut reactor = self.reactor.borrow_mut();
+        match reactor.pending_irqs.get_mut(&self.irq) {
+            Some(count) if *count > 0 => {
+                *count -= 1;
+                Poll::Ready(())
+            }
+            _ => {
+                reactor.awaited_irqs.push(self.irq);
+                reactor.register(cx);
+                Poll::Pending
+            }
+        }
+    }
+}
+
+pub struct Sleep<T: AmcTransport> {
+    reactor: Rc<RefCell<Reactor<T>>>,
+    deadline: u64,
+}
+
+impl<T: AmcTransport> Future for Sleep<T> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let mut reactor = self.reactor.borrow_mut();
+        if reactor.transport.ms_since_boot() >= self.deadline {
+            return Poll::Ready(());
+        }
+        reactor.next_deadline = Some(match reactor.next_deadline {
+            Some(deadline) => deadline.min(self.deadline),
+            None => self.deadline,
+        });
+        reactor.register(cx);
+        Poll::Pending
+    }
+}
+
+struct TaskWaker {
+    woken: AtomicBool,
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.woken.store(true, Ordering::Release);
+    }
+}
+
+struct Task {
+    future: Pin<Box<dyn Future<Output = ()>>>,
+    waker: Arc<TaskWaker>,
+}
+
+/// Runs tasks until they've all finished
+pub struct Executor<T: AmcTransport> {
+    tasks: Vec<Task>,
+    events: Events<T>,
+}
+
+#[cfg(any(target_os = "axle", feature = "amc_sim"))]
+impl Executor<AmcKernelTransport> {
+    pub fn new() -> Self {
+        Self::with_transport(AmcKernelTransport)
+    }
+}
+
+#[cfg(any(target_os = "axle", feature = "amc_sim"))]
+impl Default for Executor<AmcKernelTransport> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: AmcTransport> Executor<T> {
+    pub fn with_transport(transport: T) -> Self {
+        Self {
+            tasks: Vec::new(),
+            events: Events {
+                reactor: Rc::new(RefCell::new(Reactor::new(transport))),
+            },
+        }
+    }
+
+    pub fn events(&self) -> Events<T> {
+        self.events.clone()
+    }
+
+    pub fn spawn(&mut self, future: impl Future<Output = ()> + 'static) {
+        self.tasks.push(Task {
+            future: Box::pin(future),
+            waker: Arc::new(TaskWaker {
+                woken: AtomicBool::new(true),
+            }),
+        });
+    }
+
+    pub fn run(&mut self) {
+        while !self.tasks.is_empty() {
+            let mut made_progress = false;
+            self.tasks.retain_mut(|task| {
+                if !task.waker.woken.swap(false, Ordering::Acquire) {
+                    return true;
+                }
+                made_progress = true;
+                let waker = Waker::from(Arc::clone(&task.waker));
+                let mut cx = Context::from_waker(&waker);
+                task.future.as_mut().poll(&mut cx).is_pending()
+            });
+            if !made_progress && !self.tasks.is_empty() {
+                self.events.reactor.borrow_mut().wait_for_event();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_transport::MockTransport;
+    use alloc::vec;
+
+    #[test]
+    fn test_messages_are_routed_by_source() {
+        let mut transport = MockTransport::default();
+        transport.deliver_at(5, "com.axle.b", b"b1");
+        transport.deliver_at(10, "com.axle.a", b"a1");
+        transport.deliver_at(15, "com.axle.b", b"b2");
+        let mut executor = Executor::with_transport(transport);
+
+        let log = Rc::new(RefCell::new(Vec::new()));
+        for service in ["com.axle.a", "com.axle.b"] {
+            let events = executor.events();
+            let log = Rc::clone(&log);
+            executor.spawn(async move {
+                let expected = if service == "com.axle.a" { 1 } else { 2 };
+                for _ in 0..expected {
+                    let msg = events.next_message(Some(service)).await;
+                    log.borrow_mut().push((events.ms_since_boot(), msg.body));
+                }
+            });
+        }
+        executor.run();
+
+        assert_eq!(
+            *log.borrow(),
+            [
+                (5, b"b1".to_vec()),
+                (10, b"a1".to_vec()),
+                (15, b"b2".to_vec()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_timers() {
+        let mut transport = MockTransport::default();
+        transport.deliver_at(25, "com.axle.a", b"ping");
+        let sent = Rc::clone(&transport.sent);
+        let mut executor = Executor::with_transport(transport);
+
+        // A task that ticks every 10ms runs alongside one that replies to a message
+        let events = executor.events();
+        executor.spawn(async move {
+            for i in 0..4_u8 {
+                events.sleep(10).await;
+                events.send("com.axle.ticks", &[i]);
+            }
+        });
+        let events = executor.events();
+        executor.spawn(async move {
+            let msg = events.next_message(None).await;
+            events.send(&msg.source, b"pong");
+        });
+        executor.run();
+
+        let sent: Vec<_> = sent.borrow().iter().map(|(to, _)| to.clone()).collect();
+        assert_eq!(
+            sent,
+            [
+                "com.axle.ticks",
+                "com.axle.ticks",
+                "com.axle.a",
+                "com.axle.ticks",
+                "com.axle.ticks"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_irqs_and_messages() {
+        let mut transport = MockTransport::default();
+        transport.irqs = vec![3, 8, 20].into();
+        transport.deliver_at(5, "com.axle.fs", b"read");
+        transport.deliver_at(12, "com.axle.fs", b"write");
+        let mut executor = Executor::with_transport(transport);
+
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let events = executor.events();
+        let irq_log = Rc::clone(&log);
+        executor.spawn(async move {
+            for _ in 0..3 {
+                events.next_irq(11).await;
+                irq_log
+                    .borrow_mut()
+                    .push((events.ms_since_boot(), b"irq".to_vec()));
+            }
+        });
+        let events = executor.events();
+        let message_log = Rc::clone(&log);
+        executor.spawn(async move {
+            for _ in 0..2 {
+                let msg = events.next_message(Some("com.axle.fs")).await;
+                message_log
+                    .borrow_mut()
+                    .push((events.ms_since_boot(), msg.body));
+            }
+        });
+        executor.run();
+
+        assert_eq!(
+            *log.borrow(),
+            [
+                (3, b"irq".to_vec()),
+                (5, b"read".to_vec()),
+                (8, b"irq".to_vec()),
+                (12, b"write".to_vec()),
+                (20, b"irq".to_vec()),
+            ]
+        );
+    }
+}
@@ -6,10 +6,20 @@
 #![feature(panic_info_message)]
 #![feature(stmt_expr_attributes)]
 
+#[cfg(all(not(target_os = "axle"), feature = "amc_sim"))]
+pub mod amc_sim;
 pub mod core_commands;
+pub mod executor;
+#[cfg(test)]
+mod mock_transport;
+pub mod process;
+pub mod rpc;
+pub mod serialized;
 
 extern crate alloc;
 
+#[cfg(all(not(target_os = "axle"), feature = "amc_sim"))]
+pub use amc_sim::libc;
 #[cfg(target_os = "axle")]
 pub extern crate libc;
 use core::cmp::min;
@@ -109,7 +119,7 @@ pub trait ContainsEventField {
 
 // This allows receiving messages for which the format of the body is not yet known
 // It is the caller's responsibility to parse the body into the correct type
-#[cfg(target_os = "axle")]
+#[cfg(any(target_os = "axle", feature = "amc_sim"))]
 pub unsafe fn amc_message_await_untyped(
     from_service: Option<&str>,
 ) -> Result<AmcMessage<[u8]>, core::str::Utf8Error> {
@@ -148,7 +158,7 @@ pub unsafe fn amc_message_await_untyped(
 // This allows receiving messages that might not specify an event field
 // The caller _must_ be certain that the type they've parsed
 // is indeed the type that was provided in the message
-#[cfg(target_os = "axle")]
+#[cfg(any(target_os = "axle", feature = "amc_sim"))]
 pub unsafe fn amc_message_await_unchecked<T>(
     from_service: Option<&str>,
 ) -> Result<AmcMessage<T>, core::str::Utf8Error> {
@@ -161,7 +171,7 @@ pub unsafe fn amc_message_await_unchecked<T>(
     })
 }
 
-#[cfg(target_os = "axle")]
+#[cfg(any(target_os = "axle", feature = "amc_sim"))]
 pub fn amc_message_await<T>(from_service: Option<&str>) -> AmcMessage<T>
 where
     T: ExpectsEventField + ContainsEventField,
@@ -182,7 +192,7 @@ where
 
 // This allows receiving messages for which the format of the body is not yet known
 // It is the caller's responsibility to parse the body into the correct type
-#[cfg(target_os = "axle")]
+#[cfg(any(target_os = "axle", feature = "amc_sim"))]
 pub unsafe fn amc_message_await__u32_event_untyped(
     from_service: &str,
     expected_event: u32,
@@ -222,7 +232,7 @@ pub unsafe fn amc_message_await__u32_event_untyped(
 // This allows receiving messages that might not specify an event field
 // The caller _must_ be certain that the type they've parsed
 // is indeed the type that was provided in the message
-#[cfg(target_os = "axle")]
+#[cfg(any(target_os = "axle", feature = "amc_sim"))]
 pub unsafe fn amc_message_await__u32_event_unchecked<T>(
     from_service: &str,
     expected_event: u32,
@@ -236,7 +246,7 @@ pub unsafe fn amc_message_await__u32_event_unchecked<T>(
     })
 }
 
-#[cfg(target_os = "axle")]
+#[cfg(any(target_os = "axle", feature = "amc_sim"))]
 pub fn amc_message_await__u32_event<T>(from_service: &str) -> AmcMessage<T>
 where
     T: ExpectsEventField + ContainsEventField,
@@ -256,7 +266,7 @@ where
     msg
 }
 
-#[cfg(target_os = "axle")]
+#[cfg(any(target_os = "axle", feature = "amc_sim"))]
 pub fn amc_has_message(from_service: Option<&str>) -> bool {
     if let Some(from_service) = from_service {
         let from_service_c_str = CString::new(from_service).expect("cstr new failed");
@@ -266,10 +276,10 @@ pub fn amc_has_message(from_service: Option<&str>) -> bool {
     }
 }
 
-#[cfg(target_os = "axle")]
+#[cfg(any(target_os = "axle", feature = "amc_sim"))]
 pub fn amc_register_service(this_service: &str) {
     unsafe {
-        ::libc::amc_register_service(
+        libc::amc_register_service(
             CString::new(this_service)
                 .expect("register_service failed")
                 .as_ptr() as *const u8,
@@ -278,12 +288,12 @@ pub fn amc_register_service(this_service: &str) {
     }
 }
 
-#[cfg(target_os = "axle")]
+#[cfg(any(target_os = "axle", feature = "amc_sim"))]
 pub fn amc_message_send<T>(to_service: &str, message: T) {
     let to_service_c_str = CString::new(to_service).unwrap();
     let msg_ptr = &message as *const _ as *const libc::c_void;
     unsafe {
-        ::libc::amc_message_send(
+        libc::amc_message_send(
             to_service_c_str.as_ptr() as *const u8,
             msg_ptr,
             core::mem::size_of::<T>() as u32,
@@ -291,25 +301,28 @@ pub fn amc_message_send<T>(to_service: &str, message: T) {
     }
 }
 
-#[cfg(target_os = "axle")]
+#[cfg(any(target_os = "axle", feature = "amc_sim"))]
 pub unsafe fn amc_message_send_untyped(to_service: &str, message: *const u8, size: usize) {
     let to_service_c_str = CString::new(to_service).unwrap();
-    ::libc::amc_message_send(
+    libc::amc_message_send(
         to_service_c_str.as_ptr() as *const u8,
         message as *const libc::c_void,
         size as u32,
     );
 }
 
+/// Returns false if the kernel refused the registration, such as when this service wasn't
+/// granted the register-driver capability
 #[cfg(target_os = "axle")]
-pub fn adi_register_driver(this_service: &str, irq: u32) {
+#[must_use]
+pub fn adi_register_driver(this_service: &str, irq: u32) -> bool {
     unsafe {
         ::libc::adi_register_driver(
             CString::new(this_service)
                 .expect("adi_register_driver failed")
                 .as_ptr() as *const u8,
             irq,
-        );
+        )
     }
 }
 
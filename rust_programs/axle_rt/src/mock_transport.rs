@@ -0,0 +1,91 @@
+//! A scripted `AmcTransport`, shared by the tests of everything that's built on a transport
+
+use alloc::collections::VecDeque;
+use alloc::rc::Rc;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::cell::RefCell;
+
+use crate::rpc::{AmcTransport, OwnedAmcMessage};
+
+/// Each message that was sent, along with the service it was sent to
+pub(crate) type SentMessages = Rc<RefCell<Vec<(String, Vec<u8>)>>>;
+
+/// Delivers queued messages and IRQs, each at a given time. Sleeping advances the clock.
+#[derive(Default)]
+pub(crate) struct MockTransport {
+    pub(crate) now: u64,
+    incoming: VecDeque<(u64, OwnedAmcMessage)>,
+    pub(crate) irqs: VecDeque<u64>,
+    /// Shared so that tests can inspect what was sent after handing the transport over
+    pub(crate) sent: SentMessages,
+}
+
+impl MockTransport {
+    /// Messages must be delivered in the order they become available
+    pub(crate) fn deliver_at(&mut self, at: u64, source: &str, body: &[u8]) {
+        self.incoming.push_back((
+            at,
+            OwnedAmcMessage {
+                source: source.to_string(),
+                body: body.to_vec(),
+            },
+        ));
+    }
+
+    /// Delivers a message that's available straight away
+    pub(crate) fn deliver(&mut self, source: &str, body: &[u8]) {
+        self.deliver_at(self.now, source, body)
+    }
+
+    fn next_message_at(&self) -> Option<u64> {
+        self.incoming.front().map(|(at, _)| *at)
+    }
+}
+
+impl AmcTransport for MockTransport {
+    fn send(&mut self, to_service: &str, message: &[u8]) {
+        self.sent
+            .borrow_mut()
+            .push((to_service.to_string(), message.to_vec()));
+    }
+
+    fn has_message(&mut self) -> bool {
+        matches!(self.next_message_at(), Some(at) if at <= self.now)
+    }
+
+    fn receive(&mut self) -> OwnedAmcMessage {
+        let (at, msg) = self.incoming.pop_front().expect("Would block forever");
+        self.now = self.now.max(at);
+        msg
+    }
+
+    fn sleep_until_message_or_delay(&mut self, ms: u32) {
+        let wake_at = self.now + ms as u64;
+        self.now = match self.next_message_at() {
+            Some(at) => wake_at.min(at.max(self.now)),
+            None => wake_at,
+        };
+    }
+
+    fn ms_since_boot(&mut self) -> u64 {
+        self.now
+    }
+
+    fn await_irq_or_message(&mut self, _irq: u32) -> bool {
+        let irq_at = self.irqs.front().copied();
+        let message_at = self.next_message_at();
+        let irq_first = match (irq_at, message_at) {
+            (Some(irq_at), Some(message_at)) => irq_at < message_at,
+            (Some(_), None) => true,
+            (None, Some(_)) => false,
+            (None, None) => panic!("Would block forever"),
+        };
+        if irq_first {
+            self.now = self.now.max(self.irqs.pop_front().unwrap());
+        } else {
+            self.now = self.now.max(message_at.unwrap());
+        }
+        irq_first
+    }
+}
@@ -0,0 +1,647 @@
+//! Spawning programs with arguments and an environment, and supervising them.
+//!
+//! The spawner becomes the supervisor of every program it launches, so the kernel reports each
+//! program's creation, output and exit to it as a `SupervisedProcessEvent`.
+//! `ProcessSupervisor` collects those events for each `Child`:
+//!
+//! ```ignore
+//! let mut supervisor = ProcessSupervisor::new(|path| read_file(path).ok());
+//! let child = Command::new("/usr/applications/linker")
+//!     .arg("output.elf")
+//!     .env("VERBOSE", "1")
+//!     .spawn(&mut supervisor)?;
+//! let output = supervisor.wait(child);
+//! println!("Exited with {:?}: {}", output.status, String::from_utf8_lossy(&output.stdout));
+//! ```
+//!
+//! Arguments are passed to the kernel as NUL-terminated entries, so an argument can contain
+//! spaces, but can't be empty or contain a NUL. Processes don't have a working directory of their own, so `Command::current_dir` is passed in `PWD`.
+//!
+//! A spawned program receives its arguments and environment through its entry point, and can
+//! read them with `arguments` and `environment`.
+
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::mem::size_of;
+use cstr_core::{CStr, CString};
+
+use crate::core_commands::{
+    AmcCapabilities, AmcExecBuffer, AmcExecBufferDenied, AmcSupervisedProcessEventMsg,
+    SupervisedProcessEvent, AMC_CORE_SERVICE_NAME,
+};
+#[cfg(any(target_os = "axle", feature = "amc_sim"))]
+use crate::rpc::AmcKernelTransport;
+use crate::rpc::{AmcTransport, OwnedAmcMessage};
+use crate::ExpectsEventField;
+
+/// The kernel passes at most this many arguments after the program name
+pub const MAX_ARGUMENTS: usize = 15;
+/// The kernel accepts at most this many bytes of encoded arguments
+pub const MAX_ARGUMENTS_LEN: usize = 4096;
+/// The kernel passes at most this many environment entries
+pub const MAX_ENVIRONMENT_ENTRIES: usize = 32;
+/// The kernel accepts at most this many bytes of encoded environment entries
+pub const MAX_ENVIRONMENT_LEN: usize = 4096;
+
+/// Reads the ELF at a path, for spawning programs by path
+pub type ProgramLoader = fn(&str) -> Option<Vec<u8>>;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SpawnError {
+    /// The supervisor has no `ProgramLoader`, so it can only spawn programs from buffers
+    NoProgramLoader,
+    /// The loader couldn't read the program
+    ProgramNotFound,
+    /// An argument was empty, or contained a NUL
+    InvalidArgument,
+    /// An environment key was empty, or contained a `=` or NUL, or a value contained a NUL
+    InvalidEnvironment,
+    /// There were more than `MAX_ARGUMENTS` arguments, or they took more than
+    /// `MAX_ARGUMENTS_LEN` bytes
+    TooManyArguments,
+    /// There were more than `MAX_ENVIRONMENT_ENTRIES` entries, or they took more than
+    /// `MAX_ENVIRONMENT_LEN` bytes
+    TooManyEnvironmentEntries,
+    /// The kernel refused to launch the program, because the spawner lacks this capability
+    Denied(AmcCapabilities),
+    /// The kernel refused to launch the program, because the request was malformed
+    Malformed,
+}
+
+#[derive(Debug, Clone)]
+enum Program {
+    Path(String),
+    Buffer { name: String, elf: Vec<u8> },
+}
+
+/// Describes a program to launch
+#[derive(Debug, Clone)]
+pub struct Command {
+    program: Program,
+    args: Vec<String>,
+    env: Vec<(String, String)>,
+    current_dir: Option<String>,
+    capabilities: AmcCapabilities,
+}
+
+impl Command {
+    /// Launches the ELF at `path`, which is read by the supervisor's `ProgramLoader`
+    pub fn new(path: &str) -> Self {
+        Self::with_program(Program::Path(String::from(path)))
+    }
+
+    /// Launches an ELF that's already in memory
+    pub fn from_buffer(name: &str, elf: Vec<u8>) -> Self {
+        Self::with_program(Program::Buffer {
+            name: String::from(name),
+            elf,
+        })
+    }
+
+    fn with_program(program: Program) -> Self {
+        Self {
+            program,
+            args: Vec::new(),
+            env: Vec::new(),
+            current_dir: None,
+            capabilities: AmcCapabilities::NONE,
+        }
+    }
+
+    pub fn arg(&mut self, arg: &str) -> &mut Self {
+        self.args.push(String::from(arg));
+        self
+    }
+
+    pub fn args(&mut self, args: &[&str]) -> &mut Self {
+        self.args.extend(args.iter().map(|a| String::from(*a)));
+        self
+    }
+
+    /// Sets an environment variable, replacing any earlier value for the same key
+    pub fn env(&mut self, key: &str, value: &str) -> &mut Self {
+        self.env.retain(|(k, _)| k != key);
+        self.env.push((String::from(key), String::from(value)));
+        self
+    }
+
+    /// Passed to the program as `PWD`, unless the environment already sets it
+    pub fn current_dir(&mut self, dir: &str) -> &mut Self {
+        self.current_dir = Some(String::from(dir));
+        self
+    }
+
+    /// Grants the program whichever of `capabilities` the spawner holds
+    pub fn capabilities(&mut self, capabilities: AmcCapabilities) -> &mut Self {
+        self.capabilities = capabilities;
+        self
+    }
+
+    /// The name the program is launched under, which is the last component of its path
+    fn name(&self) -> &str {
+        match &self.program {
+            Program::Path(path) => path.rsplit('/').next().unwrap_or(path),
+            Program::Buffer { name, .. } => name,
+        }
+    }
+
+    /// NUL-terminated KEY=VALUE entries. The kernel ends the block with an empty entry when it
+    /// copies it.
+    fn encode_environment(&self) -> Result<Vec<u8>, SpawnError> {
+        let mut entries: Vec<(&str, &str)> = self
+            .env
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect();
+        if let Some(dir) = &self.current_dir {
+            if !entries.iter().any(|(k, _)| *k == "PWD") {
+                entries.push(("PWD", dir));
+            }
+        }
+        if entries.len() > MAX_ENVIRONMENT_ENTRIES {
+            return Err(SpawnError::TooManyEnvironmentEntries);
+        }
+
+        let mut block = Vec::new();
+        for (key, value) in entries {
+            if key.is_empty() || key.contains(&['=', '\0'][..]) || value.contains('\0') {
+                return Err(SpawnError::InvalidEnvironment);
+            }
+            block.extend_from_slice(key.as_bytes());
+            block.push(b'=');
+            block.extend_from_slice(value.as_bytes());
+            block.push(0);
+        }
+        Ok(block)
+    }
+
+    /// Launches the program, supervised by `supervisor`, and waits until the kernel has
+    /// created its process
+    pub fn spawn<T: AmcTransport>(
+        &self,
+        supervisor: &mut ProcessSupervisor<T>,
+    ) -> Result<Child, SpawnError> {
+        let arguments: Vec<&str> = self.args.iter().map(|a| a.as_str()).collect();
+        let environment = self.encode_environment()?;
+        let name = CString::new(self.name()).map_err(|_| SpawnError::InvalidArgument)?;
+
+        let loaded_elf;
+        let elf = match &self.program {
+            Program::Path(path) => {
+                let loader = supervisor.loader.ok_or(SpawnError::NoProgramLoader)?;
+                loaded_elf = loader(path).ok_or(SpawnError::ProgramNotFound)?;
+                &loaded_elf
+            }
+            Program::Buffer { elf, .. } => elf,
+        };
+
+        let request = AmcExecBuffer::with_environment(
+            &name,
+            &arguments,
+            &environment,
+            elf,
+            true,
+            self.capabilities,
+        )?;
+        supervisor.launch(&request)
+    }
+}
+
+/// A launched program, identified by its PID
+#[derive(Debug, PartialEq, Eq)]
+pub struct Child {
+    pid: u64,
+}
+
+impl Child {
+    pub fn pid(&self) -> u64 {
+        self.pid
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ExitStatus(u64);
+
+impl ExitStatus {
+    pub fn code(&self) -> u64 {
+        self.0
+    }
+
+    pub fn success(&self) -> bool {
+        self.0 == 0
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Output {
+    pub status: ExitStatus,
+    /// Everything the program wrote that hadn't already been taken with `take_output`
+    pub stdout: Vec<u8>,
+}
+
+#[derive(Debug, Default)]
+struct ChildState {
+    started: bool,
+    output: Vec<u8>,
+    exit_status: Option<ExitStatus>,
+}
+
+/// Launches programs and tracks their events. Messages that aren't about its children are queued,
+/// and handed out by `next_message`.
+pub struct ProcessSupervisor<T: AmcTransport> {
+    transport: T,
+    loader: Option<ProgramLoader>,
+    children: BTreeMap<u64, ChildState>,
+    deferred_messages: VecDeque<OwnedAmcMessage>,
+}
+
+#[cfg(any(target_os = "axle", feature = "amc_sim"))]
+impl ProcessSupervisor<AmcKernelTransport> {
+    pub fn new(loader: ProgramLoader) -> Self {
+        Self::with_transport(AmcKernelTransport, Some(loader))
+    }
+}
+
+impl<T: AmcTransport> ProcessSupervisor<T> {
+    /// Without a loader, only programs in buffers can be spawned
+    pub fn with_transport(transport: T, loader: Option<ProgramLoader>) -> Self {
+        Self {
+            transport,
+            loader,
+            children: BTreeMap::new(),
+            deferred_messages: VecDeque::new(),
+        }
+    }
+
+    fn launch(&mut self, request: &[u8]) -> Result<Child, SpawnError> {
+        self.transport.send(AMC_CORE_SERVICE_NAME, request);
+
+        // Programs are launched one at a time, so the next creation event is for this one
+        loop {
+            let msg = self.transport.receive();
+            if msg.source == AMC_CORE_SERVICE_NAME
+                && msg.event() == Some(AmcExecBufferDenied::EXPECTED_EVENT)
+                && msg.body.len() >= size_of::<AmcExecBufferDenied>()
+            {
+                let denied: AmcExecBufferDenied =
+                    unsafe { core::ptr::read_unaligned(msg.body.as_ptr() as *const _) };
+                let missing_capability = denied.missing_capability();
+                if missing_capability.is_empty() {
+                    return Err(SpawnError::Malformed);
+                }
+                return Err(SpawnError::Denied(missing_capability));
+            }
+            if let Some(SupervisedProcessEvent::ProcessCreate(pid)) = supervised_event(&msg) {
+                self.children.insert(pid, ChildState::default());
+                return Ok(Child { pid });
+            }
+            if !self.handle_message(&msg) {
+                self.deferred_messages.push_back(msg);
+            }
+        }
+    }
+
+    /// Records `msg` if it's an event about one of this supervisor's children.
+    /// Returns whether the message was consumed, for callers that run their own event loop.
+    pub fn handle_message(&mut self, msg: &OwnedAmcMessage) -> bool {
+        let event = match supervised_event(msg) {
+            Some(event) => event,
+            None => return false,
+        };
+        let pid = match event {
+            SupervisedProcessEvent::ProcessCreate(_) => return false,
+            SupervisedProcessEvent::ProcessStart(pid, _)
+            | SupervisedProcessEvent::ProcessExit(pid, _)
+            | SupervisedProcessEvent::ProcessWrite(pid, _, _) => pid,
+        };
+        let state = match self.children.get_mut(&pid) {
+            Some(state) => state,
+            None => return false,
+        };
+        match event {
+            SupervisedProcessEvent::ProcessStart(_, _) => state.started = true,
+            SupervisedProcessEvent::ProcessExit(_, status_code) => {
+                state.exit_status = Some(ExitStatus(status_code))
+            }
+            SupervisedProcessEvent::ProcessWrite(_, len, buf) => {
+                let len = (len as usize).min(buf.len());
+                state.output.extend_from_slice(&buf[..len])
+            }
+            SupervisedProcessEvent::ProcessCreate(_) => unreachable!(),
+        }
+        true
+    }
+
+    /// Records the events that have already arrived, without blocking
+    fn poll(&mut self) {
+        while self.transport.has_message() {
+            let msg = self.transport.receive();
+            if !self.handle_message(&msg) {
+                self.deferred_messages.push_back(msg);
+            }
+        }
+    }
+
+    /// Returns whether the program has reached its entry point
+    pub fn has_started(&mut self, child: &Child) -> bool {
+        self.poll();
+        self.children[&child.pid].started
+    }
+
+    /// Returns the exit status if the program has exited, without blocking
+    pub fn try_wait(&mut self, child: &Child) -> Option<ExitStatus> {
+        self.poll();
+        self.children[&child.pid].exit_status
+    }
+
+    /// Returns what the program has written since the last call, without blocking
+    pub fn take_output(&mut self, child: &Child) -> Vec<u8> {
+        self.poll();
+        core::mem::take(&mut self.children.get_mut(&child.pid).unwrap().output)
+    }
+
+    /// Blocks until the program exits
+    pub fn wait(&mut self, child: Child) -> Output {
+        loop {
+            if let Some(status) = self.children[&child.pid].exit_status {
+                let state = self.children.remove(&child.pid).unwrap();
+                return Output {
+                    status,
+                    stdout: state.output,
+                };
+            }
+            let msg = self.transport.receive();
+            if !self.handle_message(&msg) {
+                self.deferred_messages.push_back(msg);
+            }
+        }
+    }
+
+    pub fn has_message(&mut self) -> bool {
+        !self.deferred_messages.is_empty() || self.transport.has_message()
+    }
+
+    /// Returns the next message that isn't about one of this supervisor's children, starting
+    /// with those that arrived while it was waiting
+    pub fn next_message(&mut self) -> OwnedAmcMessage {
+        if let Some(msg) = self.deferred_messages.pop_front() {
+            return msg;
+        }
+        loop {
+            let msg = self.transport.receive();
+            if !self.handle_message(&msg) {
+                return msg;
+            }
+        }
+    }
+
+    pub fn transport(&mut self) -> &mut T {
+        &mut self.transport
+    }
+}
+
+fn supervised_event(msg: &OwnedAmcMessage) -> Option<SupervisedProcessEvent> {
+    if msg.source != AMC_CORE_SERVICE_NAME
+        || msg.event() != Some(AmcSupervisedProcessEventMsg::EXPECTED_EVENT)
+        || msg.body.len() < size_of::<AmcSupervisedProcessEventMsg>()
+    {
+        return None;
+    }
+    let msg: AmcSupervisedProcessEventMsg =
+        unsafe { core::ptr::read_unaligned(msg.body.as_ptr() as *const _) };
+    Some(msg.supervised_process_event)
+}
+
+unsafe fn string_at(ptr: *const u8) -> String {
+    let s = CStr::from_ptr(ptr as *const cstr_core::c_char);
+    String::from_utf8_lossy(s.to_bytes()).into_owned()
+}
+
+/// Reads the arguments that the kernel passed to this program's entry point, starting with
+/// its name.
+///
+/// # Safety
+/// `argc` and `argv` must be the values the entry point received
+pub unsafe fn arguments(argc: isize, argv: *const *const u8) -> Vec<String> {
+    (0..argc).map(|i| string_at(*argv.offset(i))).collect()
+}
+
+/// Reads the KEY=VALUE entries that the kernel placed after this program's arguments.
+///
+/// # Safety
+/// `argc` and `argv` must be the values the entry point received
+pub unsafe fn environment(argc: isize, argv: *const *const u8) -> Vec<(String, String)> {
+    let mut entries = Vec::new();
+    let mut entry = argv.offset(argc + 1);
+    while !(*entry).is_null() {
+        let s = string_at(*entry);
+        if let Some((key, value)) = s.split_once('=') {
+            entries.push((String::from(key), String::from(value)));
+        }
+        entry = entry.offset(1);
+    }
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_transport::MockTransport;
+    use crate::rpc::message_bytes;
+    use alloc::string::ToString;
+    use alloc::vec;
+
+    fn deliver_event(transport: &mut MockTransport, event: SupervisedProcessEvent) {
+        let msg = AmcSupervisedProcessEventMsg::new(event);
+        transport.deliver(AMC_CORE_SERVICE_NAME, message_bytes(&msg));
+    }
+
+    fn write_event(pid: u64, text: &str) -> SupervisedProcessEvent {
+        let mut buf = [0; 128];
+        buf[..text.len()].copy_from_slice(text.as_bytes());
+        SupervisedProcessEvent::ProcessWrite(pid, text.len() as u64, buf)
+    }
+
+    #[test]
+    fn test_spawn_and_wait() {
+        let mut transport = MockTransport::default();
+        transport.deliver("com.axle.awm", &[1, 0, 0, 0]);
+        deliver_event(&mut transport, SupervisedProcessEvent::ProcessCreate(7));
+        deliver_event(
+            &mut transport,
+            SupervisedProcessEvent::ProcessStart(7, 0x1000),
+        );
+        deliver_event(&mut transport, write_event(7, "Hello, "));
+        // Events about processes this supervisor didn't spawn are left for the caller
+        deliver_event(&mut transport, write_event(9, "Unrelated"));
+        deliver_event(&mut transport, write_event(7, "world!"));
+        deliver_event(&mut transport, SupervisedProcessEvent::ProcessExit(7, 3));
+
+        let mut supervisor = ProcessSupervisor::with_transport(transport, None);
+        let child = Command::from_buffer("linker", vec![0x7f, b'E', b'L', b'F'])
+            .args(&["-o", "out.elf"])
+            .env("VERBOSE", "1")
+            .spawn(&mut supervisor)
+            .unwrap();
+        assert_eq!(child.pid(), 7);
+
+        let (to_service, request) = supervisor.transport().sent.borrow()[0].clone();
+        assert_eq!(to_service, AMC_CORE_SERVICE_NAME);
+        assert_eq!(&request[..4], &AmcExecBuffer::EXPECTED_EVENT.to_ne_bytes());
+        // The arguments and environment follow the header
+        assert_eq!(
+            &request[size_of::<AmcExecBuffer>()..],
+            b"-o\0out.elf\0VERBOSE=1\0"
+        );
+
+        assert!(supervisor.has_started(&child));
+        let output = supervisor.wait(child);
+        assert_eq!(output.status.code(), 3);
+        assert!(!output.status.success());
+        assert_eq!(output.stdout, b"Hello, world!");
+
+        assert_eq!(supervisor.next_message().source, "com.axle.awm");
+        assert!(supervisor.has_message());
+        let unrelated = supervisor.next_message();
+        assert_eq!(unrelated.source, AMC_CORE_SERVICE_NAME);
+        assert!(!supervisor.has_message());
+    }
+
+    #[test]
+    fn test_take_output() {
+        let mut transport = MockTransport::default();
+        deliver_event(&mut transport, SupervisedProcessEvent::ProcessCreate(4));
+        deliver_event(&mut transport, write_event(4, "first"));
+        let mut supervisor = ProcessSupervisor::with_transport(transport, None);
+        let child = Command::from_buffer("prog", vec![])
+            .spawn(&mut supervisor)
+            .unwrap();
+
+        assert_eq!(supervisor.take_output(&child), b"first");
+        assert_eq!(supervisor.try_wait(&child), None);
+
+        deliver_event(supervisor.transport(), write_event(4, "second"));
+        deliver_event(
+            supervisor.transport(),
+            SupervisedProcessEvent::ProcessExit(4, 0),
+        );
+        assert_eq!(supervisor.try_wait(&child), Some(ExitStatus(0)));
+        let output = supervisor.wait(child);
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"second");
+    }
+
+    #[test]
+    fn test_spawn_errors() {
+        let mut transport = MockTransport::default();
+        let mut denied = Vec::new();
+        denied.extend_from_slice(&AmcExecBufferDenied::EXPECTED_EVENT.to_ne_bytes());
+        denied.extend_from_slice(&AmcCapabilities::EXEC_BUFFER.bits().to_ne_bytes());
+        transport.deliver(AMC_CORE_SERVICE_NAME, &denied);
+        let mut supervisor = ProcessSupervisor::with_transport(transport, None);
+
+        assert_eq!(
+            Command::from_buffer("prog", vec![]).spawn(&mut supervisor),
+            Err(SpawnError::Denied(AmcCapabilities::EXEC_BUFFER))
+        );
+        assert_eq!(
+            Command::new("/usr/applications/prog").spawn(&mut supervisor),
+            Err(SpawnError::NoProgramLoader)
+        );
+        assert_eq!(
+            Command::from_buffer("prog", vec![])
+                .arg("nul\0byte")
+                .spawn(&mut supervisor),
+            Err(SpawnError::InvalidArgument)
+        );
+        assert_eq!(
+            Command::from_buffer("prog", vec![])
+                .env("A=B", "C")
+                .spawn(&mut supervisor),
+            Err(SpawnError::InvalidEnvironment)
+        );
+
+        let mut supervisor =
+            ProcessSupervisor::with_transport(MockTransport::default(), Some(|_| None));
+        assert_eq!(
+            Command::new("/usr/applications/prog").spawn(&mut supervisor),
+            Err(SpawnError::ProgramNotFound)
+        );
+    }
+
+    #[test]
+    fn test_encode_arguments() {
+        assert_eq!(AmcExecBuffer::encode_arguments(&[]).unwrap(), b"");
+        // Arguments are NUL-separated, so they may contain spaces
+        assert_eq!(
+            AmcExecBuffer::encode_arguments(&["two words", "-v"]).unwrap(),
+            b"two words\0-v\0"
+        );
+        assert_eq!(
+            AmcExecBuffer::encode_arguments(&["a", ""]),
+            Err(SpawnError::InvalidArgument)
+        );
+        assert_eq!(
+            AmcExecBuffer::encode_arguments(&["a"; MAX_ARGUMENTS + 1]),
+            Err(SpawnError::TooManyArguments)
+        );
+        let long_argument = "a".repeat(MAX_ARGUMENTS_LEN);
+        assert_eq!(
+            AmcExecBuffer::encode_arguments(&[&long_argument]),
+            Err(SpawnError::TooManyArguments)
+        );
+    }
+
+    #[test]
+    fn test_encode_environment() {
+        let mut command = Command::new("/usr/applications/prog");
+        assert_eq!(command.name(), "prog");
+        assert_eq!(command.encode_environment().unwrap(), b"");
+
+        command
+            .env("HOME", "/")
+            .env("TERM", "none")
+            .env("HOME", "/home")
+            .current_dir("/usr");
+        assert_eq!(
+            command.encode_environment().unwrap(),
+            b"TERM=none\0HOME=/home\0PWD=/usr\0"
+        );
+
+        // An explicit PWD wins over the working directory
+        command.env("PWD", "/tmp");
+        assert_eq!(
+            command.encode_environment().unwrap(),
+            b"TERM=none\0HOME=/home\0PWD=/tmp\0"
+        );
+    }
+
+    #[test]
+    fn test_read_arguments_and_environment() {
+        let strings = [
+            CString::new("prog").unwrap(),
+            CString::new("-v").unwrap(),
+            CString::new("HOME=/home").unwrap(),
+            CString::new("EMPTY=").unwrap(),
+        ];
+        let argv: [*const u8; 6] = [
+            strings[0].as_ptr() as *const u8,
+            strings[1].as_ptr() as *const u8,
+            core::ptr::null(),
+            strings[2].as_ptr() as *const u8,
+            strings[3].as_ptr() as *const u8,
+            core::ptr::null(),
+        ];
+        unsafe {
+            assert_eq!(arguments(2, argv.as_ptr()), vec!["prog", "-v"]);
+            assert_eq!(
+                environment(2, argv.as_ptr()),
+                vec![
+                    ("HOME".to_string(), "/home".to_string()),
+                    ("EMPTY".to_string(), "".to_string())
+                ]
+            );
+        }
+    }
+}
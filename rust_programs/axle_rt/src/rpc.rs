@@ -0,0 +1,527 @@
+//! Request/response calls over AMC.
+//!
+//! A request is wrapped in an envelope that carries a correlation ID, and the server echoes the
+//! ID in the envelope around its response. While a call waits for its response, any other
+//! message that arrives is queued, and is handed out later by `RpcClient::next_message`.
+//!
+//! The envelope is laid out as:
+//!
+//! ```text
+//! event: u32 = AMC_RPC_EVENT
+//! correlation_id: u32
+//! kind: u32 (0 for a request, 1 for a response)
+//! body: the wrapped message, starting with its own event field
+//! ```
+
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::marker::PhantomData;
+use core::mem::size_of;
+use core::ops::Deref;
+
+use crate::ExpectsEventField;
+#[cfg(any(target_os = "axle", feature = "amc_sim"))]
+use crate::{
+    amc_has_message, amc_message_await_untyped, amc_message_send, amc_message_send_untyped,
+    core_commands::{AmcSleepUntilDelayOrMessage, AMC_CORE_SERVICE_NAME},
+};
+
+pub const AMC_RPC_EVENT: u32 = 900;
+const RPC_HEADER_LEN: usize = size_of::<u32>() * 3;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RpcMessageKind {
+    Request,
+    Response,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RpcError {
+    /// No response arrived before the timeout elapsed
+    Timeout,
+    /// The response carried a different event than the caller expected
+    UnexpectedResponse(u32),
+    /// The response was too short to hold the expected message
+    MalformedResponse,
+}
+
+/// Views the in-memory representation of a message, which is what AMC delivers.
+/// This covers exactly `size_of::<T>()` bytes, so it drops the trailing data of messages that
+/// end in a `data: [u8; 0]` field.
+pub(crate) fn message_bytes<T>(message: &T) -> &[u8] {
+    unsafe { core::slice::from_raw_parts(message as *const T as *const u8, size_of::<T>()) }
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    let bytes = bytes.get(offset..offset + size_of::<u32>())?;
+    Some(u32::from_ne_bytes(bytes.try_into().unwrap()))
+}
+
+pub fn encode_rpc_message(correlation_id: u32, kind: RpcMessageKind, body: &[u8]) -> Vec<u8> {
+    let kind = match kind {
+        RpcMessageKind::Request => 0_u32,
+        RpcMessageKind::Response => 1_u32,
+    };
+    let mut message = Vec::with_capacity(RPC_HEADER_LEN + body.len());
+    message.extend_from_slice(&AMC_RPC_EVENT.to_ne_bytes());
+    message.extend_from_slice(&correlation_id.to_ne_bytes());
+    message.extend_from_slice(&kind.to_ne_bytes());
+    message.extend_from_slice(body);
+    message
+}
+
+/// A message unwrapped from its RPC envelope
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct RpcEnvelope<'a> {
+    pub correlation_id: u32,
+    pub kind: RpcMessageKind,
+    pub body: &'a [u8],
+}
+
+impl<'a> RpcEnvelope<'a> {
+    /// Returns None if the message isn't a well-formed RPC envelope
+    pub fn parse(message: &'a [u8]) -> Option<Self> {
+        if read_u32(message, 0)? != AMC_RPC_EVENT {
+            return None;
+        }
+        let kind = match read_u32(message, 8)? {
+            0 => RpcMessageKind::Request,
+            1 => RpcMessageKind::Response,
+            _ => return None,
+        };
+        Some(Self {
+            correlation_id: read_u32(message, 4)?,
+            kind,
+            body: &message[RPC_HEADER_LEN..],
+        })
+    }
+
+    /// The event of the wrapped message
+    pub fn event(&self) -> Option<u32> {
+        read_u32(self.body, 0)
+    }
+
+    /// Wraps a response to this request.
+    /// Only `size_of::<T>()` bytes are sent, so use `encode_response_with_body` for responses
+    /// that carry trailing data.
+    pub fn encode_response<T>(&self, response: &T) -> Vec<u8> {
+        self.encode_response_with_body(message_bytes(response))
+    }
+
+    /// Wraps a response that has already been laid out, trailing data included
+    pub fn encode_response_with_body(&self, response: &[u8]) -> Vec<u8> {
+        encode_rpc_message(self.correlation_id, RpcMessageKind::Response, response)
+    }
+}
+
+/// Sends a response to an RPC request that was received from `to_service`.
+/// Only `size_of::<T>()` bytes are sent, so use `rpc_reply_with_body` for responses that carry
+/// trailing data.
+#[cfg(any(target_os = "axle", feature = "amc_sim"))]
+pub fn rpc_reply<T>(to_service: &str, request: &RpcEnvelope, response: &T) {
+    rpc_reply_with_body(to_service, request, message_bytes(response))
+}
+
+/// Sends a response that has already been laid out, trailing data included
+#[cfg(any(target_os = "axle", feature = "amc_sim"))]
+pub fn rpc_reply_with_body(to_service: &str, request: &RpcEnvelope, response: &[u8]) {
+    let message = request.encode_response_with_body(response);
+    unsafe { amc_message_send_untyped(to_service, message.as_ptr(), message.len()) };
+}
+
+/// A message copied out of the AMC delivery pool, so that it can be held onto
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OwnedAmcMessage {
+    pub source: String,
+    pub body: Vec<u8>,
+}
+
+impl OwnedAmcMessage {
+    pub fn event(&self) -> Option<u32> {
+        read_u32(&self.body, 0)
+    }
+}
+
+/// A typed response, which owns a suitably aligned copy of the message
+pub struct RpcResponse<T> {
+    storage: Vec<u64>,
+    len: usize,
+    _message_type: PhantomData<T>,
+}
+
+impl<T> RpcResponse<T> {
+    fn from_bytes(bytes: &[u8]) -> Result<Self, RpcError> {
+        if bytes.len() < size_of::<T>() || core::mem::align_of::<T>() > size_of::<u64>() {
+            return Err(RpcError::MalformedResponse);
+        }
+        let mut storage = vec![0_u64; bytes.len().div_ceil(size_of::<u64>())];
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                bytes.as_ptr(),
+                storage.as_mut_ptr() as *mut u8,
+                bytes.len(),
+            );
+        }
+        Ok(Self {
+            storage,
+            len: bytes.len(),
+            _message_type: PhantomData,
+        })
+    }
+
+    /// The whole response as it was delivered, including any trailing data
+    pub fn bytes(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.storage.as_ptr() as *const u8, self.len) }
+    }
+}
+
+impl<T> Deref for RpcResponse<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // The length and alignment were checked when the response was received
+        unsafe { &*(self.storage.as_ptr() as *const T) }
+    }
+}
+
+/// The operations `RpcClient` needs from AMC, so that it can be driven by something else in tests
+pub trait AmcTransport {
+    fn send(&mut self, to_service: &str, message: &[u8]);
+    fn has_message(&mut self) -> bool;
+    /// Blocks until any message arrives
+    fn receive(&mut self) -> OwnedAmcMessage;
+    /// Blocks until a message arrives or `ms` have passed
+    fn sleep_until_message_or_delay(&mut self, ms: u32);
+    fn ms_since_boot(&mut self) -> u64;
+    /// Blocks until `irq` fires or a message arrives, and returns true if it was the IRQ
+    fn await_irq_or_message(&mut self, _irq: u32) -> bool {
+        panic!("This transport doesn't deliver IRQs")
+    }
+}
+
+#[cfg(any(target_os = "axle", feature = "amc_sim"))]
+pub struct AmcKernelTransport;
+
+#[cfg(any(target_os = "axle", feature = "amc_sim"))]
+impl AmcTransport for AmcKernelTransport {
+    fn send(&mut self, to_service: &str, message: &[u8]) {
+        unsafe { amc_message_send_untyped(to_service, message.as_ptr(), message.len()) };
+    }
+
+    fn has_message(&mut self) -> bool {
+        amc_has_message(None)
+    }
+
+    fn receive(&mut self) -> OwnedAmcMessage {
+        let msg = unsafe { amc_message_await_untyped(None).expect("Failed to await message") };
+        OwnedAmcMessage {
+            source: String::from(msg.source()),
+            body: msg.body().to_vec(),
+        }
+    }
+
+    fn sleep_until_message_or_delay(&mut self, ms: u32) {
+        amc_message_send(AMC_CORE_SERVICE_NAME, AmcSleepUntilDelayOrMessage::new(ms));
+    }
+
+    fn ms_since_boot(&mut self) -> u64 {
+        unsafe { crate::libc::ms_since_boot() as u64 }
+    }
+
+    #[cfg(target_os = "axle")]
+    fn await_irq_or_message(&mut self, irq: u32) -> bool {
+        crate::adi_event_await(irq)
+    }
+}
+
+/// Makes one call at a time, and queues the messages that arrive while a call is waiting
+pub struct RpcClient<T: AmcTransport> {
+    transport: T,
+    next_correlation_id: u32,
+    deferred_messages: VecDeque<OwnedAmcMessage>,
+}
+
+#[cfg(any(target_os = "axle", feature = "amc_sim"))]
+impl RpcClient<AmcKernelTransport> {
+    pub fn new() -> Self {
+        Self::with_transport(AmcKernelTransport)
+    }
+}
+
+#[cfg(any(target_os = "axle", feature = "amc_sim"))]
+impl Default for RpcClient<AmcKernelTransport> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: AmcTransport> RpcClient<T> {
+    pub fn with_transport(transport: T) -> Self {
+        Self {
+            transport,
+            next_correlation_id: 1,
+            deferred_messages: VecDeque::new(),
+        }
+    }
+
+    /// Sends `request` to `to_service`, and waits for the matching response.
+    /// Waits forever if `timeout_ms` is None.
+    /// Only `size_of::<Req>()` bytes are sent, so use `call_with_body` for requests that carry
+    /// trailing data.
+    pub fn call<Req, Resp: ExpectsEventField>(
+        &mut self,
+        to_service: &str,
+        request: &Req,
+        timeout_ms: Option<u32>,
+    ) -> Result<RpcResponse<Resp>, RpcError> {
+        self.call_with_body(to_service, message_bytes(request), timeout_ms)
+    }
+
+    /// Like `call`, but sends a request that has already been laid out, trailing data included.
+    /// The whole response is kept, so its trailing data is available via `RpcResponse::bytes`.
+    pub fn call_with_body<Resp: ExpectsEventField>(
+        &mut self,
+        to_service: &str,
+        request: &[u8],
+        timeout_ms: Option<u32>,
+    ) -> Result<RpcResponse<Resp>, RpcError> {
+        let correlation_id = self.next_correlation_id;
+        self.next_correlation_id = self.next_correlation_id.wrapping_add(1);
+        let message = encode_rpc_message(correlation_id, RpcMessageKind::Request, request);
+        self.transport.send(to_service, &message);
+
+        let deadline = timeout_ms.map(|ms| self.transport.ms_since_boot() + ms as u64);
+        loop {
+            if let Some(deadline) = deadline {
+                if !self.transport.has_message() {
+                    let now = self.transport.ms_since_boot();
+                    if now >= deadline {
+                        return Err(RpcError::Timeout);
+                    }
+                    self.transport
+                        .sleep_until_message_or_delay((deadline - now) as u32);
+                    continue;
+                }
+            }
+
+            let msg = self.transport.receive();
+            let envelope = match RpcEnvelope::parse(&msg.body) {
+                Some(envelope) if envelope.kind == RpcMessageKind::Response => envelope,
+                _ => {
+                    self.deferred_messages.push_back(msg);
+                    continue;
+                }
+            };
+            // Calls are made one at a time, so any other response belongs to a call that
+            // already timed out
+            if envelope.correlation_id != correlation_id || msg.source != to_service {
+                continue;
+            }
+            return match envelope.event() {
+                Some(event) if event == Resp::EXPECTED_EVENT => {
+                    RpcResponse::from_bytes(envelope.body)
+                }
+                Some(event) => Err(RpcError::UnexpectedResponse(event)),
+                None => Err(RpcError::MalformedResponse),
+            };
+        }
+    }
+
+    pub fn has_message(&mut self) -> bool {
+        !self.deferred_messages.is_empty() || self.transport.has_message()
+    }
+
+    /// Returns the next message that isn't a response to a call, starting with those that
+    /// arrived while a call was waiting
+    pub fn next_message(&mut self) -> OwnedAmcMessage {
+        if let Some(msg) = self.deferred_messages.pop_front() {
+            return msg;
+        }
+        loop {
+            let msg = self.transport.receive();
+            match RpcEnvelope::parse(&msg.body) {
+                // A late response to a call that timed out
+                Some(envelope) if envelope.kind == RpcMessageKind::Response => continue,
+                _ => return msg,
+            }
+        }
+    }
+
+    pub fn transport(&mut self) -> &mut T {
+        &mut self.transport
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_transport::MockTransport;
+
+    #[repr(C)]
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    struct PingRequest {
+        event: u32,
+        value: u32,
+    }
+
+    #[repr(C)]
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    struct PingResponse {
+        event: u32,
+        value: u64,
+    }
+
+    impl ExpectsEventField for PingResponse {
+        const EXPECTED_EVENT: u32 = 50;
+    }
+
+    fn response(correlation_id: u32, value: u64) -> Vec<u8> {
+        let body = PingResponse {
+            event: PingResponse::EXPECTED_EVENT,
+            value,
+        };
+        encode_rpc_message(
+            correlation_id,
+            RpcMessageKind::Response,
+            message_bytes(&body),
+        )
+    }
+
+    fn ping() -> PingRequest {
+        PingRequest {
+            event: 49,
+            value: 7,
+        }
+    }
+
+    #[test]
+    fn test_envelope_round_trip() {
+        let request = ping();
+        let encoded = encode_rpc_message(12, RpcMessageKind::Request, message_bytes(&request));
+        let envelope = RpcEnvelope::parse(&encoded).unwrap();
+        assert_eq!(envelope.correlation_id, 12);
+        assert_eq!(envelope.kind, RpcMessageKind::Request);
+        assert_eq!(envelope.event(), Some(49));
+        assert_eq!(envelope.body, message_bytes(&request));
+
+        // Messages that aren't envelopes are left alone
+        assert_eq!(RpcEnvelope::parse(message_bytes(&request)), None);
+        assert_eq!(RpcEnvelope::parse(&encoded[..6]), None);
+    }
+
+    #[test]
+    fn test_call() {
+        let mut transport = MockTransport::default();
+        transport.deliver_at(0, "com.axle.server", &response(1, 0xdeadbeef));
+        let mut client = RpcClient::with_transport(transport);
+
+        let resp: RpcResponse<PingResponse> =
+            client.call("com.axle.server", &ping(), None).unwrap();
+        assert_eq!(resp.value, 0xdeadbeef);
+
+        // The request was sent in an envelope
+        let sent = client.transport().sent.borrow();
+        assert_eq!(sent[0].0, "com.axle.server");
+        let envelope = RpcEnvelope::parse(&sent[0].1).unwrap();
+        assert_eq!(envelope.correlation_id, 1);
+        assert_eq!(envelope.body, message_bytes(&ping()));
+    }
+
+    #[test]
+    fn test_call_with_trailing_data() {
+        // Given a response that carries data past the end of its type
+        let mut response_bytes = message_bytes(&PingResponse {
+            event: PingResponse::EXPECTED_EVENT,
+            value: 3,
+        })
+        .to_vec();
+        response_bytes.extend_from_slice(b"abc");
+        let mut transport = MockTransport::default();
+        transport.deliver_at(
+            0,
+            "com.axle.server",
+            &encode_rpc_message(1, RpcMessageKind::Response, &response_bytes),
+        );
+        let mut client = RpcClient::with_transport(transport);
+
+        // When I send a request that carries data past the end of its type
+        let mut request = message_bytes(&ping()).to_vec();
+        request.extend_from_slice(b"xyz");
+        let resp: RpcResponse<PingResponse> = client
+            .call_with_body("com.axle.server", &request, None)
+            .unwrap();
+
+        // Then the trailing data is sent and received intact
+        let sent = client.transport().sent.borrow();
+        let envelope = RpcEnvelope::parse(&sent[0].1).unwrap();
+        assert_eq!(envelope.body, &request[..]);
+        assert_eq!(resp.value, 3);
+        assert_eq!(resp.bytes(), &response_bytes[..]);
+    }
+
+    #[test]
+    fn test_interleaved_messages_are_queued() {
+        // Given unrelated messages that arrive before the response, including one from the
+        // service that was called
+        let mut transport = MockTransport::default();
+        transport.deliver_at(0, "com.axle.server", &[1, 0, 0, 0]);
+        transport.deliver_at(0, "com.axle.other", &[2, 0, 0, 0]);
+        transport.deliver_at(0, "com.axle.server", &response(1, 5));
+        transport.deliver_at(0, "com.axle.other", &[3, 0, 0, 0]);
+        let mut client = RpcClient::with_transport(transport);
+
+        // When I make a call
+        let resp: RpcResponse<PingResponse> =
+            client.call("com.axle.server", &ping(), None).unwrap();
+        assert_eq!(resp.value, 5);
+
+        // Then the unrelated messages are delivered afterwards, in the order they arrived
+        let events: Vec<Option<u32>> = (0..3).map(|_| client.next_message().event()).collect();
+        assert_eq!(events, [Some(1), Some(2), Some(3)]);
+        assert!(!client.has_message());
+    }
+
+    #[test]
+    fn test_timeout() {
+        // Given a response that arrives too late
+        let mut transport = MockTransport::default();
+        transport.deliver_at(500, "com.axle.server", &response(1, 5));
+        transport.deliver_at(600, "com.axle.server", &response(2, 6));
+        let mut client = RpcClient::with_transport(transport);
+
+        // Then the call times out
+        let resp = client.call::<_, PingResponse>("com.axle.server", &ping(), Some(100));
+        assert_eq!(resp.err(), Some(RpcError::Timeout));
+        assert_eq!(client.transport().now, 100);
+
+        // And the late response isn't mistaken for the response to the next call
+        let resp: RpcResponse<PingResponse> =
+            client.call("com.axle.server", &ping(), Some(1000)).unwrap();
+        assert_eq!(resp.value, 6);
+    }
+
+    #[test]
+    fn test_unexpected_response() {
+        let mut transport = MockTransport::default();
+        let wrong_event = PingRequest { event: 3, value: 0 };
+        transport.deliver_at(
+            0,
+            "com.axle.server",
+            &encode_rpc_message(1, RpcMessageKind::Response, message_bytes(&wrong_event)),
+        );
+        transport.deliver_at(
+            0,
+            "com.axle.server",
+            &encode_rpc_message(2, RpcMessageKind::Response, &[50, 0, 0, 0]),
+        );
+        let mut client = RpcClient::with_transport(transport);
+
+        let resp = client.call::<_, PingResponse>("com.axle.server", &ping(), None);
+        assert_eq!(resp.err(), Some(RpcError::UnexpectedResponse(3)));
+        // A response too short for the expected type is rejected rather than read past its end
+        let resp = client.call::<_, PingResponse>("com.axle.server", &ping(), None);
+        assert_eq!(resp.err(), Some(RpcError::MalformedResponse));
+    }
+}
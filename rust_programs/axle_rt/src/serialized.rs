@@ -0,0 +1,158 @@
+//! Message bodies that are serialized with postcard rather than sent as `#[repr(C)]` structs.
+//!
+//! This lets a message carry strings, byte slices and vectors of any length. A serialized body
+//! still starts with the native-endian `u32` event, so receivers can dispatch on it as usual,
+//! and the rest of the body is the postcard encoding of the message. On receipt, `&str` and
+//! `&[u8]` fields borrow directly from the body.
+//!
+//! ```ignore
+//! #[derive(Serialize, Deserialize)]
+//! pub struct LaunchProgramWithArguments<'a> {
+//!     pub path: &'a str,
+//!     #[serde(borrow)]
+//!     pub arguments: Vec<&'a str>,
+//! }
+//! ```
+
+use alloc::vec::Vec;
+use core::mem::size_of;
+use serde::{Deserialize, Serialize};
+
+use crate::ExpectsEventField;
+#[cfg(any(target_os = "axle", feature = "amc_sim"))]
+use crate::{amc_message_await__u32_event_untyped, amc_message_send_untyped};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MessageDecodeError {
+    /// The body was too short to hold an event
+    Truncated,
+    UnexpectedEvent(u32),
+    /// The body didn't hold a valid encoding of the message
+    Malformed,
+}
+
+pub fn encode_message<T: ExpectsEventField + Serialize>(message: &T) -> Vec<u8> {
+    let mut body = Vec::from(T::EXPECTED_EVENT.to_ne_bytes());
+    let encoded = postcard::to_allocvec(message).expect("Failed to encode message");
+    body.extend_from_slice(&encoded);
+    body
+}
+
+/// Decodes a message, borrowing any string and byte slice fields from `body`
+pub fn decode_message<'a, T: ExpectsEventField + Deserialize<'a>>(
+    body: &'a [u8],
+) -> Result<T, MessageDecodeError> {
+    let event = body
+        .get(..size_of::<u32>())
+        .ok_or(MessageDecodeError::Truncated)?;
+    let event = u32::from_ne_bytes(event.try_into().unwrap());
+    if event != T::EXPECTED_EVENT {
+        return Err(MessageDecodeError::UnexpectedEvent(event));
+    }
+    postcard::from_bytes(&body[size_of::<u32>()..]).map_err(|_| MessageDecodeError::Malformed)
+}
+
+#[cfg(any(target_os = "axle", feature = "amc_sim"))]
+pub fn amc_message_send_serialized<T: ExpectsEventField + Serialize>(
+    to_service: &str,
+    message: &T,
+) {
+    let body = encode_message(message);
+    unsafe { amc_message_send_untyped(to_service, body.as_ptr(), body.len()) };
+}
+
+/// A received serialized message. The body is copied out of the kernel's delivery pool, whose
+/// slot is reused by the next await, so that decoded messages can borrow from it.
+#[derive(Debug, Clone)]
+pub struct SerializedMessage {
+    body: Vec<u8>,
+}
+
+impl SerializedMessage {
+    /// Borrowed fields point into this message's body
+    pub fn decode<'a, T: ExpectsEventField + Deserialize<'a>>(
+        &'a self,
+    ) -> Result<T, MessageDecodeError> {
+        decode_message(&self.body)
+    }
+}
+
+/// Awaits a serialized `T` from `from_service`. Decode it with `SerializedMessage::decode`.
+#[cfg(any(target_os = "axle", feature = "amc_sim"))]
+pub fn amc_message_await_serialized<T: ExpectsEventField>(from_service: &str) -> SerializedMessage {
+    let msg = unsafe {
+        amc_message_await__u32_event_untyped(from_service, T::EXPECTED_EVENT)
+            .expect("Failed to await message")
+    };
+    SerializedMessage {
+        body: msg.body.to_vec(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::String;
+    use alloc::vec;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct LaunchRequest<'a> {
+        path: &'a str,
+        #[serde(borrow)]
+        arguments: Vec<&'a str>,
+        payload: &'a [u8],
+    }
+
+    impl ExpectsEventField for LaunchRequest<'_> {
+        const EXPECTED_EVENT: u32 = 40;
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct OwnedRequest {
+        path: String,
+    }
+
+    impl ExpectsEventField for OwnedRequest {
+        const EXPECTED_EVENT: u32 = 41;
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let long_path: String = core::iter::repeat("directory/").take(50).collect();
+        let message = LaunchRequest {
+            path: &long_path,
+            arguments: vec!["--one", "two"],
+            payload: &[0xff; 300],
+        };
+        let body = encode_message(&message);
+        // Receivers can dispatch on the leading event
+        assert_eq!(body[..4], 40_u32.to_ne_bytes());
+
+        let decoded: LaunchRequest = decode_message(&body).unwrap();
+        assert_eq!(decoded, message);
+        // Strings are borrowed from the body rather than copied
+        let body_range = body.as_ptr_range();
+        assert!(body_range.contains(&decoded.path.as_ptr()));
+        assert!(body_range.contains(&decoded.payload.as_ptr()));
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        let body = encode_message(&OwnedRequest {
+            path: String::from("/usr/applications/ide"),
+        });
+        assert_eq!(
+            decode_message::<LaunchRequest>(&body),
+            Err(MessageDecodeError::UnexpectedEvent(41))
+        );
+        assert_eq!(
+            decode_message::<OwnedRequest>(&body[..2]),
+            Err(MessageDecodeError::Truncated)
+        );
+        // The string's length says it runs past the end of the body
+        assert_eq!(
+            decode_message::<OwnedRequest>(&body[..10]),
+            Err(MessageDecodeError::Malformed)
+        );
+    }
+}
@@ -0,0 +1,381 @@
+#![cfg(feature = "amc_sim")]
+
+use std::sync::{mpsc, Arc, Barrier};
+use std::time::Duration;
+
+use axle_rt::amc_sim::AmcSimulator;
+use axle_rt::core_commands::{
+    amc_configure_inbox, AmcInboxOverflowPolicy, AmcMessageDropped, AmcQueryServiceRequest,
+    AmcRegisterServiceDiedNotif, AmcServiceDiedNotif, AmcSharedMemoryCreateRequest,
+    AMC_CORE_SERVICE_NAME,
+};
+use axle_rt::executor::Executor;
+use axle_rt::rpc::{rpc_reply, RpcClient, RpcEnvelope, RpcError};
+use axle_rt::serialized::{amc_message_await_serialized, amc_message_send_serialized};
+use axle_rt::{
+    amc_has_message, amc_message_await, amc_message_await__u32_event, amc_message_await_untyped,
+    amc_message_send, amc_register_service, AmcMessage, ExpectsEventField,
+};
+use axle_rt_derive::amc_message;
+use serde::{Deserialize, Serialize};
+
+const ECHO_SERVICE: &str = "com.axle.echo";
+const TEST_SERVICE: &str = "com.axle.test";
+
+#[amc_message(event = 1)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct Ping {
+    value: u64,
+}
+
+#[amc_message(event = 2)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct Shutdown {}
+
+/// Echoes pings back to their sender until it's asked to shut down
+fn echo_service() {
+    amc_register_service(ECHO_SERVICE);
+    loop {
+        let msg = unsafe { amc_message_await_untyped(None).unwrap() };
+        match u32::from_ne_bytes(msg.body()[..4].try_into().unwrap()) {
+            Ping::EXPECTED_EVENT => {
+                let ping = unsafe { &*(msg.body().as_ptr() as *const Ping) };
+                amc_message_send(msg.source(), *ping);
+            }
+            Shutdown::EXPECTED_EVENT => return,
+            event => panic!("Unexpected event {event}"),
+        }
+    }
+}
+
+#[test]
+fn test_message_exchange() {
+    let sim = AmcSimulator::new();
+    sim.run(|| {
+        amc_register_service(TEST_SERVICE);
+        // Messages sent before a service registers are delivered once it does
+        amc_message_send(ECHO_SERVICE, Ping::new(1));
+        let echo = sim.spawn(echo_service);
+
+        let msg: AmcMessage<Ping> = amc_message_await(Some(ECHO_SERVICE));
+        assert_eq!(msg.body().value, 1);
+        assert_eq!(msg.source(), ECHO_SERVICE);
+
+        for value in 2..10 {
+            amc_message_send(ECHO_SERVICE, Ping::new(value));
+        }
+        for value in 2..10 {
+            let msg: AmcMessage<Ping> = amc_message_await__u32_event(ECHO_SERVICE);
+            assert_eq!(msg.body().value, value);
+        }
+        assert!(!amc_has_message(None));
+
+        amc_message_send(ECHO_SERVICE, Shutdown::new());
+        echo.join().unwrap();
+    });
+}
+
+#[test]
+fn test_service_died_notifications() {
+    let sim = AmcSimulator::new();
+    let (crash, crash_requested) = mpsc::channel::<()>();
+    let crasher = sim.spawn(move || {
+        amc_register_service("com.axle.crasher");
+        crash_requested.recv().unwrap();
+        panic!("Crashing on purpose");
+    });
+    assert!(sim.wait_for_service("com.axle.crasher", Duration::from_secs(5)));
+
+    sim.run(|| {
+        amc_register_service(TEST_SERVICE);
+        assert!(AmcQueryServiceRequest::send("com.axle.crasher").service_exists);
+        assert!(!AmcQueryServiceRequest::send("com.axle.missing").service_exists);
+
+        AmcRegisterServiceDiedNotif::send("com.axle.crasher");
+        crash.send(()).unwrap();
+        let notif: AmcMessage<AmcServiceDiedNotif> =
+            amc_message_await__u32_event(AMC_CORE_SERVICE_NAME);
+        let dead_service = std::str::from_utf8(&notif.body().dead_service)
+            .unwrap()
+            .trim_end_matches('\0');
+        assert_eq!(dead_service, "com.axle.crasher");
+        assert!(!AmcQueryServiceRequest::send("com.axle.crasher").service_exists);
+    });
+    assert!(crasher.join().is_err());
+    assert!(!sim.service_exists(TEST_SERVICE));
+}
+
+#[amc_message(event = 3)]
+#[derive(Debug, Copy, Clone)]
+struct AddRequest {
+    a: u32,
+    b: u32,
+}
+
+#[amc_message(event = 3)]
+#[derive(Debug, Copy, Clone)]
+struct AddResponse {
+    sum: u32,
+}
+
+#[test]
+fn test_rpc() {
+    let sim = AmcSimulator::new();
+    let server = sim.spawn(|| {
+        amc_register_service("com.axle.adder");
+        for _ in 0..2 {
+            let msg = unsafe { amc_message_await_untyped(None).unwrap() };
+            let envelope = RpcEnvelope::parse(msg.body()).unwrap();
+            let request = unsafe { &*(envelope.body.as_ptr() as *const AddRequest) };
+            // Interleave an unrelated message with the response
+            amc_message_send(msg.source(), Ping::new(request.a as u64));
+            rpc_reply(
+                msg.source(),
+                &envelope,
+                &AddResponse::new(request.a + request.b),
+            );
+        }
+    });
+
+    sim.run(|| {
+        amc_register_service(TEST_SERVICE);
+        let mut client = RpcClient::new();
+        for (a, b) in [(2, 3), (40, 2)] {
+            let response: axle_rt::rpc::RpcResponse<AddResponse> = client
+                .call("com.axle.adder", &AddRequest::new(a, b), Some(5000))
+                .unwrap();
+            assert_eq!(response.sum, a + b);
+        }
+        // The pings that arrived during the calls were held onto
+        for a in [2, 40] {
+            let msg = client.next_message();
+            assert_eq!(msg.source, "com.axle.adder");
+            assert_eq!(msg.event(), Some(Ping::EXPECTED_EVENT));
+            assert_eq!(msg.body[8..16], (a as u64).to_ne_bytes());
+        }
+
+        server.join().unwrap();
+        // Nobody is left to answer
+        let result =
+            client.call::<_, AddResponse>("com.axle.adder", &AddRequest::new(1, 1), Some(50));
+        assert_eq!(result.err(), Some(RpcError::Timeout));
+    });
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Greeting<'a> {
+    name: &'a str,
+    #[serde(borrow)]
+    languages: Vec<&'a str>,
+}
+
+impl ExpectsEventField for Greeting<'_> {
+    const EXPECTED_EVENT: u32 = 4;
+}
+
+#[test]
+fn test_serialized_messages() {
+    let sim = AmcSimulator::new();
+    let long_name = "a".repeat(1000);
+    let expected_name = long_name.clone();
+    let receiver = sim.spawn(move || {
+        amc_register_service("com.axle.greeter");
+        let message = amc_message_await_serialized::<Greeting>(TEST_SERVICE);
+        let greeting: Greeting = message.decode().unwrap();
+        assert_eq!(greeting.name, expected_name);
+        assert_eq!(greeting.languages, ["en", "fr"]);
+    });
+    sim.run(|| {
+        amc_register_service(TEST_SERVICE);
+        amc_message_send_serialized(
+            "com.axle.greeter",
+            &Greeting {
+                name: &long_name,
+                languages: vec!["en", "fr"],
+            },
+        );
+    });
+    receiver.join().unwrap();
+}
+
+#[test]
+fn test_shared_memory() {
+    let sim = AmcSimulator::new();
+    let reader = sim.spawn(|| {
+        amc_register_service("com.axle.reader");
+        let msg: AmcMessage<Ping> = amc_message_await(Some(TEST_SERVICE));
+        let buffer = msg.body().value as *const u8;
+        let contents = unsafe { std::slice::from_raw_parts(buffer, 5) };
+        assert_eq!(contents, b"hello");
+    });
+    sim.run(|| {
+        amc_register_service(TEST_SERVICE);
+        let shared_memory = AmcSharedMemoryCreateRequest::send("com.axle.reader", 0x1000);
+        let buffer = shared_memory.local_buffer_start as *mut u8;
+        unsafe { std::ptr::copy_nonoverlapping(b"hello".as_ptr(), buffer, 5) };
+        amc_message_send(
+            "com.axle.reader",
+            Ping::new(shared_memory.remote_buffer_start as u64),
+        );
+    });
+    reader.join().unwrap();
+}
+
+#[test]
+fn test_executor() {
+    let sim = AmcSimulator::new();
+    let echo = sim.spawn(echo_service);
+    sim.run(|| {
+        amc_register_service(TEST_SERVICE);
+        let mut executor = Executor::new();
+        let events = executor.events();
+        executor.spawn(async move {
+            for value in 0..3 {
+                amc_message_send(ECHO_SERVICE, Ping::new(value));
+                let msg = events.next_message(Some(ECHO_SERVICE)).await;
+                assert_eq!(msg.body[8..16], value.to_ne_bytes());
+            }
+            amc_message_send(ECHO_SERVICE, Shutdown::new());
+        });
+        let events = executor.events();
+        executor.spawn(async move {
+            let start = events.ms_since_boot();
+            events.sleep(30).await;
+            assert!(events.ms_since_boot() - start >= 30);
+        });
+        executor.run();
+    });
+    echo.join().unwrap();
+}
+
+#[test]
+fn test_inbox_overflow() {
+    let sim = AmcSimulator::new();
+    let (ready, await_ready) = mpsc::channel::<()>();
+    let (drain, await_drain) = mpsc::channel::<()>();
+    let receiver = sim.spawn(move || {
+        amc_register_service("com.axle.receiver");
+        amc_configure_inbox(4, AmcInboxOverflowPolicy::Coalesce, &[Ping::EXPECTED_EVENT]);
+        ready.send(()).unwrap();
+        await_drain.recv().unwrap();
+        (0..4)
+            .map(|_| {
+                let msg = unsafe { amc_message_await_untyped(Some(TEST_SERVICE)).unwrap() };
+                msg.body().to_vec()
+            })
+            .collect::<Vec<_>>()
+    });
+    await_ready.recv().unwrap();
+
+    sim.run(|| {
+        amc_register_service(TEST_SERVICE);
+        for value in 0..4 {
+            amc_message_send("com.axle.receiver", Ping::new(value));
+        }
+        // Supersedes the newest ping, so there's nothing to report
+        amc_message_send("com.axle.receiver", Ping::new(10));
+        assert!(!amc_has_message(None));
+
+        // Not coalesced, so the oldest ping is dropped to make room
+        amc_message_send("com.axle.receiver", AddRequest::new(1, 2));
+        let dropped: AmcMessage<AmcMessageDropped> =
+            amc_message_await__u32_event(AMC_CORE_SERVICE_NAME);
+        assert_eq!(dropped.body().dest(), "com.axle.receiver");
+        assert_eq!(dropped.body().dropped_event, Ping::EXPECTED_EVENT);
+        drain.send(()).unwrap();
+    });
+
+    let received = receiver.join().unwrap();
+    let events: Vec<u32> = received
+        .iter()
+        .map(|body| u32::from_ne_bytes(body[..4].try_into().unwrap()))
+        .collect();
+    assert_eq!(
+        events,
+        [
+            Ping::EXPECTED_EVENT,
+            Ping::EXPECTED_EVENT,
+            Ping::EXPECTED_EVENT,
+            AddRequest::EXPECTED_EVENT
+        ]
+    );
+    let ping_values: Vec<u64> = received[..3]
+        .iter()
+        .map(|body| u64::from_ne_bytes(body[8..16].try_into().unwrap()))
+        .collect();
+    assert_eq!(ping_values, [1, 2, 10]);
+}
+
+#[test]
+fn test_inbox_blocks_sender() {
+    let sim = AmcSimulator::new();
+    let (ready, await_ready) = mpsc::channel::<()>();
+    let (drain, await_drain) = mpsc::channel::<()>();
+    let receiver = sim.spawn(move || {
+        amc_register_service("com.axle.receiver");
+        amc_configure_inbox(2, AmcInboxOverflowPolicy::BlockSender, &[]);
+        ready.send(()).unwrap();
+        await_drain.recv().unwrap();
+        for value in 0..5 {
+            let msg: AmcMessage<Ping> = amc_message_await(Some(TEST_SERVICE));
+            assert_eq!(msg.body().value, value);
+        }
+    });
+    await_ready.recv().unwrap();
+
+    let (sent, await_sent) = mpsc::channel::<()>();
+    let sender = sim.spawn(move || {
+        amc_register_service(TEST_SERVICE);
+        for value in 0..5 {
+            amc_message_send("com.axle.receiver", Ping::new(value));
+        }
+        sent.send(()).unwrap();
+        // Nothing was dropped
+        assert!(!amc_has_message(None));
+    });
+
+    // The sender can't finish until the receiver makes room
+    assert!(await_sent.recv_timeout(Duration::from_millis(100)).is_err());
+    drain.send(()).unwrap();
+    await_sent.recv_timeout(Duration::from_secs(5)).unwrap();
+    sender.join().unwrap();
+    receiver.join().unwrap();
+}
+
+#[test]
+fn test_blocked_senders_dont_deadlock() {
+    let sim = AmcSimulator::new();
+    let ready = Arc::new(Barrier::new(2));
+    let (done, await_done) = mpsc::channel::<()>();
+    let peers = [
+        ("com.axle.peer_a", "com.axle.peer_b"),
+        ("com.axle.peer_b", "com.axle.peer_a"),
+    ];
+    let peers: Vec<_> = peers
+        .into_iter()
+        .map(|(this_service, other_service)| {
+            let ready = Arc::clone(&ready);
+            let done = done.clone();
+            sim.spawn(move || {
+                amc_register_service(this_service);
+                amc_configure_inbox(1, AmcInboxOverflowPolicy::BlockSender, &[]);
+                ready.wait();
+                // The second message finds the other's inbox full, while neither is draining
+                for value in 0..2 {
+                    amc_message_send(other_service, Ping::new(value));
+                }
+                done.send(()).unwrap();
+                // Make room for the other service, if it's still waiting
+                unsafe { amc_message_await_untyped(None).unwrap() };
+            })
+        })
+        .collect();
+
+    // The cycle is noticed well before the senders would give up waiting
+    for _ in 0..2 {
+        await_done.recv_timeout(Duration::from_secs(1)).unwrap();
+    }
+    for peer in peers {
+        peer.join().unwrap();
+    }
+}
@@ -3,6 +3,8 @@ extern crate proc_macro;
 use proc_macro::TokenStream;
 use quote::quote;
 use syn;
+use syn::parse::{Parse, ParseStream, Parser};
+use syn::spanned::Spanned;
 
 #[proc_macro_derive(ContainsEventField)]
 pub fn contains_event_field_derive(input: TokenStream) -> TokenStream {
@@ -21,3 +23,309 @@ fn impl_contains_event_field(ast: &syn::DeriveInput) -> TokenStream {
     };
     gen.into()
 }
+
+struct AmcMessageArgs {
+    /// The service that the message is sent to
+    service: Option<syn::Expr>,
+    event: syn::LitInt,
+}
+
+impl Parse for AmcMessageArgs {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let mut service = None;
+        let mut event = None;
+        while !input.is_empty() {
+            let key: syn::Ident = input.parse()?;
+            input.parse::<syn::Token![=]>()?;
+            match key.to_string().as_str() {
+                "service" => service = Some(input.parse()?),
+                "event" => event = Some(input.parse()?),
+                _ => {
+                    return Err(syn::Error::new(
+                        key.span(),
+                        "Expected `service = ...` or `event = ...`",
+                    ))
+                }
+            }
+            if !input.is_empty() {
+                input.parse::<syn::Token![,]>()?;
+            }
+        }
+        match event {
+            Some(event) => Ok(Self { service, event }),
+            None => Err(input.error("amc_message requires an `event = ...` ID")),
+        }
+    }
+}
+
+/// Turns a struct into an AMC message.
+///
+/// ```ignore
+/// #[amc_message(service = IMAGE_VIEWER_SERVICE_NAME, event = 1)]
+/// pub struct LoadImage {
+///     #[string]
+///     pub path: [u8; 128],
+/// }
+/// ```
+///
+/// The struct becomes `#[repr(C)]` with a leading `event` field, and gets `ContainsEventField`
+/// and `ExpectsEventField` impls. `new()` takes each field in order, with `&str` for fields
+/// marked `#[string]`, which are copied in NUL-terminated. `await_from()` awaits the message
+/// from a given service, and when a `service` is given, `send()` builds the message and sends it
+/// to that service.
+#[proc_macro_attribute]
+pub fn amc_message(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let args = syn::parse_macro_input!(attr as AmcMessageArgs);
+    let ast = syn::parse_macro_input!(item as syn::DeriveInput);
+    match impl_amc_message(args, ast) {
+        Ok(gen) => gen,
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+fn impl_amc_message(args: AmcMessageArgs, mut ast: syn::DeriveInput) -> syn::Result<TokenStream> {
+    if !ast.generics.params.is_empty() {
+        return Err(syn::Error::new(
+            ast.generics.span(),
+            "AMC messages can't be generic",
+        ));
+    }
+    let fields = match &mut ast.data {
+        syn::Data::Struct(syn::DataStruct {
+            fields: syn::Fields::Named(fields),
+            ..
+        }) => fields,
+        _ => {
+            return Err(syn::Error::new(
+                ast.ident.span(),
+                "amc_message can only be applied to structs with named fields",
+            ))
+        }
+    };
+
+    let mut params = Vec::new();
+    let mut initializers = Vec::new();
+    for field in fields.named.iter_mut() {
+        let field_name = field.ident.clone().unwrap();
+        if field_name == "event" {
+            return Err(syn::Error::new(
+                field_name.span(),
+                "amc_message adds the event field itself",
+            ));
+        }
+        let attrs_count = field.attrs.len();
+        field.attrs.retain(|attr| !attr.path.is_ident("string"));
+        if field.attrs.len() == attrs_count {
+            let ty = &field.ty;
+            params.push(quote! { #field_name: #ty });
+            initializers.push(quote! { #field_name });
+            continue;
+        }
+
+        let len = match &field.ty {
+            syn::Type::Array(array) => &array.len,
+            ty => {
+                return Err(syn::Error::new(
+                    ty.span(),
+                    "#[string] fields must be byte arrays",
+                ))
+            }
+        };
+        params.push(quote! { #field_name: &str });
+        initializers.push(quote! {
+            #field_name: {
+                let mut buf = [0; #len];
+                ::axle_rt::copy_str_into_sized_slice(&mut buf, #field_name);
+                buf
+            }
+        });
+    }
+    let event_field: syn::Field = syn::Field::parse_named.parse2(quote! { event: u32 })?;
+    fields.named.insert(0, event_field);
+
+    let name = &ast.ident;
+    let event = &args.event;
+    let param_names: Vec<_> = fields
+        .named
+        .iter()
+        .skip(1)
+        .map(|f| f.ident.clone().unwrap())
+        .collect();
+    let send = args.service.map(|service| {
+        quote! {
+            #[cfg(target_os = "axle")]
+            pub fn send(#(#params),*) {
+                ::axle_rt::amc_message_send(#service, Self::new(#(#param_names),*));
+            }
+        }
+    });
+
+    Ok(quote! {
+        #[repr(C)]
+        #ast
+
+        impl ::axle_rt::ContainsEventField for #name {
+            fn event(&self) -> u32 {
+                self.event
+            }
+        }
+
+        impl ::axle_rt::ExpectsEventField for #name {
+            const EXPECTED_EVENT: u32 = #event;
+        }
+
+        impl #name {
+            pub fn new(#(#params),*) -> Self {
+                Self {
+                    event: <Self as ::axle_rt::ExpectsEventField>::EXPECTED_EVENT,
+                    #(#initializers),*
+                }
+            }
+
+            #send
+
+            #[cfg(target_os = "axle")]
+            pub fn await_from(from_service: &str) -> ::axle_rt::AmcMessage<Self> {
+                ::axle_rt::amc_message_await__u32_event(from_service)
+            }
+        }
+    }
+    .into())
+}
+
+/// Generates a dispatcher for an enum whose variants each wrap one message type, either by
+/// reference or by value.
+///
+/// ```ignore
+/// #[derive(AmcProtocol)]
+/// pub enum DockProtocol<'a> {
+///     WindowCreated(&'a AwmDockWindowCreatedEvent),
+///     WindowClosed(&'a AwmDockWindowClosed),
+/// }
+/// ```
+///
+/// `parse()` reads the event from the front of a message body and returns the matching variant,
+/// or None if the event isn't part of the protocol or the body is too short. Two variants with
+/// the same event ID are rejected at compile time.
+#[proc_macro_derive(AmcProtocol)]
+pub fn amc_protocol_derive(input: TokenStream) -> TokenStream {
+    let ast = syn::parse_macro_input!(input as syn::DeriveInput);
+    match impl_amc_protocol(&ast) {
+        Ok(gen) => gen,
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+fn impl_amc_protocol(ast: &syn::DeriveInput) -> syn::Result<TokenStream> {
+    let name = &ast.ident;
+    let variants = match &ast.data {
+        syn::Data::Enum(data) => &data.variants,
+        _ => {
+            return Err(syn::Error::new(
+                name.span(),
+                "AmcProtocol can only be derived for enums",
+            ))
+        }
+    };
+    let lifetime = ast.generics.lifetimes().next().map(|l| &l.lifetime);
+    let body_type = match lifetime {
+        Some(lifetime) => quote! { &#lifetime [u8] },
+        None => quote! { &[u8] },
+    };
+
+    let mut message_types = Vec::new();
+    let mut parse_arms = Vec::new();
+    let mut event_arms = Vec::new();
+    for variant in variants.iter() {
+        let variant_name = &variant.ident;
+        let ty = match &variant.fields {
+            syn::Fields::Unnamed(fields) if fields.unnamed.len() == 1 => &fields.unnamed[0].ty,
+            _ => {
+                return Err(syn::Error::new(
+                    variant.span(),
+                    "Each AmcProtocol variant must wrap exactly one message",
+                ))
+            }
+        };
+        let (message_type, value) = match ty {
+            syn::Type::Reference(reference) => {
+                if lifetime.is_none() {
+                    return Err(syn::Error::new(
+                        ty.span(),
+                        "Variants that borrow a message need a lifetime on the enum",
+                    ));
+                }
+                let message_type = &reference.elem;
+                (
+                    message_type.as_ref(),
+                    quote! { &*(body.as_ptr() as *const #message_type) },
+                )
+            }
+            _ => (
+                ty,
+                quote! { ::core::ptr::read_unaligned(body.as_ptr() as *const #ty) },
+            ),
+        };
+        parse_arms.push(quote! {
+            <#message_type as ::axle_rt::ExpectsEventField>::EXPECTED_EVENT => {
+                if body.len() < ::core::mem::size_of::<#message_type>() {
+                    return None;
+                }
+                Some(Self::#variant_name(#value))
+            }
+        });
+        event_arms.push(quote! {
+            Self::#variant_name(_) => {
+                <#message_type as ::axle_rt::ExpectsEventField>::EXPECTED_EVENT
+            }
+        });
+        message_types.push(message_type);
+    }
+
+    let duplicate_event_error = format!("Each message in {name} must have a distinct event ID");
+    let (impl_generics, ty_generics, where_clause) = ast.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics #name #ty_generics #where_clause {
+            /// Parses a message body into the variant for its event.
+            ///
+            /// # Safety
+            ///
+            /// The body must hold a valid instance of the message type that its event names,
+            /// so it should only be parsed when it comes from a service that speaks this
+            /// protocol.
+            pub unsafe fn parse(body: #body_type) -> Option<Self> {
+                let event = u32::from_ne_bytes(
+                    <[u8; 4] as ::core::convert::TryFrom<&[u8]>>::try_from(body.get(..4)?).unwrap(),
+                );
+                match event {
+                    #(#parse_arms)*
+                    _ => None,
+                }
+            }
+
+            pub fn event(&self) -> u32 {
+                match self {
+                    #(#event_arms)*
+                }
+            }
+        }
+
+        const _: () = {
+            let events: &[u32] = &[
+                #(<#message_types as ::axle_rt::ExpectsEventField>::EXPECTED_EVENT),*
+            ];
+            let mut i = 0;
+            while i < events.len() {
+                let mut j = i + 1;
+                while j < events.len() {
+                    if events[i] == events[j] {
+                        panic!(#duplicate_event_error);
+                    }
+                    j += 1;
+                }
+                i += 1;
+            }
+        };
+    }
+    .into())
+}
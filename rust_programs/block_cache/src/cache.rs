@@ -0,0 +1,621 @@
+use alloc::{collections::BTreeMap, vec, vec::Vec};
+use core::cmp;
+
+use block_cache_messages::BlockCacheStats;
+use sata_driver_messages::{
+    BlockDeviceStatus, DeviceInfo, MAX_SECTORS_PER_REQUEST, SATA_SECTOR_SIZE,
+};
+
+/// The disk behind the cache
+pub trait BlockBackend {
+    fn device_info(&mut self) -> Result<DeviceInfo, BlockDeviceStatus>;
+
+    /// `sector_count` is at most `MAX_SECTORS_PER_REQUEST`
+    fn read_sectors(
+        &mut self,
+        start_sector: u64,
+        sector_count: u64,
+    ) -> Result<Vec<u8>, BlockDeviceStatus>;
+
+    /// `data` spans at most `MAX_SECTORS_PER_REQUEST` sectors
+    fn write_sectors(&mut self, start_sector: u64, data: &[u8]) -> Result<(), BlockDeviceStatus>;
+
+    /// Returns once everything written so far has reached the disk
+    fn flush(&mut self) -> Result<(), BlockDeviceStatus>;
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct CacheConfig {
+    /// Must be at least twice `MAX_SECTORS_PER_REQUEST`, so that a request and its read-ahead
+    /// always fit
+    pub capacity_sectors: usize,
+    /// How far to read ahead of a sequential reader. At most `MAX_SECTORS_PER_REQUEST`.
+    pub read_ahead_sectors: u64,
+}
+
+#[derive(Debug)]
+struct CachedSector {
+    data: Vec<u8>,
+    // Written by a client, and not yet written back to the disk
+    dirty: bool,
+    // The sector's key in the LRU index
+    last_used: u64,
+    // Read ahead of a sequential reader, and not requested since
+    read_ahead: bool,
+}
+
+/// A write-back cache of the disk's sectors, with least-recently-used eviction and sequential
+/// read-ahead
+pub struct BlockCache<B: BlockBackend> {
+    backend: B,
+    config: CacheConfig,
+    device_info: DeviceInfo,
+    sectors: BTreeMap<u64, CachedSector>,
+    // Cached sectors by the time they were last used, oldest first
+    lru: BTreeMap<u64, u64>,
+    clock: u64,
+    // Where the previous read ended, which is where a sequential reader will read next
+    next_sequential_sector: Option<u64>,
+    stats: BlockCacheStats,
+}
+
+impl<B: BlockBackend> BlockCache<B> {
+    pub fn new(mut backend: B, config: CacheConfig) -> Result<Self, BlockDeviceStatus> {
+        assert!(
+            config.capacity_sectors >= 2 * MAX_SECTORS_PER_REQUEST as usize,
+            "Cache is too small"
+        );
+        assert!(
+            config.read_ahead_sectors <= MAX_SECTORS_PER_REQUEST,
+            "Read-ahead is too large"
+        );
+        let device_info = backend.device_info()?;
+        Ok(Self {
+            backend,
+            config,
+            device_info,
+            sectors: BTreeMap::new(),
+            lru: BTreeMap::new(),
+            clock: 0,
+            next_sequential_sector: None,
+            stats: BlockCacheStats {
+                capacity_sectors: config.capacity_sectors as u64,
+                ..Default::default()
+            },
+        })
+    }
+
+    pub fn device_info(&self) -> &DeviceInfo {
+        &self.device_info
+    }
+
+    pub fn stats(&self) -> BlockCacheStats {
+        BlockCacheStats {
+            cached_sectors: self.sectors.len() as u64,
+            dirty_sectors: self.sectors.values().filter(|s| s.dirty).count() as u64,
+            ..self.stats
+        }
+    }
+
+    pub fn has_dirty_sectors(&self) -> bool {
+        self.sectors.values().any(|s| s.dirty)
+    }
+
+    /// Checks a request against the disk's bounds, and the protocol's maximum request size
+    fn validate(&self, start_sector: u64, sector_count: u64) -> Result<(), BlockDeviceStatus> {
+        if sector_count == 0 || sector_count > MAX_SECTORS_PER_REQUEST {
+            return Err(BlockDeviceStatus::InvalidRequest);
+        }
+        match start_sector.checked_add(sector_count) {
+            Some(end_sector) if end_sector <= self.device_info.sector_count => Ok(()),
+            _ => Err(BlockDeviceStatus::OutOfRange),
+        }
+    }
+
+    pub fn read(
+        &mut self,
+        start_sector: u64,
+        sector_count: u64,
+    ) -> Result<Vec<u8>, BlockDeviceStatus> {
+        self.validate(start_sector, sector_count)?;
+        let end_sector = start_sector + sector_count;
+        let sequential = self.next_sequential_sector == Some(start_sector);
+        self.next_sequential_sector = Some(end_sector);
+
+        // Serve what we can from the cache, and note the runs of sectors that must be read
+        let mut data = vec![0; sector_count as usize * SATA_SECTOR_SIZE];
+        let mut misses: Vec<(u64, u64)> = vec![];
+        for sector in start_sector..end_sector {
+            let offset = (sector - start_sector) as usize * SATA_SECTOR_SIZE;
+            match self.sectors.get_mut(&sector) {
+                Some(cached) => {
+                    data[offset..offset + SATA_SECTOR_SIZE].copy_from_slice(&cached.data);
+                    self.stats.read_hits += 1;
+                    if cached.read_ahead {
+                        cached.read_ahead = false;
+                        self.stats.read_ahead_hits += 1;
+                    }
+                    self.touch(sector);
+                }
+                None => {
+                    self.stats.read_misses += 1;
+                    match misses.last_mut() {
+                        Some((_, run_end)) if *run_end == sector => *run_end += 1,
+                        _ => misses.push((sector, sector + 1)),
+                    }
+                }
+            }
+        }
+
+        // Once a sequential reader catches up with what's been read ahead, read the next batch.
+        // While the sectors after this read are still cached, there's nothing to do yet.
+        let disk_sector_count = self.device_info.sector_count;
+        if sequential
+            && self.config.read_ahead_sectors > 0
+            && end_sector < disk_sector_count
+            && !self.sectors.contains_key(&end_sector)
+        {
+            let limit = cmp::min(
+                end_sector + self.config.read_ahead_sectors,
+                disk_sector_count,
+            );
+            let mut read_ahead_end = end_sector;
+            while read_ahead_end < limit && !self.sectors.contains_key(&read_ahead_end) {
+                read_ahead_end += 1;
+            }
+            // Fetch the read-ahead along with the end of the request, if it fits
+            match misses.last_mut() {
+                Some((run_start, run_end))
+                    if *run_end == end_sector
+                        && read_ahead_end - *run_start <= MAX_SECTORS_PER_REQUEST =>
+                {
+                    *run_end = read_ahead_end
+                }
+                _ => misses.push((end_sector, read_ahead_end)),
+            }
+        }
+
+        for (run_start, run_end) in misses {
+            let run_sector_count = run_end - run_start;
+            self.make_room(run_sector_count as usize)?;
+            let run_data = self.backend.read_sectors(run_start, run_sector_count)?;
+            if run_data.len() != run_sector_count as usize * SATA_SECTOR_SIZE {
+                return Err(BlockDeviceStatus::IoError);
+            }
+            for (sector, sector_data) in (run_start..).zip(run_data.chunks(SATA_SECTOR_SIZE)) {
+                let read_ahead = sector >= end_sector;
+                if read_ahead {
+                    self.stats.read_ahead_sectors += 1;
+                } else {
+                    let offset = (sector - start_sector) as usize * SATA_SECTOR_SIZE;
+                    data[offset..offset + SATA_SECTOR_SIZE].copy_from_slice(sector_data);
+                }
+                self.insert(sector, sector_data, false, read_ahead);
+            }
+        }
+        Ok(data)
+    }
+
+    /// The data is held in the cache until it's written back by an eviction or a flush
+    pub fn write(&mut self, start_sector: u64, data: &[u8]) -> Result<(), BlockDeviceStatus> {
+        if data.len() % SATA_SECTOR_SIZE != 0 {
+            return Err(BlockDeviceStatus::InvalidRequest);
+        }
+        let sector_count = (data.len() / SATA_SECTOR_SIZE) as u64;
+        self.validate(start_sector, sector_count)?;
+
+        let end_sector = start_sector + sector_count;
+        let incoming = (start_sector..end_sector)
+            .filter(|sector| !self.sectors.contains_key(sector))
+            .count();
+        self.make_room(incoming)?;
+        for (sector, sector_data) in (start_sector..).zip(data.chunks(SATA_SECTOR_SIZE)) {
+            self.insert(sector, sector_data, true, false);
+        }
+        self.stats.sectors_written += sector_count;
+        Ok(())
+    }
+
+    /// Writes back every dirty sector, then flushes the drive's own write cache.
+    /// If writing back fails, the sectors that weren't written stay dirty.
+    pub fn flush(&mut self) -> Result<(), BlockDeviceStatus> {
+        self.stats.flushes += 1;
+        for (run_start, run_end) in self.dirty_runs() {
+            self.write_back(run_start, run_end)?;
+        }
+        self.backend.flush()
+    }
+
+    /// Runs of contiguous dirty sectors, each small enough for one write request
+    fn dirty_runs(&self) -> Vec<(u64, u64)> {
+        let mut runs: Vec<(u64, u64)> = vec![];
+        for (&sector, _) in self.sectors.iter().filter(|(_, cached)| cached.dirty) {
+            match runs.last_mut() {
+                Some((run_start, run_end))
+                    if *run_end == sector && *run_end - *run_start < MAX_SECTORS_PER_REQUEST =>
+                {
+                    *run_end += 1
+                }
+                _ => runs.push((sector, sector + 1)),
+            }
+        }
+        runs
+    }
+
+    fn write_back(&mut self, run_start: u64, run_end: u64) -> Result<(), BlockDeviceStatus> {
+        let mut data = Vec::with_capacity((run_end - run_start) as usize * SATA_SECTOR_SIZE);
+        for sector in run_start..run_end {
+            data.extend_from_slice(&self.sectors[&sector].data);
+        }
+        self.stats.write_backs += 1;
+        self.backend.write_sectors(run_start, &data)?;
+        for sector in run_start..run_end {
+            self.sectors.get_mut(&sector).unwrap().dirty = false;
+        }
+        Ok(())
+    }
+
+    fn is_dirty(&self, sector: u64) -> bool {
+        self.sectors.get(&sector).is_some_and(|cached| cached.dirty)
+    }
+
+    /// Evicts the least recently used sectors until `incoming` more sectors fit
+    fn make_room(&mut self, incoming: usize) -> Result<(), BlockDeviceStatus> {
+        while !self.sectors.is_empty()
+            && self.sectors.len() + incoming > self.config.capacity_sectors
+        {
+            let (&last_used, &victim) = self.lru.iter().next().unwrap();
+            if self.is_dirty(victim) {
+                // Write back its neighbours too, as they'll likely be evicted soon
+                let mut run_start = victim;
+                while run_start > 0
+                    && victim - run_start + 1 < MAX_SECTORS_PER_REQUEST
+                    && self.is_dirty(run_start - 1)
+                {
+                    run_start -= 1;
+                }
+                let mut run_end = victim + 1;
+                while run_end - run_start < MAX_SECTORS_PER_REQUEST && self.is_dirty(run_end) {
+                    run_end += 1;
+                }
+                self.write_back(run_start, run_end)?;
+            }
+            self.lru.remove(&last_used);
+            self.sectors.remove(&victim);
+            self.stats.evictions += 1;
+        }
+        Ok(())
+    }
+
+    /// Marks a cached sector as the most recently used
+    fn touch(&mut self, sector: u64) {
+        let cached = self.sectors.get_mut(&sector).unwrap();
+        self.lru.remove(&cached.last_used);
+        self.clock += 1;
+        cached.last_used = self.clock;
+        self.lru.insert(self.clock, sector);
+    }
+
+    fn insert(&mut self, sector: u64, data: &[u8], dirty: bool, read_ahead: bool) {
+        match self.sectors.get_mut(&sector) {
+            Some(cached) => {
+                cached.data.copy_from_slice(data);
+                cached.dirty |= dirty;
+                cached.read_ahead = read_ahead;
+            }
+            None => {
+                self.sectors.insert(
+                    sector,
+                    CachedSector {
+                        data: data.to_vec(),
+                        dirty,
+                        last_used: 0,
+                        read_ahead,
+                    },
+                );
+            }
+        }
+        self.touch(sector);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use alloc::{rc::Rc, vec, vec::Vec};
+    use core::cell::RefCell;
+
+    use block_cache_messages::BlockCacheStats;
+    use sata_driver_messages::{
+        BlockDeviceStatus, DeviceInfo, MAX_SECTORS_PER_REQUEST, SATA_SECTOR_SIZE,
+    };
+
+    use crate::cache::{BlockBackend, BlockCache, CacheConfig};
+
+    const SECTOR_COUNT: u64 = 2048;
+    const MAX: u64 = MAX_SECTORS_PER_REQUEST;
+
+    #[derive(Debug, Default)]
+    struct MemoryDisk {
+        sectors: Vec<u8>,
+        // (start_sector, sector_count) of each request the cache made
+        reads: Vec<(u64, u64)>,
+        writes: Vec<(u64, u64)>,
+        flushes: usize,
+        fail_writes: bool,
+    }
+
+    /// Shared so that tests can inspect the disk while the cache owns it
+    #[derive(Debug, Clone)]
+    struct SharedDisk(Rc<RefCell<MemoryDisk>>);
+
+    impl SharedDisk {
+        fn new() -> Self {
+            let sectors = (0..SECTOR_COUNT as usize * SATA_SECTOR_SIZE)
+                .map(|i| (i / SATA_SECTOR_SIZE) as u8 ^ (i as u8))
+                .collect();
+            Self(Rc::new(RefCell::new(MemoryDisk {
+                sectors,
+                ..Default::default()
+            })))
+        }
+
+        fn sectors(&self, start_sector: u64, sector_count: u64) -> Vec<u8> {
+            let start = start_sector as usize * SATA_SECTOR_SIZE;
+            let end = start + sector_count as usize * SATA_SECTOR_SIZE;
+            self.0.borrow().sectors[start..end].to_vec()
+        }
+
+        fn take_reads(&self) -> Vec<(u64, u64)> {
+            core::mem::take(&mut self.0.borrow_mut().reads)
+        }
+
+        fn take_writes(&self) -> Vec<(u64, u64)> {
+            core::mem::take(&mut self.0.borrow_mut().writes)
+        }
+    }
+
+    impl BlockBackend for SharedDisk {
+        fn device_info(&mut self) -> Result<DeviceInfo, BlockDeviceStatus> {
+            Ok(DeviceInfo::new(
+                0,
+                BlockDeviceStatus::Success,
+                SECTOR_COUNT,
+                "memory",
+                "MEM-0001",
+            ))
+        }
+
+        fn read_sectors(
+            &mut self,
+            start_sector: u64,
+            sector_count: u64,
+        ) -> Result<Vec<u8>, BlockDeviceStatus> {
+            assert!(sector_count <= MAX);
+            self.0.borrow_mut().reads.push((start_sector, sector_count));
+            Ok(self.sectors(start_sector, sector_count))
+        }
+
+        fn write_sectors(
+            &mut self,
+            start_sector: u64,
+            data: &[u8],
+        ) -> Result<(), BlockDeviceStatus> {
+            let sector_count = (data.len() / SATA_SECTOR_SIZE) as u64;
+            assert!(sector_count <= MAX);
+            let mut disk = self.0.borrow_mut();
+            if disk.fail_writes {
+                return Err(BlockDeviceStatus::IoError);
+            }
+            disk.writes.push((start_sector, sector_count));
+            let start = start_sector as usize * SATA_SECTOR_SIZE;
+            disk.sectors[start..start + data.len()].copy_from_slice(data);
+            Ok(())
+        }
+
+        fn flush(&mut self) -> Result<(), BlockDeviceStatus> {
+            self.0.borrow_mut().flushes += 1;
+            Ok(())
+        }
+    }
+
+    fn cache_with(
+        capacity_sectors: usize,
+        read_ahead_sectors: u64,
+    ) -> (BlockCache<SharedDisk>, SharedDisk) {
+        let disk = SharedDisk::new();
+        let config = CacheConfig {
+            capacity_sectors,
+            read_ahead_sectors,
+        };
+        (BlockCache::new(disk.clone(), config).unwrap(), disk)
+    }
+
+    fn pattern(sector_count: u64, seed: u8) -> Vec<u8> {
+        (0..sector_count as usize * SATA_SECTOR_SIZE)
+            .map(|i| (i as u8).wrapping_mul(13).wrapping_add(seed))
+            .collect()
+    }
+
+    #[test]
+    fn test_read_hits_and_misses() {
+        let (mut cache, disk) = cache_with(512, 0);
+        assert_eq!(cache.read(10, 4), Ok(disk.sectors(10, 4)));
+        assert_eq!(disk.take_reads(), vec![(10, 4)]);
+
+        // Only the sectors that aren't cached are read, in contiguous runs
+        assert_eq!(cache.read(8, 8), Ok(disk.sectors(8, 8)));
+        assert_eq!(disk.take_reads(), vec![(8, 2), (14, 2)]);
+        assert_eq!(cache.read(8, 8), Ok(disk.sectors(8, 8)));
+        assert!(disk.take_reads().is_empty());
+
+        let stats = cache.stats();
+        assert_eq!(stats.read_misses, 8);
+        assert_eq!(stats.read_hits, 12);
+        assert_eq!(stats.cached_sectors, 8);
+    }
+
+    #[test]
+    fn test_invalid_requests() {
+        let (mut cache, disk) = cache_with(512, 0);
+        assert_eq!(cache.read(0, 0), Err(BlockDeviceStatus::InvalidRequest));
+        assert_eq!(
+            cache.read(0, MAX + 1),
+            Err(BlockDeviceStatus::InvalidRequest)
+        );
+        assert_eq!(
+            cache.read(SECTOR_COUNT - 1, 2),
+            Err(BlockDeviceStatus::OutOfRange)
+        );
+        assert_eq!(
+            cache.write(SECTOR_COUNT, &pattern(1, 0)),
+            Err(BlockDeviceStatus::OutOfRange)
+        );
+        assert_eq!(
+            cache.write(0, &[0; 100]),
+            Err(BlockDeviceStatus::InvalidRequest)
+        );
+        assert!(disk.take_reads().is_empty());
+        assert_eq!(cache.stats().cached_sectors, 0);
+    }
+
+    #[test]
+    fn test_sequential_read_ahead() {
+        let (mut cache, disk) = cache_with(512, 32);
+        cache.read(0, 8).unwrap();
+        assert_eq!(disk.take_reads(), vec![(0, 8)]);
+
+        // The second read in a row is sequential, and the read-ahead joins its own read
+        assert_eq!(cache.read(8, 8), Ok(disk.sectors(8, 8)));
+        assert_eq!(disk.take_reads(), vec![(8, 40)]);
+
+        // Reads within the read-ahead window are served from the cache
+        for start_sector in [16, 24, 32] {
+            assert_eq!(
+                cache.read(start_sector, 8),
+                Ok(disk.sectors(start_sector, 8))
+            );
+        }
+        assert!(disk.take_reads().is_empty());
+
+        // Reaching the end of the window reads the next batch, even though the read itself hits
+        cache.read(40, 8).unwrap();
+        assert_eq!(disk.take_reads(), vec![(48, 32)]);
+
+        let stats = cache.stats();
+        assert_eq!(stats.read_ahead_sectors, 64);
+        assert_eq!(stats.read_ahead_hits, 32);
+
+        // A read elsewhere isn't sequential, so nothing extra is read
+        cache.read(1000, 4).unwrap();
+        assert_eq!(disk.take_reads(), vec![(1000, 4)]);
+
+        // Read-ahead stops at the end of the disk
+        cache.read(SECTOR_COUNT - 8, 4).unwrap();
+        cache.read(SECTOR_COUNT - 4, 2).unwrap();
+        assert_eq!(
+            disk.take_reads(),
+            vec![(SECTOR_COUNT - 8, 4), (SECTOR_COUNT - 4, 4)]
+        );
+    }
+
+    #[test]
+    fn test_write_back() {
+        let (mut cache, disk) = cache_with(512, 0);
+        let original = disk.sectors(5, 3);
+        let data = pattern(3, 1);
+        cache.write(5, &data).unwrap();
+
+        // Writes are held in the cache, and reads see them
+        assert!(disk.take_writes().is_empty());
+        assert_eq!(disk.sectors(5, 3), original);
+        assert_eq!(cache.read(5, 3), Ok(data.clone()));
+        assert!(disk.take_reads().is_empty());
+        assert!(cache.has_dirty_sectors());
+        assert_eq!(cache.stats().dirty_sectors, 3);
+
+        cache.flush().unwrap();
+        assert_eq!(disk.take_writes(), vec![(5, 3)]);
+        assert_eq!(disk.0.borrow().flushes, 1);
+        assert_eq!(disk.sectors(5, 3), data);
+        assert!(!cache.has_dirty_sectors());
+
+        // Nothing is written twice
+        cache.flush().unwrap();
+        assert!(disk.take_writes().is_empty());
+    }
+
+    #[test]
+    fn test_flush_coalesces_writes() {
+        let (mut cache, disk) = cache_with(512, 0);
+        cache.write(0, &pattern(2, 1)).unwrap();
+        cache.write(2, &pattern(2, 2)).unwrap();
+        cache.write(10, &pattern(1, 3)).unwrap();
+        // Larger than a single request
+        cache.write(100, &pattern(MAX, 4)).unwrap();
+        cache.write(100 + MAX, &pattern(72, 5)).unwrap();
+        // Rewriting a dirty sector doesn't add another write
+        cache.write(1, &pattern(1, 6)).unwrap();
+
+        cache.flush().unwrap();
+        assert_eq!(
+            disk.take_writes(),
+            vec![(0, 4), (10, 1), (100, MAX), (100 + MAX, 72)]
+        );
+        assert_eq!(disk.sectors(1, 1), pattern(1, 6));
+        assert_eq!(disk.sectors(100 + MAX, 72), pattern(72, 5));
+        assert_eq!(cache.stats().sectors_written, 4 + 1 + MAX + 72 + 1);
+    }
+
+    #[test]
+    fn test_eviction() {
+        let capacity = 2 * MAX as usize;
+        let (mut cache, disk) = cache_with(capacity, 0);
+        let data = pattern(4, 7);
+        cache.write(0, &data).unwrap();
+        cache.read(500, 1).unwrap();
+
+        // Fill the cache, which evicts the least recently used sectors first
+        cache.read(1000, MAX).unwrap();
+        cache.read(1200, MAX - 5).unwrap();
+        assert!(disk.take_writes().is_empty());
+        cache.read(1400, 1).unwrap();
+        // Sector 0 was evicted, and its dirty neighbours were written back with it
+        assert_eq!(disk.take_writes(), vec![(0, 4)]);
+        assert_eq!(disk.sectors(0, 4), data);
+
+        let stats = cache.stats();
+        assert_eq!(stats.evictions, 1);
+        assert_eq!(stats.cached_sectors, capacity as u64);
+        assert_eq!(stats.dirty_sectors, 0);
+
+        // Evicted sectors are read from the disk again
+        disk.take_reads();
+        assert_eq!(cache.read(0, 4), Ok(data));
+        assert_eq!(disk.take_reads(), vec![(0, 1)]);
+    }
+
+    #[test]
+    fn test_failed_write_back_stays_dirty() {
+        let (mut cache, disk) = cache_with(512, 0);
+        cache.write(20, &pattern(2, 8)).unwrap();
+        disk.0.borrow_mut().fail_writes = true;
+        assert_eq!(cache.flush(), Err(BlockDeviceStatus::IoError));
+        assert_eq!(cache.stats().dirty_sectors, 2);
+        assert_eq!(disk.0.borrow().flushes, 0);
+
+        disk.0.borrow_mut().fail_writes = false;
+        cache.flush().unwrap();
+        assert_eq!(disk.sectors(20, 2), pattern(2, 8));
+        assert_eq!(
+            cache.stats(),
+            BlockCacheStats {
+                capacity_sectors: 512,
+                cached_sectors: 2,
+                sectors_written: 2,
+                write_backs: 2,
+                flushes: 2,
+                ..Default::default()
+            }
+        );
+    }
+}
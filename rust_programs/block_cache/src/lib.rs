@@ -0,0 +1,12 @@
+#![no_std]
+
+//! The caching logic and request handling behind the block cache service. The disk is reached
+//! through the `BlockBackend` trait, so the cache can be tested on the host against an in-memory
+//! disk, and the request handling can be run against the simulated AMC.
+
+extern crate alloc;
+
+pub mod cache;
+// Serving requests needs AMC, which is simulated when testing on the host
+#[cfg(any(target_os = "axle", test))]
+pub mod service;
@@ -0,0 +1,90 @@
+#![no_std]
+#![feature(start)]
+#![feature(default_alloc_error_handler)]
+
+extern crate alloc;
+
+use alloc::{rc::Rc, vec::Vec};
+use core::cell::RefCell;
+
+use axle_rt::{amc_register_service, executor::Executor, printf};
+use block_cache::cache::{BlockBackend, BlockCache, CacheConfig};
+use block_cache::service::handle_message;
+use block_cache_messages::BLOCK_CACHE_SERVICE_NAME;
+use sata_driver_messages::{BlockDeviceStatus, DeviceInfo, RemoteBlockDevice};
+
+/// 2MiB of sectors
+const CACHE_CONFIG: CacheConfig = CacheConfig {
+    capacity_sectors: 4096,
+    read_ahead_sectors: 64,
+};
+/// How often dirty sectors are written back to the disk
+const FLUSH_INTERVAL_MS: u32 = 5000;
+
+/// Reaches the disk through the SATA driver
+struct SataDisk(RemoteBlockDevice);
+
+impl BlockBackend for SataDisk {
+    fn device_info(&mut self) -> Result<DeviceInfo, BlockDeviceStatus> {
+        self.0.device_info()
+    }
+
+    fn read_sectors(
+        &mut self,
+        start_sector: u64,
+        sector_count: u64,
+    ) -> Result<Vec<u8>, BlockDeviceStatus> {
+        self.0.read_sectors(start_sector, sector_count)
+    }
+
+    fn write_sectors(&mut self, start_sector: u64, data: &[u8]) -> Result<(), BlockDeviceStatus> {
+        self.0.write_sectors(start_sector, data)
+    }
+
+    fn flush(&mut self) -> Result<(), BlockDeviceStatus> {
+        self.0.flush()
+    }
+}
+
+#[start]
+#[allow(unreachable_code)]
+fn start(_argc: isize, _argv: *const *const u8) -> isize {
+    amc_register_service(BLOCK_CACHE_SERVICE_NAME);
+
+    let cache = match BlockCache::new(SataDisk(RemoteBlockDevice::sata_disk()), CACHE_CONFIG) {
+        Ok(cache) => cache,
+        Err(e) => {
+            printf!("Failed to query the disk: {e:?}\n");
+            return 1;
+        }
+    };
+    let cache = Rc::new(RefCell::new(cache));
+    let mut executor = Executor::new();
+
+    let events = executor.events();
+    let message_cache = Rc::clone(&cache);
+    executor.spawn(async move {
+        loop {
+            let msg_unparsed = events.next_message(None).await;
+            handle_message(&mut message_cache.borrow_mut(), msg_unparsed);
+        }
+    });
+
+    // Bound how much written data is lost if the system goes down
+    let events = executor.events();
+    executor.spawn(async move {
+        loop {
+            events.sleep(FLUSH_INTERVAL_MS).await;
+            let mut cache = cache.borrow_mut();
+            if cache.has_dirty_sectors() {
+                if let Err(e) = cache.flush() {
+                    printf!("Periodic flush failed: {e:?}\n");
+                }
+            }
+        }
+    });
+
+    executor.run();
+
+    0
+}
@@ -0,0 +1,361 @@
+//! Serves the block device protocol from a `BlockCache`. Requests reach the cache over AMC, so
+//! the service can be run against the simulated AMC and an in-memory disk on the host.
+
+use core::mem;
+
+use axle_rt::{amc_message_send, printf, rpc::OwnedAmcMessage, ExpectsEventField};
+use block_cache_messages::{CacheStats, GetCacheStats};
+use sata_driver_messages::{
+    BlockDeviceStatus, DeviceInfo, Flush, FlushResponse, GetDeviceInfo, ReadSectors,
+    ReadSectorsResponse, WriteSectors, WriteSectorsResponse, SATA_SECTOR_SIZE, WHOLE_DISK_DEVICE,
+};
+
+use crate::cache::{BlockBackend, BlockCache};
+
+fn get_device_info<B: BlockBackend>(cache: &BlockCache<B>, sender: &str, request: &GetDeviceInfo) {
+    let response = match request.device {
+        WHOLE_DISK_DEVICE => {
+            let disk_info = cache.device_info();
+            DeviceInfo::new(
+                request.request_id,
+                BlockDeviceStatus::Success,
+                disk_info.sector_count,
+                disk_info.model(),
+                disk_info.serial_number(),
+            )
+        }
+        _ => DeviceInfo::new(
+            request.request_id,
+            BlockDeviceStatus::NoSuchDevice,
+            0,
+            "",
+            "",
+        ),
+    };
+    amc_message_send(sender, response);
+}
+
+fn read_sectors<B: BlockBackend>(cache: &mut BlockCache<B>, sender: &str, request: &ReadSectors) {
+    let result = match request.device {
+        WHOLE_DISK_DEVICE => cache.read(request.start_sector, request.sector_count),
+        _ => Err(BlockDeviceStatus::NoSuchDevice),
+    };
+    match result {
+        Ok(data) => {
+            ReadSectorsResponse::send(sender, request.request_id, request.start_sector, &data)
+        }
+        Err(status) => ReadSectorsResponse::send_error(
+            sender,
+            request.request_id,
+            request.start_sector,
+            request.sector_count,
+            status,
+        ),
+    }
+}
+
+/// `message_len` is the size of the delivered message, including the trailing data
+fn write_sectors<B: BlockBackend>(
+    cache: &mut BlockCache<B>,
+    sender: &str,
+    request: &WriteSectors,
+    message_len: usize,
+) {
+    let expected_len = (request.sector_count as usize).checked_mul(SATA_SECTOR_SIZE);
+    let result = if request.device != WHOLE_DISK_DEVICE {
+        Err(BlockDeviceStatus::NoSuchDevice)
+    } else {
+        match unsafe { request.data(message_len) } {
+            Some(data) if Some(data.len()) == expected_len => {
+                cache.write(request.start_sector, data)
+            }
+            _ => Err(BlockDeviceStatus::InvalidRequest),
+        }
+    };
+    WriteSectorsResponse::send(
+        sender,
+        request.request_id,
+        request.start_sector,
+        request.sector_count,
+        result.err().unwrap_or(BlockDeviceStatus::Success),
+    );
+}
+
+fn flush<B: BlockBackend>(cache: &mut BlockCache<B>, sender: &str, request: &Flush) {
+    let result = match request.device {
+        WHOLE_DISK_DEVICE => cache.flush(),
+        _ => Err(BlockDeviceStatus::NoSuchDevice),
+    };
+    amc_message_send(
+        sender,
+        FlushResponse::new(
+            request.request_id,
+            result.err().unwrap_or(BlockDeviceStatus::Success),
+        ),
+    );
+}
+
+fn get_cache_stats<B: BlockBackend>(cache: &BlockCache<B>, sender: &str, request: &GetCacheStats) {
+    amc_message_send(sender, CacheStats::new(request.request_id, cache.stats()));
+}
+
+unsafe fn body_as_type_unchecked<T>(body: &[u8]) -> &T {
+    &*(body.as_ptr() as *const T)
+}
+
+/// Rejects a request whose body is too short to hold it. The request ID is echoed if the body
+/// is long enough to hold one.
+fn reply_truncated_request(sender: &str, event: u32, raw_body: &[u8]) {
+    let request_id = raw_body
+        .get(mem::size_of::<u32>()..mem::size_of::<u32>() * 2)
+        .map_or(0, |request_id| {
+            u32::from_ne_bytes(request_id.try_into().unwrap())
+        });
+    let status = BlockDeviceStatus::InvalidRequest;
+    match event {
+        GetDeviceInfo::EXPECTED_EVENT => {
+            amc_message_send(sender, DeviceInfo::new(request_id, status, 0, "", ""))
+        }
+        ReadSectors::EXPECTED_EVENT => {
+            ReadSectorsResponse::send_error(sender, request_id, 0, 0, status)
+        }
+        WriteSectors::EXPECTED_EVENT => {
+            WriteSectorsResponse::send(sender, request_id, 0, 0, status)
+        }
+        Flush::EXPECTED_EVENT => amc_message_send(sender, FlushResponse::new(request_id, status)),
+        // Cache stats have no status to report an error with
+        _ => (),
+    }
+}
+
+/// Parses the first bytes of the message as a u32 event field, and checks that the body is long
+/// enough to be cast to the request that the event names. Requests that are too short are
+/// rejected here, and None is returned.
+fn request_event(msg_unparsed: &OwnedAmcMessage) -> Option<u32> {
+    let raw_body = &msg_unparsed.body;
+    let event = match raw_body.get(..mem::size_of::<u32>()) {
+        Some(event) => u32::from_ne_bytes(event.try_into().unwrap()),
+        None => {
+            printf!(
+                "Dropping message from {} that's too short to hold an event\n",
+                msg_unparsed.source
+            );
+            return None;
+        }
+    };
+    let request_len = match event {
+        GetDeviceInfo::EXPECTED_EVENT => mem::size_of::<GetDeviceInfo>(),
+        ReadSectors::EXPECTED_EVENT => mem::size_of::<ReadSectors>(),
+        WriteSectors::EXPECTED_EVENT => mem::size_of::<WriteSectors>(),
+        Flush::EXPECTED_EVENT => mem::size_of::<Flush>(),
+        GetCacheStats::EXPECTED_EVENT => mem::size_of::<GetCacheStats>(),
+        // Left for the caller to report
+        _ => return Some(event),
+    };
+    if raw_body.len() < request_len {
+        printf!(
+            "Rejecting truncated request {event} from {}\n",
+            msg_unparsed.source
+        );
+        reply_truncated_request(&msg_unparsed.source, event, raw_body);
+        return None;
+    }
+    Some(event)
+}
+
+pub fn handle_message<B: BlockBackend>(cache: &mut BlockCache<B>, msg_unparsed: OwnedAmcMessage) {
+    let event = match request_event(&msg_unparsed) {
+        Some(event) => event,
+        None => return,
+    };
+    let raw_body = &msg_unparsed.body;
+    let sender = &msg_unparsed.source;
+
+    // Each inner call to body_as_type_unchecked is unsafe because we must be
+    // sure we're casting to the right type.
+    // Since we verify the type on the LHS, and request_event checked that the body is long
+    // enough to hold it, each usage is safe.
+    unsafe {
+        match event {
+            GetDeviceInfo::EXPECTED_EVENT => {
+                get_device_info(cache, sender, body_as_type_unchecked(raw_body))
+            }
+            ReadSectors::EXPECTED_EVENT => {
+                read_sectors(cache, sender, body_as_type_unchecked(raw_body))
+            }
+            WriteSectors::EXPECTED_EVENT => write_sectors(
+                cache,
+                sender,
+                body_as_type_unchecked(raw_body),
+                raw_body.len(),
+            ),
+            Flush::EXPECTED_EVENT => flush(cache, sender, body_as_type_unchecked(raw_body)),
+            GetCacheStats::EXPECTED_EVENT => {
+                get_cache_stats(cache, sender, body_as_type_unchecked(raw_body))
+            }
+            _ => printf!("Unknown event from {sender}: {event}\n"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use alloc::{string::ToString, vec, vec::Vec};
+    use core::mem;
+
+    use axle_rt::amc_sim::AmcSimulator;
+    use axle_rt::core_commands::{AmcRegisterServiceDiedNotif, AMC_CORE_SERVICE_NAME};
+    use axle_rt::rpc::OwnedAmcMessage;
+    use axle_rt::{
+        amc_message_await__u32_event_untyped, amc_message_await_untyped, amc_message_send_untyped,
+        amc_register_service, ExpectsEventField,
+    };
+    use block_cache_messages::{BlockCacheStats, BLOCK_CACHE_SERVICE_NAME};
+    use sata_driver_messages::{
+        BlockDeviceStatus, DeviceInfo, ReadSectors, ReadSectorsResponse, RemoteBlockDevice,
+        SATA_SECTOR_SIZE, WHOLE_DISK_DEVICE,
+    };
+
+    use crate::cache::{BlockBackend, BlockCache, CacheConfig};
+    use crate::service::handle_message;
+
+    const TEST_SERVICE: &str = "com.axle.test";
+    const SECTOR_COUNT: u64 = 1024;
+
+    /// Each byte of a sector initially holds the sector's number
+    struct MemoryDisk(Vec<u8>);
+
+    impl BlockBackend for MemoryDisk {
+        fn device_info(&mut self) -> Result<DeviceInfo, BlockDeviceStatus> {
+            Ok(DeviceInfo::new(
+                0,
+                BlockDeviceStatus::Success,
+                SECTOR_COUNT,
+                "memory",
+                "MEM-0001",
+            ))
+        }
+
+        fn read_sectors(
+            &mut self,
+            start_sector: u64,
+            sector_count: u64,
+        ) -> Result<Vec<u8>, BlockDeviceStatus> {
+            let start = start_sector as usize * SATA_SECTOR_SIZE;
+            let end = start + sector_count as usize * SATA_SECTOR_SIZE;
+            Ok(self.0[start..end].to_vec())
+        }
+
+        fn write_sectors(
+            &mut self,
+            start_sector: u64,
+            data: &[u8],
+        ) -> Result<(), BlockDeviceStatus> {
+            let start = start_sector as usize * SATA_SECTOR_SIZE;
+            self.0[start..start + data.len()].copy_from_slice(data);
+            Ok(())
+        }
+
+        fn flush(&mut self) -> Result<(), BlockDeviceStatus> {
+            Ok(())
+        }
+    }
+
+    /// Serves requests just as the block cache does on axle, until the test's process exits.
+    /// Returns the cache's stats at that point.
+    fn block_cache_service() -> BlockCacheStats {
+        amc_register_service(BLOCK_CACHE_SERVICE_NAME);
+        AmcRegisterServiceDiedNotif::send(TEST_SERVICE);
+        let disk = MemoryDisk(
+            (0..SECTOR_COUNT as usize * SATA_SECTOR_SIZE)
+                .map(|i| (i / SATA_SECTOR_SIZE) as u8)
+                .collect(),
+        );
+        let config = CacheConfig {
+            capacity_sectors: 512,
+            read_ahead_sectors: 16,
+        };
+        let mut cache = BlockCache::new(disk, config).unwrap();
+        loop {
+            let msg = unsafe { amc_message_await_untyped(None).unwrap() };
+            if msg.source() == AMC_CORE_SERVICE_NAME {
+                return cache.stats();
+            }
+            handle_message(
+                &mut cache,
+                OwnedAmcMessage {
+                    source: msg.source().to_string(),
+                    body: msg.body().to_vec(),
+                },
+            );
+        }
+    }
+
+    #[test]
+    fn test_serve_requests() {
+        let sim = AmcSimulator::new();
+        let service = sim.run(|| {
+            amc_register_service(TEST_SERVICE);
+            let service = sim.spawn(block_cache_service);
+            let disk = RemoteBlockDevice::new(BLOCK_CACHE_SERVICE_NAME, WHOLE_DISK_DEVICE);
+
+            let info = disk.device_info().unwrap();
+            assert_eq!(info.sector_count, SECTOR_COUNT);
+            assert_eq!(info.model(), "memory");
+
+            // Reads are served from the disk behind the cache
+            let expected: Vec<u8> = (10..13)
+                .flat_map(|sector| vec![sector as u8; SATA_SECTOR_SIZE])
+                .collect();
+            assert_eq!(disk.read_sectors(10, 3), Ok(expected));
+
+            // Writes are seen by later reads, and written back when the client flushes
+            let data = vec![0xaa; 4 * SATA_SECTOR_SIZE];
+            disk.write_sectors(100, &data).unwrap();
+            assert_eq!(disk.read_sectors(100, 4), Ok(data));
+            disk.flush().unwrap();
+
+            // Requests the cache can't serve are rejected with a status
+            assert_eq!(
+                disk.read_sectors(SECTOR_COUNT, 1),
+                Err(BlockDeviceStatus::OutOfRange)
+            );
+            assert_eq!(
+                RemoteBlockDevice::new(BLOCK_CACHE_SERVICE_NAME, 1)
+                    .device_info()
+                    .err(),
+                Some(BlockDeviceStatus::NoSuchDevice)
+            );
+
+            // A truncated request is rejected, rather than read past
+            let mut truncated = ReadSectors::EXPECTED_EVENT.to_ne_bytes().to_vec();
+            truncated.extend_from_slice(&77_u32.to_ne_bytes());
+            unsafe {
+                amc_message_send_untyped(
+                    BLOCK_CACHE_SERVICE_NAME,
+                    truncated.as_ptr(),
+                    truncated.len(),
+                )
+            };
+            let msg = unsafe {
+                amc_message_await__u32_event_untyped(
+                    BLOCK_CACHE_SERVICE_NAME,
+                    ReadSectorsResponse::EXPECTED_EVENT,
+                )
+                .unwrap()
+            };
+            assert!(msg.body().len() >= mem::size_of::<ReadSectorsResponse>());
+            let response = unsafe { &*(msg.body().as_ptr() as *const ReadSectorsResponse) };
+            assert_eq!(response.request_id, 77);
+            assert_eq!(response.status, BlockDeviceStatus::InvalidRequest);
+
+            service
+        });
+
+        // The service exits once the test's process does
+        let stats = service.join().unwrap();
+        assert_eq!(stats.sectors_written, 4);
+        assert_eq!(stats.dirty_sectors, 0);
+        assert_eq!(stats.flushes, 1);
+    }
+}
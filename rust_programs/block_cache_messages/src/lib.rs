@@ -0,0 +1,108 @@
+#![no_std]
+
+//! The block cache sits between filesystem servers and the SATA driver. It serves the block
+//! device protocol from `sata_driver_messages` for the whole disk (`WHOLE_DISK_DEVICE`), and
+//! keeps recently used sectors in memory.
+//!
+//! Writes are write-back: a `WriteSectorsResponse` reports that the cache has taken the data,
+//! and the data reaches the disk when it's evicted, when the cache periodically flushes, or when
+//! a client sends a `Flush`. Errors from writing back are therefore reported by a later `Flush`
+//! (or by the request that needed the space), rather than by the write itself.
+
+#[cfg(target_os = "axle")]
+mod conditional_imports {
+    pub use axle_rt::{amc_message_await__u32_event, amc_message_send, AmcMessage};
+    pub use sata_driver_messages::{RemoteBlockDevice, WHOLE_DISK_DEVICE};
+}
+#[cfg(not(target_os = "axle"))]
+mod conditional_imports {}
+
+use crate::conditional_imports::*;
+
+use axle_rt::{ContainsEventField, ExpectsEventField};
+use axle_rt_derive::ContainsEventField;
+
+pub const BLOCK_CACHE_SERVICE_NAME: &'static str = "com.axle.block_cache";
+
+/// Counters describing how well the cache is doing. Sector counts are in units of
+/// `SATA_SECTOR_SIZE`.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct BlockCacheStats {
+    /// How many sectors the cache holds before it starts evicting
+    pub capacity_sectors: u64,
+    pub cached_sectors: u64,
+    /// Sectors that have been written by a client, but not yet written back to the disk
+    pub dirty_sectors: u64,
+    /// Requested sectors that were served from the cache
+    pub read_hits: u64,
+    /// Requested sectors that had to be read from the disk
+    pub read_misses: u64,
+    /// Sectors read from the disk ahead of a sequential reader
+    pub read_ahead_sectors: u64,
+    /// Sectors read ahead that were later requested
+    pub read_ahead_hits: u64,
+    /// Sectors written by clients
+    pub sectors_written: u64,
+    /// Write requests sent to the disk to write back dirty sectors
+    pub write_backs: u64,
+    pub evictions: u64,
+    /// Flushes of the whole cache, whether requested by a client or periodic
+    pub flushes: u64,
+}
+
+#[repr(C)]
+#[derive(Debug, ContainsEventField)]
+pub struct GetCacheStats {
+    event: u32,
+    pub request_id: u32,
+}
+
+impl GetCacheStats {
+    pub fn new(request_id: u32) -> Self {
+        Self {
+            event: Self::EXPECTED_EVENT,
+            request_id,
+        }
+    }
+}
+
+impl ExpectsEventField for GetCacheStats {
+    const EXPECTED_EVENT: u32 = 205;
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, ContainsEventField)]
+pub struct CacheStats {
+    event: u32,
+    pub request_id: u32,
+    pub stats: BlockCacheStats,
+}
+
+impl CacheStats {
+    pub fn new(request_id: u32, stats: BlockCacheStats) -> Self {
+        Self {
+            event: Self::EXPECTED_EVENT,
+            request_id,
+            stats,
+        }
+    }
+}
+
+impl ExpectsEventField for CacheStats {
+    const EXPECTED_EVENT: u32 = 205;
+}
+
+/// The whole disk, accessed through the block cache
+#[cfg(target_os = "axle")]
+pub const fn cached_disk() -> RemoteBlockDevice {
+    RemoteBlockDevice::new(BLOCK_CACHE_SERVICE_NAME, WHOLE_DISK_DEVICE)
+}
+
+/// Blocks until the block cache has responded
+#[cfg(target_os = "axle")]
+pub fn cache_stats() -> BlockCacheStats {
+    amc_message_send(BLOCK_CACHE_SERVICE_NAME, GetCacheStats::new(0));
+    let response: AmcMessage<CacheStats> = amc_message_await__u32_event(BLOCK_CACHE_SERVICE_NAME);
+    response.body().stats
+}
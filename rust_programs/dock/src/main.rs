@@ -22,7 +22,6 @@ use libgui::view::View;
 use libgui::AwmWindow;
 
 use axle_rt::{amc_message_await, amc_message_send, amc_register_service, printf, AmcMessage};
-use axle_rt::{ContainsEventField, ExpectsEventField};
 
 use agx_definitions::{
     Color, Drawable, LayerSlice, LikeLayerSlice, Line, NestedLayerSlice, Point, Rect, RectInsets,
@@ -30,7 +29,7 @@ use agx_definitions::{
 };
 
 use dock_messages::{
-    AwmDockEvent, AwmDockTaskViewClicked, AwmDockTaskViewHoverExited, AwmDockTaskViewHovered,
+    AwmDockProtocol, AwmDockTaskViewClicked, AwmDockTaskViewHoverExited, AwmDockTaskViewHovered,
     AwmDockWindowClosed, AwmDockWindowCreatedEvent, AwmDockWindowMinimizeRequestedEvent,
     AwmDockWindowMinimizeWithInfo, AwmDockWindowTitleUpdatedEvent, AWM_DOCK_HEIGHT,
     AWM_DOCK_SERVICE_NAME,
@@ -464,10 +463,6 @@ impl Dock {
         })
     }
 
-    pub unsafe fn body_as_type_unchecked<T: AwmDockEvent>(body: &[u8]) -> &T {
-        &*(body.as_ptr() as *const T)
-    }
-
     pub fn handle_window_created(self: Rc<Self>, event: &AwmDockWindowCreatedEvent) {
         let title_with_null_bytes = core::str::from_utf8(&(*event).title).unwrap();
         let title_without_null_bytes = title_with_null_bytes.trim_matches(char::from(0));
@@ -650,41 +645,28 @@ fn start(_argc: isize, _argv: *const *const u8) -> isize {
     window.add_message_handler(move |_window, msg_unparsed: AmcMessage<[u8]>| {
         printf!("Dock got message from {}!\n", msg_unparsed.source());
         if (msg_unparsed.source() == AwmWindow::AWM_SERVICE_NAME) {
-            let raw_body = msg_unparsed.body();
-            let event = u32::from_ne_bytes(
-                // We must slice the array to the exact size of a u32 for the conversion to succeed
-                raw_body[..core::mem::size_of::<u32>()]
-                    .try_into()
-                    .expect("Failed to get 4-length array from message body"),
-            );
-
-            let consumed = unsafe {
-                match event {
-                    AwmDockWindowCreatedEvent::EXPECTED_EVENT => {
-                        Rc::clone(&dock.borrow())
-                            .handle_window_created(Dock::body_as_type_unchecked(raw_body));
-                        true
-                    }
-                    AwmDockWindowTitleUpdatedEvent::EXPECTED_EVENT => {
-                        printf!("Received dock window created event!\n");
-                        Rc::clone(&dock.borrow())
-                            .handle_window_title_updated(Dock::body_as_type_unchecked(raw_body));
-                        true
-                    }
-                    AwmDockWindowMinimizeRequestedEvent::EXPECTED_EVENT => {
-                        printf!("Received window minimize request!\n");
-                        Rc::clone(&dock.borrow())
-                            .handle_window_minimize_request(Dock::body_as_type_unchecked(raw_body));
-                        true
-                    }
-                    AwmDockWindowClosed::EXPECTED_EVENT => {
-                        printf!("Received window closed event!\n");
-                        Rc::clone(&dock.borrow())
-                            .handle_window_closed(Dock::body_as_type_unchecked(raw_body));
-                        true
-                    }
-                    _ => false,
+            // The message came from awm, so it's safe to parse it as part of awm's protocol
+            let consumed = match unsafe { AwmDockProtocol::parse(msg_unparsed.body()) } {
+                Some(AwmDockProtocol::WindowCreated(event)) => {
+                    Rc::clone(&dock.borrow()).handle_window_created(event);
+                    true
+                }
+                Some(AwmDockProtocol::WindowTitleUpdated(event)) => {
+                    printf!("Received dock window created event!\n");
+                    Rc::clone(&dock.borrow()).handle_window_title_updated(event);
+                    true
+                }
+                Some(AwmDockProtocol::WindowMinimizeRequested(event)) => {
+                    printf!("Received window minimize request!\n");
+                    Rc::clone(&dock.borrow()).handle_window_minimize_request(event);
+                    true
+                }
+                Some(AwmDockProtocol::WindowClosed(event)) => {
+                    printf!("Received window closed event!\n");
+                    Rc::clone(&dock.borrow()).handle_window_closed(event);
+                    true
                 }
+                None => false,
             };
             if consumed {
                 return;
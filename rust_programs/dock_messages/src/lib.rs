@@ -4,7 +4,7 @@ extern crate alloc;
 
 use agx_definitions::{Rect, RectU32};
 use axle_rt::{copy_str_into_sized_slice, ContainsEventField, ExpectsEventField};
-use axle_rt_derive::ContainsEventField;
+use axle_rt_derive::{AmcProtocol, ContainsEventField};
 
 // PT: Must match the definitions in the corresponding C header
 
@@ -224,3 +224,43 @@ impl ExpectsEventField for AwmDockWindowClosed {
 }
 
 impl AwmDockEvent for AwmDockWindowClosed {}
+
+/// The messages that awm sends to the dock
+#[derive(Debug, AmcProtocol)]
+pub enum AwmDockProtocol<'a> {
+    WindowCreated(&'a AwmDockWindowCreatedEvent),
+    WindowTitleUpdated(&'a AwmDockWindowTitleUpdatedEvent),
+    WindowMinimizeRequested(&'a AwmDockWindowMinimizeRequestedEvent),
+    WindowClosed(&'a AwmDockWindowClosed),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use core::mem::size_of;
+
+    fn message_bytes<T>(message: &T) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(message as *const T as *const u8, size_of::<T>()) }
+    }
+
+    #[test]
+    fn test_parse_protocol() {
+        let created = AwmDockWindowCreatedEvent::new(3, "Window");
+        match unsafe { AwmDockProtocol::parse(message_bytes(&created)) } {
+            Some(AwmDockProtocol::WindowCreated(event)) => {
+                assert_eq!(event.window_id, 3);
+                assert_eq!(&event.title[..7], b"Window\0");
+            }
+            other => panic!("Parsed the wrong message: {other:?}"),
+        }
+
+        let closed = AwmDockWindowClosed::new(4);
+        let parsed = unsafe { AwmDockProtocol::parse(message_bytes(&closed)) }.unwrap();
+        assert_eq!(parsed.event(), AwmDockWindowClosed::EXPECTED_EVENT);
+
+        // Messages from outside the protocol, and truncated messages, aren't parsed
+        let clicked = AwmDockTaskViewClicked::new(4);
+        assert!(unsafe { AwmDockProtocol::parse(message_bytes(&clicked)) }.is_none());
+        assert!(unsafe { AwmDockProtocol::parse(&message_bytes(&created)[..8]) }.is_none());
+    }
+}
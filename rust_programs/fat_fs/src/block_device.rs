@@ -0,0 +1,105 @@
+use alloc::vec;
+
+pub const SECTOR_SIZE: usize = 512;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BlockDeviceError {
+    OutOfRange,
+    Io,
+}
+
+/// A device that can be read and written in whole sectors
+pub trait BlockDevice {
+    /// Fills `buf` with the sectors starting at `start_sector`. `buf` must be sector-aligned.
+    fn read_sectors(&mut self, start_sector: u64, buf: &mut [u8]) -> Result<(), BlockDeviceError>;
+
+    /// Writes `buf` to the sectors starting at `start_sector`. `buf` must be sector-aligned.
+    fn write_sectors(&mut self, start_sector: u64, buf: &[u8]) -> Result<(), BlockDeviceError>;
+
+    fn zero_sectors(
+        &mut self,
+        start_sector: u64,
+        sector_count: u64,
+    ) -> Result<(), BlockDeviceError> {
+        // Write in batches to avoid allocating a buffer the size of the whole range
+        let batch_size = 64;
+        let zeroes = vec![0; SECTOR_SIZE * batch_size];
+        let mut sector = start_sector;
+        let end_sector = start_sector + sector_count;
+        while sector < end_sector {
+            let sectors_in_batch = core::cmp::min(batch_size as u64, end_sector - sector);
+            self.write_sectors(sector, &zeroes[..(sectors_in_batch as usize) * SECTOR_SIZE])?;
+            sector += sectors_in_batch;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "use_std")]
+pub use file_block_device::FileBlockDevice;
+
+#[cfg(feature = "use_std")]
+mod file_block_device {
+    extern crate std;
+    use super::{BlockDevice, BlockDeviceError, SECTOR_SIZE};
+    use std::fs::{File, OpenOptions};
+    use std::io::{self, Read, Seek, SeekFrom, Write};
+    use std::path::Path;
+
+    /// Exposes a disk image file on the host as a block device
+    pub struct FileBlockDevice {
+        file: File,
+        sector_count: u64,
+    }
+
+    impl FileBlockDevice {
+        pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+            let file = OpenOptions::new().read(true).write(true).open(path)?;
+            let sector_count = file.metadata()?.len() / (SECTOR_SIZE as u64);
+            Ok(Self { file, sector_count })
+        }
+
+        /// Creates (or truncates) a zero-filled disk image of the provided size
+        pub fn create<P: AsRef<Path>>(path: P, sector_count: u64) -> io::Result<Self> {
+            let file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(path)?;
+            file.set_len(sector_count * (SECTOR_SIZE as u64))?;
+            Ok(Self { file, sector_count })
+        }
+
+        fn check_range(&self, start_sector: u64, len: usize) -> Result<(), BlockDeviceError> {
+            assert!(len % SECTOR_SIZE == 0, "Buffer is not sector-aligned");
+            let sector_count = (len / SECTOR_SIZE) as u64;
+            if start_sector + sector_count > self.sector_count {
+                return Err(BlockDeviceError::OutOfRange);
+            }
+            Ok(())
+        }
+    }
+
+    impl BlockDevice for FileBlockDevice {
+        fn read_sectors(
+            &mut self,
+            start_sector: u64,
+            buf: &mut [u8],
+        ) -> Result<(), BlockDeviceError> {
+            self.check_range(start_sector, buf.len())?;
+            self.file
+                .seek(SeekFrom::Start(start_sector * (SECTOR_SIZE as u64)))
+                .and_then(|_| self.file.read_exact(buf))
+                .map_err(|_| BlockDeviceError::Io)
+        }
+
+        fn write_sectors(&mut self, start_sector: u64, buf: &[u8]) -> Result<(), BlockDeviceError> {
+            self.check_range(start_sector, buf.len())?;
+            self.file
+                .seek(SeekFrom::Start(start_sector * (SECTOR_SIZE as u64)))
+                .and_then(|_| self.file.write_all(buf))
+                .map_err(|_| BlockDeviceError::Io)
+        }
+    }
+}
@@ -0,0 +1,1419 @@
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cmp::min;
+
+use crate::block_device::{BlockDevice, BlockDeviceError, SECTOR_SIZE};
+
+// Ref: Microsoft Extensible Firmware Initiative FAT32 File System Specification, v1.03
+
+const DIR_ENTRY_SIZE: usize = 32;
+
+const ATTR_READ_ONLY: u8 = 0x01;
+const ATTR_HIDDEN: u8 = 0x02;
+const ATTR_SYSTEM: u8 = 0x04;
+const ATTR_VOLUME_ID: u8 = 0x08;
+const ATTR_DIRECTORY: u8 = 0x10;
+const ATTR_ARCHIVE: u8 = 0x20;
+const ATTR_LONG_NAME: u8 = ATTR_READ_ONLY | ATTR_HIDDEN | ATTR_SYSTEM | ATTR_VOLUME_ID;
+
+// The first byte of a directory entry's name doubles as a marker
+const DIR_ENTRY_END_MARKER: u8 = 0x00;
+const DIR_ENTRY_FREE_MARKER: u8 = 0xE5;
+const LONG_NAME_LAST_ENTRY_FLAG: u8 = 0x40;
+const LONG_NAME_CHARS_PER_ENTRY: usize = 13;
+const MAX_LONG_NAME_LEN: usize = 255;
+
+const FAT_ENTRY_MASK: u32 = 0x0FFF_FFFF;
+const FAT_FREE_CLUSTER: u32 = 0;
+// Any FAT entry at or above this value marks the end of a cluster chain
+const FAT_END_OF_CHAIN_MIN: u32 = 0x0FFF_FFF8;
+const FAT_END_OF_CHAIN: u32 = 0x0FFF_FFFF;
+const FIRST_DATA_CLUSTER: u32 = 2;
+// Volumes with fewer clusters are FAT12 or FAT16, by definition
+const MIN_FAT32_CLUSTER_COUNT: u32 = 65525;
+// File sizes are stored in 32 bits
+const MAX_FILE_SIZE: usize = u32::MAX as usize;
+
+// We don't have a wall clock, so stamp every entry with 1980-01-01 00:00:00
+const DEFAULT_DATE: u16 = (1 << 5) | 1;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FatError {
+    NotFound,
+    NotADirectory,
+    IsADirectory,
+    AlreadyExists,
+    DirectoryNotEmpty,
+    InvalidName,
+    NoSpace,
+    OutOfRange,
+    InvalidFilesystem,
+    Io,
+}
+
+impl From<BlockDeviceError> for FatError {
+    fn from(_: BlockDeviceError) -> Self {
+        FatError::Io
+    }
+}
+
+#[derive(Debug, Clone)]
+struct BiosParameterBlock {
+    sectors_per_cluster: u32,
+    reserved_sector_count: u32,
+    fat_count: u32,
+    sectors_per_fat: u32,
+    total_sectors: u32,
+    root_cluster: u32,
+}
+
+impl BiosParameterBlock {
+    fn parse(boot_sector: &[u8]) -> Result<Self, FatError> {
+        if boot_sector[510] != 0x55 || boot_sector[511] != 0xAA {
+            return Err(FatError::InvalidFilesystem);
+        }
+        let bytes_per_sector = read_u16(boot_sector, 11) as usize;
+        let sectors_per_cluster = boot_sector[13] as u32;
+        let reserved_sector_count = read_u16(boot_sector, 14) as u32;
+        let fat_count = boot_sector[16] as u32;
+        let root_entry_count = read_u16(boot_sector, 17);
+        let total_sectors_16 = read_u16(boot_sector, 19) as u32;
+        let sectors_per_fat_16 = read_u16(boot_sector, 22);
+        let total_sectors_32 = read_u32(boot_sector, 32);
+        let sectors_per_fat = read_u32(boot_sector, 36);
+        let root_cluster = read_u32(boot_sector, 44);
+
+        // Only FAT32 volumes are supported: these have no fixed root directory region
+        // and always store the FAT size in the 32-bit field
+        if bytes_per_sector != SECTOR_SIZE
+            || !sectors_per_cluster.is_power_of_two()
+            || reserved_sector_count == 0
+            || fat_count == 0
+            || root_entry_count != 0
+            || sectors_per_fat_16 != 0
+            || sectors_per_fat == 0
+            || root_cluster < FIRST_DATA_CLUSTER
+        {
+            return Err(FatError::InvalidFilesystem);
+        }
+
+        let bpb = Self {
+            sectors_per_cluster,
+            reserved_sector_count,
+            fat_count,
+            sectors_per_fat,
+            total_sectors: if total_sectors_16 != 0 {
+                total_sectors_16
+            } else {
+                total_sectors_32
+            },
+            root_cluster,
+        };
+        // The data region must hold at least the root directory
+        if bpb.total_sectors as u64 <= bpb.first_data_sector() {
+            return Err(FatError::InvalidFilesystem);
+        }
+        Ok(bpb)
+    }
+
+    fn first_data_sector(&self) -> u64 {
+        self.reserved_sector_count as u64 + (self.fat_count as u64 * self.sectors_per_fat as u64)
+    }
+
+    /// Only valid once `parse` has checked that the data region isn't empty
+    fn cluster_count(&self) -> u32 {
+        ((self.total_sectors as u64 - self.first_data_sector()) / self.sectors_per_cluster as u64)
+            as u32
+    }
+}
+
+/// The location of a 32-byte slot within a directory
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct DirSlot {
+    sector: u64,
+    offset: usize,
+}
+
+/// A file or directory found within a directory
+#[derive(Debug, Clone)]
+pub struct FatDirEntry {
+    pub name: String,
+    pub is_directory: bool,
+    pub size: u32,
+    first_cluster: u32,
+    short_name: [u8; 11],
+    attributes: u8,
+    // All the slots occupied by this entry: any long-name slots, then the short-name slot
+    slots: Vec<DirSlot>,
+}
+
+impl FatDirEntry {
+    fn parse(slots: Vec<DirSlot>, long_name: Option<String>, raw: &[u8]) -> Self {
+        let mut short_name = [0; 11];
+        short_name.copy_from_slice(&raw[..11]);
+        let attributes = raw[11];
+        let first_cluster = ((read_u16(raw, 20) as u32) << 16) | (read_u16(raw, 26) as u32);
+        Self {
+            name: long_name.unwrap_or_else(|| format_short_name(&short_name)),
+            is_directory: attributes & ATTR_DIRECTORY != 0,
+            size: read_u32(raw, 28),
+            first_cluster,
+            short_name,
+            attributes,
+            slots,
+        }
+    }
+
+    fn short_slot(&self) -> DirSlot {
+        *self.slots.last().unwrap()
+    }
+}
+
+/// Either the root directory, which has no directory entry, or an entry within some directory
+#[derive(Debug, Clone)]
+enum Node {
+    Root,
+    Entry(FatDirEntry),
+}
+
+pub struct FatFs<D: BlockDevice> {
+    device: D,
+    bpb: BiosParameterBlock,
+    // Where to begin the next search for a free cluster
+    next_free_cluster_hint: u32,
+}
+
+impl<D: BlockDevice> FatFs<D> {
+    pub fn mount(mut device: D) -> Result<Self, FatError> {
+        let mut boot_sector = [0; SECTOR_SIZE];
+        device.read_sectors(0, &mut boot_sector)?;
+        let bpb = BiosParameterBlock::parse(&boot_sector)?;
+        Ok(Self {
+            device,
+            bpb,
+            next_free_cluster_hint: FIRST_DATA_CLUSTER,
+        })
+    }
+
+    /// Writes a fresh, empty FAT32 volume spanning `sector_count` sectors of the device
+    pub fn format(mut device: D, sector_count: u64, volume_label: &str) -> Result<Self, FatError> {
+        let total_sectors: u32 = sector_count.try_into().map_err(|_| FatError::NoSpace)?;
+        // Cluster sizes recommended by the spec for each volume size
+        let sectors_per_cluster: u32 = match total_sectors {
+            0..=532_480 => 1,
+            532_481..=16_777_216 => 8,
+            16_777_217..=33_554_432 => 16,
+            33_554_433..=67_108_864 => 32,
+            _ => 64,
+        };
+        let reserved_sector_count: u32 = 32;
+        let fat_count: u32 = 2;
+        // FAT size computation from the spec, §3.5
+        let sectors_per_fat = {
+            let tmp1 = total_sectors.saturating_sub(reserved_sector_count);
+            let tmp2 = ((256 * sectors_per_cluster) + fat_count) / 2;
+            (tmp1 + (tmp2 - 1)) / tmp2
+        };
+        let first_data_sector = reserved_sector_count + (fat_count * sectors_per_fat);
+        // The spec's cluster sizes give enough clusters on any volume large enough to be FAT32
+        let cluster_count = total_sectors.saturating_sub(first_data_sector) / sectors_per_cluster;
+        if cluster_count < MIN_FAT32_CLUSTER_COUNT {
+            return Err(FatError::NoSpace);
+        }
+
+        let mut boot_sector = [0; SECTOR_SIZE];
+        boot_sector[0..3].copy_from_slice(&[0xEB, 0x58, 0x90]);
+        boot_sector[3..11].copy_from_slice(b"AXLE    ");
+        write_u16(&mut boot_sector, 11, SECTOR_SIZE as u16);
+        boot_sector[13] = sectors_per_cluster as u8;
+        write_u16(&mut boot_sector, 14, reserved_sector_count as u16);
+        boot_sector[16] = fat_count as u8;
+        // Fixed media
+        boot_sector[21] = 0xF8;
+        write_u16(&mut boot_sector, 24, 63);
+        write_u16(&mut boot_sector, 26, 255);
+        write_u32(&mut boot_sector, 32, total_sectors);
+        write_u32(&mut boot_sector, 36, sectors_per_fat);
+        write_u32(&mut boot_sector, 44, FIRST_DATA_CLUSTER);
+        // FSInfo and backup boot sector locations
+        write_u16(&mut boot_sector, 48, 1);
+        write_u16(&mut boot_sector, 50, 6);
+        boot_sector[64] = 0x80;
+        boot_sector[66] = 0x29;
+        write_u32(&mut boot_sector, 67, 0x41584c45);
+        let mut label = [b' '; 11];
+        for (dst, src) in label.iter_mut().zip(volume_label.bytes()) {
+            *dst = src.to_ascii_uppercase();
+        }
+        boot_sector[71..82].copy_from_slice(&label);
+        boot_sector[82..90].copy_from_slice(b"FAT32   ");
+        boot_sector[510] = 0x55;
+        boot_sector[511] = 0xAA;
+
+        let mut fs_info_sector = [0; SECTOR_SIZE];
+        write_u32(&mut fs_info_sector, 0, 0x4161_5252);
+        write_u32(&mut fs_info_sector, 484, 0x6141_7272);
+        // Free count and next-free hint are unknown
+        write_u32(&mut fs_info_sector, 488, 0xFFFF_FFFF);
+        write_u32(&mut fs_info_sector, 492, 0xFFFF_FFFF);
+        write_u32(&mut fs_info_sector, 508, 0xAA55_0000);
+
+        device.zero_sectors(0, first_data_sector as u64 + sectors_per_cluster as u64)?;
+        device.write_sectors(0, &boot_sector)?;
+        device.write_sectors(1, &fs_info_sector)?;
+        device.write_sectors(6, &boot_sector)?;
+        device.write_sectors(7, &fs_info_sector)?;
+
+        let mut fs = Self::mount(device)?;
+        // The first two FAT entries are reserved, and the third holds the root directory
+        fs.set_fat_entry(0, 0x0FFF_FFF8)?;
+        fs.set_fat_entry(1, FAT_END_OF_CHAIN)?;
+        fs.set_fat_entry(FIRST_DATA_CLUSTER, FAT_END_OF_CHAIN)?;
+        Ok(fs)
+    }
+
+    /// Gives back the underlying device, such as to remount it later
+    pub fn into_device(self) -> D {
+        self.device
+    }
+
+    fn cluster_size(&self) -> usize {
+        self.bpb.sectors_per_cluster as usize * SECTOR_SIZE
+    }
+
+    fn first_sector_of_cluster(&self, cluster: u32) -> u64 {
+        self.bpb.first_data_sector()
+            + ((cluster - FIRST_DATA_CLUSTER) as u64 * self.bpb.sectors_per_cluster as u64)
+    }
+
+    fn is_valid_cluster(&self, cluster: u32) -> bool {
+        cluster >= FIRST_DATA_CLUSTER && cluster < self.bpb.cluster_count() + FIRST_DATA_CLUSTER
+    }
+
+    /* FAT manipulation */
+
+    fn fat_entry_location(&self, cluster: u32) -> (u64, usize) {
+        let byte_offset = cluster as usize * 4;
+        (
+            self.bpb.reserved_sector_count as u64 + (byte_offset / SECTOR_SIZE) as u64,
+            byte_offset % SECTOR_SIZE,
+        )
+    }
+
+    fn fat_entry(&mut self, cluster: u32) -> Result<u32, FatError> {
+        let (sector, offset) = self.fat_entry_location(cluster);
+        let mut buf = [0; SECTOR_SIZE];
+        self.device.read_sectors(sector, &mut buf)?;
+        Ok(read_u32(&buf, offset) & FAT_ENTRY_MASK)
+    }
+
+    fn set_fat_entry(&mut self, cluster: u32, value: u32) -> Result<(), FatError> {
+        let (sector, offset) = self.fat_entry_location(cluster);
+        let mut buf = [0; SECTOR_SIZE];
+        // Keep every copy of the FAT in sync
+        for fat_idx in 0..self.bpb.fat_count {
+            let fat_sector = sector + (fat_idx * self.bpb.sectors_per_fat) as u64;
+            self.device.read_sectors(fat_sector, &mut buf)?;
+            // The high 4 bits are reserved and must be preserved
+            let preserved = read_u32(&buf, offset) & !FAT_ENTRY_MASK;
+            write_u32(&mut buf, offset, preserved | (value & FAT_ENTRY_MASK));
+            self.device.write_sectors(fat_sector, &buf)?;
+        }
+        Ok(())
+    }
+
+    fn cluster_chain(&mut self, first_cluster: u32) -> Result<Vec<u32>, FatError> {
+        let mut chain = Vec::new();
+        let mut cluster = first_cluster;
+        while self.is_valid_cluster(cluster) {
+            chain.push(cluster);
+            // Guard against a corrupted, cyclic chain
+            if chain.len() > self.bpb.cluster_count() as usize {
+                return Err(FatError::InvalidFilesystem);
+            }
+            cluster = self.fat_entry(cluster)?;
+        }
+        if !chain.is_empty() && cluster < FAT_END_OF_CHAIN_MIN {
+            return Err(FatError::InvalidFilesystem);
+        }
+        Ok(chain)
+    }
+
+    fn find_free_cluster(&mut self) -> Result<u32, FatError> {
+        let cluster_count = self.bpb.cluster_count();
+        for i in 0..cluster_count {
+            let cluster = FIRST_DATA_CLUSTER
+                + ((self.next_free_cluster_hint - FIRST_DATA_CLUSTER + i) % cluster_count);
+            if self.fat_entry(cluster)? == FAT_FREE_CLUSTER {
+                self.next_free_cluster_hint = cluster;
+                return Ok(cluster);
+            }
+        }
+        Err(FatError::NoSpace)
+    }
+
+    /// Allocates and links `count` clusters, returning the chain
+    fn alloc_chain(&mut self, count: usize) -> Result<Vec<u32>, FatError> {
+        let mut chain: Vec<u32> = Vec::with_capacity(count);
+        for _ in 0..count {
+            let cluster = match self.find_free_cluster() {
+                Ok(cluster) => cluster,
+                Err(e) => {
+                    // Roll back the partial allocation
+                    if let Some(&first) = chain.first() {
+                        self.free_chain(first)?;
+                    }
+                    return Err(e);
+                }
+            };
+            self.set_fat_entry(cluster, FAT_END_OF_CHAIN)?;
+            if let Some(&prev) = chain.last() {
+                self.set_fat_entry(prev, cluster)?;
+            }
+            chain.push(cluster);
+        }
+        Ok(chain)
+    }
+
+    fn free_chain(&mut self, first_cluster: u32) -> Result<(), FatError> {
+        for cluster in self.cluster_chain(first_cluster)? {
+            self.set_fat_entry(cluster, FAT_FREE_CLUSTER)?;
+        }
+        Ok(())
+    }
+
+    fn read_cluster(&mut self, cluster: u32, buf: &mut [u8]) -> Result<(), FatError> {
+        let sector = self.first_sector_of_cluster(cluster);
+        Ok(self.device.read_sectors(sector, buf)?)
+    }
+
+    fn write_cluster(&mut self, cluster: u32, buf: &[u8]) -> Result<(), FatError> {
+        let sector = self.first_sector_of_cluster(cluster);
+        Ok(self.device.write_sectors(sector, buf)?)
+    }
+
+    /// Allocates a chain holding `data`, returning its first cluster (0 for empty data)
+    fn write_new_chain(&mut self, data: &[u8]) -> Result<u32, FatError> {
+        let cluster_size = self.cluster_size();
+        let cluster_count = (data.len() + cluster_size - 1) / cluster_size;
+        let chain = self.alloc_chain(cluster_count)?;
+        let mut buf = vec![0; cluster_size];
+        for (cluster, chunk) in chain.iter().zip(data.chunks(cluster_size)) {
+            buf[..chunk.len()].copy_from_slice(chunk);
+            buf[chunk.len()..].fill(0);
+            self.write_cluster(*cluster, &buf)?;
+        }
+        Ok(chain.first().copied().unwrap_or(0))
+    }
+
+    /* Directory manipulation */
+
+    fn dir_cluster(&self, node: &Node) -> Result<u32, FatError> {
+        match node {
+            Node::Root => Ok(self.bpb.root_cluster),
+            Node::Entry(entry) if entry.is_directory => Ok(entry.first_cluster),
+            Node::Entry(_) => Err(FatError::NotADirectory),
+        }
+    }
+
+    /// Returns every raw slot in the directory, stopping at the end-of-directory marker
+    fn dir_slots(
+        &mut self,
+        dir_cluster: u32,
+    ) -> Result<Vec<(DirSlot, [u8; DIR_ENTRY_SIZE])>, FatError> {
+        let mut slots = Vec::new();
+        let mut buf = vec![0; self.cluster_size()];
+        for cluster in self.cluster_chain(dir_cluster)? {
+            self.read_cluster(cluster, &mut buf)?;
+            let first_sector = self.first_sector_of_cluster(cluster);
+            for (i, raw) in buf.chunks(DIR_ENTRY_SIZE).enumerate() {
+                if raw[0] == DIR_ENTRY_END_MARKER {
+                    return Ok(slots);
+                }
+                let byte_offset = i * DIR_ENTRY_SIZE;
+                let slot = DirSlot {
+                    sector: first_sector + (byte_offset / SECTOR_SIZE) as u64,
+                    offset: byte_offset % SECTOR_SIZE,
+                };
+                let mut raw_copy = [0; DIR_ENTRY_SIZE];
+                raw_copy.copy_from_slice(raw);
+                slots.push((slot, raw_copy));
+            }
+        }
+        Ok(slots)
+    }
+
+    /// Returns the entries in a directory, excluding `.`, `..` and the volume label
+    fn dir_entries(&mut self, dir_cluster: u32) -> Result<Vec<FatDirEntry>, FatError> {
+        let mut entries = Vec::new();
+        let mut long_name_parts: Vec<(u8, [u16; LONG_NAME_CHARS_PER_ENTRY])> = Vec::new();
+        let mut long_name_slots: Vec<DirSlot> = Vec::new();
+        for (slot, raw) in self.dir_slots(dir_cluster)? {
+            if raw[0] == DIR_ENTRY_FREE_MARKER {
+                long_name_parts.clear();
+                long_name_slots.clear();
+                continue;
+            }
+            let attributes = raw[11];
+            if attributes & ATTR_LONG_NAME == ATTR_LONG_NAME {
+                if raw[0] & LONG_NAME_LAST_ENTRY_FLAG != 0 {
+                    // The first physical long-name slot starts a new name
+                    long_name_parts.clear();
+                    long_name_slots.clear();
+                }
+                long_name_parts.push((raw[13], long_name_chars(&raw)));
+                long_name_slots.push(slot);
+                continue;
+            }
+
+            let mut short_name = [0; 11];
+            short_name.copy_from_slice(&raw[..11]);
+            let is_dot_entry = &short_name == b".          " || &short_name == b"..         ";
+            if attributes & ATTR_VOLUME_ID != 0 || is_dot_entry {
+                long_name_parts.clear();
+                long_name_slots.clear();
+                continue;
+            }
+
+            // Only honour the long name if it was written alongside this short name
+            let checksum = short_name_checksum(&short_name);
+            let long_name = if !long_name_parts.is_empty()
+                && long_name_parts.iter().all(|(c, _)| *c == checksum)
+            {
+                // Long name slots are stored in reverse order
+                let chars: Vec<u16> = long_name_parts
+                    .iter()
+                    .rev()
+                    .flat_map(|(_, chars)| chars.iter().copied())
+                    .take_while(|c| *c != 0x0000)
+                    .collect();
+                Some(String::from_utf16_lossy(&chars))
+            } else {
+                long_name_slots.clear();
+                None
+            };
+
+            let mut slots = core::mem::take(&mut long_name_slots);
+            slots.push(slot);
+            long_name_parts.clear();
+            entries.push(FatDirEntry::parse(slots, long_name, &raw));
+        }
+        Ok(entries)
+    }
+
+    fn find_in_dir(
+        &mut self,
+        dir_cluster: u32,
+        name: &str,
+    ) -> Result<Option<FatDirEntry>, FatError> {
+        Ok(self
+            .dir_entries(dir_cluster)?
+            .into_iter()
+            .find(|entry| entry.name.eq_ignore_ascii_case(name)))
+    }
+
+    fn resolve(&mut self, path: &str) -> Result<Node, FatError> {
+        let mut node = Node::Root;
+        for component in path_components(path) {
+            let dir_cluster = self.dir_cluster(&node)?;
+            node = match self.find_in_dir(dir_cluster, component)? {
+                Some(entry) => Node::Entry(entry),
+                None => return Err(FatError::NotFound),
+            };
+        }
+        Ok(node)
+    }
+
+    /// Resolves the directory containing `path`, and returns the final path component
+    fn resolve_parent<'a>(&mut self, path: &'a str) -> Result<(u32, &'a str), FatError> {
+        let mut components = path_components(path);
+        let name = components.pop().ok_or(FatError::InvalidName)?;
+        validate_long_name(name)?;
+        let mut parent = Node::Root;
+        for component in components {
+            let dir_cluster = self.dir_cluster(&parent)?;
+            parent = match self.find_in_dir(dir_cluster, component)? {
+                Some(entry) => Node::Entry(entry),
+                None => return Err(FatError::NotFound),
+            };
+        }
+        Ok((self.dir_cluster(&parent)?, name))
+    }
+
+    fn write_slot(&mut self, slot: DirSlot, raw: &[u8]) -> Result<(), FatError> {
+        let mut buf = [0; SECTOR_SIZE];
+        self.device.read_sectors(slot.sector, &mut buf)?;
+        buf[slot.offset..slot.offset + DIR_ENTRY_SIZE].copy_from_slice(raw);
+        self.device.write_sectors(slot.sector, &buf)?;
+        Ok(())
+    }
+
+    fn mark_slots_free(&mut self, slots: &[DirSlot]) -> Result<(), FatError> {
+        let mut buf = [0; SECTOR_SIZE];
+        for slot in slots {
+            self.device.read_sectors(slot.sector, &mut buf)?;
+            buf[slot.offset] = DIR_ENTRY_FREE_MARKER;
+            self.device.write_sectors(slot.sector, &buf)?;
+        }
+        Ok(())
+    }
+
+    /// Finds `count` consecutive unused slots in the directory, growing it if necessary
+    fn alloc_dir_slots(
+        &mut self,
+        dir_cluster: u32,
+        count: usize,
+    ) -> Result<Vec<DirSlot>, FatError> {
+        let cluster_size = self.cluster_size();
+        let chain = self.cluster_chain(dir_cluster)?;
+        let mut run: Vec<DirSlot> = Vec::new();
+        let mut buf = vec![0; cluster_size];
+        for cluster in chain.iter() {
+            self.read_cluster(*cluster, &mut buf)?;
+            let first_sector = self.first_sector_of_cluster(*cluster);
+            for (i, raw) in buf.chunks(DIR_ENTRY_SIZE).enumerate() {
+                if raw[0] == DIR_ENTRY_END_MARKER || raw[0] == DIR_ENTRY_FREE_MARKER {
+                    let byte_offset = i * DIR_ENTRY_SIZE;
+                    run.push(DirSlot {
+                        sector: first_sector + (byte_offset / SECTOR_SIZE) as u64,
+                        offset: byte_offset % SECTOR_SIZE,
+                    });
+                    if run.len() == count {
+                        return Ok(run);
+                    }
+                } else {
+                    run.clear();
+                }
+            }
+        }
+
+        // Extend the directory with zeroed clusters until the run fits
+        let slots_per_cluster = cluster_size / DIR_ENTRY_SIZE;
+        let needed_clusters = ((count - run.len()) + slots_per_cluster - 1) / slots_per_cluster;
+        let new_chain = self.alloc_chain(needed_clusters)?;
+        self.set_fat_entry(*chain.last().unwrap(), new_chain[0])?;
+        let zeroes = vec![0; cluster_size];
+        for cluster in new_chain.iter() {
+            self.write_cluster(*cluster, &zeroes)?;
+            let first_sector = self.first_sector_of_cluster(*cluster);
+            for i in 0..slots_per_cluster {
+                if run.len() == count {
+                    break;
+                }
+                let byte_offset = i * DIR_ENTRY_SIZE;
+                run.push(DirSlot {
+                    sector: first_sector + (byte_offset / SECTOR_SIZE) as u64,
+                    offset: byte_offset % SECTOR_SIZE,
+                });
+            }
+        }
+        Ok(run)
+    }
+
+    /// Writes a new entry named `name` into the directory, with the provided short-entry contents
+    fn insert_dir_entry(
+        &mut self,
+        dir_cluster: u32,
+        name: &str,
+        attributes: u8,
+        first_cluster: u32,
+        size: u32,
+    ) -> Result<(), FatError> {
+        let existing_short_names: Vec<[u8; 11]> = self
+            .dir_slots(dir_cluster)?
+            .iter()
+            .map(|(_, raw)| {
+                let mut short_name = [0; 11];
+                short_name.copy_from_slice(&raw[..11]);
+                short_name
+            })
+            .collect();
+        let (short_name, needs_long_name) = generate_short_name(name, &existing_short_names);
+
+        let long_name_entries = if needs_long_name {
+            build_long_name_entries(name, &short_name)
+        } else {
+            Vec::new()
+        };
+        let slots = self.alloc_dir_slots(dir_cluster, long_name_entries.len() + 1)?;
+        for (slot, raw) in slots.iter().zip(long_name_entries.iter()) {
+            self.write_slot(*slot, raw)?;
+        }
+        let short_entry = build_short_entry(&short_name, attributes, first_cluster, size);
+        self.write_slot(*slots.last().unwrap(), &short_entry)
+    }
+
+    fn update_short_entry(
+        &mut self,
+        entry: &FatDirEntry,
+        first_cluster: u32,
+        size: u32,
+    ) -> Result<(), FatError> {
+        let short_entry =
+            build_short_entry(&entry.short_name, entry.attributes, first_cluster, size);
+        self.write_slot(entry.short_slot(), &short_entry)
+    }
+
+    /* Public interface */
+
+    pub fn exists(&mut self, path: &str) -> Result<bool, FatError> {
+        match self.resolve(path) {
+            Ok(_) => Ok(true),
+            Err(FatError::NotFound) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Returns the entry at the path, or None if the path is the root directory
+    pub fn stat(&mut self, path: &str) -> Result<Option<FatDirEntry>, FatError> {
+        match self.resolve(path)? {
+            Node::Root => Ok(None),
+            Node::Entry(entry) => Ok(Some(entry)),
+        }
+    }
+
+    pub fn read_dir(&mut self, path: &str) -> Result<Vec<FatDirEntry>, FatError> {
+        let node = self.resolve(path)?;
+        let dir_cluster = self.dir_cluster(&node)?;
+        self.dir_entries(dir_cluster)
+    }
+
+    pub fn read_file(&mut self, path: &str) -> Result<Vec<u8>, FatError> {
+        let size = match self.resolve(path)? {
+            Node::Entry(entry) if !entry.is_directory => entry.size,
+            _ => return Err(FatError::IsADirectory),
+        };
+        self.read_file_part(path, 0, size as usize)
+    }
+
+    /// Reads up to `len` bytes from `offset`. The returned data is truncated at the end of the file.
+    pub fn read_file_part(
+        &mut self,
+        path: &str,
+        offset: usize,
+        len: usize,
+    ) -> Result<Vec<u8>, FatError> {
+        let entry = match self.resolve(path)? {
+            Node::Entry(entry) if !entry.is_directory => entry,
+            _ => return Err(FatError::IsADirectory),
+        };
+        let file_size = entry.size as usize;
+        if offset > file_size {
+            return Err(FatError::OutOfRange);
+        }
+        let start = offset;
+        let end = min(offset.saturating_add(len), file_size);
+        let mut out = Vec::with_capacity(end - start);
+        if start == end {
+            return Ok(out);
+        }
+
+        let cluster_size = self.cluster_size();
+        let chain = self.cluster_chain(entry.first_cluster)?;
+        let mut buf = vec![0; cluster_size];
+        let first_cluster_idx = start / cluster_size;
+        let last_cluster_idx = (end - 1) / cluster_size;
+        for cluster_idx in first_cluster_idx..=last_cluster_idx {
+            let cluster = *chain.get(cluster_idx).ok_or(FatError::InvalidFilesystem)?;
+            self.read_cluster(cluster, &mut buf)?;
+            let cluster_start = cluster_idx * cluster_size;
+            let from = start.saturating_sub(cluster_start);
+            let to = min(end - cluster_start, cluster_size);
+            out.extend_from_slice(&buf[from..to]);
+        }
+        Ok(out)
+    }
+
+    /// Creates the file if it doesn't exist, and replaces its contents with `data`
+    pub fn write_file(&mut self, path: &str, data: &[u8]) -> Result<(), FatError> {
+        let size: u32 = data.len().try_into().map_err(|_| FatError::NoSpace)?;
+        let (dir_cluster, name) = self.resolve_parent(path)?;
+        match self.find_in_dir(dir_cluster, name)? {
+            Some(entry) if entry.is_directory => Err(FatError::IsADirectory),
+            Some(entry) => {
+                // Write the new contents out before releasing the old ones
+                let first_cluster = self.write_new_chain(data)?;
+                self.update_short_entry(&entry, first_cluster, size)?;
+                if entry.first_cluster != 0 {
+                    self.free_chain(entry.first_cluster)?;
+                }
+                Ok(())
+            }
+            None => {
+                let first_cluster = self.write_new_chain(data)?;
+                self.insert_dir_entry(dir_cluster, name, ATTR_ARCHIVE, first_cluster, size)
+                    .or_else(|e| {
+                        if first_cluster != 0 {
+                            self.free_chain(first_cluster)?;
+                        }
+                        Err(e)
+                    })
+            }
+        }
+    }
+
+    /// Overwrites part of an existing file, extending it with zeroes if `offset` is past its end.
+    /// Files can't grow past 4GiB.
+    pub fn write_file_part(
+        &mut self,
+        path: &str,
+        offset: usize,
+        data: &[u8],
+    ) -> Result<(), FatError> {
+        // Rewrite the whole file through a fresh cluster chain, so a failed write
+        // leaves the old contents intact
+        let end = offset
+            .checked_add(data.len())
+            .filter(|end| *end <= MAX_FILE_SIZE)
+            .ok_or(FatError::NoSpace)?;
+        let mut contents = self.read_file(path)?;
+        if contents.len() < end {
+            contents.resize(end, 0);
+        }
+        contents[offset..end].copy_from_slice(data);
+        self.write_file(path, &contents)
+    }
+
+    pub fn create_file(&mut self, path: &str) -> Result<(), FatError> {
+        if self.exists(path)? {
+            return Err(FatError::AlreadyExists);
+        }
+        self.write_file(path, &[])
+    }
+
+    pub fn create_directory(&mut self, path: &str) -> Result<(), FatError> {
+        let (parent_cluster, name) = self.resolve_parent(path)?;
+        if self.find_in_dir(parent_cluster, name)?.is_some() {
+            return Err(FatError::AlreadyExists);
+        }
+
+        let cluster = self.alloc_chain(1)?[0];
+        let mut buf = vec![0; self.cluster_size()];
+        // A `..` entry pointing to the root directory records cluster 0
+        let parent_ref = if parent_cluster == self.bpb.root_cluster {
+            0
+        } else {
+            parent_cluster
+        };
+        buf[..DIR_ENTRY_SIZE].copy_from_slice(&build_short_entry(
+            b".          ",
+            ATTR_DIRECTORY,
+            cluster,
+            0,
+        ));
+        buf[DIR_ENTRY_SIZE..DIR_ENTRY_SIZE * 2].copy_from_slice(&build_short_entry(
+            b"..         ",
+            ATTR_DIRECTORY,
+            parent_ref,
+            0,
+        ));
+        self.write_cluster(cluster, &buf)?;
+
+        self.insert_dir_entry(parent_cluster, name, ATTR_DIRECTORY, cluster, 0)
+            .or_else(|e| {
+                self.free_chain(cluster)?;
+                Err(e)
+            })
+    }
+
+    /// Deletes a file, or an empty directory
+    pub fn delete(&mut self, path: &str) -> Result<(), FatError> {
+        let entry = match self.resolve(path)? {
+            // The root directory can't be deleted
+            Node::Root => return Err(FatError::InvalidName),
+            Node::Entry(entry) => entry,
+        };
+        if entry.is_directory && !self.dir_entries(entry.first_cluster)?.is_empty() {
+            return Err(FatError::DirectoryNotEmpty);
+        }
+        self.mark_slots_free(&entry.slots)?;
+        if entry.first_cluster != 0 {
+            self.free_chain(entry.first_cluster)?;
+        }
+        Ok(())
+    }
+
+    /// Renames or moves a file or directory. The destination must not already exist.
+    pub fn rename(&mut self, from_path: &str, to_path: &str) -> Result<(), FatError> {
+        let entry = match self.resolve(from_path)? {
+            Node::Root => return Err(FatError::InvalidName),
+            Node::Entry(entry) => entry,
+        };
+        // A directory can't be moved inside itself
+        let from_components = path_components(from_path);
+        let to_components = path_components(to_path);
+        if entry.is_directory
+            && to_components.len() > from_components.len()
+            && to_components
+                .iter()
+                .zip(from_components.iter())
+                .all(|(a, b)| a.eq_ignore_ascii_case(b))
+        {
+            return Err(FatError::InvalidName);
+        }
+
+        let (new_parent_cluster, new_name) = self.resolve_parent(to_path)?;
+        if self.find_in_dir(new_parent_cluster, new_name)?.is_some() {
+            return Err(FatError::AlreadyExists);
+        }
+        self.insert_dir_entry(
+            new_parent_cluster,
+            new_name,
+            entry.attributes,
+            entry.first_cluster,
+            entry.size,
+        )?;
+        self.mark_slots_free(&entry.slots)?;
+
+        // Keep the `..` entry of a moved directory pointing at its new parent
+        if entry.is_directory {
+            let parent_ref = if new_parent_cluster == self.bpb.root_cluster {
+                0
+            } else {
+                new_parent_cluster
+            };
+            let dot_dot_slot = DirSlot {
+                sector: self.first_sector_of_cluster(entry.first_cluster),
+                offset: DIR_ENTRY_SIZE,
+            };
+            self.write_slot(
+                dot_dot_slot,
+                &build_short_entry(b"..         ", ATTR_DIRECTORY, parent_ref, 0),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/* Name handling */
+
+fn path_components(path: &str) -> Vec<&str> {
+    path.split('/').filter(|c| !c.is_empty()).collect()
+}
+
+fn validate_long_name(name: &str) -> Result<(), FatError> {
+    let is_invalid_char = |c: char| {
+        (c as u32) < 0x20 || matches!(c, '"' | '*' | '/' | ':' | '<' | '>' | '?' | '\\' | '|')
+    };
+    if name.is_empty()
+        || name == "."
+        || name == ".."
+        || name.encode_utf16().count() > MAX_LONG_NAME_LEN
+        || name.chars().any(is_invalid_char)
+    {
+        return Err(FatError::InvalidName);
+    }
+    Ok(())
+}
+
+fn is_valid_short_name_char(c: u8) -> bool {
+    c.is_ascii_uppercase()
+        || c.is_ascii_digit()
+        || matches!(
+            c,
+            b'!' | b'#'
+                | b'$'
+                | b'%'
+                | b'&'
+                | b'\''
+                | b'('
+                | b')'
+                | b'-'
+                | b'@'
+                | b'^'
+                | b'_'
+                | b'`'
+                | b'{'
+                | b'}'
+                | b'~'
+        )
+}
+
+fn format_short_name(short_name: &[u8; 11]) -> String {
+    let base = core::str::from_utf8(&short_name[..8])
+        .unwrap_or("")
+        .trim_end();
+    let ext = core::str::from_utf8(&short_name[8..])
+        .unwrap_or("")
+        .trim_end();
+    if ext.is_empty() {
+        base.to_string()
+    } else {
+        let mut name = base.to_string();
+        name.push('.');
+        name.push_str(ext);
+        name
+    }
+}
+
+/// Returns the 8.3 name to store for `name`, and whether long-name entries are needed to preserve it
+fn generate_short_name(name: &str, existing: &[[u8; 11]]) -> ([u8; 11], bool) {
+    let (base, ext) = match name.rfind('.') {
+        Some(idx) if idx > 0 => (&name[..idx], &name[idx + 1..]),
+        _ => (name, ""),
+    };
+
+    // Names that are already valid, upper-case 8.3 names can be stored as-is
+    let fits_exactly = !base.is_empty()
+        && base.len() <= 8
+        && ext.len() <= 3
+        && base.bytes().all(is_valid_short_name_char)
+        && ext.bytes().all(is_valid_short_name_char);
+    if fits_exactly {
+        let mut short_name = [b' '; 11];
+        short_name[..base.len()].copy_from_slice(base.as_bytes());
+        short_name[8..8 + ext.len()].copy_from_slice(ext.as_bytes());
+        if !existing.contains(&short_name) {
+            return (short_name, false);
+        }
+    }
+
+    // Otherwise, derive a unique basis name with a numeric tail, such as LONGNA~1.TXT
+    let sanitize = |s: &str| -> Vec<u8> {
+        s.bytes()
+            .filter(|c| *c != b' ' && *c != b'.')
+            .map(|c| {
+                let c = c.to_ascii_uppercase();
+                if is_valid_short_name_char(c) {
+                    c
+                } else {
+                    b'_'
+                }
+            })
+            .collect()
+    };
+    let basis_base = sanitize(base);
+    let basis_ext = sanitize(ext);
+    let mut short_name = [b' '; 11];
+    for (dst, src) in short_name[8..].iter_mut().zip(basis_ext.iter()) {
+        *dst = *src;
+    }
+    for tail in 1..1_000_000u32 {
+        let mut digits = Vec::new();
+        let mut n = tail;
+        while n > 0 {
+            digits.insert(0, b'0' + (n % 10) as u8);
+            n /= 10;
+        }
+        let prefix_len = min(basis_base.len(), 8 - (digits.len() + 1));
+        short_name[..8].fill(b' ');
+        short_name[..prefix_len].copy_from_slice(&basis_base[..prefix_len]);
+        short_name[prefix_len] = b'~';
+        short_name[prefix_len + 1..prefix_len + 1 + digits.len()].copy_from_slice(&digits);
+        if !existing.contains(&short_name) {
+            break;
+        }
+    }
+    (short_name, true)
+}
+
+fn short_name_checksum(short_name: &[u8; 11]) -> u8 {
+    short_name.iter().fold(0u8, |sum, c| {
+        ((sum & 1) << 7).wrapping_add(sum >> 1).wrapping_add(*c)
+    })
+}
+
+fn long_name_chars(raw: &[u8]) -> [u16; LONG_NAME_CHARS_PER_ENTRY] {
+    // Characters are split across three regions of the slot
+    let offsets = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+    let mut chars = [0; LONG_NAME_CHARS_PER_ENTRY];
+    for (c, offset) in chars.iter_mut().zip(offsets.iter()) {
+        *c = read_u16(raw, *offset);
+    }
+    chars
+}
+
+/// Builds the long-name slots for `name`, in the order they're stored on disk
+fn build_long_name_entries(name: &str, short_name: &[u8; 11]) -> Vec<[u8; DIR_ENTRY_SIZE]> {
+    let offsets = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+    let checksum = short_name_checksum(short_name);
+    let utf16: Vec<u16> = name.encode_utf16().collect();
+    let entry_count = (utf16.len() + LONG_NAME_CHARS_PER_ENTRY - 1) / LONG_NAME_CHARS_PER_ENTRY;
+
+    let mut entries = Vec::with_capacity(entry_count);
+    for seq in (1..=entry_count).rev() {
+        let mut raw = [0; DIR_ENTRY_SIZE];
+        raw[0] = seq as u8;
+        if seq == entry_count {
+            raw[0] |= LONG_NAME_LAST_ENTRY_FLAG;
+        }
+        raw[11] = ATTR_LONG_NAME;
+        raw[13] = checksum;
+        let start = (seq - 1) * LONG_NAME_CHARS_PER_ENTRY;
+        for (i, offset) in offsets.iter().enumerate() {
+            let idx = start + i;
+            // The name is NUL-terminated if it doesn't fill the slot, then padded with 0xFFFF
+            let c = match idx.cmp(&utf16.len()) {
+                core::cmp::Ordering::Less => utf16[idx],
+                core::cmp::Ordering::Equal => 0x0000,
+                core::cmp::Ordering::Greater => 0xFFFF,
+            };
+            write_u16(&mut raw, *offset, c);
+        }
+        entries.push(raw);
+    }
+    entries
+}
+
+fn build_short_entry(
+    short_name: &[u8; 11],
+    attributes: u8,
+    first_cluster: u32,
+    size: u32,
+) -> [u8; DIR_ENTRY_SIZE] {
+    let mut raw = [0; DIR_ENTRY_SIZE];
+    raw[..11].copy_from_slice(short_name);
+    raw[11] = attributes;
+    write_u16(&mut raw, 16, DEFAULT_DATE);
+    write_u16(&mut raw, 18, DEFAULT_DATE);
+    write_u16(&mut raw, 20, (first_cluster >> 16) as u16);
+    write_u16(&mut raw, 24, DEFAULT_DATE);
+    write_u16(&mut raw, 26, (first_cluster & 0xFFFF) as u16);
+    write_u32(&mut raw, 28, size);
+    raw
+}
+
+fn read_u16(buf: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([buf[offset], buf[offset + 1]])
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        buf[offset],
+        buf[offset + 1],
+        buf[offset + 2],
+        buf[offset + 3],
+    ])
+}
+
+fn write_u16(buf: &mut [u8], offset: usize, value: u16) {
+    buf[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+fn write_u32(buf: &mut [u8], offset: usize, value: u32) {
+    buf[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+#[cfg(all(test, feature = "use_std"))]
+mod tests {
+    extern crate std;
+    use std::format;
+    use std::path::PathBuf;
+
+    use super::{write_u32, FatError, FatFs};
+    use crate::block_device::{BlockDevice, FileBlockDevice, SECTOR_SIZE};
+
+    const IMAGE_SECTOR_COUNT: u64 = (64 * 1024 * 1024) / 512;
+
+    /// A disk image in the temporary directory, which is deleted when the test finishes
+    struct TestImage(PathBuf);
+
+    impl TestImage {
+        fn new(test_name: &str) -> Self {
+            Self(
+                std::env::temp_dir().join(format!("fat_fs_{}_{test_name}.img", std::process::id())),
+            )
+        }
+
+        fn fresh_fs(&self) -> FatFs<FileBlockDevice> {
+            let device = FileBlockDevice::create(&self.0, IMAGE_SECTOR_COUNT).unwrap();
+            FatFs::format(device, IMAGE_SECTOR_COUNT, "axle").unwrap()
+        }
+
+        fn remount(&self, fs: FatFs<FileBlockDevice>) -> FatFs<FileBlockDevice> {
+            drop(fs.into_device());
+            FatFs::mount(FileBlockDevice::open(&self.0).unwrap()).unwrap()
+        }
+    }
+
+    impl Drop for TestImage {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn test_fresh_volume_is_empty() {
+        // Given a freshly formatted volume
+        let image = TestImage::new("fresh");
+        let mut fs = image.fresh_fs();
+        // Then the root directory is empty
+        assert!(fs.read_dir("/").unwrap().is_empty());
+        assert_eq!(fs.exists("/missing.txt"), Ok(false));
+    }
+
+    #[test]
+    fn test_write_and_read_file() {
+        // Given a volume
+        let image = TestImage::new("write_read");
+        let mut fs = image.fresh_fs();
+        // When I write a file with a long, mixed-case name spanning several clusters
+        let data: std::vec::Vec<u8> = (0..5000u32).map(|i| (i % 251) as u8).collect();
+        fs.write_file("/Run On Startup.txt", &data).unwrap();
+
+        // Then the file can be read back by its original name
+        assert_eq!(fs.read_file("/Run On Startup.txt").unwrap(), data);
+        // And its name is preserved in the directory listing
+        let entries = fs.read_dir("/").unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].name, "Run On Startup.txt");
+        assert_eq!(entries[0].size, 5000);
+        assert!(!entries[0].is_directory);
+    }
+
+    #[test]
+    fn test_read_file_part() {
+        let image = TestImage::new("read_part");
+        let mut fs = image.fresh_fs();
+        let data: std::vec::Vec<u8> = (0..2000u32).map(|i| (i % 256) as u8).collect();
+        fs.write_file("/data.bin", &data).unwrap();
+
+        // When I read a range that crosses a cluster boundary
+        assert_eq!(
+            fs.read_file_part("/data.bin", 500, 100).unwrap(),
+            &data[500..600]
+        );
+        // And a range that runs past the end of the file
+        assert_eq!(
+            fs.read_file_part("/data.bin", 1990, 100).unwrap(),
+            &data[1990..]
+        );
+        // Then a read starting at the end of the file is empty
+        assert!(fs.read_file_part("/data.bin", 2000, 10).unwrap().is_empty());
+        // And a read starting past the end of the file is rejected
+        assert_eq!(
+            fs.read_file_part("/data.bin", 4000, 10).err(),
+            Some(FatError::OutOfRange)
+        );
+    }
+
+    #[test]
+    fn test_overwrite_file() {
+        let image = TestImage::new("overwrite");
+        let mut fs = image.fresh_fs();
+        fs.write_file("/a.txt", &[1; 3000]).unwrap();
+        // When I overwrite a file with shorter contents
+        fs.write_file("/a.txt", b"short").unwrap();
+        // Then only the new contents are visible
+        assert_eq!(fs.read_file("/a.txt").unwrap(), b"short");
+        assert_eq!(fs.read_dir("/").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_create_file_and_write_part() {
+        let image = TestImage::new("write_part");
+        let mut fs = image.fresh_fs();
+        // Given an empty file
+        fs.create_file("/game.sav").unwrap();
+        assert_eq!(
+            fs.create_file("/game.sav").err(),
+            Some(FatError::AlreadyExists)
+        );
+        assert!(fs.read_file("/game.sav").unwrap().is_empty());
+
+        // When I write past the end of the file
+        fs.write_file_part("/game.sav", 4, b"abc").unwrap();
+        // Then the gap is zero-filled
+        assert_eq!(
+            fs.read_file("/game.sav").unwrap(),
+            &[0, 0, 0, 0, b'a', b'b', b'c']
+        );
+
+        // And writes within the file overwrite its contents in place
+        fs.write_file_part("/game.sav", 1, b"xy").unwrap();
+        assert_eq!(
+            fs.read_file("/game.sav").unwrap(),
+            &[0, b'x', b'y', 0, b'a', b'b', b'c']
+        );
+        assert_eq!(
+            fs.write_file_part("/missing", 0, b"a").err(),
+            Some(FatError::NotFound)
+        );
+    }
+
+    #[test]
+    fn test_directories() {
+        let image = TestImage::new("directories");
+        let mut fs = image.fresh_fs();
+        // When I create nested directories containing files
+        fs.create_directory("/usr").unwrap();
+        fs.create_directory("/usr/saves").unwrap();
+        fs.write_file("/usr/saves/zelda.sav", b"save data").unwrap();
+
+        // Then they can be traversed
+        let usr = fs.read_dir("/usr").unwrap();
+        assert_eq!(usr.len(), 1);
+        assert_eq!(usr[0].name, "saves");
+        assert!(usr[0].is_directory);
+        assert_eq!(fs.read_file("/usr/saves/zelda.sav").unwrap(), b"save data");
+        // And paths are matched case-insensitively
+        assert_eq!(fs.read_file("/USR/Saves/ZELDA.SAV").unwrap(), b"save data");
+
+        // And errors are reported for invalid traversals
+        assert_eq!(
+            fs.create_directory("/usr").err(),
+            Some(FatError::AlreadyExists)
+        );
+        assert_eq!(
+            fs.read_dir("/usr/saves/zelda.sav").err(),
+            Some(FatError::NotADirectory)
+        );
+        assert_eq!(fs.read_file("/usr").err(), Some(FatError::IsADirectory));
+        assert_eq!(
+            fs.write_file("/nope/file.txt", b"").err(),
+            Some(FatError::NotFound)
+        );
+    }
+
+    #[test]
+    fn test_directory_grows_past_one_cluster() {
+        let image = TestImage::new("grow_dir");
+        let mut fs = image.fresh_fs();
+        // When I create more entries than fit in a single directory cluster
+        for i in 0..40 {
+            fs.write_file(&format!("/file number {i}.txt"), format!("{i}").as_bytes())
+                .unwrap();
+        }
+        // Then every entry is still readable
+        assert_eq!(fs.read_dir("/").unwrap().len(), 40);
+        for i in 0..40 {
+            assert_eq!(
+                fs.read_file(&format!("/file number {i}.txt")).unwrap(),
+                format!("{i}").as_bytes()
+            );
+        }
+    }
+
+    #[test]
+    fn test_delete() {
+        let image = TestImage::new("delete");
+        let mut fs = image.fresh_fs();
+        fs.create_directory("/dir").unwrap();
+        fs.write_file("/dir/file with a long name.txt", b"abc")
+            .unwrap();
+
+        // A non-empty directory can't be deleted
+        assert_eq!(fs.delete("/dir").err(), Some(FatError::DirectoryNotEmpty));
+
+        // When I delete the file, then the directory
+        fs.delete("/dir/file with a long name.txt").unwrap();
+        fs.delete("/dir").unwrap();
+        // Then both are gone
+        assert_eq!(fs.exists("/dir"), Ok(false));
+        assert!(fs.read_dir("/").unwrap().is_empty());
+        assert_eq!(fs.delete("/dir").err(), Some(FatError::NotFound));
+    }
+
+    #[test]
+    fn test_rename_and_move() {
+        let image = TestImage::new("rename");
+        let mut fs = image.fresh_fs();
+        fs.create_directory("/a").unwrap();
+        fs.create_directory("/b").unwrap();
+        fs.create_directory("/a/inner").unwrap();
+        fs.write_file("/a/inner/file.txt", b"contents").unwrap();
+
+        // When I rename a file within its directory
+        fs.rename("/a/inner/file.txt", "/a/inner/renamed file.txt")
+            .unwrap();
+        assert_eq!(
+            fs.read_file("/a/inner/renamed file.txt").unwrap(),
+            b"contents"
+        );
+        assert_eq!(fs.exists("/a/inner/file.txt"), Ok(false));
+
+        // And move a directory into another directory
+        fs.rename("/a/inner", "/b/moved").unwrap();
+        assert_eq!(
+            fs.read_file("/b/moved/renamed file.txt").unwrap(),
+            b"contents"
+        );
+        assert!(fs.read_dir("/a").unwrap().is_empty());
+
+        // Then the moved directory can still be deleted from its new location
+        fs.delete("/b/moved/renamed file.txt").unwrap();
+        fs.delete("/b/moved").unwrap();
+
+        // And a directory can't be moved inside itself
+        assert_eq!(fs.rename("/b", "/b/c").err(), Some(FatError::InvalidName));
+        assert_eq!(fs.rename("/a", "/b").err(), Some(FatError::AlreadyExists));
+    }
+
+    #[test]
+    fn test_contents_persist_across_mounts() {
+        // Given a volume with some files
+        let image = TestImage::new("persistence");
+        let mut fs = image.fresh_fs();
+        fs.create_directory("/config").unwrap();
+        fs.write_file("/config/preferences.txt", b"theme=dark")
+            .unwrap();
+
+        // When the volume is unmounted and mounted again from the disk image
+        let mut fs = image.remount(fs);
+
+        // Then the files are still there
+        assert_eq!(
+            fs.read_file("/config/preferences.txt").unwrap(),
+            b"theme=dark"
+        );
+    }
+
+    #[test]
+    fn test_invalid_names() {
+        let image = TestImage::new("invalid_names");
+        let mut fs = image.fresh_fs();
+        assert_eq!(
+            fs.write_file("/a:b", b"").err(),
+            Some(FatError::InvalidName)
+        );
+        assert_eq!(fs.create_directory("/").err(), Some(FatError::InvalidName));
+        assert_eq!(fs.delete("/").err(), Some(FatError::InvalidName));
+    }
+
+    #[test]
+    fn test_write_file_part_size_limit() {
+        let image = TestImage::new("part_limit");
+        let mut fs = image.fresh_fs();
+        fs.write_file("/file", b"data").unwrap();
+        // Files can't grow past 4GiB, and the offset is rejected before anything is allocated
+        assert_eq!(
+            fs.write_file_part("/file", u32::MAX as usize, b"a").err(),
+            Some(FatError::NoSpace)
+        );
+        assert_eq!(
+            fs.write_file_part("/file", usize::MAX, b"a").err(),
+            Some(FatError::NoSpace)
+        );
+        assert_eq!(fs.read_file("/file").unwrap(), b"data");
+    }
+
+    #[test]
+    fn test_format_rejects_fat16_sized_volumes() {
+        // A 32MiB volume has too few clusters to be FAT32
+        let image = TestImage::new("too_small");
+        let sector_count = (32 * 1024 * 1024) / 512;
+        let device = FileBlockDevice::create(&image.0, sector_count).unwrap();
+        assert_eq!(
+            FatFs::format(device, sector_count, "axle").err(),
+            Some(FatError::NoSpace)
+        );
+    }
+
+    #[test]
+    fn test_mount_rejects_volume_without_data_region() {
+        // Given a volume whose boot sector claims fewer sectors than its FATs occupy
+        let image = TestImage::new("no_data_region");
+        let mut device = image.fresh_fs().into_device();
+        let mut boot_sector = [0; SECTOR_SIZE];
+        device.read_sectors(0, &mut boot_sector).unwrap();
+        write_u32(&mut boot_sector, 32, 64);
+        device.write_sectors(0, &boot_sector).unwrap();
+
+        // Then it isn't mounted
+        assert_eq!(
+            FatFs::mount(device).err(),
+            Some(FatError::InvalidFilesystem)
+        );
+    }
+}
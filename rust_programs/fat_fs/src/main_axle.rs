@@ -0,0 +1,357 @@
+use core::cmp::min;
+
+use axle_rt::{
+    amc_message_await_untyped, amc_message_send, amc_register_service, printf, AmcMessage,
+    ExpectsEventField,
+};
+use file_manager_messages::{
+    str_from_u8_nul_utf8_unchecked, CheckFileExists, CheckFileExistsResponse, CreateDirectory,
+    CreateDirectoryResponse, CreateFile, CreateFileResponse, DeleteEntry, DeleteEntryResponse,
+    DirectoryContents, DirectoryEntry, FileMetadata, FileOperationStatus, FileType, ReadDirectory,
+    ReadFile, ReadFilePart, ReadFilePartResponse, ReadFileResponse, RenameEntry,
+    RenameEntryResponse, StatPath, StatPathResponse, WriteFile, WriteFilePart,
+    WriteFilePartResponse, WriteFileResponse, FAT_FS_SERVICE_NAME,
+};
+use gpt_helper::Guid;
+use partition_manager_messages::{list_partitions, PARTITION_MANAGER_SERVICE_NAME};
+use sata_driver_messages::{BlockDeviceStatus, RemoteBlockDevice, WHOLE_DISK_DEVICE};
+
+use crate::block_device::{BlockDevice, BlockDeviceError, SECTOR_SIZE};
+use crate::fat::{FatDirEntry, FatError, FatFs};
+
+/// Accesses a partition through the partition manager, which reads the disk through the block
+/// cache
+struct PartitionDevice(RemoteBlockDevice);
+
+fn block_device_error(status: BlockDeviceStatus) -> BlockDeviceError {
+    match status {
+        BlockDeviceStatus::OutOfRange => BlockDeviceError::OutOfRange,
+        _ => BlockDeviceError::Io,
+    }
+}
+
+impl BlockDevice for PartitionDevice {
+    fn read_sectors(&mut self, start_sector: u64, buf: &mut [u8]) -> Result<(), BlockDeviceError> {
+        assert!(buf.len() % SECTOR_SIZE == 0, "Buffer is not sector-aligned");
+        let data = self
+            .0
+            .read_sectors(start_sector, (buf.len() / SECTOR_SIZE) as u64)
+            .map_err(block_device_error)?;
+        buf.copy_from_slice(&data);
+        Ok(())
+    }
+
+    fn write_sectors(&mut self, start_sector: u64, buf: &[u8]) -> Result<(), BlockDeviceError> {
+        self.0
+            .write_sectors(start_sector, buf)
+            .map_err(block_device_error)
+    }
+}
+
+unsafe fn body_as_type_unchecked<T>(body: &[u8]) -> &T {
+    &*(body.as_ptr() as *const T)
+}
+
+fn status_from_error(e: FatError) -> FileOperationStatus {
+    match e {
+        FatError::NotFound => FileOperationStatus::NotFound,
+        FatError::NotADirectory => FileOperationStatus::NotADirectory,
+        FatError::IsADirectory => FileOperationStatus::IsDirectory,
+        FatError::AlreadyExists => FileOperationStatus::AlreadyExists,
+        FatError::DirectoryNotEmpty => FileOperationStatus::DirectoryNotEmpty,
+        FatError::InvalidName => FileOperationStatus::InvalidPath,
+        FatError::NoSpace => FileOperationStatus::NoSpace,
+        FatError::OutOfRange => FileOperationStatus::OutOfRange,
+        FatError::InvalidFilesystem | FatError::Io => FileOperationStatus::IoError,
+    }
+}
+
+fn status_of(operation: &str, path: &str, result: Result<(), FatError>) -> FileOperationStatus {
+    match result {
+        Ok(()) => FileOperationStatus::Success,
+        Err(e) => {
+            printf!("{operation} {path} failed: {e:?}\n");
+            status_from_error(e)
+        }
+    }
+}
+
+// FAT timestamps aren't tracked yet, so modification times are reported as unknown
+fn metadata_of(entry: &FatDirEntry) -> FileMetadata {
+    match entry.is_directory {
+        true => FileMetadata::directory(),
+        false => FileMetadata::new(
+            entry.size as u64,
+            0,
+            false,
+            FileType::from_name(&entry.name),
+        ),
+    }
+}
+
+fn read_directory(fs: &mut FatFs<PartitionDevice>, sender: &str, request: &ReadDirectory) {
+    let requested_dir = str_from_u8_nul_utf8_unchecked(&request.dir);
+    match fs.read_dir(requested_dir) {
+        Ok(entries) => {
+            let mut response = DirectoryContents::new(FileOperationStatus::Success);
+            let entry_count = min(entries.len(), response.entries.len());
+            if entry_count < entries.len() {
+                printf!("Truncating listing of {requested_dir} to {entry_count} entries\n");
+            }
+            for (slot, entry) in response.entries.iter_mut().zip(entries.iter()) {
+                *slot = Some(DirectoryEntry::new(&entry.name, metadata_of(entry)));
+            }
+            amc_message_send(sender, response);
+        }
+        Err(e) => {
+            printf!("Failed to read directory {requested_dir}: {e:?}\n");
+            amc_message_send(sender, DirectoryContents::new(status_from_error(e)));
+        }
+    }
+}
+
+fn read_file(fs: &mut FatFs<PartitionDevice>, sender: &str, request: &ReadFile) {
+    let requested_path = str_from_u8_nul_utf8_unchecked(&request.path);
+    match fs.read_file(requested_path) {
+        Ok(data) => ReadFileResponse::send(sender, requested_path, &data),
+        Err(e) => {
+            printf!("Failed to read {requested_path}: {e:?}\n");
+            ReadFileResponse::send_error(sender, requested_path, status_from_error(e));
+        }
+    }
+}
+
+fn read_file_part(fs: &mut FatFs<PartitionDevice>, sender: &str, request: &ReadFilePart) {
+    let requested_path = str_from_u8_nul_utf8_unchecked(&request.path);
+    match fs.read_file_part(requested_path, request.offset, request.len) {
+        Ok(data) => ReadFilePartResponse::send(sender, requested_path, &data),
+        Err(e) => {
+            printf!("Failed to read {requested_path}: {e:?}\n");
+            ReadFilePartResponse::send_error(sender, requested_path, status_from_error(e));
+        }
+    }
+}
+
+fn check_file_exists(fs: &mut FatFs<PartitionDevice>, sender: &str, request: &CheckFileExists) {
+    let requested_path = str_from_u8_nul_utf8_unchecked(&request.path);
+    let (exists, file_size) = match fs.stat(requested_path) {
+        Ok(Some(entry)) => (true, entry.size as usize),
+        // The root directory
+        Ok(None) => (true, 0),
+        Err(_) => (false, 0),
+    };
+    CheckFileExistsResponse::send(sender, requested_path, exists, file_size);
+}
+
+fn stat_path(fs: &mut FatFs<PartitionDevice>, sender: &str, request: &StatPath) {
+    let requested_path = str_from_u8_nul_utf8_unchecked(&request.path);
+    let (status, metadata) = match fs.stat(requested_path) {
+        Ok(Some(entry)) => (FileOperationStatus::Success, metadata_of(&entry)),
+        // The root directory
+        Ok(None) => (FileOperationStatus::Success, FileMetadata::directory()),
+        Err(e) => (
+            status_from_error(e),
+            FileMetadata::new(0, 0, false, FileType::Unknown),
+        ),
+    };
+    StatPathResponse::send(sender, requested_path, status, metadata);
+}
+
+fn write_file(
+    fs: &mut FatFs<PartitionDevice>,
+    sender: &str,
+    request: &WriteFile,
+    message_len: usize,
+) {
+    let requested_path = str_from_u8_nul_utf8_unchecked(&request.path);
+    let data = match unsafe { request.data(message_len) } {
+        Some(data) => data,
+        None => {
+            printf!("Dropping malformed WriteFile of {requested_path} from {sender}\n");
+            WriteFileResponse::send(sender, requested_path, FileOperationStatus::IoError);
+            return;
+        }
+    };
+    printf!(
+        "Writing {} bytes to {requested_path} for {sender}\n",
+        data.len()
+    );
+    let result = fs.write_file(requested_path, data);
+    WriteFileResponse::send(
+        sender,
+        requested_path,
+        status_of("Write", requested_path, result),
+    );
+}
+
+fn write_file_part(
+    fs: &mut FatFs<PartitionDevice>,
+    sender: &str,
+    request: &WriteFilePart,
+    message_len: usize,
+) {
+    let requested_path = str_from_u8_nul_utf8_unchecked(&request.path);
+    let data = match unsafe { request.data(message_len) } {
+        Some(data) => data,
+        None => {
+            printf!("Dropping malformed WriteFilePart of {requested_path} from {sender}\n");
+            WriteFilePartResponse::send(sender, requested_path, FileOperationStatus::IoError);
+            return;
+        }
+    };
+    let result = fs.write_file_part(requested_path, request.offset, data);
+    WriteFilePartResponse::send(
+        sender,
+        requested_path,
+        status_of("Partial write", requested_path, result),
+    );
+}
+
+fn create_file(fs: &mut FatFs<PartitionDevice>, sender: &str, request: &CreateFile) {
+    let requested_path = str_from_u8_nul_utf8_unchecked(&request.path);
+    let result = fs.create_file(requested_path);
+    CreateFileResponse::send(
+        sender,
+        requested_path,
+        status_of("Create file", requested_path, result),
+    );
+}
+
+fn create_directory(fs: &mut FatFs<PartitionDevice>, sender: &str, request: &CreateDirectory) {
+    let requested_path = str_from_u8_nul_utf8_unchecked(&request.path);
+    let result = fs.create_directory(requested_path);
+    CreateDirectoryResponse::send(
+        sender,
+        requested_path,
+        status_of("Create directory", requested_path, result),
+    );
+}
+
+fn delete_entry(fs: &mut FatFs<PartitionDevice>, sender: &str, request: &DeleteEntry) {
+    let requested_path = str_from_u8_nul_utf8_unchecked(&request.path);
+    let result = fs.delete(requested_path);
+    DeleteEntryResponse::send(
+        sender,
+        requested_path,
+        status_of("Delete", requested_path, result),
+    );
+}
+
+fn rename_entry(fs: &mut FatFs<PartitionDevice>, sender: &str, request: &RenameEntry) {
+    let from_path = str_from_u8_nul_utf8_unchecked(&request.from_path);
+    let to_path = str_from_u8_nul_utf8_unchecked(&request.to_path);
+    let result = fs.rename(from_path, to_path);
+    RenameEntryResponse::send(
+        sender,
+        from_path,
+        to_path,
+        status_of("Rename", from_path, result),
+    );
+}
+
+/// On a GPT disk, the volume is the first basic data or EFI system partition, in partition table
+/// order, that holds a FAT32 filesystem. A disk without a partition table is expected to hold a
+/// single volume spanning the whole disk, like those prepared on the host with
+/// `fat_fs <image> format <size_in_mb>`.
+fn mount_volume() -> Result<FatFs<PartitionDevice>, FatError> {
+    let partitions = list_partitions().map_err(|e| {
+        printf!("Failed to list partitions: {e:?}\n");
+        FatError::Io
+    })?;
+    if partitions.is_empty() {
+        let whole_disk = RemoteBlockDevice::new(PARTITION_MANAGER_SERVICE_NAME, WHOLE_DISK_DEVICE);
+        return FatFs::mount(PartitionDevice(whole_disk));
+    }
+
+    let candidates = partitions.iter().filter(|p| {
+        p.type_guid() == Guid::BASIC_DATA || p.type_guid() == Guid::EFI_SYSTEM_PARTITION
+    });
+    for partition in candidates {
+        match FatFs::mount(PartitionDevice(partition.block_device())) {
+            Ok(fs) => {
+                printf!("Using partition \"{}\"\n", partition.name());
+                return Ok(fs);
+            }
+            Err(e) => printf!(
+                "Partition \"{}\" doesn't hold a FAT32 volume: {e:?}\n",
+                partition.name()
+            ),
+        }
+    }
+    Err(FatError::NotFound)
+}
+
+pub fn main() {
+    amc_register_service(FAT_FS_SERVICE_NAME);
+
+    let mut fs = match mount_volume() {
+        Ok(fs) => fs,
+        Err(e) => {
+            printf!("Failed to mount FAT32 volume: {e:?}\n");
+            return;
+        }
+    };
+    printf!("Mounted FAT32 volume\n");
+
+    loop {
+        let msg_unparsed: AmcMessage<[u8]> = unsafe { amc_message_await_untyped(None).unwrap() };
+
+        // Parse the first bytes of the message as a u32 event field
+        let raw_body = msg_unparsed.body();
+        let event = u32::from_ne_bytes(
+            // We must slice the array to the exact size of a u32 for the conversion to succeed
+            raw_body[..core::mem::size_of::<u32>()]
+                .try_into()
+                .expect("Failed to get 4-length array from message body"),
+        );
+
+        // Each inner call to body_as_type_unchecked is unsafe because we must be
+        // sure we're casting to the right type.
+        // Since we verify the type on the LHS, each usage is safe.
+        unsafe {
+            let sender = msg_unparsed.source();
+            match event {
+                ReadDirectory::EXPECTED_EVENT => {
+                    read_directory(&mut fs, sender, body_as_type_unchecked(raw_body))
+                }
+                ReadFile::EXPECTED_EVENT => {
+                    read_file(&mut fs, sender, body_as_type_unchecked(raw_body))
+                }
+                ReadFilePart::EXPECTED_EVENT => {
+                    read_file_part(&mut fs, sender, body_as_type_unchecked(raw_body))
+                }
+                CheckFileExists::EXPECTED_EVENT => {
+                    check_file_exists(&mut fs, sender, body_as_type_unchecked(raw_body))
+                }
+                StatPath::EXPECTED_EVENT => {
+                    stat_path(&mut fs, sender, body_as_type_unchecked(raw_body))
+                }
+                WriteFile::EXPECTED_EVENT => write_file(
+                    &mut fs,
+                    sender,
+                    body_as_type_unchecked(raw_body),
+                    raw_body.len(),
+                ),
+                WriteFilePart::EXPECTED_EVENT => write_file_part(
+                    &mut fs,
+                    sender,
+                    body_as_type_unchecked(raw_body),
+                    raw_body.len(),
+                ),
+                CreateFile::EXPECTED_EVENT => {
+                    create_file(&mut fs, sender, body_as_type_unchecked(raw_body))
+                }
+                CreateDirectory::EXPECTED_EVENT => {
+                    create_directory(&mut fs, sender, body_as_type_unchecked(raw_body))
+                }
+                DeleteEntry::EXPECTED_EVENT => {
+                    delete_entry(&mut fs, sender, body_as_type_unchecked(raw_body))
+                }
+                RenameEntry::EXPECTED_EVENT => {
+                    rename_entry(&mut fs, sender, body_as_type_unchecked(raw_body))
+                }
+                _ => printf!("Unknown event from {sender}: {event}\n"),
+            }
+        }
+    }
+}
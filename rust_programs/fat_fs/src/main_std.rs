@@ -0,0 +1,70 @@
+use std::env;
+use std::fs;
+use std::process::exit;
+
+use crate::block_device::FileBlockDevice;
+use crate::fat::FatFs;
+
+fn usage() -> ! {
+    eprintln!("Usage: fat_fs <image> format <size_in_mb>");
+    eprintln!("       fat_fs <image> ls <dir>");
+    eprintln!("       fat_fs <image> cat <path>");
+    eprintln!("       fat_fs <image> put <host_path> <path>");
+    eprintln!("       fat_fs <image> mkdir <path>");
+    eprintln!("       fat_fs <image> rm <path>");
+    eprintln!("       fat_fs <image> mv <from_path> <to_path>");
+    exit(1);
+}
+
+pub fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 4 {
+        usage();
+    }
+    let image_path = &args[1];
+    let command = args[2].as_str();
+
+    if command == "format" {
+        let size_in_mb: u64 = args[3].parse().unwrap_or_else(|_| usage());
+        let sector_count = (size_in_mb * 1024 * 1024) / 512;
+        let device = FileBlockDevice::create(image_path, sector_count)
+            .unwrap_or_else(|e| panic!("Failed to create {image_path}: {e}"));
+        FatFs::format(device, sector_count, "axle")
+            .unwrap_or_else(|e| panic!("Failed to format {image_path}: {e:?}"));
+        println!("Formatted {image_path} ({size_in_mb}MB)");
+        return;
+    }
+
+    let device = FileBlockDevice::open(image_path)
+        .unwrap_or_else(|e| panic!("Failed to open {image_path}: {e}"));
+    let mut fs =
+        FatFs::mount(device).unwrap_or_else(|e| panic!("Failed to mount {image_path}: {e:?}"));
+
+    let result = match (command, &args[3..]) {
+        ("ls", [dir]) => fs.read_dir(dir).map(|entries| {
+            for entry in entries {
+                if entry.is_directory {
+                    println!("{}/", entry.name);
+                } else {
+                    println!("{} ({} bytes)", entry.name, entry.size);
+                }
+            }
+        }),
+        ("cat", [path]) => fs
+            .read_file(path)
+            .map(|data| print!("{}", String::from_utf8_lossy(&data))),
+        ("put", [host_path, path]) => {
+            let data =
+                fs::read(host_path).unwrap_or_else(|e| panic!("Failed to read {host_path}: {e}"));
+            fs.write_file(path, &data)
+        }
+        ("mkdir", [path]) => fs.create_directory(path),
+        ("rm", [path]) => fs.delete(path),
+        ("mv", [from_path, to_path]) => fs.rename(from_path, to_path),
+        _ => usage(),
+    };
+    if let Err(e) = result {
+        eprintln!("{command} failed: {e:?}");
+        exit(1);
+    }
+}
@@ -23,8 +23,7 @@ use libgui::view::View;
 use libgui::AwmWindow;
 
 use axle_rt::{
-    amc_message_await, amc_message_send, amc_register_service,
-    core_commands::AmcQueryServiceRequest, printf, println, AmcMessage,
+    amc_message_send, amc_register_service, core_commands::AmcQueryServiceRequest, printf, println,
 };
 
 use agx_definitions::{
@@ -33,8 +32,8 @@ use agx_definitions::{
 };
 
 use file_manager_messages::{
-    str_from_u8_nul_utf8_unchecked, DirectoryContents, DirectoryEntry, LaunchProgram,
-    ReadDirectory, FILE_SERVER_SERVICE_NAME,
+    find_files, launch_program, read_directory, str_from_u8_nul_utf8_unchecked, DirectoryEntry,
+    LaunchProgram, FILE_SERVER_SERVICE_NAME,
 };
 use libgui::scroll_view::ScrollView;
 
@@ -49,6 +48,7 @@ struct CurrentPathView {
     current_path_label: RefCell<Rc<Label>>,
     pub current_path: RefCell<String>,
     pub back_button: Rc<Button>,
+    pub find_all_button: Rc<Button>,
 }
 
 impl CurrentPathView {
@@ -74,6 +74,15 @@ impl CurrentPathView {
         let back_button_clone = Rc::clone(&back_button);
         Rc::clone(&view).add_component(back_button_clone);
 
+        let find_all_button = Button::new("Find All", None, |_b, superview_size| {
+            let size = Size::new(80, 30);
+            Rect::from_parts(
+                Point::new(100, superview_size.height - size.height - 10),
+                size,
+            )
+        });
+        Rc::clone(&view).add_component(Rc::clone(&find_all_button));
+
         // TODO(PT): Read shortcuts from /config/file_browser/shortcuts.txt
 
         CurrentPathView {
@@ -81,6 +90,7 @@ impl CurrentPathView {
             current_path_label: RefCell::new(Rc::clone(&current_path_label)),
             current_path: RefCell::new(initial_path.to_string()),
             back_button,
+            find_all_button,
         }
     }
 
@@ -164,6 +174,48 @@ impl UIElement for CurrentPathView {
     }
 }
 
+fn is_search_query(path: &str) -> bool {
+    path.contains(|ch| ch == '*' || ch == '?')
+}
+
+/// Lists a directory, or runs a search if the path contains wildcards.
+/// Search results are named by their full path.
+fn read_entries_at_path(path: &str) -> Vec<DirectoryEntry> {
+    if !is_search_query(path) {
+        return read_directory(path).unwrap_or_else(|e| {
+            println!("Failed to read directory {path}: {e:?}");
+            Vec::new()
+        });
+    }
+    match find_files(path) {
+        Ok((matches, truncated)) => {
+            if truncated {
+                println!(
+                    "Only showing the first {} matches for {path}",
+                    matches.len()
+                );
+            }
+            matches
+        }
+        Err(e) => {
+            println!("Failed to search for {path}: {e:?}");
+            Vec::new()
+        }
+    }
+}
+
+fn format_file_size(size: u64) -> String {
+    const KB: u64 = 1024;
+    const MB: u64 = KB * 1024;
+    if size < KB {
+        format!("{size} B")
+    } else if size < MB {
+        format!("{} KB", size / KB)
+    } else {
+        format!("{} MB", size / MB)
+    }
+}
+
 struct DirectoryEntryView {
     view: Rc<View>,
     pub entry: DirectoryEntry,
@@ -202,6 +254,23 @@ impl DirectoryEntryView {
         // TODO(PT): Set font size as attribute?
         Rc::clone(&view).add_component(name_label);
 
+        if !entry.is_directory {
+            let size_label_width = 100;
+            let size_label = Rc::new(Label::new(
+                &format_file_size(entry.metadata.size),
+                Color::new(30, 30, 30),
+                move |_, superview_size| {
+                    // Sits just to the left of the divider drawn before the button
+                    let origin_x = superview_size.width - button_width - 10 - 20 - size_label_width;
+                    Rect::from_parts(
+                        Point::new(origin_x, 10),
+                        Size::new(size_label_width, height),
+                    )
+                },
+            ));
+            Rc::clone(&view).add_component(size_label);
+        }
+
         let button_text = match entry.is_directory {
             true => "Browse",
             false => "Open",
@@ -348,21 +417,13 @@ impl DirectoryContentsView {
         //println!("Got ScrollView");
 
         // TODO(PT): Should return the normalized path (ie strip extra slashes and normalize ../)
-        amc_message_send(FILE_SERVER_SERVICE_NAME, ReadDirectory::new(path));
-        let dir_contents: AmcMessage<DirectoryContents> =
-            amc_message_await(Some(FILE_SERVER_SERVICE_NAME));
+        let dir_entries = read_entries_at_path(path);
 
         let entry_height = 30;
 
         let mut dir_entry_views = Vec::new();
 
-        for (i, entry) in dir_contents
-            .body()
-            .entries
-            .iter()
-            .filter_map(|e| e.as_ref())
-            .enumerate()
-        {
+        for (i, entry) in dir_entries.iter().enumerate() {
             let entry_view = Rc::new(DirectoryEntryView::new(
                 i,
                 *entry,
@@ -497,6 +558,14 @@ impl FileBrowser2 {
                 Rc::clone(&browser_clone_for_back_button_closure).browse_back();
             });
 
+        let browser_clone_for_find_all_button_closure = Rc::clone(&browser);
+        current_path_view_clone2
+            .find_all_button
+            .on_left_click(move |_b| {
+                printf!("Find all button clicked!\n");
+                Rc::clone(&browser_clone_for_find_all_button_closure).find_all_below_current_path();
+            });
+
         let browser_clone = Rc::clone(&browser);
         let dir_contents_view = FileBrowser2::create_directory_contents_view(browser_clone);
         //let dir_contents_view_clone = Rc::clone(&dir_contents_view);
@@ -538,15 +607,19 @@ impl FileBrowser2 {
                 entry_view.button.on_left_click(move |_b| {
                     printf!("Button with path {:?} clicked!\n", path);
                     let browser_clone = Rc::clone(&browser_clone);
-                    let full_path = format!(
-                        "{}/{}",
-                        browser_clone
-                            .current_path_view
-                            .borrow()
-                            .current_path
-                            .borrow(),
-                        path
-                    );
+                    // Search results are already named by their full path
+                    let full_path = match path.starts_with("/") {
+                        true => path.clone(),
+                        false => format!(
+                            "{}/{}",
+                            browser_clone
+                                .current_path_view
+                                .borrow()
+                                .current_path
+                                .borrow(),
+                            path
+                        ),
+                    };
 
                     if [".bmp", ".jpg", ".jpeg"]
                         .iter()
@@ -557,7 +630,7 @@ impl FileBrowser2 {
                         image_viewer_messages::LoadImage::send(&full_path);
                     } else {
                         printf!("\tSeems to be a program, launching...\n");
-                        amc_message_send(FILE_SERVER_SERVICE_NAME, LaunchProgram::new(&full_path));
+                        launch_program(&full_path, &[]);
                     }
                 });
             }
@@ -632,6 +705,11 @@ impl FileBrowser2 {
     }
 
     fn browse_by_appending_path_component(self: Rc<FileBrowser2>, path_component: &str) {
+        // Directories in search results are named by their full path
+        if path_component.starts_with("/") {
+            self.browse_to_path(path_component);
+            return;
+        }
         let path = self
             .current_path_view
             .borrow()
@@ -645,6 +723,25 @@ impl FileBrowser2 {
         self.browse_to_path(&new_path);
     }
 
+    /// Shows every file and directory nested anywhere beneath the current directory
+    fn find_all_below_current_path(self: Rc<FileBrowser2>) {
+        let path = self
+            .current_path_view
+            .borrow()
+            .current_path
+            .borrow()
+            .clone();
+        if is_search_query(&path) {
+            printf!("Already showing search results for {path}\n");
+            return;
+        }
+        let pattern = match path.as_str() {
+            "/" => "/**/*".to_string(),
+            _ => format!("{path}/**/*"),
+        };
+        self.browse_to_path(&pattern);
+    }
+
     fn pop_from_history(self: Rc<FileBrowser2>) -> Option<String> {
         let mut history = self.history.borrow_mut();
         history.pop()
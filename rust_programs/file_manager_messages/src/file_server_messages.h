@@ -7,6 +7,19 @@
 
 #define FILE_SERVER_SERVICE_NAME "com.axle.file_server"
 
+// Mirrors FileOperationStatus
+#define FILE_SERVER_STATUS_SUCCESS 0
+#define FILE_SERVER_STATUS_NOT_FOUND 1
+#define FILE_SERVER_STATUS_ALREADY_EXISTS 2
+#define FILE_SERVER_STATUS_IS_DIRECTORY 3
+#define FILE_SERVER_STATUS_NOT_A_DIRECTORY 4
+#define FILE_SERVER_STATUS_DIRECTORY_NOT_EMPTY 5
+#define FILE_SERVER_STATUS_INVALID_PATH 6
+#define FILE_SERVER_STATUS_NO_SPACE 7
+#define FILE_SERVER_STATUS_IO_ERROR 8
+#define FILE_SERVER_STATUS_OUT_OF_RANGE 9
+#define FILE_SERVER_STATUS_PERMISSION_DENIED 10
+
 #define FILE_SERVER_READ_FILE_EVENT 100
 typedef struct file_server_read {
     uint32_t event;
@@ -16,6 +29,7 @@ typedef struct file_server_read {
 typedef struct file_server_read_response {
     uint32_t event;
     char path[64];
+    uint32_t status;
     uintptr_t len;
     uint8_t data[];
 } file_server_read_response_t;
@@ -53,6 +67,7 @@ typedef struct file_SERVER_read_file_partial_request {
 typedef struct file_server_read_file_partial_response {
     uint32_t event;
     char path[64];
+    uint32_t status;
     uintptr_t data_length;
     uint8_t file_data[];
 } file_server_read_file_partial_response_t;
@@ -4,10 +4,12 @@ extern crate alloc;
 
 #[cfg(target_os = "axle")]
 mod conditional_imports {
-    pub use alloc::alloc::alloc;
     pub use alloc::alloc::Layout;
-    pub use alloc::vec::Vec;
-    pub use axle_rt::{amc_message_send, amc_message_send_untyped};
+    pub use alloc::alloc::{alloc, dealloc};
+    pub use axle_rt::{
+        amc_message_await__u32_event, amc_message_await__u32_event_untyped, amc_message_send,
+        amc_message_send_untyped, serialized::amc_message_send_serialized, AmcMessage,
+    };
     pub use core::mem::align_of;
     pub use core::mem::size_of;
     pub use core::ptr::copy_nonoverlapping;
@@ -17,10 +19,14 @@ mod conditional_imports {}
 
 use crate::conditional_imports::*;
 
+use alloc::vec::Vec;
 use axle_rt::{copy_str_into_sized_slice, ContainsEventField, ExpectsEventField};
 use axle_rt_derive::ContainsEventField;
+use serde::{Deserialize, Serialize};
 
 pub const FILE_SERVER_SERVICE_NAME: &'static str = "com.axle.file_server";
+// Persistent, writable storage backed by a FAT32 volume on the SATA drive
+pub const FAT_FS_SERVICE_NAME: &'static str = "com.axle.fat_fs";
 
 pub fn str_from_u8_nul_utf8_unchecked(utf8_src: &[u8]) -> &str {
     let nul_range_end = utf8_src
@@ -30,6 +36,73 @@ pub fn str_from_u8_nul_utf8_unchecked(utf8_src: &[u8]) -> &str {
     unsafe { core::str::from_utf8_unchecked(&utf8_src[0..nul_range_end]) }
 }
 
+/// The outcome of a request to the file server
+#[repr(u32)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FileOperationStatus {
+    Success,
+    NotFound,
+    AlreadyExists,
+    IsDirectory,
+    NotADirectory,
+    DirectoryNotEmpty,
+    InvalidPath,
+    NoSpace,
+    IoError,
+    /// A partial read started past the end of the file
+    OutOfRange,
+    PermissionDenied,
+}
+
+impl TryFrom<u32> for FileOperationStatus {
+    type Error = u32;
+
+    fn try_from(value: u32) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(FileOperationStatus::Success),
+            1 => Ok(FileOperationStatus::NotFound),
+            2 => Ok(FileOperationStatus::AlreadyExists),
+            3 => Ok(FileOperationStatus::IsDirectory),
+            4 => Ok(FileOperationStatus::NotADirectory),
+            5 => Ok(FileOperationStatus::DirectoryNotEmpty),
+            6 => Ok(FileOperationStatus::InvalidPath),
+            7 => Ok(FileOperationStatus::NoSpace),
+            8 => Ok(FileOperationStatus::IoError),
+            9 => Ok(FileOperationStatus::OutOfRange),
+            10 => Ok(FileOperationStatus::PermissionDenied),
+            other => Err(other),
+        }
+    }
+}
+
+// Responses are read straight out of message bodies, so the status is kept as the raw u32 the
+// sender wrote. Reading it as a FileOperationStatus directly would be UB for unknown values.
+macro_rules! impl_status_accessor {
+    ($($response:ty),*) => {
+        $(
+            impl $response {
+                /// Statuses this build doesn't know about are reported as IoError
+                pub fn status(&self) -> FileOperationStatus {
+                    FileOperationStatus::try_from(self.status).unwrap_or(FileOperationStatus::IoError)
+                }
+            }
+        )*
+    };
+}
+
+impl FileOperationStatus {
+    pub fn is_success(&self) -> bool {
+        *self == FileOperationStatus::Success
+    }
+
+    pub fn into_result(self) -> Result<(), FileOperationStatus> {
+        match self {
+            FileOperationStatus::Success => Ok(()),
+            e => Err(e),
+        }
+    }
+}
+
 #[repr(C)]
 #[derive(Debug, ContainsEventField)]
 pub struct ReadFile {
@@ -57,6 +130,7 @@ impl ExpectsEventField for ReadFile {
 pub struct ReadFileResponse {
     pub event: u32,
     pub path: [u8; 64],
+    status: u32,
     pub len: usize,
     pub data: [u8; 0],
 }
@@ -70,6 +144,7 @@ impl ReadFileResponse {
             let mut s = alloc(layout) as *mut ReadFileResponse;
             (*s).event = Self::EXPECTED_EVENT;
             copy_str_into_sized_slice(&mut (*s).path, path);
+            (*s).status = FileOperationStatus::Success as u32;
             (*s).len = data.len();
             copy_nonoverlapping(data.as_ptr(), (*s).data.as_mut_ptr(), data.len());
             amc_message_send_untyped(service, s as *const u8, total_size);
@@ -77,6 +152,40 @@ impl ReadFileResponse {
     }
 }
 
+#[cfg(target_os = "axle")]
+impl ReadFileResponse {
+    pub fn send_error(service: &str, path: &str, status: FileOperationStatus) {
+        let mut response = ReadFileResponse {
+            event: Self::EXPECTED_EVENT,
+            path: [0; 64],
+            status: status as u32,
+            len: 0,
+            data: [],
+        };
+        copy_str_into_sized_slice(&mut response.path, path);
+        amc_message_send(service, response);
+    }
+}
+
+impl ReadFileResponse {
+    /// Returns the trailing data, or None if it doesn't fit in the delivered message.
+    ///
+    /// # Safety
+    ///
+    /// `self` must point to the start of a delivered message body that is `message_len` bytes
+    /// long.
+    pub unsafe fn data(&self, message_len: usize) -> Option<&[u8]> {
+        let end = core::mem::size_of::<Self>().checked_add(self.len)?;
+        if end > message_len {
+            return None;
+        }
+        Some(&*core::ptr::slice_from_raw_parts(
+            self.data.as_ptr(),
+            self.len,
+        ))
+    }
+}
+
 impl ExpectsEventField for ReadFileResponse {
     const EXPECTED_EVENT: u32 = 100;
 }
@@ -105,18 +214,79 @@ impl ExpectsEventField for ReadDirectory {
     const EXPECTED_EVENT: u32 = 101;
 }
 
+/// A hint about what a file contains, so that clients can pick an icon or a program to open it with
+#[repr(u32)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum FileType {
+    Unknown,
+    Directory,
+    Executable,
+    Text,
+    Image,
+    Font,
+    GameRom,
+}
+
+impl FileType {
+    /// Guesses the type of a (non-executable) file from its extension
+    pub fn from_name(name: &str) -> Self {
+        let extension = match name.rsplit_once('.') {
+            Some((_, extension)) => extension,
+            None => return FileType::Unknown,
+        };
+        match extension.to_ascii_lowercase().as_str() {
+            "txt" | "md" | "cfg" | "c" | "h" | "rs" | "s" | "asm" | "py" | "sh" => FileType::Text,
+            "bmp" | "png" | "jpg" | "jpeg" => FileType::Image,
+            "ttf" | "otf" => FileType::Font,
+            "gb" | "gbc" => FileType::GameRom,
+            _ => FileType::Unknown,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct FileMetadata {
+    pub size: u64,
+    /// Seconds since the Unix epoch, or 0 if unknown
+    pub modified: u64,
+    pub is_executable: bool,
+    pub file_type: FileType,
+}
+
+impl FileMetadata {
+    pub fn new(size: u64, modified: u64, is_executable: bool, file_type: FileType) -> Self {
+        Self {
+            size,
+            modified,
+            is_executable,
+            file_type,
+        }
+    }
+
+    pub fn directory() -> Self {
+        Self::new(0, 0, false, FileType::Directory)
+    }
+
+    pub fn is_directory(&self) -> bool {
+        self.file_type == FileType::Directory
+    }
+}
+
 #[repr(C)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct DirectoryEntry {
     pub name: [u8; 64],
     pub is_directory: bool,
+    pub metadata: FileMetadata,
 }
 
 impl DirectoryEntry {
-    pub fn new(name: &str, is_directory: bool) -> Self {
+    pub fn new(name: &str, metadata: FileMetadata) -> Self {
         let mut ret = DirectoryEntry {
             name: [0; 64],
-            is_directory,
+            is_directory: metadata.is_directory(),
+            metadata,
         };
         copy_str_into_sized_slice(&mut ret.name, name);
         ret
@@ -129,9 +299,20 @@ impl DirectoryEntry {
 #[derive(Debug, ContainsEventField)]
 pub struct DirectoryContents {
     pub event: u32,
+    status: u32,
     pub entries: [Option<DirectoryEntry>; 128],
 }
 
+impl DirectoryContents {
+    pub fn new(status: FileOperationStatus) -> Self {
+        Self {
+            event: Self::EXPECTED_EVENT,
+            status: status as u32,
+            entries: [None; 128],
+        }
+    }
+}
+
 impl ExpectsEventField for DirectoryContents {
     const EXPECTED_EVENT: u32 = 101;
 }
@@ -160,6 +341,19 @@ impl ExpectsEventField for LaunchProgram {
     const EXPECTED_EVENT: u32 = 102;
 }
 
+/// Launches a program without a limit on the length of its path, and passes it arguments.
+/// The body is serialized (see `axle_rt::serialized`) rather than sent as a fixed-size struct.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct LaunchProgramWithArguments<'a> {
+    pub path: &'a str,
+    #[serde(borrow)]
+    pub arguments: Vec<&'a str>,
+}
+
+impl ExpectsEventField for LaunchProgramWithArguments<'_> {
+    const EXPECTED_EVENT: u32 = 114;
+}
+
 // File metadata
 
 #[repr(C)]
@@ -244,6 +438,7 @@ impl ExpectsEventField for ReadFilePart {
 pub struct ReadFilePartResponse {
     pub event: u32,
     pub path: [u8; 64],
+    status: u32,
     pub data_len: usize,
     pub data: [u8; 0],
 }
@@ -257,6 +452,7 @@ impl ReadFilePartResponse {
             let mut s = alloc(layout) as *mut ReadFilePartResponse;
             (*s).event = Self::EXPECTED_EVENT;
             copy_str_into_sized_slice(&mut (*s).path, path);
+            (*s).status = FileOperationStatus::Success as u32;
             (*s).data_len = data.len();
             copy_nonoverlapping(data.as_ptr(), (*s).data.as_mut_ptr(), data.len());
             amc_message_send_untyped(service, s as *const u8, total_size);
@@ -264,6 +460,733 @@ impl ReadFilePartResponse {
     }
 }
 
+#[cfg(target_os = "axle")]
+impl ReadFilePartResponse {
+    pub fn send_error(service: &str, path: &str, status: FileOperationStatus) {
+        let mut response = ReadFilePartResponse {
+            event: Self::EXPECTED_EVENT,
+            path: [0; 64],
+            status: status as u32,
+            data_len: 0,
+            data: [],
+        };
+        copy_str_into_sized_slice(&mut response.path, path);
+        amc_message_send(service, response);
+    }
+}
+
+impl ReadFilePartResponse {
+    /// Returns the trailing data, or None if it doesn't fit in the delivered message.
+    ///
+    /// # Safety
+    ///
+    /// `self` must point to the start of a delivered message body that is `message_len` bytes
+    /// long.
+    pub unsafe fn data(&self, message_len: usize) -> Option<&[u8]> {
+        let end = core::mem::size_of::<Self>().checked_add(self.data_len)?;
+        if end > message_len {
+            return None;
+        }
+        Some(&*core::ptr::slice_from_raw_parts(
+            self.data.as_ptr(),
+            self.data_len,
+        ))
+    }
+}
+
 impl ExpectsEventField for ReadFilePartResponse {
     const EXPECTED_EVENT: u32 = 104;
 }
+
+// Writing files
+
+#[repr(C)]
+#[derive(Debug, ContainsEventField)]
+pub struct WriteFile {
+    pub event: u32,
+    pub path: [u8; 64],
+    pub len: usize,
+    pub data: [u8; 0],
+}
+
+#[cfg(target_os = "axle")]
+impl WriteFile {
+    pub fn send(service: &str, path: &str, data: &[u8]) {
+        let total_size = size_of::<WriteFile>() + data.len();
+        let layout = Layout::from_size_align(total_size, align_of::<usize>()).unwrap();
+        unsafe {
+            let s = alloc(layout) as *mut WriteFile;
+            (*s).event = Self::EXPECTED_EVENT;
+            (*s).path = [0; 64];
+            copy_str_into_sized_slice(&mut (*s).path, path);
+            (*s).len = data.len();
+            copy_nonoverlapping(data.as_ptr(), (*s).data.as_mut_ptr(), data.len());
+            amc_message_send_untyped(service, s as *const u8, total_size);
+            dealloc(s as *mut u8, layout);
+        }
+    }
+}
+
+impl WriteFile {
+    /// Returns the trailing data, or None if it doesn't fit in the delivered message.
+    ///
+    /// # Safety
+    ///
+    /// `self` must point to the start of a delivered message body that is `message_len` bytes
+    /// long.
+    pub unsafe fn data(&self, message_len: usize) -> Option<&[u8]> {
+        let end = core::mem::size_of::<Self>().checked_add(self.len)?;
+        if end > message_len {
+            return None;
+        }
+        Some(&*core::ptr::slice_from_raw_parts(
+            self.data.as_ptr(),
+            self.len,
+        ))
+    }
+}
+
+impl ExpectsEventField for WriteFile {
+    const EXPECTED_EVENT: u32 = 105;
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, ContainsEventField)]
+pub struct WriteFileResponse {
+    pub event: u32,
+    pub path: [u8; 64],
+    status: u32,
+}
+
+#[cfg(target_os = "axle")]
+impl WriteFileResponse {
+    pub fn send(service: &str, path: &str, status: FileOperationStatus) {
+        let mut response = WriteFileResponse {
+            event: Self::EXPECTED_EVENT,
+            path: [0; 64],
+            status: status as u32,
+        };
+        copy_str_into_sized_slice(&mut response.path, path);
+        amc_message_send(service, response);
+    }
+}
+
+impl ExpectsEventField for WriteFileResponse {
+    const EXPECTED_EVENT: u32 = 105;
+}
+
+// Creating directories
+
+#[repr(C)]
+#[derive(Debug, ContainsEventField)]
+pub struct CreateDirectory {
+    pub event: u32,
+    pub path: [u8; 64],
+}
+
+impl CreateDirectory {
+    pub fn new(path: &str) -> Self {
+        let mut s = Self {
+            event: Self::EXPECTED_EVENT,
+            path: [0; 64],
+        };
+        copy_str_into_sized_slice(&mut s.path, path);
+        s
+    }
+}
+
+impl ExpectsEventField for CreateDirectory {
+    const EXPECTED_EVENT: u32 = 106;
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, ContainsEventField)]
+pub struct CreateDirectoryResponse {
+    pub event: u32,
+    pub path: [u8; 64],
+    status: u32,
+}
+
+#[cfg(target_os = "axle")]
+impl CreateDirectoryResponse {
+    pub fn send(service: &str, path: &str, status: FileOperationStatus) {
+        let mut response = CreateDirectoryResponse {
+            event: Self::EXPECTED_EVENT,
+            path: [0; 64],
+            status: status as u32,
+        };
+        copy_str_into_sized_slice(&mut response.path, path);
+        amc_message_send(service, response);
+    }
+}
+
+impl ExpectsEventField for CreateDirectoryResponse {
+    const EXPECTED_EVENT: u32 = 106;
+}
+
+// Deleting files and directories
+
+#[repr(C)]
+#[derive(Debug, ContainsEventField)]
+pub struct DeleteEntry {
+    pub event: u32,
+    pub path: [u8; 64],
+}
+
+impl DeleteEntry {
+    pub fn new(path: &str) -> Self {
+        let mut s = Self {
+            event: Self::EXPECTED_EVENT,
+            path: [0; 64],
+        };
+        copy_str_into_sized_slice(&mut s.path, path);
+        s
+    }
+}
+
+impl ExpectsEventField for DeleteEntry {
+    const EXPECTED_EVENT: u32 = 107;
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, ContainsEventField)]
+pub struct DeleteEntryResponse {
+    pub event: u32,
+    pub path: [u8; 64],
+    status: u32,
+}
+
+#[cfg(target_os = "axle")]
+impl DeleteEntryResponse {
+    pub fn send(service: &str, path: &str, status: FileOperationStatus) {
+        let mut response = DeleteEntryResponse {
+            event: Self::EXPECTED_EVENT,
+            path: [0; 64],
+            status: status as u32,
+        };
+        copy_str_into_sized_slice(&mut response.path, path);
+        amc_message_send(service, response);
+    }
+}
+
+impl ExpectsEventField for DeleteEntryResponse {
+    const EXPECTED_EVENT: u32 = 107;
+}
+
+// Renaming and moving files and directories
+
+#[repr(C)]
+#[derive(Debug, ContainsEventField)]
+pub struct RenameEntry {
+    pub event: u32,
+    pub from_path: [u8; 64],
+    pub to_path: [u8; 64],
+}
+
+impl RenameEntry {
+    pub fn new(from_path: &str, to_path: &str) -> Self {
+        let mut s = Self {
+            event: Self::EXPECTED_EVENT,
+            from_path: [0; 64],
+            to_path: [0; 64],
+        };
+        copy_str_into_sized_slice(&mut s.from_path, from_path);
+        copy_str_into_sized_slice(&mut s.to_path, to_path);
+        s
+    }
+}
+
+impl ExpectsEventField for RenameEntry {
+    const EXPECTED_EVENT: u32 = 108;
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, ContainsEventField)]
+pub struct RenameEntryResponse {
+    pub event: u32,
+    pub from_path: [u8; 64],
+    pub to_path: [u8; 64],
+    status: u32,
+}
+
+#[cfg(target_os = "axle")]
+impl RenameEntryResponse {
+    pub fn send(service: &str, from_path: &str, to_path: &str, status: FileOperationStatus) {
+        let mut response = RenameEntryResponse {
+            event: Self::EXPECTED_EVENT,
+            from_path: [0; 64],
+            to_path: [0; 64],
+            status: status as u32,
+        };
+        copy_str_into_sized_slice(&mut response.from_path, from_path);
+        copy_str_into_sized_slice(&mut response.to_path, to_path);
+        amc_message_send(service, response);
+    }
+}
+
+impl ExpectsEventField for RenameEntryResponse {
+    const EXPECTED_EVENT: u32 = 108;
+}
+
+// Partial file writes
+
+#[repr(C)]
+#[derive(Debug, ContainsEventField)]
+pub struct WriteFilePart {
+    pub event: u32,
+    pub path: [u8; 64],
+    pub offset: usize,
+    pub len: usize,
+    pub data: [u8; 0],
+}
+
+#[cfg(target_os = "axle")]
+impl WriteFilePart {
+    pub fn send(service: &str, path: &str, offset: usize, data: &[u8]) {
+        let total_size = size_of::<WriteFilePart>() + data.len();
+        let layout = Layout::from_size_align(total_size, align_of::<usize>()).unwrap();
+        unsafe {
+            let s = alloc(layout) as *mut WriteFilePart;
+            (*s).event = Self::EXPECTED_EVENT;
+            (*s).path = [0; 64];
+            copy_str_into_sized_slice(&mut (*s).path, path);
+            (*s).offset = offset;
+            (*s).len = data.len();
+            copy_nonoverlapping(data.as_ptr(), (*s).data.as_mut_ptr(), data.len());
+            amc_message_send_untyped(service, s as *const u8, total_size);
+            dealloc(s as *mut u8, layout);
+        }
+    }
+}
+
+impl WriteFilePart {
+    /// Returns the trailing data, or None if it doesn't fit in the delivered message.
+    ///
+    /// # Safety
+    ///
+    /// `self` must point to the start of a delivered message body that is `message_len` bytes
+    /// long.
+    pub unsafe fn data(&self, message_len: usize) -> Option<&[u8]> {
+        let end = core::mem::size_of::<Self>().checked_add(self.len)?;
+        if end > message_len {
+            return None;
+        }
+        Some(&*core::ptr::slice_from_raw_parts(
+            self.data.as_ptr(),
+            self.len,
+        ))
+    }
+}
+
+impl ExpectsEventField for WriteFilePart {
+    const EXPECTED_EVENT: u32 = 109;
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, ContainsEventField)]
+pub struct WriteFilePartResponse {
+    pub event: u32,
+    pub path: [u8; 64],
+    status: u32,
+}
+
+#[cfg(target_os = "axle")]
+impl WriteFilePartResponse {
+    pub fn send(service: &str, path: &str, status: FileOperationStatus) {
+        let mut response = WriteFilePartResponse {
+            event: Self::EXPECTED_EVENT,
+            path: [0; 64],
+            status: status as u32,
+        };
+        copy_str_into_sized_slice(&mut response.path, path);
+        amc_message_send(service, response);
+    }
+}
+
+impl ExpectsEventField for WriteFilePartResponse {
+    const EXPECTED_EVENT: u32 = 109;
+}
+
+// Creating empty files
+
+#[repr(C)]
+#[derive(Debug, ContainsEventField)]
+pub struct CreateFile {
+    pub event: u32,
+    pub path: [u8; 64],
+}
+
+impl CreateFile {
+    pub fn new(path: &str) -> Self {
+        let mut s = Self {
+            event: Self::EXPECTED_EVENT,
+            path: [0; 64],
+        };
+        copy_str_into_sized_slice(&mut s.path, path);
+        s
+    }
+}
+
+impl ExpectsEventField for CreateFile {
+    const EXPECTED_EVENT: u32 = 110;
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, ContainsEventField)]
+pub struct CreateFileResponse {
+    pub event: u32,
+    pub path: [u8; 64],
+    status: u32,
+}
+
+#[cfg(target_os = "axle")]
+impl CreateFileResponse {
+    pub fn send(service: &str, path: &str, status: FileOperationStatus) {
+        let mut response = CreateFileResponse {
+            event: Self::EXPECTED_EVENT,
+            path: [0; 64],
+            status: status as u32,
+        };
+        copy_str_into_sized_slice(&mut response.path, path);
+        amc_message_send(service, response);
+    }
+}
+
+impl ExpectsEventField for CreateFileResponse {
+    const EXPECTED_EVENT: u32 = 110;
+}
+
+// Mapping whole files via shared memory
+// Large files (ROMs, fonts, images) don't fit comfortably in a single AMC message, so the
+// file server can instead copy the file into a buffer shared with the requester.
+
+#[repr(C)]
+#[derive(Debug, ContainsEventField)]
+pub struct MapFile {
+    pub event: u32,
+    pub path: [u8; 64],
+}
+
+impl MapFile {
+    pub fn new(path: &str) -> Self {
+        let mut s = Self {
+            event: Self::EXPECTED_EVENT,
+            path: [0; 64],
+        };
+        copy_str_into_sized_slice(&mut s.path, path);
+        s
+    }
+}
+
+impl ExpectsEventField for MapFile {
+    const EXPECTED_EVENT: u32 = 111;
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, ContainsEventField)]
+pub struct MapFileResponse {
+    pub event: u32,
+    pub path: [u8; 64],
+    status: u32,
+    pub len: usize,
+    // Address of the file contents within the requester's address space
+    pub remote_buffer_start: usize,
+}
+
+#[cfg(target_os = "axle")]
+impl MapFileResponse {
+    pub fn send(
+        service: &str,
+        path: &str,
+        status: FileOperationStatus,
+        len: usize,
+        remote_buffer_start: usize,
+    ) {
+        let mut response = MapFileResponse {
+            event: Self::EXPECTED_EVENT,
+            path: [0; 64],
+            status: status as u32,
+            len,
+            remote_buffer_start,
+        };
+        copy_str_into_sized_slice(&mut response.path, path);
+        amc_message_send(service, response);
+    }
+}
+
+impl ExpectsEventField for MapFileResponse {
+    const EXPECTED_EVENT: u32 = 111;
+}
+
+// Querying file metadata
+
+#[repr(C)]
+#[derive(Debug, ContainsEventField)]
+pub struct StatPath {
+    pub event: u32,
+    pub path: [u8; 64],
+}
+
+impl StatPath {
+    pub fn new(path: &str) -> Self {
+        let mut s = Self {
+            event: Self::EXPECTED_EVENT,
+            path: [0; 64],
+        };
+        copy_str_into_sized_slice(&mut s.path, path);
+        s
+    }
+}
+
+impl ExpectsEventField for StatPath {
+    const EXPECTED_EVENT: u32 = 112;
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, ContainsEventField)]
+pub struct StatPathResponse {
+    pub event: u32,
+    pub path: [u8; 64],
+    status: u32,
+    pub metadata: FileMetadata,
+}
+
+#[cfg(target_os = "axle")]
+impl StatPathResponse {
+    pub fn send(service: &str, path: &str, status: FileOperationStatus, metadata: FileMetadata) {
+        let mut response = StatPathResponse {
+            event: Self::EXPECTED_EVENT,
+            path: [0; 64],
+            status: status as u32,
+            metadata,
+        };
+        copy_str_into_sized_slice(&mut response.path, path);
+        amc_message_send(service, response);
+    }
+}
+
+impl ExpectsEventField for StatPathResponse {
+    const EXPECTED_EVENT: u32 = 112;
+}
+
+// Searching
+
+/// Finds every entry matching a glob pattern, such as `/usr/applications/*` or `/**/*.txt`.
+/// `*` and `?` match within a path component, and a `**` component matches any number of
+/// nested directories.
+#[repr(C)]
+#[derive(Debug, ContainsEventField)]
+pub struct FindFiles {
+    pub event: u32,
+    pub pattern: [u8; 64],
+}
+
+impl FindFiles {
+    pub fn new(pattern: &str) -> Self {
+        let mut s = Self {
+            event: Self::EXPECTED_EVENT,
+            pattern: [0; 64],
+        };
+        copy_str_into_sized_slice(&mut s.pattern, pattern);
+        s
+    }
+}
+
+impl ExpectsEventField for FindFiles {
+    const EXPECTED_EVENT: u32 = 113;
+}
+
+/// Each match's name holds its full path
+#[repr(C)]
+#[derive(Debug, ContainsEventField)]
+pub struct FindFilesResponse {
+    pub event: u32,
+    status: u32,
+    /// Set if there were more matches than fit in `matches`
+    pub truncated: bool,
+    pub matches: [Option<DirectoryEntry>; 128],
+}
+
+impl FindFilesResponse {
+    pub fn new(status: FileOperationStatus) -> Self {
+        Self {
+            event: Self::EXPECTED_EVENT,
+            status: status as u32,
+            truncated: false,
+            matches: [None; 128],
+        }
+    }
+}
+
+impl ExpectsEventField for FindFilesResponse {
+    const EXPECTED_EVENT: u32 = 113;
+}
+
+impl_status_accessor!(
+    ReadFileResponse,
+    DirectoryContents,
+    ReadFilePartResponse,
+    WriteFileResponse,
+    CreateDirectoryResponse,
+    DeleteEntryResponse,
+    RenameEntryResponse,
+    WriteFilePartResponse,
+    CreateFileResponse,
+    MapFileResponse,
+    StatPathResponse,
+    FindFilesResponse
+);
+
+// Client helpers
+// Each of these sends a request to the file server and blocks until the response arrives
+
+/// Awaits a response that carries trailing data, along with the length it was delivered with
+#[cfg(target_os = "axle")]
+fn await_response_with_data<T: ExpectsEventField>(
+) -> Result<(&'static T, usize), FileOperationStatus> {
+    let msg = unsafe {
+        amc_message_await__u32_event_untyped(FILE_SERVER_SERVICE_NAME, T::EXPECTED_EVENT)
+    }
+    .map_err(|_| FileOperationStatus::IoError)?;
+    if msg.body.len() < size_of::<T>() {
+        return Err(FileOperationStatus::IoError);
+    }
+    Ok((unsafe { &*(msg.body.as_ptr() as *const T) }, msg.body.len()))
+}
+
+#[cfg(target_os = "axle")]
+pub fn read_file(path: &str) -> Result<Vec<u8>, FileOperationStatus> {
+    amc_message_send(FILE_SERVER_SERVICE_NAME, ReadFile::new(path));
+    let (response, message_len) = await_response_with_data::<ReadFileResponse>()?;
+    response.status().into_result()?;
+    let data = unsafe { response.data(message_len) }.ok_or(FileOperationStatus::IoError)?;
+    Ok(data.to_vec())
+}
+
+#[cfg(target_os = "axle")]
+pub fn read_file_part(
+    path: &str,
+    offset: usize,
+    len: usize,
+) -> Result<Vec<u8>, FileOperationStatus> {
+    amc_message_send(
+        FILE_SERVER_SERVICE_NAME,
+        ReadFilePart::new(path, offset, len),
+    );
+    let (response, message_len) = await_response_with_data::<ReadFilePartResponse>()?;
+    response.status().into_result()?;
+    let data = unsafe { response.data(message_len) }.ok_or(FileOperationStatus::IoError)?;
+    Ok(data.to_vec())
+}
+
+/// Maps the file into this process without sending its contents through AMC.
+/// The kernel can't tear down shared memory yet, so the mapping lives as long as the process.
+/// Mapping a file again reuses the existing buffer as long as the file hasn't changed since.
+#[cfg(target_os = "axle")]
+pub fn map_file(path: &str) -> Result<&'static [u8], FileOperationStatus> {
+    amc_message_send(FILE_SERVER_SERVICE_NAME, MapFile::new(path));
+    let response: AmcMessage<MapFileResponse> =
+        amc_message_await__u32_event(FILE_SERVER_SERVICE_NAME);
+    let response = response.body();
+    response.status().into_result()?;
+    if response.len == 0 {
+        return Ok(&[]);
+    }
+    Ok(unsafe {
+        core::slice::from_raw_parts(response.remote_buffer_start as *const u8, response.len)
+    })
+}
+
+#[cfg(target_os = "axle")]
+pub fn read_directory(path: &str) -> Result<Vec<DirectoryEntry>, FileOperationStatus> {
+    amc_message_send(FILE_SERVER_SERVICE_NAME, ReadDirectory::new(path));
+    let response: AmcMessage<DirectoryContents> =
+        amc_message_await__u32_event(FILE_SERVER_SERVICE_NAME);
+    let response = response.body();
+    response.status().into_result()?;
+    Ok(response.entries.iter().filter_map(|e| *e).collect())
+}
+
+#[cfg(target_os = "axle")]
+pub fn launch_program(path: &str, arguments: &[&str]) {
+    amc_message_send_serialized(
+        FILE_SERVER_SERVICE_NAME,
+        &LaunchProgramWithArguments {
+            path,
+            arguments: arguments.to_vec(),
+        },
+    );
+}
+
+/// Returns the matches in path order, along with whether the server had to leave some out
+#[cfg(target_os = "axle")]
+pub fn find_files(pattern: &str) -> Result<(Vec<DirectoryEntry>, bool), FileOperationStatus> {
+    amc_message_send(FILE_SERVER_SERVICE_NAME, FindFiles::new(pattern));
+    let response: AmcMessage<FindFilesResponse> =
+        amc_message_await__u32_event(FILE_SERVER_SERVICE_NAME);
+    let response = response.body();
+    response.status().into_result()?;
+    let matches = response.matches.iter().filter_map(|e| *e).collect();
+    Ok((matches, response.truncated))
+}
+
+#[cfg(target_os = "axle")]
+pub fn stat_path(path: &str) -> Result<FileMetadata, FileOperationStatus> {
+    amc_message_send(FILE_SERVER_SERVICE_NAME, StatPath::new(path));
+    let response: AmcMessage<StatPathResponse> =
+        amc_message_await__u32_event(FILE_SERVER_SERVICE_NAME);
+    let response = response.body();
+    response.status().into_result()?;
+    Ok(response.metadata)
+}
+
+#[cfg(target_os = "axle")]
+pub fn write_file(path: &str, data: &[u8]) -> Result<(), FileOperationStatus> {
+    WriteFile::send(FILE_SERVER_SERVICE_NAME, path, data);
+    let response: AmcMessage<WriteFileResponse> =
+        amc_message_await__u32_event(FILE_SERVER_SERVICE_NAME);
+    response.body().status().into_result()
+}
+
+#[cfg(target_os = "axle")]
+pub fn write_file_part(path: &str, offset: usize, data: &[u8]) -> Result<(), FileOperationStatus> {
+    WriteFilePart::send(FILE_SERVER_SERVICE_NAME, path, offset, data);
+    let response: AmcMessage<WriteFilePartResponse> =
+        amc_message_await__u32_event(FILE_SERVER_SERVICE_NAME);
+    response.body().status().into_result()
+}
+
+#[cfg(target_os = "axle")]
+pub fn create_file(path: &str) -> Result<(), FileOperationStatus> {
+    amc_message_send(FILE_SERVER_SERVICE_NAME, CreateFile::new(path));
+    let response: AmcMessage<CreateFileResponse> =
+        amc_message_await__u32_event(FILE_SERVER_SERVICE_NAME);
+    response.body().status().into_result()
+}
+
+#[cfg(target_os = "axle")]
+pub fn create_directory(path: &str) -> Result<(), FileOperationStatus> {
+    amc_message_send(FILE_SERVER_SERVICE_NAME, CreateDirectory::new(path));
+    let response: AmcMessage<CreateDirectoryResponse> =
+        amc_message_await__u32_event(FILE_SERVER_SERVICE_NAME);
+    response.body().status().into_result()
+}
+
+#[cfg(target_os = "axle")]
+pub fn delete_entry(path: &str) -> Result<(), FileOperationStatus> {
+    amc_message_send(FILE_SERVER_SERVICE_NAME, DeleteEntry::new(path));
+    let response: AmcMessage<DeleteEntryResponse> =
+        amc_message_await__u32_event(FILE_SERVER_SERVICE_NAME);
+    response.body().status().into_result()
+}
+
+#[cfg(target_os = "axle")]
+pub fn rename_entry(from_path: &str, to_path: &str) -> Result<(), FileOperationStatus> {
+    amc_message_send(
+        FILE_SERVER_SERVICE_NAME,
+        RenameEntry::new(from_path, to_path),
+    );
+    let response: AmcMessage<RenameEntryResponse> =
+        amc_message_await__u32_event(FILE_SERVER_SERVICE_NAME);
+    response.body().status().into_result()
+}
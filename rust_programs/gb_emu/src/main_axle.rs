@@ -165,12 +165,25 @@ impl GraphicsLayer for Window {
 pub fn main() {
     amc_register_service("com.axle.gameboy");
 
-    let window = Window::new("GameBoy", Size::new(160 * 2, 144 * 2));
+    // Load the ROMs before opening a window, so there's nothing to tear down if they're missing
+    let bootrom = match BootRom::new("/usr/roms/bootrom.gb") {
+        Ok(bootrom) => Rc::new(bootrom),
+        Err(e) => {
+            println!("{e}");
+            return;
+        }
+    };
+    let game_rom = match GameRom::new("/usr/roms/legend_of_zelda.gb") {
+        Ok(game_rom) => Rc::new(game_rom),
+        Err(e) => {
+            println!("{e}");
+            return;
+        }
+    };
 
-    let bootrom = Rc::new(BootRom::new("/usr/roms/bootrom.gb"));
+    let window = Window::new("GameBoy", Size::new(160 * 2, 144 * 2));
     let ppu = Rc::new(Ppu::new(Box::new(window)));
     let ppu_clone = Rc::clone(&ppu);
-    let game_rom = Rc::new(GameRom::new("/usr/roms/legend_of_zelda.gb"));
     let tile_ram = Rc::new(Ram::new(0x8000, 0x1800));
     let background_map = Rc::new(Ram::new(0x9800, 0x800));
     let oam_ram = Rc::new(Ram::new(0xfe00, 0xa0));
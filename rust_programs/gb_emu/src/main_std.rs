@@ -129,12 +129,12 @@ pub fn main() {
     };
     pixels.render().unwrap();
 
-    let bootrom = Rc::new(BootRom::new("/Users/philliptennen/Downloads/DMG_ROM.bin"));
+    let bootrom = Rc::new(BootRom::new("/Users/philliptennen/Downloads/DMG_ROM.bin").unwrap());
     //let ppu = Rc::new(Ppu::new(Box::new(pixels), Box::new(vram_debug_pixels)));
     let ppu = Rc::new(Ppu::new(Box::new(pixels)));
     let ppu_clone = Rc::clone(&ppu);
     let rom_path = &std::env::args().collect::<Vec<String>>()[1];
-    let game_rom = Rc::new(GameRom::new(&rom_path));
+    let game_rom = Rc::new(GameRom::new(&rom_path).unwrap());
     let tile_ram = Rc::new(Ram::new(0x8000, 0x1800));
     let background_map = Rc::new(Ram::new(0x9800, 0x800));
     let oam_ram = Rc::new(Ram::new(0xfe00, 0xa0));
@@ -298,11 +298,11 @@ fn main_debug() {
         .unwrap()
     };
 
-    let bootrom = Rc::new(BootRom::new("/Users/philliptennen/Downloads/DMG_ROM.bin"));
+    let bootrom = Rc::new(BootRom::new("/Users/philliptennen/Downloads/DMG_ROM.bin").unwrap());
     let ppu = Rc::new(Ppu::new(Box::new(pixels)));
     let ppu_clone = Rc::clone(&ppu);
     let rom_path = &std::env::args().collect::<Vec<String>>()[1];
-    let game_rom = Rc::new(GameRom::new(&rom_path));
+    let game_rom = Rc::new(GameRom::new(&rom_path).unwrap());
     let tile_ram = Rc::new(Ram::new(0x8000, 0x1800));
     let oam_ram = Rc::new(Ram::new(0xfe00, 0x00a0));
     let background_map = Rc::new(Ram::new(0x9800, 0x800));
@@ -1,3 +1,6 @@
+use alloc::borrow::Cow;
+use alloc::format;
+use alloc::string::String;
 use alloc::vec;
 use alloc::{rc::Rc, vec::Vec};
 use core::{
@@ -7,10 +10,7 @@ use core::{
 };
 
 #[cfg(not(feature = "use_std"))]
-use {
-    axle_rt::{amc_message_await, amc_message_send, println, AmcMessage},
-    file_manager_messages::{ReadFile, ReadFileResponse, FILE_SERVER_SERVICE_NAME},
-};
+use {axle_rt::println, file_manager_messages::map_file};
 
 use crate::gameboy::GameBoyHardwareProvider;
 
@@ -66,31 +66,29 @@ impl Mmu {
     }
 }
 
+fn read_rom_file(path: &str) -> Result<Cow<'static, [u8]>, String> {
+    #[cfg(feature = "use_std")]
+    let data = std::fs::read(path)
+        .map(Cow::Owned)
+        .map_err(|e| format!("Failed to read {path}: {e}"));
+    // ROMs can be several megabytes, so map them rather than copying them through a message
+    #[cfg(not(feature = "use_std"))]
+    let data = map_file(path)
+        .map(Cow::Borrowed)
+        .map_err(|e| format!("Failed to read {path}: {e:?}"));
+    data
+}
+
 pub struct BootRom {
-    data: Vec<u8>,
+    data: Cow<'static, [u8]>,
     is_disabled: RefCell<bool>,
 }
 
 impl BootRom {
     pub const BANK_REGISTER_ADDR: u16 = 0xff50;
 
-    pub fn new(bootrom_path: &str) -> Self {
-        #[cfg(feature = "use_std")]
-        let data = std::fs::read(bootrom_path).unwrap();
-        #[cfg(not(feature = "use_std"))]
-        let data = {
-            amc_message_send(FILE_SERVER_SERVICE_NAME, ReadFile::new(bootrom_path));
-            let rom_data_msg: AmcMessage<ReadFileResponse> =
-                amc_message_await(Some(FILE_SERVER_SERVICE_NAME));
-            unsafe {
-                let rom_data_slice = core::ptr::slice_from_raw_parts(
-                    (&rom_data_msg.body().data) as *const u8,
-                    rom_data_msg.body().len,
-                );
-                let rom_data: &mut [u8] = &mut *(rom_data_slice as *mut [u8]);
-                rom_data.to_vec()
-            }
-        };
+    pub fn new(bootrom_path: &str) -> Result<Self, String> {
+        let data = read_rom_file(bootrom_path)?;
 
         /*
         let data = if cfg!(feature = "use_std") {
@@ -100,16 +98,16 @@ impl BootRom {
             panic!("Cannot read bootROM on axle")
         };
         */
-        Self {
+        Ok(Self {
             is_disabled: RefCell::new(false),
             data,
-        }
+        })
     }
 
     fn from_bytes(bytes: &[u8]) -> Self {
         Self {
             is_disabled: RefCell::new(false),
-            data: bytes.to_vec(),
+            data: Cow::Owned(bytes.to_vec()),
         }
     }
 }
@@ -161,35 +159,27 @@ impl Display for MbcType {
 
 pub struct GameRom {
     mbc_type: MbcType,
-    data: RefCell<Vec<u8>>,
+    data: RefCell<Cow<'static, [u8]>>,
     current_rom_bank: RefCell<u8>,
     current_ram_bank: RefCell<u8>,
     ram_banks: RefCell<Vec<Vec<u8>>>,
 }
 
 impl GameRom {
-    pub fn new(rom_path: &str) -> Self {
-        #[cfg(feature = "use_std")]
-        let data = std::fs::read(rom_path).unwrap();
-
-        #[cfg(not(feature = "use_std"))]
-        //let data = [0xff; 1024 * 32].to_vec();
-        let data = {
-            amc_message_send(FILE_SERVER_SERVICE_NAME, ReadFile::new(rom_path));
-            let rom_data_msg: AmcMessage<ReadFileResponse> =
-                amc_message_await(Some(FILE_SERVER_SERVICE_NAME));
-            unsafe {
-                let rom_data_slice = core::ptr::slice_from_raw_parts(
-                    (&rom_data_msg.body().data) as *const u8,
-                    rom_data_msg.body().len,
-                );
-                let rom_data: &mut [u8] = &mut *(rom_data_slice as *mut [u8]);
-                rom_data.to_vec()
-            }
-        };
+    // The cartridge header, which includes the MBC type, ends at this address
+    const HEADER_END: usize = 0x0150;
+
+    pub fn new(rom_path: &str) -> Result<Self, String> {
+        let data = read_rom_file(rom_path)?;
 
         //assert!(data.len() <= 0x8000, "Cannot load ROMs larger than 32k yet");
         println!("Got ROM data with len {}", data.len());
+        if data.len() < Self::HEADER_END {
+            return Err(format!(
+                "{rom_path} is too small to be a ROM ({} bytes)",
+                data.len()
+            ));
+        }
 
         // MBC Ref: https://retrocomputing.stackexchange.com/questions/11732
         let mbc_type_discriminator = data[0x0147];
@@ -200,10 +190,10 @@ impl GameRom {
             // TODO(PT): Fake, check me!
             27 => MbcType::Mbc3,
             0xff => MbcType::NoMbc,
-            _ => panic!("Unknown MBC type {mbc_type_discriminator}"),
+            _ => return Err(format!("Unknown MBC type {mbc_type_discriminator}")),
         };
 
-        Self {
+        Ok(Self {
             mbc_type,
             data: RefCell::new(data),
             current_rom_bank: RefCell::new(1),
@@ -214,7 +204,7 @@ impl GameRom {
                 vec![0; 32 * 1024],
                 vec![0; 32 * 1024],
             ]),
-        }
+        })
     }
 }
 
@@ -417,7 +407,7 @@ impl Addressable for DmaController {
 mod tests {
     use std::rc::Rc;
 
-    use crate::mmu::{Addressable, BootRom, Mmu};
+    use crate::mmu::{Addressable, BootRom, GameRom, Mmu};
 
     use super::Ram;
 
@@ -457,4 +447,43 @@ mod tests {
         // reads from the overlapping RAM instead
         assert_eq!(mmu.read(0), 0xcc);
     }
+
+    #[test]
+    fn test_game_rom_missing_file() {
+        // When I load a ROM from a path that doesn't exist
+        // Then an error is returned, rather than crashing
+        assert!(GameRom::new("/nonexistent/rom.gb").is_err());
+    }
+
+    /// A ROM file in the temp directory that's deleted when the test finishes
+    struct TestRom(std::path::PathBuf);
+
+    impl TestRom {
+        fn new(test_name: &str, contents: &[u8]) -> Self {
+            let path =
+                std::env::temp_dir().join(format!("gb_emu_{}_{test_name}.gb", std::process::id()));
+            std::fs::write(&path, contents).unwrap();
+            Self(path)
+        }
+
+        fn path(&self) -> &str {
+            self.0.to_str().unwrap()
+        }
+    }
+
+    impl Drop for TestRom {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn test_game_rom_too_small() {
+        // Given a file that's too small to contain a cartridge header
+        let rom = TestRom::new("too_small_rom", &[0; 16]);
+
+        // When I load it as a ROM
+        // Then an error is returned, rather than indexing past the end of the data
+        assert!(GameRom::new(rom.path()).is_err());
+    }
 }
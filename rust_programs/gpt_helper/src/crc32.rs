@@ -0,0 +1,43 @@
+//! CRC-32 as used by GPT (UEFI Spec v2.9, §5.3.2), which is the common IEEE 802.3 variant:
+//! reflected polynomial 0xEDB88320, an initial value of all-ones, and an inverted result.
+
+const POLYNOMIAL: u32 = 0xedb88320;
+
+const TABLE: [u32; 256] = build_table();
+
+const fn build_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut value = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            value = match value & 1 {
+                1 => (value >> 1) ^ POLYNOMIAL,
+                _ => value >> 1,
+            };
+            bit += 1;
+        }
+        table[i] = value;
+        i += 1;
+    }
+    table
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0, |crc, byte| {
+        TABLE[((crc ^ *byte as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::crc32::crc32;
+
+    #[test]
+    fn test_check_value() {
+        // The standard check value for CRC-32/ISO-HDLC
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+        assert_eq!(crc32(&[]), 0);
+    }
+}
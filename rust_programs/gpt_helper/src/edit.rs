@@ -0,0 +1,352 @@
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::crc32::crc32;
+use crate::gpt::{
+    partition_entry_array_to_bytes, GptHeader, GptPartitionEntry, GPT_HEADER_SIZE,
+    GPT_PARTITION_ENTRY_SIZE, GPT_REVISION_1_0,
+};
+use crate::guid::Guid;
+use crate::mbr::ProtectiveMasterBootRecord;
+use crate::{GptError, HeaderLocation, PartitionTable, SectorWriter};
+
+/// The number of entries in tables created by `PartitionTable::new`.
+/// UEFI Spec v2.9, §5.3.2 requires at least 16KB to be reserved for the entry array.
+pub const DEFAULT_PARTITION_ENTRY_COUNT: u32 = 128;
+/// Partitions are placed on 1MB boundaries where they fit
+const PARTITION_ALIGNMENT_BYTES: u64 = 1024 * 1024;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum EditError {
+    /// The disk can't hold both copies of the partition table
+    DiskTooSmall,
+    NoSuchPartition,
+    /// Every entry in the partition entry array is in use
+    NoFreeEntry,
+    /// Partitions can't use the unused type GUID, or be empty
+    InvalidPartition,
+    /// There's no free range large enough for the partition
+    NoSpace,
+}
+
+impl PartitionTable {
+    /// An empty partition table for a disk of the provided size.
+    /// Nothing is written until `write` is called.
+    pub fn new(
+        disk_guid: Guid,
+        disk_sector_count: u64,
+        sector_size: usize,
+    ) -> Result<Self, EditError> {
+        let mut header = GptHeader {
+            revision: GPT_REVISION_1_0,
+            header_size: GPT_HEADER_SIZE,
+            header_crc32: 0,
+            my_lba: 1,
+            alternate_lba: disk_sector_count.saturating_sub(1),
+            first_usable_lba: 0,
+            last_usable_lba: 0,
+            disk_guid,
+            partition_entry_lba: 2,
+            partition_entry_count: DEFAULT_PARTITION_ENTRY_COUNT,
+            partition_entry_size: GPT_PARTITION_ENTRY_SIZE,
+            partition_entry_array_crc32: 0,
+        };
+        let entry_array_sectors = header.partition_entry_array_sectors(sector_size);
+        // The MBR, the primary header and entry array, then the alternate entry array and header
+        let reserved_sectors = 1 + 2 * (1 + entry_array_sectors);
+        if disk_sector_count <= reserved_sectors {
+            return Err(EditError::DiskTooSmall);
+        }
+        header.first_usable_lba = 2 + entry_array_sectors;
+        header.last_usable_lba = disk_sector_count - 2 - entry_array_sectors;
+        Ok(Self {
+            header,
+            header_location: HeaderLocation::Primary,
+            sector_size,
+            entries: vec![GptPartitionEntry::unused(); DEFAULT_PARTITION_ENTRY_COUNT as usize],
+        })
+    }
+
+    /// Writes the protective MBR, and both copies of the header and entry array with freshly
+    /// computed checksums. The alternate copy is placed at the end of `disk`, so this also
+    /// repairs a table whose primary or alternate copy was damaged. Any boot code in the MBR
+    /// is preserved.
+    pub fn write<D: SectorWriter>(&mut self, disk: &mut D) -> Result<(), GptError<D::Error>> {
+        let sector_size = disk.sector_size();
+        let disk_sector_count = disk.sector_count();
+        let entry_array_sectors = self.header.partition_entry_array_sectors(sector_size);
+        let alternate_lba = disk_sector_count.saturating_sub(1);
+        let alternate_entry_lba = alternate_lba.saturating_sub(entry_array_sectors);
+        if self.header.first_usable_lba < 2 + entry_array_sectors
+            || self.header.last_usable_lba >= alternate_entry_lba
+        {
+            return Err(GptError::DiskTooSmall);
+        }
+
+        let mut mbr_sector = vec![0; sector_size];
+        disk.read_sectors(0, &mut mbr_sector)
+            .map_err(GptError::Io)?;
+        let mbr = ProtectiveMasterBootRecord::new(disk_sector_count).to_bytes();
+        // Keep the bootstrap code, and replace the partition records and signature
+        mbr_sector[446..ProtectiveMasterBootRecord::SIZE].copy_from_slice(&mbr[446..]);
+        disk.write_sectors(0, &mbr_sector).map_err(GptError::Io)?;
+
+        let entry_array = partition_entry_array_to_bytes(
+            &self.entries,
+            self.header.partition_entry_size as usize,
+            sector_size,
+        );
+        let entry_array_crc32 =
+            crc32(&entry_array[..self.header.partition_entry_array_size() as usize]);
+
+        let mut primary = GptHeader {
+            my_lba: 1,
+            alternate_lba,
+            partition_entry_lba: 2,
+            partition_entry_array_crc32: entry_array_crc32,
+            ..self.header
+        };
+        primary.update_crc32();
+        let mut alternate = GptHeader {
+            my_lba: alternate_lba,
+            alternate_lba: 1,
+            partition_entry_lba: alternate_entry_lba,
+            ..primary
+        };
+        alternate.update_crc32();
+
+        // Each header is written after its entry array, so it never describes a stale array
+        for header in [&primary, &alternate] {
+            disk.write_sectors(header.partition_entry_lba, &entry_array)
+                .map_err(GptError::Io)?;
+            disk.write_sectors(header.my_lba, &header.to_bytes(sector_size))
+                .map_err(GptError::Io)?;
+        }
+        self.header = primary;
+        self.header_location = HeaderLocation::Primary;
+        Ok(())
+    }
+
+    /// The inclusive sector ranges of in-use entries, other than `except`, sorted by start
+    fn used_ranges(&self, except: Option<usize>) -> Vec<(u64, u64)> {
+        let mut ranges: Vec<(u64, u64)> = self
+            .entries
+            .iter()
+            .enumerate()
+            .filter(|(i, entry)| entry.is_used() && Some(*i) != except)
+            .map(|(_, entry)| (entry.starting_lba, entry.ending_lba))
+            .collect();
+        ranges.sort();
+        ranges
+    }
+
+    /// The inclusive sector ranges within the usable area that no partition occupies
+    pub fn free_ranges(&self) -> Vec<(u64, u64)> {
+        let mut free = Vec::new();
+        let mut next_free = self.header.first_usable_lba;
+        for (start, end) in self.used_ranges(None) {
+            if start > next_free {
+                free.push((next_free, start - 1));
+            }
+            next_free = core::cmp::max(next_free, end + 1);
+        }
+        if next_free <= self.header.last_usable_lba {
+            free.push((next_free, self.header.last_usable_lba));
+        }
+        free
+    }
+
+    /// Places a partition in the first free range that can hold it, and returns its entry index.
+    /// If `sector_count` is None, the partition fills the free range it's placed in.
+    pub fn add_partition(
+        &mut self,
+        type_guid: Guid,
+        unique_guid: Guid,
+        sector_count: Option<u64>,
+        name: &str,
+    ) -> Result<u32, EditError> {
+        if type_guid.is_unused() || sector_count == Some(0) {
+            return Err(EditError::InvalidPartition);
+        }
+        let index = self
+            .entries
+            .iter()
+            .position(|entry| !entry.is_used())
+            .ok_or(EditError::NoFreeEntry)?;
+
+        let alignment = core::cmp::max(1, PARTITION_ALIGNMENT_BYTES / self.sector_size as u64);
+        let free_ranges = self.free_ranges();
+        // Prefer an aligned start, but allow an unaligned one rather than failing
+        let candidates = free_ranges
+            .iter()
+            .map(|(start, end)| (start.div_ceil(alignment) * alignment, *end))
+            .chain(free_ranges.iter().copied());
+        for (start, end) in candidates {
+            if start > end {
+                continue;
+            }
+            let available = end - start + 1;
+            let count = sector_count.unwrap_or(available);
+            if count <= available {
+                self.entries[index] =
+                    GptPartitionEntry::new(type_guid, unique_guid, start, start + count - 1, name);
+                return Ok(index as u32);
+            }
+        }
+        Err(EditError::NoSpace)
+    }
+
+    pub fn delete_partition(&mut self, index: u32) -> Result<(), EditError> {
+        let entry = self.used_entry_mut(index)?;
+        *entry = GptPartitionEntry::unused();
+        Ok(())
+    }
+
+    /// Moves the end of a partition, keeping its start in place.
+    /// If `sector_count` is None, the partition grows into all the free space that follows it.
+    pub fn resize_partition(
+        &mut self,
+        index: u32,
+        sector_count: Option<u64>,
+    ) -> Result<(), EditError> {
+        if sector_count == Some(0) {
+            return Err(EditError::InvalidPartition);
+        }
+        let start = self.used_entry_mut(index)?.starting_lba;
+        let limit = self
+            .used_ranges(Some(index as usize))
+            .iter()
+            .map(|(other_start, _)| *other_start)
+            .find(|other_start| *other_start > start)
+            .map_or(self.header.last_usable_lba, |other_start| other_start - 1);
+        let end = match sector_count {
+            Some(count) => start + count - 1,
+            None => limit,
+        };
+        if end > limit {
+            return Err(EditError::NoSpace);
+        }
+        self.used_entry_mut(index)?.ending_lba = end;
+        Ok(())
+    }
+
+    fn used_entry_mut(&mut self, index: u32) -> Result<&mut GptPartitionEntry, EditError> {
+        match self.entries.get_mut(index as usize) {
+            Some(entry) if entry.is_used() => Ok(entry),
+            _ => Err(EditError::NoSuchPartition),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::edit::EditError;
+    use crate::test::{MemoryDisk, SECTOR_SIZE};
+    use crate::{Guid, HeaderLocation, PartitionTable};
+
+    // 8MB
+    const SECTOR_COUNT: u64 = 16384;
+
+    fn empty_table() -> PartitionTable {
+        PartitionTable::new(Guid([7; 16]), SECTOR_COUNT, SECTOR_SIZE).unwrap()
+    }
+
+    #[test]
+    fn test_create_and_read_back() {
+        let mut disk = MemoryDisk::new(SECTOR_COUNT);
+        let mut table = empty_table();
+        assert_eq!(table.header.first_usable_lba, 34);
+        assert_eq!(table.header.last_usable_lba, SECTOR_COUNT - 34);
+
+        let efi = table
+            .add_partition(Guid::EFI_SYSTEM_PARTITION, Guid([1; 16]), Some(2048), "EFI")
+            .unwrap();
+        let data = table
+            .add_partition(Guid::BASIC_DATA, Guid([2; 16]), None, "axle")
+            .unwrap();
+        table.write(&mut disk).unwrap();
+
+        let read = PartitionTable::read(&mut disk).unwrap();
+        assert_eq!(read.header_location, HeaderLocation::Primary);
+        assert_eq!(read.header.disk_guid, Guid([7; 16]));
+        let partitions = read.partitions();
+        assert_eq!(partitions.len(), 2);
+        // Partitions are placed on 1MB boundaries
+        assert_eq!(partitions[efi as usize].first_lba, 2048);
+        assert_eq!(partitions[efi as usize].sector_count(), 2048);
+        assert_eq!(partitions[data as usize].first_lba, 4096);
+        assert_eq!(partitions[data as usize].last_lba, SECTOR_COUNT - 34);
+        assert_eq!(partitions[data as usize].name, "axle");
+
+        // The alternate copy is complete on its own
+        disk.sector_mut(1).fill(0);
+        let read = PartitionTable::read(&mut disk).unwrap();
+        assert_eq!(read.header_location, HeaderLocation::Alternate);
+        assert_eq!(read.partitions(), partitions);
+    }
+
+    #[test]
+    fn test_write_repairs_damaged_copy() {
+        let mut disk = MemoryDisk::new(SECTOR_COUNT);
+        let mut table = empty_table();
+        table
+            .add_partition(Guid::BASIC_DATA, Guid([2; 16]), Some(100), "data")
+            .unwrap();
+        table.write(&mut disk).unwrap();
+
+        disk.sector_mut(2)[0] ^= 0xff;
+        let mut read = PartitionTable::read(&mut disk).unwrap();
+        assert_eq!(read.header_location, HeaderLocation::Alternate);
+        read.write(&mut disk).unwrap();
+        let repaired = PartitionTable::read(&mut disk).unwrap();
+        assert_eq!(repaired.header_location, HeaderLocation::Primary);
+        assert_eq!(repaired.partitions()[0].name, "data");
+    }
+
+    #[test]
+    fn test_delete_and_resize() {
+        let mut table = empty_table();
+        let a = table
+            .add_partition(Guid::BASIC_DATA, Guid([1; 16]), Some(2048), "a")
+            .unwrap();
+        let b = table
+            .add_partition(Guid::BASIC_DATA, Guid([2; 16]), Some(2048), "b")
+            .unwrap();
+
+        // `a` can't grow into `b`
+        assert_eq!(
+            table.resize_partition(a, Some(2049)),
+            Err(EditError::NoSpace)
+        );
+        table.resize_partition(a, Some(1024)).unwrap();
+        assert_eq!(table.partitions()[0].sector_count(), 1024);
+        // Growing into the free space that follows stops at the next partition
+        table.resize_partition(a, None).unwrap();
+        assert_eq!(table.partitions()[0].last_lba, 4095);
+
+        table.delete_partition(b).unwrap();
+        assert_eq!(table.delete_partition(b), Err(EditError::NoSuchPartition));
+        table.resize_partition(a, None).unwrap();
+        assert_eq!(table.partitions()[0].last_lba, SECTOR_COUNT - 34);
+        assert_eq!(table.free_ranges(), [(34, 2047)]);
+
+        // The freed entry is reused, and the partition lands in the remaining gap
+        let c = table
+            .add_partition(Guid::LINUX_FILESYSTEM, Guid([3; 16]), None, "c")
+            .unwrap();
+        assert_eq!(c, b);
+        assert_eq!(table.free_ranges(), []);
+        assert_eq!(
+            table.add_partition(Guid::BASIC_DATA, Guid([4; 16]), Some(1), "d"),
+            Err(EditError::NoSpace)
+        );
+    }
+
+    #[test]
+    fn test_disk_too_small() {
+        assert_eq!(
+            PartitionTable::new(Guid([7; 16]), 67, SECTOR_SIZE).unwrap_err(),
+            EditError::DiskTooSmall
+        );
+    }
+}
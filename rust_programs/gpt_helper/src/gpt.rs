@@ -0,0 +1,280 @@
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::crc32::crc32;
+use crate::guid::Guid;
+use crate::{read_u32, read_u64};
+
+pub const GPT_SIGNATURE: [u8; 8] = *b"EFI PART";
+pub const GPT_REVISION_1_0: u32 = 0x00010000;
+/// The size of the fields defined by the spec. Anything past this in the header is reserved.
+pub const GPT_HEADER_SIZE: u32 = 92;
+pub const GPT_PARTITION_ENTRY_SIZE: u32 = 128;
+/// Guards against allocating an absurd entry array for a corrupted header
+const MAX_PARTITION_ENTRY_ARRAY_SIZE: u64 = 4 * 1024 * 1024;
+
+/// Why a single copy of the GPT header (and its entry array) was rejected
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum HeaderError {
+    BadSignature,
+    UnsupportedRevision,
+    BadHeaderSize,
+    HeaderChecksumMismatch,
+    /// The header's `my_lba` doesn't match the sector it was read from
+    WrongLocation,
+    BadEntryArrayLayout,
+    EntryArrayChecksumMismatch,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct GptHeader {
+    // UEFI Spec v2.9, §Table 5-5
+    pub revision: u32,
+    pub header_size: u32,
+    pub header_crc32: u32,
+    pub my_lba: u64,
+    pub alternate_lba: u64,
+    pub first_usable_lba: u64,
+    pub last_usable_lba: u64,
+    pub disk_guid: Guid,
+    pub partition_entry_lba: u64,
+    pub partition_entry_count: u32,
+    pub partition_entry_size: u32,
+    pub partition_entry_array_crc32: u32,
+}
+
+impl GptHeader {
+    const HEADER_CRC32_OFFSET: usize = 16;
+
+    /// Parses a header and validates its signature, revision, size and checksum.
+    /// The location and the entry array are validated by `validate_layout` and
+    /// `validate_entry_array`.
+    pub fn parse(sector: &[u8]) -> Result<Self, HeaderError> {
+        if sector.len() < GPT_HEADER_SIZE as usize || sector[..8] != GPT_SIGNATURE {
+            return Err(HeaderError::BadSignature);
+        }
+        let header = Self {
+            revision: read_u32(sector, 8),
+            header_size: read_u32(sector, 12),
+            header_crc32: read_u32(sector, 16),
+            my_lba: read_u64(sector, 24),
+            alternate_lba: read_u64(sector, 32),
+            first_usable_lba: read_u64(sector, 40),
+            last_usable_lba: read_u64(sector, 48),
+            disk_guid: Guid(sector[56..72].try_into().unwrap()),
+            partition_entry_lba: read_u64(sector, 72),
+            partition_entry_count: read_u32(sector, 80),
+            partition_entry_size: read_u32(sector, 84),
+            partition_entry_array_crc32: read_u32(sector, 88),
+        };
+        // Minor revisions are backwards-compatible
+        if header.revision >> 16 != GPT_REVISION_1_0 >> 16 {
+            return Err(HeaderError::UnsupportedRevision);
+        }
+        let header_size = header.header_size as usize;
+        if header_size < GPT_HEADER_SIZE as usize || header_size > sector.len() {
+            return Err(HeaderError::BadHeaderSize);
+        }
+        if Self::checksum(&sector[..header_size]) != header.header_crc32 {
+            return Err(HeaderError::HeaderChecksumMismatch);
+        }
+        Ok(header)
+    }
+
+    /// The CRC32 of the header bytes, computed with the header's own CRC field zeroed
+    fn checksum(header_bytes: &[u8]) -> u32 {
+        let mut header_bytes = header_bytes.to_vec();
+        header_bytes[Self::HEADER_CRC32_OFFSET..Self::HEADER_CRC32_OFFSET + 4].fill(0);
+        crc32(&header_bytes)
+    }
+
+    /// Serializes the header into a sector, using the stored `header_crc32`
+    pub fn to_bytes(&self, sector_size: usize) -> Vec<u8> {
+        let mut out = vec![0; sector_size];
+        out[..8].copy_from_slice(&GPT_SIGNATURE);
+        out[8..12].copy_from_slice(&self.revision.to_le_bytes());
+        out[12..16].copy_from_slice(&self.header_size.to_le_bytes());
+        out[16..20].copy_from_slice(&self.header_crc32.to_le_bytes());
+        out[24..32].copy_from_slice(&self.my_lba.to_le_bytes());
+        out[32..40].copy_from_slice(&self.alternate_lba.to_le_bytes());
+        out[40..48].copy_from_slice(&self.first_usable_lba.to_le_bytes());
+        out[48..56].copy_from_slice(&self.last_usable_lba.to_le_bytes());
+        out[56..72].copy_from_slice(&self.disk_guid.0);
+        out[72..80].copy_from_slice(&self.partition_entry_lba.to_le_bytes());
+        out[80..84].copy_from_slice(&self.partition_entry_count.to_le_bytes());
+        out[84..88].copy_from_slice(&self.partition_entry_size.to_le_bytes());
+        out[88..92].copy_from_slice(&self.partition_entry_array_crc32.to_le_bytes());
+        out
+    }
+
+    /// Recomputes `header_crc32`. Must be called after any other field is modified.
+    pub fn update_crc32(&mut self) {
+        let bytes = self.to_bytes(self.header_size as usize);
+        self.header_crc32 = Self::checksum(&bytes);
+    }
+
+    pub fn partition_entry_array_size(&self) -> u64 {
+        self.partition_entry_count as u64 * self.partition_entry_size as u64
+    }
+
+    pub fn partition_entry_array_sectors(&self, sector_size: usize) -> u64 {
+        let sector_size = sector_size as u64;
+        self.partition_entry_array_size().div_ceil(sector_size)
+    }
+
+    /// Checks that the header describes itself as living at `expected_lba`, and that the
+    /// regions it describes fit on a disk of `disk_sector_count` sectors.
+    pub fn validate_layout(
+        &self,
+        expected_lba: u64,
+        disk_sector_count: u64,
+        sector_size: usize,
+    ) -> Result<(), HeaderError> {
+        if self.my_lba != expected_lba {
+            return Err(HeaderError::WrongLocation);
+        }
+        // UEFI Spec v2.9, §5.3.2: entries are 128 * 2^n bytes
+        if self.partition_entry_size < GPT_PARTITION_ENTRY_SIZE
+            || !self.partition_entry_size.is_power_of_two()
+            || self.partition_entry_array_size() > MAX_PARTITION_ENTRY_ARRAY_SIZE
+        {
+            return Err(HeaderError::BadEntryArrayLayout);
+        }
+        let entry_array_end =
+            self.partition_entry_lba + self.partition_entry_array_sectors(sector_size);
+        if self.partition_entry_lba < 2
+            || entry_array_end > disk_sector_count
+            || self.first_usable_lba > self.last_usable_lba + 1
+            || self.last_usable_lba >= disk_sector_count
+        {
+            return Err(HeaderError::BadEntryArrayLayout);
+        }
+        Ok(())
+    }
+
+    /// `entry_array` must hold at least `partition_entry_array_size` bytes
+    pub fn validate_entry_array(&self, entry_array: &[u8]) -> Result<(), HeaderError> {
+        let len = self.partition_entry_array_size() as usize;
+        if entry_array.len() < len {
+            return Err(HeaderError::BadEntryArrayLayout);
+        }
+        match crc32(&entry_array[..len]) == self.partition_entry_array_crc32 {
+            true => Ok(()),
+            false => Err(HeaderError::EntryArrayChecksumMismatch),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct GptPartitionEntry {
+    // UEFI Spec v2.9, §Table 5-6
+    pub partition_type_guid: Guid,
+    pub unique_partition_guid: Guid,
+    pub starting_lba: u64,
+    /// Inclusive
+    pub ending_lba: u64,
+    pub attributes: u64,
+    /// UTF-16LE, NUL-padded
+    pub partition_name: [u16; 36],
+}
+
+impl GptPartitionEntry {
+    pub fn new(
+        partition_type_guid: Guid,
+        unique_partition_guid: Guid,
+        starting_lba: u64,
+        ending_lba: u64,
+        name: &str,
+    ) -> Self {
+        let mut entry = Self {
+            partition_type_guid,
+            unique_partition_guid,
+            starting_lba,
+            ending_lba,
+            attributes: 0,
+            partition_name: [0; 36],
+        };
+        entry.set_name(name);
+        entry
+    }
+
+    pub fn unused() -> Self {
+        Self::new(Guid::UNUSED, Guid::UNUSED, 0, 0, "")
+    }
+
+    pub fn parse(data: &[u8]) -> Self {
+        let mut partition_name = [0; 36];
+        for (i, code_unit) in partition_name.iter_mut().enumerate() {
+            *code_unit = u16::from_le_bytes([data[56 + (i * 2)], data[56 + (i * 2) + 1]]);
+        }
+        Self {
+            partition_type_guid: Guid(data[0..16].try_into().unwrap()),
+            unique_partition_guid: Guid(data[16..32].try_into().unwrap()),
+            starting_lba: read_u64(data, 32),
+            ending_lba: read_u64(data, 40),
+            attributes: read_u64(data, 48),
+            partition_name,
+        }
+    }
+
+    pub fn write(&self, out: &mut [u8]) {
+        out.fill(0);
+        out[0..16].copy_from_slice(&self.partition_type_guid.0);
+        out[16..32].copy_from_slice(&self.unique_partition_guid.0);
+        out[32..40].copy_from_slice(&self.starting_lba.to_le_bytes());
+        out[40..48].copy_from_slice(&self.ending_lba.to_le_bytes());
+        out[48..56].copy_from_slice(&self.attributes.to_le_bytes());
+        for (i, code_unit) in self.partition_name.iter().enumerate() {
+            out[56 + (i * 2)..56 + (i * 2) + 2].copy_from_slice(&code_unit.to_le_bytes());
+        }
+    }
+
+    pub fn is_used(&self) -> bool {
+        !self.partition_type_guid.is_unused()
+    }
+
+    pub fn sector_count(&self) -> u64 {
+        (self.ending_lba + 1).saturating_sub(self.starting_lba)
+    }
+
+    pub fn name(&self) -> String {
+        let len = self
+            .partition_name
+            .iter()
+            .position(|c| *c == 0)
+            .unwrap_or(self.partition_name.len());
+        char::decode_utf16(self.partition_name[..len].iter().copied())
+            .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+            .collect()
+    }
+
+    /// Names that don't fit in 36 UTF-16 code units are truncated
+    pub fn set_name(&mut self, name: &str) {
+        self.partition_name = [0; 36];
+        let mut len = 0;
+        for c in name.chars() {
+            let mut buf = [0; 2];
+            let encoded = c.encode_utf16(&mut buf);
+            if len + encoded.len() > self.partition_name.len() {
+                break;
+            }
+            self.partition_name[len..len + encoded.len()].copy_from_slice(encoded);
+            len += encoded.len();
+        }
+    }
+}
+
+/// Serializes `entries` into an entry array, padded out to a whole number of sectors
+pub fn partition_entry_array_to_bytes(
+    entries: &[GptPartitionEntry],
+    entry_size: usize,
+    sector_size: usize,
+) -> Vec<u8> {
+    let len = entries.len() * entry_size;
+    let mut out = vec![0; len.div_ceil(sector_size) * sector_size];
+    for (entry, slot) in entries.iter().zip(out.chunks_exact_mut(entry_size)) {
+        entry.write(slot);
+    }
+    out
+}
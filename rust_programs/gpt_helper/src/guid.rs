@@ -0,0 +1,144 @@
+use core::fmt;
+use core::str::FromStr;
+
+/// A GUID as stored on disk (UEFI Spec v2.9, Appendix A): the first three fields are
+/// little-endian, and the final 8 bytes are stored as-is.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Guid(pub [u8; 16]);
+
+impl Guid {
+    /// Partition entries with this type are not in use
+    pub const UNUSED: Guid = Guid([0; 16]);
+    pub const EFI_SYSTEM_PARTITION: Guid = Guid::from_fields(
+        0xc12a7328,
+        0xf81f,
+        0x11d2,
+        [0xba, 0x4b, 0x00, 0xa0, 0xc9, 0x3e, 0xc9, 0x3b],
+    );
+    pub const BASIC_DATA: Guid = Guid::from_fields(
+        0xebd0a0a2,
+        0xb9e5,
+        0x4433,
+        [0x87, 0xc0, 0x68, 0xb6, 0xb7, 0x26, 0x99, 0xc7],
+    );
+    pub const LINUX_FILESYSTEM: Guid = Guid::from_fields(
+        0x0fc63daf,
+        0x8483,
+        0x4772,
+        [0x8e, 0x79, 0x3d, 0x69, 0xd8, 0x47, 0x7d, 0xe4],
+    );
+
+    pub const fn from_fields(time_low: u32, time_mid: u16, time_high: u16, tail: [u8; 8]) -> Self {
+        let low = time_low.to_le_bytes();
+        let mid = time_mid.to_le_bytes();
+        let high = time_high.to_le_bytes();
+        Self([
+            low[0], low[1], low[2], low[3], mid[0], mid[1], high[0], high[1], tail[0], tail[1],
+            tail[2], tail[3], tail[4], tail[5], tail[6], tail[7],
+        ])
+    }
+
+    pub fn is_unused(&self) -> bool {
+        *self == Self::UNUSED
+    }
+
+    /// A human-readable name for well-known partition types
+    pub fn partition_type_name(&self) -> Option<&'static str> {
+        match *self {
+            Self::UNUSED => Some("Unused"),
+            Self::EFI_SYSTEM_PARTITION => Some("EFI System"),
+            Self::BASIC_DATA => Some("Basic data"),
+            Self::LINUX_FILESYSTEM => Some("Linux filesystem"),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let b = &self.0;
+        write!(
+            f,
+            "{:08X}-{:04X}-{:04X}-{:02X}{:02X}-",
+            u32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+            u16::from_le_bytes([b[4], b[5]]),
+            u16::from_le_bytes([b[6], b[7]]),
+            b[8],
+            b[9],
+        )?;
+        for byte in &b[10..] {
+            write!(f, "{byte:02X}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Returned when a string isn't of the form C12A7328-F81F-11D2-BA4B-00A0C93EC93B
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct GuidParseError;
+
+impl FromStr for Guid {
+    type Err = GuidParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let groups: [&str; 5] = s
+            .split('-')
+            .collect::<alloc::vec::Vec<&str>>()
+            .try_into()
+            .map_err(|_| GuidParseError)?;
+        let expected_lengths = [8, 4, 4, 4, 12];
+        for (group, len) in groups.iter().zip(expected_lengths) {
+            if group.len() != len || !group.chars().all(|c| c.is_ascii_hexdigit()) {
+                return Err(GuidParseError);
+            }
+        }
+        let hex_byte =
+            |group: &str, i: usize| u8::from_str_radix(&group[i * 2..i * 2 + 2], 16).unwrap();
+        let mut tail = [0; 8];
+        for (i, byte) in tail.iter_mut().enumerate() {
+            *byte = match i {
+                0..=1 => hex_byte(groups[3], i),
+                _ => hex_byte(groups[4], i - 2),
+            };
+        }
+        Ok(Self::from_fields(
+            u32::from_str_radix(groups[0], 16).unwrap(),
+            u16::from_str_radix(groups[1], 16).unwrap(),
+            u16::from_str_radix(groups[2], 16).unwrap(),
+            tail,
+        ))
+    }
+}
+
+impl fmt::Debug for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Guid({self})")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::guid::Guid;
+    use alloc::string::ToString;
+    use core::str::FromStr;
+
+    #[test]
+    fn test_mixed_endian_format() {
+        let guid = Guid::EFI_SYSTEM_PARTITION;
+        assert_eq!(&guid.0[..4], &[0x28, 0x73, 0x2a, 0xc1]);
+        assert_eq!(guid.to_string(), "C12A7328-F81F-11D2-BA4B-00A0C93EC93B");
+        assert_eq!(guid.partition_type_name(), Some("EFI System"));
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            Guid::from_str("0fc63daf-8483-4772-8E79-3D69D8477DE4"),
+            Ok(Guid::LINUX_FILESYSTEM)
+        );
+        let guid = Guid([0x5a; 16]);
+        assert_eq!(Guid::from_str(&guid.to_string()), Ok(guid));
+        assert!(Guid::from_str("0fc63daf-8483-4772-8E79").is_err());
+        assert!(Guid::from_str("0fc63daf-8483-4772-8E79-3D69D8477DEZ").is_err());
+    }
+}
@@ -0,0 +1,385 @@
+#![no_std]
+
+//! GUID Partition Table parsing (UEFI Spec v2.9, §5).
+//!
+//! The primary header at LBA 1 is used if it and its entry array pass validation. Otherwise,
+//! the alternate header in the disk's last sector is used.
+
+extern crate alloc;
+#[cfg(test)]
+extern crate std;
+
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+pub mod crc32;
+mod edit;
+pub mod gpt;
+pub mod guid;
+pub mod mbr;
+
+pub use crate::edit::{EditError, DEFAULT_PARTITION_ENTRY_COUNT};
+pub use crate::gpt::{GptHeader, GptPartitionEntry, HeaderError};
+pub use crate::guid::Guid;
+pub use crate::mbr::{MasterBootRecordPartitionRecord, ProtectiveMasterBootRecord};
+
+pub(crate) fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(data[offset..offset + 2].try_into().unwrap())
+}
+
+pub(crate) fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+pub(crate) fn read_u64(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+/// The disk being inspected, addressed in logical blocks
+pub trait SectorReader {
+    type Error;
+
+    fn sector_size(&self) -> usize;
+
+    fn sector_count(&self) -> u64;
+
+    /// Fills `buf`, which spans a whole number of sectors, starting at `lba`
+    fn read_sectors(&mut self, lba: u64, buf: &mut [u8]) -> Result<(), Self::Error>;
+}
+
+pub trait SectorWriter: SectorReader {
+    /// Writes `buf`, which spans a whole number of sectors, starting at `lba`
+    fn write_sectors(&mut self, lba: u64, buf: &[u8]) -> Result<(), Self::Error>;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GptError<E> {
+    Io(E),
+    /// LBA 0 doesn't hold an MBR with a GPT protective partition record
+    MissingProtectiveMbr,
+    NoValidHeader {
+        primary: HeaderError,
+        alternate: HeaderError,
+    },
+    /// The disk is too small for the table being written to it
+    DiskTooSmall,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum HeaderLocation {
+    Primary,
+    Alternate,
+}
+
+/// A partition entry that's in use
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Partition {
+    /// The index of the entry within the partition entry array
+    pub index: u32,
+    pub type_guid: Guid,
+    pub unique_guid: Guid,
+    pub first_lba: u64,
+    /// Inclusive
+    pub last_lba: u64,
+    pub attributes: u64,
+    pub name: String,
+}
+
+impl Partition {
+    pub fn sector_count(&self) -> u64 {
+        (self.last_lba + 1).saturating_sub(self.first_lba)
+    }
+}
+
+type HeaderAndEntries = (GptHeader, Vec<GptPartitionEntry>);
+
+#[derive(Debug, Clone)]
+pub struct PartitionTable {
+    pub header: GptHeader,
+    /// Where the header that passed validation was found
+    pub header_location: HeaderLocation,
+    pub sector_size: usize,
+    /// Every entry in the partition entry array, including unused entries
+    pub entries: Vec<GptPartitionEntry>,
+}
+
+impl PartitionTable {
+    pub fn read<D: SectorReader>(disk: &mut D) -> Result<Self, GptError<D::Error>> {
+        let sector_size = disk.sector_size();
+        let mut sector = vec![0; sector_size];
+        disk.read_sectors(0, &mut sector).map_err(GptError::Io)?;
+        if ProtectiveMasterBootRecord::parse(&sector)
+            .protective_record()
+            .is_none()
+        {
+            return Err(GptError::MissingProtectiveMbr);
+        }
+
+        // The primary GPT header will be at LBA 1 (UEFI v2.9, §5.3.1)
+        let primary = match Self::read_at(disk, 1)? {
+            Ok((header, entries)) => {
+                return Ok(Self {
+                    header,
+                    header_location: HeaderLocation::Primary,
+                    sector_size,
+                    entries,
+                })
+            }
+            Err(e) => e,
+        };
+        // The alternate header is always in the last sector of the disk
+        let alternate_lba = disk.sector_count().saturating_sub(1);
+        match Self::read_at(disk, alternate_lba)? {
+            Ok((header, entries)) => Ok(Self {
+                header,
+                header_location: HeaderLocation::Alternate,
+                sector_size,
+                entries,
+            }),
+            Err(alternate) => Err(GptError::NoValidHeader { primary, alternate }),
+        }
+    }
+
+    /// The outer Result reports I/O failures, and the inner Result reports invalid contents
+    fn read_at<D: SectorReader>(
+        disk: &mut D,
+        lba: u64,
+    ) -> Result<Result<HeaderAndEntries, HeaderError>, GptError<D::Error>> {
+        let sector_size = disk.sector_size();
+        let mut sector = vec![0; sector_size];
+        disk.read_sectors(lba, &mut sector).map_err(GptError::Io)?;
+        let header = match GptHeader::parse(&sector) {
+            Ok(header) => header,
+            Err(e) => return Ok(Err(e)),
+        };
+        if let Err(e) = header.validate_layout(lba, disk.sector_count(), sector_size) {
+            return Ok(Err(e));
+        }
+
+        let mut entry_array =
+            vec![0; header.partition_entry_array_sectors(sector_size) as usize * sector_size];
+        disk.read_sectors(header.partition_entry_lba, &mut entry_array)
+            .map_err(GptError::Io)?;
+        if let Err(e) = header.validate_entry_array(&entry_array) {
+            return Ok(Err(e));
+        }
+        let entries = entry_array
+            .chunks_exact(header.partition_entry_size as usize)
+            .take(header.partition_entry_count as usize)
+            .map(GptPartitionEntry::parse)
+            .collect();
+        Ok(Ok((header, entries)))
+    }
+
+    pub fn partitions(&self) -> Vec<Partition> {
+        self.entries
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| entry.is_used())
+            .map(|(index, entry)| Partition {
+                index: index as u32,
+                type_guid: entry.partition_type_guid,
+                unique_guid: entry.unique_partition_guid,
+                first_lba: entry.starting_lba,
+                last_lba: entry.ending_lba,
+                attributes: entry.attributes,
+                name: entry.name(),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    use crate::gpt::{
+        partition_entry_array_to_bytes, GPT_HEADER_SIZE, GPT_PARTITION_ENTRY_SIZE, GPT_REVISION_1_0,
+    };
+    use crate::{
+        GptError, GptHeader, GptPartitionEntry, Guid, HeaderError, HeaderLocation, PartitionTable,
+        ProtectiveMasterBootRecord, SectorReader, SectorWriter,
+    };
+
+    pub(crate) const SECTOR_SIZE: usize = 512;
+    const SECTOR_COUNT: u64 = 2048;
+    const ENTRY_COUNT: u32 = 128;
+    // 128 entries of 128 bytes
+    const ENTRY_ARRAY_SECTORS: u64 = 32;
+
+    pub(crate) struct MemoryDisk(Vec<u8>);
+
+    impl MemoryDisk {
+        pub(crate) fn new(sector_count: u64) -> Self {
+            Self(vec![0; sector_count as usize * SECTOR_SIZE])
+        }
+
+        pub(crate) fn sector_mut(&mut self, lba: u64) -> &mut [u8] {
+            let offset = lba as usize * SECTOR_SIZE;
+            &mut self.0[offset..offset + SECTOR_SIZE]
+        }
+    }
+
+    impl SectorReader for MemoryDisk {
+        type Error = ();
+
+        fn sector_size(&self) -> usize {
+            SECTOR_SIZE
+        }
+
+        fn sector_count(&self) -> u64 {
+            (self.0.len() / SECTOR_SIZE) as u64
+        }
+
+        fn read_sectors(&mut self, lba: u64, buf: &mut [u8]) -> Result<(), ()> {
+            let offset = lba as usize * SECTOR_SIZE;
+            let data = self.0.get(offset..offset + buf.len()).ok_or(())?;
+            buf.copy_from_slice(data);
+            Ok(())
+        }
+    }
+
+    impl SectorWriter for MemoryDisk {
+        fn write_sectors(&mut self, lba: u64, buf: &[u8]) -> Result<(), ()> {
+            let offset = lba as usize * SECTOR_SIZE;
+            let data = self.0.get_mut(offset..offset + buf.len()).ok_or(())?;
+            data.copy_from_slice(buf);
+            Ok(())
+        }
+    }
+
+    fn header_at(my_lba: u64, alternate_lba: u64, partition_entry_lba: u64) -> GptHeader {
+        GptHeader {
+            revision: GPT_REVISION_1_0,
+            header_size: GPT_HEADER_SIZE,
+            header_crc32: 0,
+            my_lba,
+            alternate_lba,
+            first_usable_lba: 2 + ENTRY_ARRAY_SECTORS,
+            last_usable_lba: SECTOR_COUNT - 2 - ENTRY_ARRAY_SECTORS,
+            disk_guid: Guid([0xab; 16]),
+            partition_entry_lba,
+            partition_entry_count: ENTRY_COUNT,
+            partition_entry_size: GPT_PARTITION_ENTRY_SIZE,
+            partition_entry_array_crc32: 0,
+        }
+    }
+
+    fn formatted_disk(partitions: &[GptPartitionEntry]) -> MemoryDisk {
+        let mut disk = MemoryDisk::new(SECTOR_COUNT);
+        disk.sector_mut(0)
+            .copy_from_slice(&ProtectiveMasterBootRecord::new(SECTOR_COUNT).to_bytes());
+
+        let mut entries = vec![GptPartitionEntry::unused(); ENTRY_COUNT as usize];
+        entries[..partitions.len()].copy_from_slice(partitions);
+        let entry_array = partition_entry_array_to_bytes(
+            &entries,
+            GPT_PARTITION_ENTRY_SIZE as usize,
+            SECTOR_SIZE,
+        );
+        let entry_array_crc32 = crate::crc32::crc32(&entry_array);
+
+        let alternate_lba = SECTOR_COUNT - 1;
+        let alternate_entry_lba = alternate_lba - ENTRY_ARRAY_SECTORS;
+        for (my_lba, other_lba, entry_lba) in [
+            (1, alternate_lba, 2),
+            (alternate_lba, 1, alternate_entry_lba),
+        ] {
+            let mut header = header_at(my_lba, other_lba, entry_lba);
+            header.partition_entry_array_crc32 = entry_array_crc32;
+            header.update_crc32();
+            disk.sector_mut(my_lba)
+                .copy_from_slice(&header.to_bytes(SECTOR_SIZE));
+            let offset = entry_lba as usize * SECTOR_SIZE;
+            disk.0[offset..offset + entry_array.len()].copy_from_slice(&entry_array);
+        }
+        disk
+    }
+
+    fn sample_partitions() -> Vec<GptPartitionEntry> {
+        vec![
+            GptPartitionEntry::new(Guid::EFI_SYSTEM_PARTITION, Guid([1; 16]), 34, 133, "EFI"),
+            GptPartitionEntry::new(Guid::BASIC_DATA, Guid([2; 16]), 134, 2013, "axle data"),
+        ]
+    }
+
+    #[test]
+    fn test_read_primary() {
+        let mut disk = formatted_disk(&sample_partitions());
+        let table = PartitionTable::read(&mut disk).unwrap();
+        assert_eq!(table.header_location, HeaderLocation::Primary);
+        assert_eq!(table.header.alternate_lba, SECTOR_COUNT - 1);
+        assert_eq!(table.entries.len(), ENTRY_COUNT as usize);
+
+        let partitions = table.partitions();
+        assert_eq!(partitions.len(), 2);
+        assert_eq!(partitions[0].index, 0);
+        assert_eq!(partitions[0].type_guid, Guid::EFI_SYSTEM_PARTITION);
+        assert_eq!(partitions[0].name, "EFI");
+        assert_eq!(partitions[0].sector_count(), 100);
+        assert_eq!(partitions[1].unique_guid, Guid([2; 16]));
+        assert_eq!(partitions[1].name, "axle data");
+        assert_eq!(partitions[1].first_lba, 134);
+    }
+
+    #[test]
+    fn test_fallback_on_corrupt_header() {
+        let mut disk = formatted_disk(&sample_partitions());
+        // Flip a bit in the primary header's disk GUID
+        disk.sector_mut(1)[60] ^= 1;
+        let table = PartitionTable::read(&mut disk).unwrap();
+        assert_eq!(table.header_location, HeaderLocation::Alternate);
+        assert_eq!(table.header.my_lba, SECTOR_COUNT - 1);
+        assert_eq!(table.partitions().len(), 2);
+    }
+
+    #[test]
+    fn test_fallback_on_corrupt_entry_array() {
+        let mut disk = formatted_disk(&sample_partitions());
+        // Corrupt the name of the first primary entry
+        disk.sector_mut(2)[56] = b'X';
+        let table = PartitionTable::read(&mut disk).unwrap();
+        assert_eq!(table.header_location, HeaderLocation::Alternate);
+        assert_eq!(table.partitions()[0].name, "EFI");
+    }
+
+    #[test]
+    fn test_no_valid_header() {
+        let mut disk = formatted_disk(&sample_partitions());
+        disk.sector_mut(1)[..8].copy_from_slice(b"NOT PART");
+        disk.sector_mut(SECTOR_COUNT - 1)[60] ^= 1;
+        assert_eq!(
+            PartitionTable::read(&mut disk).unwrap_err(),
+            GptError::NoValidHeader {
+                primary: HeaderError::BadSignature,
+                alternate: HeaderError::HeaderChecksumMismatch,
+            }
+        );
+    }
+
+    #[test]
+    fn test_missing_protective_mbr() {
+        let mut disk = formatted_disk(&[]);
+        disk.sector_mut(0).fill(0);
+        assert_eq!(
+            PartitionTable::read(&mut disk).unwrap_err(),
+            GptError::MissingProtectiveMbr
+        );
+    }
+
+    #[test]
+    fn test_partition_name_round_trip() {
+        let mut entry = GptPartitionEntry::unused();
+        entry.set_name("Données 💾");
+        assert_eq!(entry.name(), "Données 💾");
+        let mut bytes = [0; GPT_PARTITION_ENTRY_SIZE as usize];
+        entry.write(&mut bytes);
+        assert_eq!(GptPartitionEntry::parse(&bytes), entry);
+
+        // Names are truncated to 36 UTF-16 code units
+        entry.set_name(&"a".repeat(40));
+        assert_eq!(entry.name().len(), 36);
+    }
+}
@@ -0,0 +1,105 @@
+use crate::{read_u16, read_u32};
+
+/// UEFI Spec v2.9, §5.2.2 - OS Types
+pub const GPT_PROTECTIVE_OS_TYPE: u8 = 0xee;
+pub const MBR_SIGNATURE: u16 = 0xaa55;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct MasterBootRecordPartitionRecord {
+    // UEFI Spec v2.9, §Table 5-2: Legacy MBR Partition Record
+    pub boot_indicator: u8,
+    pub start_chs: [u8; 3],
+    pub os_type: u8,
+    pub end_chs: [u8; 3],
+    pub start_lba: u32,
+    pub size_in_lba: u32,
+}
+
+impl MasterBootRecordPartitionRecord {
+    pub const SIZE: usize = 16;
+
+    pub fn parse(data: &[u8]) -> Self {
+        Self {
+            boot_indicator: data[0],
+            start_chs: [data[1], data[2], data[3]],
+            os_type: data[4],
+            end_chs: [data[5], data[6], data[7]],
+            start_lba: read_u32(data, 8),
+            size_in_lba: read_u32(data, 12),
+        }
+    }
+
+    pub fn write(&self, out: &mut [u8]) {
+        out[0] = self.boot_indicator;
+        out[1..4].copy_from_slice(&self.start_chs);
+        out[4] = self.os_type;
+        out[5..8].copy_from_slice(&self.end_chs);
+        out[8..12].copy_from_slice(&self.start_lba.to_le_bytes());
+        out[12..16].copy_from_slice(&self.size_in_lba.to_le_bytes());
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ProtectiveMasterBootRecord {
+    pub partition_records: [MasterBootRecordPartitionRecord; 4],
+    pub signature: u16,
+}
+
+impl ProtectiveMasterBootRecord {
+    pub const SIZE: usize = 512;
+    const PARTITION_RECORDS_OFFSET: usize = 446;
+
+    /// UEFI Spec v2.9, §5.2.3: a single record covering the whole disk after LBA 0
+    pub fn new(disk_sector_count: u64) -> Self {
+        let mut partition_records = [MasterBootRecordPartitionRecord::default(); 4];
+        partition_records[0] = MasterBootRecordPartitionRecord {
+            boot_indicator: 0,
+            start_chs: [0x00, 0x02, 0x00],
+            os_type: GPT_PROTECTIVE_OS_TYPE,
+            end_chs: [0xff, 0xff, 0xff],
+            start_lba: 1,
+            // > Set to 0xFFFFFFFF if the size of the disk is too large to be represented
+            size_in_lba: u32::try_from(disk_sector_count - 1).unwrap_or(u32::MAX),
+        };
+        Self {
+            partition_records,
+            signature: MBR_SIGNATURE,
+        }
+    }
+
+    pub fn parse(sector: &[u8]) -> Self {
+        let mut partition_records = [MasterBootRecordPartitionRecord::default(); 4];
+        for (i, record) in partition_records.iter_mut().enumerate() {
+            let offset =
+                Self::PARTITION_RECORDS_OFFSET + (MasterBootRecordPartitionRecord::SIZE * i);
+            *record = MasterBootRecordPartitionRecord::parse(
+                &sector[offset..offset + MasterBootRecordPartitionRecord::SIZE],
+            );
+        }
+        Self {
+            partition_records,
+            // Last 2 bytes
+            signature: read_u16(sector, 510),
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let mut out = [0; Self::SIZE];
+        for (i, record) in self.partition_records.iter().enumerate() {
+            let offset =
+                Self::PARTITION_RECORDS_OFFSET + (MasterBootRecordPartitionRecord::SIZE * i);
+            record.write(&mut out[offset..offset + MasterBootRecordPartitionRecord::SIZE]);
+        }
+        out[510..512].copy_from_slice(&self.signature.to_le_bytes());
+        out
+    }
+
+    pub fn protective_record(&self) -> Option<&MasterBootRecordPartitionRecord> {
+        if self.signature != MBR_SIGNATURE {
+            return None;
+        }
+        self.partition_records
+            .iter()
+            .find(|record| record.os_type == GPT_PROTECTIVE_OS_TYPE)
+    }
+}
@@ -21,7 +21,8 @@ use libgui::AwmWindow;
 use axle_rt::{
     amc_message_await__u32_event, amc_message_send, amc_register_service,
     core_commands::{
-        AmcExecBuffer, AmcQueryServiceRequest, AmcSupervisedProcessEventMsg, AMC_CORE_SERVICE_NAME,
+        AmcExecBuffer, AmcExecBufferDenied, AmcQueryServiceRequest, AmcSupervisedProcessEventMsg,
+        AMC_CORE_SERVICE_NAME,
     },
     println, AmcMessage,
 };
@@ -29,7 +30,7 @@ use axle_rt::{core_commands::SupervisedProcessEvent, ExpectsEventField};
 
 use agx_definitions::{Color, Point, Rect, Size};
 
-use file_manager_messages::{ReadFile, ReadFileResponse, FILE_SERVER_SERVICE_NAME};
+use file_manager_messages::read_file;
 
 mod ide_messages;
 mod output_view;
@@ -247,18 +248,17 @@ impl IdeMainView {
         let path = "/usr/applications/linker";
         // Don't use LaunchProgram as we want to use the special interface that allows us to supervise the child
         //amc_message_send(FILE_SERVER_SERVICE_NAME, LaunchProgram::new(path));
-        amc_message_send(FILE_SERVER_SERVICE_NAME, ReadFile::new(path));
-        let linker_program_msg: AmcMessage<ReadFileResponse> =
-            amc_message_await__u32_event(FILE_SERVER_SERVICE_NAME);
-        let linker_program_slice = core::ptr::slice_from_raw_parts(
-            (&linker_program_msg.body().data) as *const u8,
-            linker_program_msg.body().len,
-        );
-        let linker_program_bytes: &mut [u8] = unsafe { &mut *(linker_program_slice as *mut [u8]) };
+        let linker_program_bytes = match read_file(path) {
+            Ok(data) => data,
+            Err(e) => {
+                println!("Failed to read {path}: {e:?}");
+                return;
+            }
+        };
         println!("Spawning linker...");
         amc_message_send(
             AMC_CORE_SERVICE_NAME,
-            AmcExecBuffer::from("linker", &linker_program_bytes.to_vec(), true),
+            AmcExecBuffer::from("linker", &linker_program_bytes, true),
         );
         *self.awaiting_linker_spawn.borrow_mut() = true;
         /*
@@ -410,6 +410,13 @@ fn start(_argc: isize, _argv: *const *const u8) -> isize {
                     if let AmcSupervisedProcessEventMsg::EXPECTED_EVENT = event {
                         Rc::clone(&ide_view.borrow())
                             .handle_supervised_process_event(body_as_type_unchecked(raw_body));
+                    } else if let AmcExecBufferDenied::EXPECTED_EVENT = event {
+                        let denied: &AmcExecBufferDenied = body_as_type_unchecked(raw_body);
+                        println!("Launch denied, missing {:?}", denied.missing_capability());
+                        ide_view
+                            .borrow()
+                            .status_view
+                            .set_status("Not allowed to launch programs");
                     } else {
                         println!("Dropping unhandled message from core");
                     }
@@ -0,0 +1,8 @@
+#![no_std]
+
+//! The supervision logic behind the file server. The system is reached through
+//! `supervisor::ServiceHost`, so the logic can be tested on the host.
+
+extern crate alloc;
+
+pub mod supervisor;
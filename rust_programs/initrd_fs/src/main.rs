@@ -7,24 +7,45 @@ extern crate alloc;
 extern crate libc;
 
 use alloc::str;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
 
+use axle_rt::core_commands::{
+    AmcCapabilities, AmcQueryServiceRequest, AmcRegisterServiceDiedNotif,
+    AmcSharedMemoryCreateRequest,
+};
+use axle_rt::core_commands::{
+    AmcServiceDiedNotif, AmcSleepUntilDelayOrMessage, AMC_CORE_SERVICE_NAME,
+};
 use axle_rt::printf;
+use axle_rt::serialized::decode_message;
 use axle_rt::AmcMessage;
+use axle_rt::{amc_has_message, amc_register_service, core_commands::AmcExecBuffer};
 use axle_rt::{amc_message_await, ContainsEventField, ExpectsEventField};
 use axle_rt::{amc_message_await_untyped, amc_message_send};
-use axle_rt::{amc_register_service, core_commands::AmcExecBuffer};
 use axle_rt_derive::ContainsEventField;
-
-use cstr_core::CString;
+use file_manager_messages::LaunchProgramWithArguments;
 use file_manager_messages::{
     str_from_u8_nul_utf8_unchecked, CheckFileExists, LaunchProgram, ReadFilePart,
     ReadFilePartResponse,
 };
 use file_manager_messages::{CheckFileExistsResponse, ReadDirectory};
+use file_manager_messages::{
+    CreateDirectory, CreateDirectoryResponse, CreateFile, CreateFileResponse, DeleteEntry,
+    DeleteEntryResponse, FileOperationStatus, RenameEntry, RenameEntryResponse, WriteFile,
+    WriteFilePart, WriteFilePartResponse, WriteFileResponse,
+};
 use file_manager_messages::{DirectoryContents, ReadFile, ReadFileResponse};
 use file_manager_messages::{DirectoryEntry, FILE_SERVER_SERVICE_NAME};
+use file_manager_messages::{FileMetadata, FileType, StatPath, StatPathResponse};
+use file_manager_messages::{FindFiles, FindFilesResponse, MapFile, MapFileResponse};
 
-use libfs::{fs_entry_find, DirectoryImage, FsEntry};
+use libfs::image::verify_image;
+use libfs::query::fs_find_glob;
+use libfs::{fs_entry_find, DirectoryImage, FsEntry, FsError};
+
+use initrd_fs::supervisor::{ServiceHost, Supervisor};
+use service_manifest::{ServiceDescription, StartupManifest, STARTUP_MANIFEST_PATH};
 
 trait FromDirectoryImage {
     fn from_dir_image(dir: &DirectoryImage) -> Self;
@@ -32,10 +53,7 @@ trait FromDirectoryImage {
 
 impl FromDirectoryImage for DirectoryContents {
     fn from_dir_image(dir: &DirectoryImage) -> Self {
-        let mut contents = DirectoryContents {
-            event: ReadDirectory::EXPECTED_EVENT,
-            entries: [None; 128],
-        };
+        let mut contents = DirectoryContents::new(FileOperationStatus::Success);
         let files = &dir.files;
         printf!("Found {:?} files\n", files.len());
         let mut count = 0;
@@ -43,18 +61,26 @@ impl FromDirectoryImage for DirectoryContents {
         let subdirectories = &dir.subdirectories;
         for entry in subdirectories
             .into_iter()
-            .map(|kv| DirectoryEntry::new(&kv.0, true))
+            .map(|kv| DirectoryEntry::new(&kv.0, FileMetadata::directory()))
         {
             //printf!("Transformed dir entry: {:?}\n", entry);
+            if count == contents.entries.len() {
+                printf!("Truncating directory listing of {}\n", dir.name);
+                break;
+            }
             contents.entries[count] = Some(entry.clone());
             count += 1;
         }
 
         for entry in files
             .into_iter()
-            .map(|kv| DirectoryEntry::new(&kv.0, false))
+            .map(|kv| DirectoryEntry::new(&kv.0, dir.metadata_for_file(&kv.0, kv.1)))
         {
             //printf!("Transformed dir entry: {:?}\n", entry);
+            if count == contents.entries.len() {
+                printf!("Truncating directory listing of {}\n", dir.name);
+                break;
+            }
             contents.entries[count] = Some(entry.clone());
             count += 1;
         }
@@ -119,102 +145,305 @@ fn read_directory(root_dir: &DirectoryImage, sender: &str, request: &ReadDirecto
     printf!("Dir: {:?}\n", requested_dir);
 
     // Find the directory within
-    if let Some(entry) = fs_entry_find(&root_dir, &requested_dir) {
-        printf!("Found FS entry: {}\n", entry.path);
-        if entry.is_dir {
-            let response = DirectoryContents::from_dir_image(&entry.dir_image.unwrap());
-            printf!("Sending response...\n");
-            amc_message_send(sender, response);
+    let response = match fs_entry_find(&root_dir, &requested_dir) {
+        Some(entry) if entry.is_dir => {
+            printf!("Found FS entry: {}\n", entry.path);
+            DirectoryContents::from_dir_image(&entry.dir_image.unwrap())
         }
-    } else {
-        printf!("Failed to find directory {:?}\n", requested_dir);
-    }
+        Some(_) => {
+            printf!("Can't list files: {:?}\n", requested_dir);
+            DirectoryContents::new(FileOperationStatus::NotADirectory)
+        }
+        None => {
+            printf!("Failed to find directory {:?}\n", requested_dir);
+            DirectoryContents::new(FileOperationStatus::NotFound)
+        }
+    };
+    amc_message_send(sender, response);
 }
 
-fn launch_program_by_path(root_dir: &DirectoryImage, path: &str) {
+/// Returns whether the program was launched
+fn launch_program_by_path(
+    root_dir: &DirectoryImage,
+    path: &str,
+    arguments: &[&str],
+    capabilities: AmcCapabilities,
+) -> bool {
     if let Some(entry) = fs_entry_find(&root_dir, &path) {
         if entry.is_dir {
             printf!("Can't launch directories\n");
+            false
         } else {
-            // TODO(PT): Verify that this is indeed a file?
             // TODO(PT): Replace the Vec<u8> with a structured entry, describing if executable
             let file_name = entry.path.split("/").last().unwrap();
-            let c_str = CString::new(file_name).unwrap();
-            // TODO(PT): Change the C API to accept a char array instead of char pointer
-            let program_name_ptr = c_str.as_ptr() as *const u8;
-            amc_message_send(
-                "com.axle.core",
-                AmcExecBuffer::from(file_name, entry.file_data.as_ref().unwrap(), false),
-            );
+            let file_data = match entry.try_file_data() {
+                Ok(file_data) => file_data,
+                Err(e) => {
+                    printf!("Refusing to launch {path}, its contents failed verification: {e:?}\n");
+                    return false;
+                }
+            };
+            match AmcExecBuffer::send_with_arguments(
+                file_name,
+                arguments,
+                file_data,
+                false,
+                capabilities,
+            ) {
+                Ok(()) => true,
+                Err(e) => {
+                    printf!("Refusing to launch {path} with arguments {arguments:?}: {e:?}\n");
+                    false
+                }
+            }
         }
     } else {
         printf!("Couldn't find path {}\n", path);
+        false
     }
 }
 
-fn launch_program(root_dir: &DirectoryImage, _sender: &str, request: &LaunchProgram) {
-    let requested_path = str_from_u8_nul_utf8_unchecked(&request.path);
-    if requested_path == "/magic/exploit" {
-        launch_program_by_path(root_dir, "/usr/applications/memory_walker");
-        launch_program_by_path(root_dir, "/usr/applications/memory_scan_viewer");
-    } else {
-        launch_program_by_path(root_dir, requested_path)
+fn ms_since_boot() -> u64 {
+    unsafe { libc::ms_since_boot() as u64 }
+}
+
+/// Launches supervised services from the initrd, and asks the kernel about them
+struct InitrdServiceHost<'a> {
+    root_dir: &'a DirectoryImage<'a>,
+}
+
+impl ServiceHost for InitrdServiceHost<'_> {
+    fn ms_since_boot(&self) -> u64 {
+        ms_since_boot()
+    }
+
+    fn launch(&mut self, service: &ServiceDescription) -> bool {
+        let arguments: Vec<&str> = service.args.iter().map(|a| a.as_str()).collect();
+        launch_program_by_path(
+            self.root_dir,
+            &service.path,
+            &arguments,
+            service.capabilities,
+        )
+    }
+
+    fn service_exists(&mut self, service_name: &str) -> bool {
+        AmcQueryServiceRequest::send(service_name).service_exists
+    }
+
+    fn notify_when_service_dies(&mut self, service_name: &str) {
+        AmcRegisterServiceDiedNotif::send(service_name);
     }
 }
 
-fn launch_startup_programs(root_dir: &DirectoryImage) {
-    let run_on_startup_path = "/config/run_on_startup.txt";
-    let run_on_startup_config =
-        fs_entry_find(root_dir, run_on_startup_path).expect("{run_on_startup_path} is missing!");
-    let run_on_startup_contents = match str::from_utf8(run_on_startup_config.file_data.unwrap()) {
+fn load_startup_manifest(root_dir: &DirectoryImage) -> StartupManifest {
+    let manifest_entry = fs_entry_find(root_dir, STARTUP_MANIFEST_PATH)
+        .unwrap_or_else(|| panic!("{STARTUP_MANIFEST_PATH} is missing!"));
+    let manifest_contents = match str::from_utf8(manifest_entry.file_data().unwrap()) {
         Ok(v) => v,
-        Err(e) => panic!("Failed to read {run_on_startup_path}, invalid UTF-8: {e}"),
+        Err(e) => panic!("Failed to read {STARTUP_MANIFEST_PATH}, invalid UTF-8: {e}"),
     };
+    StartupManifest::parse(manifest_contents)
+        .unwrap_or_else(|e| panic!("Failed to parse {STARTUP_MANIFEST_PATH}: {e:?}"))
+}
+
+/// Programs launched on request are granted the capabilities that the manifest lists for them
+fn launch_program_on_request(
+    root_dir: &DirectoryImage,
+    supervisor: &Supervisor,
+    path: &str,
+    arguments: &[&str],
+) {
+    let capabilities = supervisor.capabilities_for_path(path);
+    launch_program_by_path(root_dir, path, arguments, capabilities);
+}
+
+fn launch_program(
+    root_dir: &DirectoryImage,
+    supervisor: &Supervisor,
+    _sender: &str,
+    request: &LaunchProgram,
+) {
+    let requested_path = str_from_u8_nul_utf8_unchecked(&request.path);
+    if requested_path == "/magic/exploit" {
+        launch_program_on_request(root_dir, supervisor, "/usr/applications/memory_walker", &[]);
+        launch_program_on_request(
+            root_dir,
+            supervisor,
+            "/usr/applications/memory_scan_viewer",
+            &[],
+        );
+    } else {
+        launch_program_on_request(root_dir, supervisor, requested_path, &[]);
+    }
+}
 
-    for line in run_on_startup_contents.split("\n") {
-        if line.starts_with("#") {
-            // Skip comments
-            continue;
+fn launch_program_with_arguments(
+    root_dir: &DirectoryImage,
+    supervisor: &Supervisor,
+    sender: &str,
+    raw_body: &[u8],
+) {
+    match decode_message::<LaunchProgramWithArguments>(raw_body) {
+        Ok(request) => {
+            launch_program_on_request(root_dir, supervisor, request.path, &request.arguments);
         }
-        launch_program_by_path(root_dir, line);
+        Err(e) => printf!("Dropping malformed launch request from {sender}: {e:?}\n"),
     }
 }
 
 fn read_file(root_dir: &DirectoryImage, sender: &str, request: &ReadFile) {
     let requested_path = str_from_u8_nul_utf8_unchecked(&request.path);
     printf!("Reading {} for {}\n", requested_path, sender);
-    if let Some(entry) = fs_entry_find(&root_dir, &requested_path) {
-        if entry.is_dir {
+    match fs_entry_find(&root_dir, &requested_path) {
+        Some(entry) if entry.is_dir => {
             printf!("Can't read directories\n");
-        } else {
-            /*
-            amc_message_send(
-                sender,
-                ReadFileResponse::new(&entry.path, entry.file_data.unwrap()),
-            );
-            */
-            ReadFileResponse::send(sender, &entry.path, entry.file_data.unwrap());
+            ReadFileResponse::send_error(sender, requested_path, FileOperationStatus::IsDirectory);
+        }
+        Some(entry) => match entry.try_file_data() {
+            Ok(file_data) => ReadFileResponse::send(sender, &entry.path, file_data),
+            Err(e) => {
+                printf!("Failed to read {requested_path}: {e:?}\n");
+                ReadFileResponse::send_error(sender, requested_path, e.into());
+            }
+        },
+        None => {
+            printf!("Couldn't find path {}\n", requested_path);
+            ReadFileResponse::send_error(sender, requested_path, FileOperationStatus::NotFound);
         }
-    } else {
-        printf!("Couldn't find path {}\n", requested_path);
     }
 }
 
 fn read_file_part(root_dir: &DirectoryImage, sender: &str, request: &ReadFilePart) {
     let requested_path = str_from_u8_nul_utf8_unchecked(&request.path);
     //printf!("Reading part of {} for {}\n", requested_path, sender);
-    if let Some(entry) = fs_entry_find(&root_dir, &requested_path) {
-        if entry.is_dir {
+    match fs_entry_find(&root_dir, &requested_path) {
+        Some(entry) if entry.is_dir => {
             printf!("Can't read directories\n");
-        } else {
-            let file_data = entry.file_data.unwrap();
-            let mut end_idx = request.offset + request.len;
-            end_idx = core::cmp::min(end_idx, file_data.len());
+            ReadFilePartResponse::send_error(
+                sender,
+                requested_path,
+                FileOperationStatus::IsDirectory,
+            );
+        }
+        Some(entry) => {
+            let file_data = match entry.try_file_data() {
+                Ok(file_data) => file_data,
+                Err(e) => {
+                    printf!("Failed to read {requested_path}: {e:?}\n");
+                    ReadFilePartResponse::send_error(sender, requested_path, e.into());
+                    return;
+                }
+            };
+            if request.offset > file_data.len() {
+                ReadFilePartResponse::send_error(
+                    sender,
+                    requested_path,
+                    FileOperationStatus::OutOfRange,
+                );
+                return;
+            }
+            let end_idx =
+                core::cmp::min(request.offset.saturating_add(request.len), file_data.len());
             ReadFilePartResponse::send(sender, &entry.path, &file_data[request.offset..end_idx]);
         }
-    } else {
-        printf!("Couldn't find path {}\n", requested_path);
+        None => {
+            printf!("Couldn't find path {}\n", requested_path);
+            ReadFilePartResponse::send_error(sender, requested_path, FileOperationStatus::NotFound);
+        }
+    }
+}
+
+/// A copy of a file placed in memory shared with a client
+struct SharedFileMapping {
+    client: String,
+    path: String,
+    local_buffer_start: usize,
+    remote_buffer_start: usize,
+    len: usize,
+}
+
+impl SharedFileMapping {
+    fn contents(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.local_buffer_start as *const u8, self.len) }
+    }
+}
+
+fn map_file(
+    root_dir: &DirectoryImage,
+    mappings: &mut Vec<SharedFileMapping>,
+    sender: &str,
+    request: &MapFile,
+) {
+    let requested_path = str_from_u8_nul_utf8_unchecked(&request.path);
+    let file_data = match fs_entry_find(&root_dir, &requested_path) {
+        Some(entry) if entry.is_dir => {
+            printf!("Can't map directories\n");
+            MapFileResponse::send(
+                sender,
+                requested_path,
+                FileOperationStatus::IsDirectory,
+                0,
+                0,
+            );
+            return;
+        }
+        Some(entry) => match entry.try_file_data() {
+            Ok(file_data) => file_data,
+            Err(e) => {
+                printf!("Failed to map {requested_path}: {e:?}\n");
+                MapFileResponse::send(sender, requested_path, e.into(), 0, 0);
+                return;
+            }
+        },
+        None => {
+            printf!("Couldn't find path {}\n", requested_path);
+            MapFileResponse::send(sender, requested_path, FileOperationStatus::NotFound, 0, 0);
+            return;
+        }
+    };
+    if file_data.is_empty() {
+        MapFileResponse::send(sender, requested_path, FileOperationStatus::Success, 0, 0);
+        return;
     }
+
+    // Shared memory can't be freed, so hand back the client's existing copy if it's still up to date.
+    // Regions are never overwritten as the client may still be holding onto an older version.
+    let existing_mapping = mappings.iter().find(|m| {
+        m.client == sender && m.path == requested_path && m.contents() == file_data.as_slice()
+    });
+    let remote_buffer_start = match existing_mapping {
+        Some(mapping) => mapping.remote_buffer_start,
+        None => {
+            printf!(
+                "Mapping {requested_path} ({} bytes) into {sender}\n",
+                file_data.len()
+            );
+            let shared_memory = AmcSharedMemoryCreateRequest::send(sender, file_data.len() as u32);
+            let local_buffer = unsafe {
+                core::slice::from_raw_parts_mut(
+                    shared_memory.local_buffer_start as *mut u8,
+                    file_data.len(),
+                )
+            };
+            local_buffer.copy_from_slice(file_data);
+            mappings.push(SharedFileMapping {
+                client: sender.to_string(),
+                path: requested_path.to_string(),
+                local_buffer_start: shared_memory.local_buffer_start,
+                remote_buffer_start: shared_memory.remote_buffer_start,
+                len: file_data.len(),
+            });
+            shared_memory.remote_buffer_start
+        }
+    };
+    MapFileResponse::send(
+        sender,
+        requested_path,
+        FileOperationStatus::Success,
+        file_data.len(),
+        remote_buffer_start,
+    );
 }
 
 fn check_file_exists(root_dir: &DirectoryImage, sender: &str, request: &ReadFile) {
@@ -223,12 +452,155 @@ fn check_file_exists(root_dir: &DirectoryImage, sender: &str, request: &ReadFile
     let exists = maybe_fs_entry.is_some();
     let file_size = match maybe_fs_entry {
         None => 0,
-        Some(fs_entry) => fs_entry.file_data.unwrap().len(),
+        Some(fs_entry) => fs_entry.file.map_or(0, |file| file.len()),
     };
     printf!("Checking if file exists: {requested_path}, {exists}, len {file_size}\n");
     CheckFileExistsResponse::send(sender, requested_path, exists, file_size);
 }
 
+fn stat_path(root_dir: &DirectoryImage, sender: &str, request: &StatPath) {
+    let requested_path = str_from_u8_nul_utf8_unchecked(&request.path);
+    match fs_entry_find(&root_dir, &requested_path) {
+        Some(entry) => StatPathResponse::send(
+            sender,
+            requested_path,
+            FileOperationStatus::Success,
+            entry.metadata,
+        ),
+        None => StatPathResponse::send(
+            sender,
+            requested_path,
+            FileOperationStatus::NotFound,
+            FileMetadata::new(0, 0, false, FileType::Unknown),
+        ),
+    }
+}
+
+fn find_files(root_dir: &DirectoryImage, sender: &str, request: &FindFiles) {
+    let pattern = str_from_u8_nul_utf8_unchecked(&request.pattern);
+    if !pattern.starts_with("/") {
+        printf!("Search patterns must be absolute: {pattern}\n");
+        amc_message_send(
+            sender,
+            FindFilesResponse::new(FileOperationStatus::InvalidPath),
+        );
+        return;
+    }
+
+    let matches = fs_find_glob(root_dir, pattern);
+    printf!("Found {} matches for {pattern}\n", matches.len());
+    let mut response = FindFilesResponse::new(FileOperationStatus::Success);
+    response.truncated = matches.len() > response.matches.len();
+    for (slot, entry) in response.matches.iter_mut().zip(matches.iter()) {
+        *slot = Some(DirectoryEntry::new(&entry.path, entry.metadata));
+    }
+    amc_message_send(sender, response);
+}
+
+// Modifications are applied to the in-memory copy of the image, so they're lost on reboot
+
+fn status_of(operation: &str, path: &str, result: Result<(), FsError>) -> FileOperationStatus {
+    match result {
+        Ok(()) => FileOperationStatus::Success,
+        Err(e) => {
+            printf!("{operation} {path} failed: {e:?}\n");
+            e.into()
+        }
+    }
+}
+
+fn write_file(
+    root_dir: &mut DirectoryImage,
+    sender: &str,
+    request: &WriteFile,
+    message_len: usize,
+) {
+    let requested_path = str_from_u8_nul_utf8_unchecked(&request.path);
+    let data = match unsafe { request.data(message_len) } {
+        Some(data) => data,
+        None => {
+            printf!("Dropping malformed WriteFile of {requested_path} from {sender}\n");
+            WriteFileResponse::send(sender, requested_path, FileOperationStatus::IoError);
+            return;
+        }
+    };
+    printf!(
+        "Writing {} bytes to {requested_path} for {sender}\n",
+        data.len()
+    );
+    let result = root_dir.write_file(requested_path, data);
+    WriteFileResponse::send(
+        sender,
+        requested_path,
+        status_of("Write", requested_path, result),
+    );
+}
+
+fn write_file_part(
+    root_dir: &mut DirectoryImage,
+    sender: &str,
+    request: &WriteFilePart,
+    message_len: usize,
+) {
+    let requested_path = str_from_u8_nul_utf8_unchecked(&request.path);
+    let data = match unsafe { request.data(message_len) } {
+        Some(data) => data,
+        None => {
+            printf!("Dropping malformed WriteFilePart of {requested_path} from {sender}\n");
+            WriteFilePartResponse::send(sender, requested_path, FileOperationStatus::IoError);
+            return;
+        }
+    };
+    let result = root_dir.write_file_part(requested_path, request.offset, data);
+    WriteFilePartResponse::send(
+        sender,
+        requested_path,
+        status_of("Partial write", requested_path, result),
+    );
+}
+
+fn create_file(root_dir: &mut DirectoryImage, sender: &str, request: &CreateFile) {
+    let requested_path = str_from_u8_nul_utf8_unchecked(&request.path);
+    let result = root_dir.create_file(requested_path);
+    CreateFileResponse::send(
+        sender,
+        requested_path,
+        status_of("Create file", requested_path, result),
+    );
+}
+
+fn create_directory(root_dir: &mut DirectoryImage, sender: &str, request: &CreateDirectory) {
+    let requested_path = str_from_u8_nul_utf8_unchecked(&request.path);
+    let result = root_dir.create_directory(requested_path);
+    CreateDirectoryResponse::send(
+        sender,
+        requested_path,
+        status_of("Create directory", requested_path, result),
+    );
+}
+
+fn delete_entry(root_dir: &mut DirectoryImage, sender: &str, request: &DeleteEntry) {
+    let requested_path = str_from_u8_nul_utf8_unchecked(&request.path);
+    let result = root_dir.delete(requested_path);
+    DeleteEntryResponse::send(
+        sender,
+        requested_path,
+        status_of("Delete", requested_path, result),
+    );
+}
+
+fn rename_entry(root_dir: &mut DirectoryImage, sender: &str, request: &RenameEntry) {
+    let from_path = str_from_u8_nul_utf8_unchecked(&request.from_path);
+    let to_path = str_from_u8_nul_utf8_unchecked(&request.to_path);
+    let result = root_dir.rename(from_path, to_path);
+    RenameEntryResponse::send(
+        sender,
+        from_path,
+        to_path,
+        status_of("Rename", from_path, result),
+    );
+}
+
 #[start]
 #[allow(unreachable_code)]
 fn start(_argc: isize, _argv: *const *const u8) -> isize {
@@ -253,14 +625,41 @@ fn start(_argc: isize, _argv: *const *const u8) -> isize {
             initrd_info.initrd_size as usize,
         )
     };
+    printf!("Verifying initrd image...\n");
+    // Every service is launched from the initrd, so don't boot from a damaged one
+    if let Err(e) = verify_image(rust_reference) {
+        panic!("Refusing to boot from a damaged initrd image: {e:?}");
+    }
     printf!("Parsing DirectoryImage...\n");
-    let root_dir: DirectoryImage = postcard::from_bytes(rust_reference).expect("Dealloc failed");
+    // File contents stay in the initrd mapping until they're first read, and are checked against
+    // their own digests then
+    let mut root_dir =
+        DirectoryImage::from_image(rust_reference).expect("Failed to parse initrd image");
     printf!("Parsed!\n");
     //traverse_dir(0, &root_dir);
 
-    launch_startup_programs(&root_dir);
+    let mut supervisor = Supervisor::new(load_startup_manifest(&root_dir), ms_since_boot());
+
+    let mut shared_file_mappings = Vec::new();
 
     loop {
+        let mut host = InitrdServiceHost {
+            root_dir: &root_dir,
+        };
+        supervisor.supervise(&mut host);
+        if let Some(poll_interval) = supervisor.ms_until_next_poll(&host) {
+            if !amc_has_message(None) {
+                // Keep serving files while services start up or wait to be restarted
+                amc_message_send(
+                    AMC_CORE_SERVICE_NAME,
+                    AmcSleepUntilDelayOrMessage::new(poll_interval),
+                );
+                if !amc_has_message(None) {
+                    continue;
+                }
+            }
+        }
+
         //printf!("Awaiting next message...\n");
         // TODO(PT): This pattern is copied from the AwmWindow event loop
         let msg_unparsed: AmcMessage<[u8]> = unsafe { amc_message_await_untyped(None).unwrap() };
@@ -289,9 +688,16 @@ fn start(_argc: isize, _argv: *const *const u8) -> isize {
                 ),
                 LaunchProgram::EXPECTED_EVENT => launch_program(
                     &root_dir,
+                    &supervisor,
                     msg_unparsed.source(),
                     body_as_type_unchecked(raw_body),
                 ),
+                LaunchProgramWithArguments::EXPECTED_EVENT => launch_program_with_arguments(
+                    &root_dir,
+                    &supervisor,
+                    msg_unparsed.source(),
+                    raw_body,
+                ),
                 ReadFile::EXPECTED_EVENT => read_file(
                     &root_dir,
                     msg_unparsed.source(),
@@ -302,11 +708,70 @@ fn start(_argc: isize, _argv: *const *const u8) -> isize {
                     msg_unparsed.source(),
                     body_as_type_unchecked(raw_body),
                 ),
+                StatPath::EXPECTED_EVENT => stat_path(
+                    &root_dir,
+                    msg_unparsed.source(),
+                    body_as_type_unchecked(raw_body),
+                ),
+                FindFiles::EXPECTED_EVENT => find_files(
+                    &root_dir,
+                    msg_unparsed.source(),
+                    body_as_type_unchecked(raw_body),
+                ),
+                MapFile::EXPECTED_EVENT => map_file(
+                    &root_dir,
+                    &mut shared_file_mappings,
+                    msg_unparsed.source(),
+                    body_as_type_unchecked(raw_body),
+                ),
                 CheckFileExists::EXPECTED_EVENT => check_file_exists(
                     &root_dir,
                     msg_unparsed.source(),
                     body_as_type_unchecked(raw_body),
                 ),
+                WriteFile::EXPECTED_EVENT => write_file(
+                    &mut root_dir,
+                    msg_unparsed.source(),
+                    body_as_type_unchecked(raw_body),
+                    raw_body.len(),
+                ),
+                WriteFilePart::EXPECTED_EVENT => write_file_part(
+                    &mut root_dir,
+                    msg_unparsed.source(),
+                    body_as_type_unchecked(raw_body),
+                    raw_body.len(),
+                ),
+                CreateFile::EXPECTED_EVENT => create_file(
+                    &mut root_dir,
+                    msg_unparsed.source(),
+                    body_as_type_unchecked(raw_body),
+                ),
+                CreateDirectory::EXPECTED_EVENT => create_directory(
+                    &mut root_dir,
+                    msg_unparsed.source(),
+                    body_as_type_unchecked(raw_body),
+                ),
+                DeleteEntry::EXPECTED_EVENT => delete_entry(
+                    &mut root_dir,
+                    msg_unparsed.source(),
+                    body_as_type_unchecked(raw_body),
+                ),
+                RenameEntry::EXPECTED_EVENT => rename_entry(
+                    &mut root_dir,
+                    msg_unparsed.source(),
+                    body_as_type_unchecked(raw_body),
+                ),
+                AmcServiceDiedNotif::EXPECTED_EVENT
+                    if msg_unparsed.source() == AMC_CORE_SERVICE_NAME =>
+                {
+                    let notif: &AmcServiceDiedNotif = body_as_type_unchecked(raw_body);
+                    supervisor.handle_service_died(
+                        &InitrdServiceHost {
+                            root_dir: &root_dir,
+                        },
+                        str_from_u8_nul_utf8_unchecked(&notif.dead_service),
+                    )
+                }
                 _ => printf!("Unknown event: {}\n", event),
             }
         }
@@ -0,0 +1,381 @@
+use alloc::vec::Vec;
+
+use axle_rt::core_commands::AmcCapabilities;
+use axle_rt::printf;
+use service_manifest::restart::RestartTracker;
+use service_manifest::{LaunchPolicy, RestartPolicy, ServiceDescription, StartupManifest};
+
+/// How often to check on services that are waiting to launch or to register
+const POLL_INTERVAL_MS: u64 = 20;
+/// How long a service can wait to launch or to register before we complain about it
+const SLOW_START_WARNING_MS: u64 = 5000;
+
+/// How the supervisor launches services and learns about them
+pub trait ServiceHost {
+    fn ms_since_boot(&self) -> u64;
+    /// Returns whether the service's program was launched
+    fn launch(&mut self, service: &ServiceDescription) -> bool;
+    /// Returns whether the service is registered with AMC
+    fn service_exists(&mut self, service_name: &str) -> bool;
+    /// Asks AMC to send us an `AmcServiceDiedNotif` when the service dies. AMC drops the request
+    /// if the service doesn't exist.
+    fn notify_when_service_dies(&mut self, service_name: &str);
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum ServiceState {
+    WaitingForDependencies {
+        since: u64,
+    },
+    /// Launched, but not yet registered with AMC
+    Starting {
+        launched_at: u64,
+    },
+    Running {
+        launched_at: u64,
+    },
+    RestartScheduled {
+        at: u64,
+    },
+    /// Exited without a restart policy, or couldn't be launched
+    Stopped,
+}
+
+struct SupervisedService {
+    description: ServiceDescription,
+    state: ServiceState,
+    restarts: RestartTracker,
+    warned_about_slow_start: bool,
+}
+
+impl SupervisedService {
+    fn set_state(&mut self, state: ServiceState) {
+        self.state = state;
+        self.warned_about_slow_start = false;
+    }
+
+    fn launch(&mut self, host: &mut impl ServiceHost, now: u64) {
+        let name = &self.description.service_name;
+        printf!("Launching {name}\n");
+        match host.launch(&self.description) {
+            true => self.set_state(ServiceState::Starting { launched_at: now }),
+            false => {
+                printf!("Failed to launch {name}, it won't be supervised\n");
+                self.set_state(ServiceState::Stopped)
+            }
+        }
+    }
+
+    /// Applies the restart policy to a service that was launched at `launched_at` and has died.
+    /// A service that dies while it's starting counts as a failed start, and backs off just like
+    /// one that dies soon after it registers.
+    fn died(&mut self, launched_at: u64, now: u64) {
+        let name = &self.description.service_name;
+        match self.description.restart {
+            RestartPolicy::Never => {
+                printf!("{name} died, and won't be restarted\n");
+                self.set_state(ServiceState::Stopped);
+            }
+            RestartPolicy::Always => {
+                let decision = self.restarts.record_death(now, now - launched_at);
+                if decision.crash_looping {
+                    printf!("{name} is crash looping! It keeps dying soon after it starts\n");
+                }
+                printf!("{name} died, restarting it in {}ms\n", decision.delay_ms);
+                self.set_state(ServiceState::RestartScheduled {
+                    at: now + decision.delay_ms,
+                });
+            }
+        }
+    }
+}
+
+/// Launches the services listed in the startup manifest, and restarts them according to their
+/// restart policy when they die.
+/// Each service is held back until everything it depends on has registered with AMC. The file
+/// server keeps serving requests in the meantime, as services often need files before they
+/// register.
+pub struct Supervisor {
+    manifest: StartupManifest,
+    /// The services launched at boot, in dependency order
+    services: Vec<SupervisedService>,
+}
+
+impl Supervisor {
+    pub fn new(manifest: StartupManifest, now: u64) -> Self {
+        Self {
+            services: manifest
+                .services
+                .iter()
+                .filter(|description| description.launch == LaunchPolicy::AtBoot)
+                .map(|description| SupervisedService {
+                    description: description.clone(),
+                    state: ServiceState::WaitingForDependencies { since: now },
+                    restarts: RestartTracker::new(),
+                    warned_about_slow_start: false,
+                })
+                .collect(),
+            manifest,
+        }
+    }
+
+    /// The capabilities to grant a program that's launched on request
+    pub fn capabilities_for_path(&self, path: &str) -> AmcCapabilities {
+        self.manifest.capabilities_for_path(path)
+    }
+
+    fn is_running(&self, service_name: &str) -> bool {
+        self.services.iter().any(|s| {
+            s.description.service_name == service_name
+                && matches!(s.state, ServiceState::Running { .. })
+        })
+    }
+
+    /// Launches services whose dependencies are running or whose restart is due, and notices
+    /// services that have finished starting up.
+    pub fn supervise(&mut self, host: &mut impl ServiceHost) {
+        let now = host.ms_since_boot();
+        // Services come after their dependencies, so a dependency that registers during this
+        // pass can unblock its dependents within the same pass
+        for i in 0..self.services.len() {
+            match self.services[i].state {
+                ServiceState::WaitingForDependencies { since } => {
+                    let missing_dependency = self.services[i]
+                        .description
+                        .depends_on
+                        .iter()
+                        .find(|dependency| !self.is_running(dependency))
+                        .cloned();
+                    let service = &mut self.services[i];
+                    match missing_dependency {
+                        None => service.launch(host, now),
+                        Some(dependency) => {
+                            if now - since > SLOW_START_WARNING_MS
+                                && !service.warned_about_slow_start
+                            {
+                                printf!(
+                                    "Still waiting for {dependency} before launching {}\n",
+                                    service.description.service_name
+                                );
+                                service.warned_about_slow_start = true;
+                            }
+                        }
+                    }
+                }
+                ServiceState::Starting { launched_at } => {
+                    let service = &mut self.services[i];
+                    let name = &service.description.service_name;
+                    if host.service_exists(name) {
+                        host.notify_when_service_dies(name);
+                        // AMC drops the request if the service died before it was handled, and
+                        // we'd never hear about the death. AMC handles our requests in order, so
+                        // if the service is still around now, we'll be told when it dies.
+                        if host.service_exists(name) {
+                            service.set_state(ServiceState::Running { launched_at });
+                        } else {
+                            printf!("{name} died while it was starting\n");
+                            service.died(launched_at, now);
+                        }
+                    } else if now - launched_at > SLOW_START_WARNING_MS
+                        && !service.warned_about_slow_start
+                    {
+                        printf!("{name} hasn't registered with AMC since it was launched\n");
+                        service.warned_about_slow_start = true;
+                    }
+                }
+                ServiceState::RestartScheduled { at } if now >= at => {
+                    self.services[i].launch(host, now)
+                }
+                _ => (),
+            }
+        }
+    }
+
+    /// How long until `supervise` has more work to do, or None if it only needs to run after a
+    /// service dies
+    pub fn ms_until_next_poll(&self, host: &impl ServiceHost) -> Option<u32> {
+        let now = host.ms_since_boot();
+        self.services
+            .iter()
+            .filter_map(|s| match s.state {
+                ServiceState::WaitingForDependencies { .. } | ServiceState::Starting { .. } => {
+                    Some(POLL_INTERVAL_MS)
+                }
+                ServiceState::RestartScheduled { at } => Some(at.saturating_sub(now).max(1)),
+                ServiceState::Running { .. } | ServiceState::Stopped => None,
+            })
+            .min()
+            .map(|ms| ms as u32)
+    }
+
+    pub fn handle_service_died(&mut self, host: &impl ServiceHost, dead_service: &str) {
+        let service = match self
+            .services
+            .iter_mut()
+            .find(|s| s.description.service_name == dead_service)
+        {
+            Some(service) => service,
+            None => {
+                printf!("Ignoring death of unsupervised service {dead_service}\n");
+                return;
+            }
+        };
+        match service.state {
+            ServiceState::Starting { launched_at } | ServiceState::Running { launched_at } => {
+                service.died(launched_at, host.ms_since_boot())
+            }
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use alloc::collections::BTreeSet;
+    use alloc::string::{String, ToString};
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    use service_manifest::restart::INITIAL_RESTART_DELAY_MS;
+    use service_manifest::{ServiceDescription, StartupManifest};
+
+    use super::{ServiceHost, ServiceState, Supervisor};
+
+    /// Services register as soon as they're launched, unless they're set to die first
+    #[derive(Default)]
+    struct FakeHost {
+        now: u64,
+        launched: Vec<String>,
+        registered: BTreeSet<String>,
+        /// These die before the request to be told about their death is handled
+        die_while_registering: BTreeSet<String>,
+        watched: Vec<String>,
+    }
+
+    impl ServiceHost for FakeHost {
+        fn ms_since_boot(&self) -> u64 {
+            self.now
+        }
+
+        fn launch(&mut self, service: &ServiceDescription) -> bool {
+            self.launched.push(service.service_name.clone());
+            self.registered.insert(service.service_name.clone());
+            true
+        }
+
+        fn service_exists(&mut self, service_name: &str) -> bool {
+            self.registered.contains(service_name)
+        }
+
+        fn notify_when_service_dies(&mut self, service_name: &str) {
+            if self.die_while_registering.contains(service_name) {
+                self.registered.remove(service_name);
+            } else {
+                self.watched.push(service_name.to_string());
+            }
+        }
+    }
+
+    fn supervisor_for(manifest: &str) -> Supervisor {
+        Supervisor::new(StartupManifest::parse(manifest).unwrap(), 0)
+    }
+
+    fn state_of(supervisor: &Supervisor, service_name: &str) -> ServiceState {
+        supervisor
+            .services
+            .iter()
+            .find(|s| s.description.service_name == service_name)
+            .unwrap()
+            .state
+    }
+
+    #[test]
+    fn test_death_while_registering_is_a_failed_start() {
+        // Given a service that dies just after it registers, before we're watching it
+        let mut supervisor = supervisor_for(
+            "
+            [com.axle.flaky]
+            path = /usr/applications/flaky
+            restart = always
+            ",
+        );
+        let mut host = FakeHost::default();
+        host.die_while_registering
+            .insert("com.axle.flaky".to_string());
+
+        // When it's launched and registers
+        supervisor.supervise(&mut host);
+        supervisor.supervise(&mut host);
+
+        // Then its death is noticed, and it's restarted after a delay
+        assert_eq!(host.launched, vec!["com.axle.flaky"]);
+        assert!(host.watched.is_empty());
+        assert_eq!(
+            state_of(&supervisor, "com.axle.flaky"),
+            ServiceState::RestartScheduled {
+                at: INITIAL_RESTART_DELAY_MS
+            }
+        );
+
+        // And the next failed start waits longer
+        host.now = INITIAL_RESTART_DELAY_MS;
+        supervisor.supervise(&mut host);
+        supervisor.supervise(&mut host);
+        assert_eq!(host.launched.len(), 2);
+        assert_eq!(
+            state_of(&supervisor, "com.axle.flaky"),
+            ServiceState::RestartScheduled {
+                at: host.now + INITIAL_RESTART_DELAY_MS * 2
+            }
+        );
+    }
+
+    #[test]
+    fn test_death_while_starting_is_a_failed_start() {
+        let mut supervisor = supervisor_for(
+            "
+            [com.axle.flaky]
+            path = /usr/applications/flaky
+            restart = always
+
+            [com.axle.once]
+            path = /usr/applications/once
+            ",
+        );
+        let mut host = FakeHost::default();
+        supervisor.supervise(&mut host);
+        assert_eq!(
+            state_of(&supervisor, "com.axle.flaky"),
+            ServiceState::Starting { launched_at: 0 }
+        );
+
+        // When services die before they've been seen to register
+        host.now = 10;
+        supervisor.handle_service_died(&host, "com.axle.flaky");
+        supervisor.handle_service_died(&host, "com.axle.once");
+
+        // Then they're handled according to their restart policy
+        assert_eq!(
+            state_of(&supervisor, "com.axle.flaky"),
+            ServiceState::RestartScheduled {
+                at: 10 + INITIAL_RESTART_DELAY_MS
+            }
+        );
+        assert_eq!(
+            state_of(&supervisor, "com.axle.once"),
+            ServiceState::Stopped
+        );
+
+        // And a service that keeps failing to start backs off
+        host.now = 10 + INITIAL_RESTART_DELAY_MS;
+        supervisor.supervise(&mut host);
+        supervisor.handle_service_died(&host, "com.axle.flaky");
+        assert_eq!(
+            state_of(&supervisor, "com.axle.flaky"),
+            ServiceState::RestartScheduled {
+                at: host.now + INITIAL_RESTART_DELAY_MS * 2
+            }
+        );
+        assert_eq!(host.launched.len(), 3);
+    }
+}
@@ -0,0 +1,451 @@
+//! The on-disk initrd format.
+//!
+//! An image is laid out as:
+//! - A fixed-size header: magic, format version, the length of the index (little-endian),
+//!   the SHA-256 of the index, and the SHA-256 of everything after the header
+//! - The index: a postcard-encoded tree of directories, with each file referring to a blob
+//! - The blob region: the contents of each distinct file, optionally compressed
+//!
+//! Files with identical contents share a blob, and blobs are only decoded when a file is read.
+//! The index is verified when the image is loaded, and each blob is verified against the
+//! SHA-256 of its decoded contents when it's first decoded.
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+use file_manager_messages::FileMetadata;
+use serde::{Deserialize, Serialize};
+
+use crate::lz4;
+use crate::sha256::{sha256, Sha256Digest, SHA256_DIGEST_LEN};
+use crate::{DirectoryImage, FileContents, FsError};
+
+pub const IMAGE_MAGIC: [u8; 4] = *b"AXFS";
+pub const IMAGE_VERSION: u32 = 2;
+const INDEX_DIGEST_OFFSET: usize = 12;
+const IMAGE_DIGEST_OFFSET: usize = INDEX_DIGEST_OFFSET + SHA256_DIGEST_LEN;
+const HEADER_LEN: usize = IMAGE_DIGEST_OFFSET + SHA256_DIGEST_LEN;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ImageError {
+    BadMagic,
+    UnsupportedVersion(u32),
+    Truncated,
+    MalformedIndex,
+    /// A file refers to a blob that isn't described by the index
+    MissingBlob(u32),
+    /// A blob's stored bytes lie outside the image
+    BlobOutOfBounds(u32),
+    IndexDigestMismatch,
+    ImageDigestMismatch,
+}
+
+#[repr(u8)]
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Lz4,
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone)]
+struct BlobDescriptor {
+    /// Relative to the start of the blob region
+    offset: u64,
+    stored_len: u64,
+    decoded_len: u64,
+    compression: Compression,
+    /// The digest of the decoded contents
+    digest: Sha256Digest,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct IndexFile {
+    blob: u32,
+    metadata: FileMetadata,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct IndexDirectory {
+    name: String,
+    files: BTreeMap<String, IndexFile>,
+    subdirectories: BTreeMap<String, IndexDirectory>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct ImageIndex {
+    blobs: Vec<BlobDescriptor>,
+    root: IndexDirectory,
+}
+
+/// The still-encoded contents of a file within an image
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct PackedBlob<'a> {
+    pub(crate) compression: Compression,
+    pub(crate) stored: &'a [u8],
+    pub(crate) decoded_len: usize,
+    pub(crate) digest: Sha256Digest,
+}
+
+impl<'a> PackedBlob<'a> {
+    /// Fails with `FsError::Corrupt` if the contents don't match the digest recorded for them
+    pub(crate) fn decode(&self) -> Result<Vec<u8>, FsError> {
+        let decoded = match self.compression {
+            Compression::None => self.stored.to_vec(),
+            Compression::Lz4 => {
+                lz4::decompress(self.stored, self.decoded_len).map_err(|_| FsError::Corrupt)?
+            }
+        };
+        if sha256(&decoded) != self.digest {
+            return Err(FsError::Corrupt);
+        }
+        Ok(decoded)
+    }
+}
+
+// FNV-1a, used to find candidate duplicates before comparing contents
+fn content_hash(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+struct ImageWriter {
+    blobs: Vec<BlobDescriptor>,
+    blob_region: Vec<u8>,
+    blobs_by_hash: BTreeMap<u64, Vec<u32>>,
+}
+
+impl ImageWriter {
+    fn new() -> Self {
+        Self {
+            blobs: Vec::new(),
+            blob_region: Vec::new(),
+            blobs_by_hash: BTreeMap::new(),
+        }
+    }
+
+    fn blob_contents(&self, blob: &BlobDescriptor) -> Vec<u8> {
+        let stored =
+            &self.blob_region[blob.offset as usize..(blob.offset + blob.stored_len) as usize];
+        PackedBlob {
+            compression: blob.compression,
+            stored,
+            decoded_len: blob.decoded_len as usize,
+            digest: blob.digest,
+        }
+        .decode()
+        .expect("Failed to decode a blob we just encoded")
+    }
+
+    fn add_blob(&mut self, data: &[u8]) -> u32 {
+        let hash = content_hash(data);
+        if let Some(candidates) = self.blobs_by_hash.get(&hash) {
+            for &candidate in candidates {
+                let blob = self.blobs[candidate as usize];
+                if blob.decoded_len as usize == data.len() && self.blob_contents(&blob) == data {
+                    return candidate;
+                }
+            }
+        }
+
+        let compressed = lz4::compress(data);
+        let (compression, stored) = match compressed.len() < data.len() {
+            true => (Compression::Lz4, compressed.as_slice()),
+            false => (Compression::None, data),
+        };
+        let blob_idx = self.blobs.len() as u32;
+        self.blobs.push(BlobDescriptor {
+            offset: self.blob_region.len() as u64,
+            stored_len: stored.len() as u64,
+            decoded_len: data.len() as u64,
+            compression,
+            digest: sha256(data),
+        });
+        self.blob_region.extend_from_slice(stored);
+        self.blobs_by_hash.entry(hash).or_default().push(blob_idx);
+        blob_idx
+    }
+
+    fn add_directory(&mut self, dir: &DirectoryImage) -> IndexDirectory {
+        let mut files = BTreeMap::new();
+        for (name, contents) in dir.files.iter() {
+            let metadata = dir.metadata_for_file(name, contents);
+            let blob = self.add_blob(contents.data());
+            files.insert(name.clone(), IndexFile { blob, metadata });
+        }
+        let subdirectories = dir
+            .subdirectories
+            .iter()
+            .map(|(name, subdir)| (name.clone(), self.add_directory(subdir)))
+            .collect();
+        IndexDirectory {
+            name: dir.name.clone(),
+            files,
+            subdirectories,
+        }
+    }
+}
+
+/// Serializes a directory tree into the image format
+pub fn encode_image(root: &DirectoryImage) -> Vec<u8> {
+    let mut writer = ImageWriter::new();
+    let root = writer.add_directory(root);
+    let index = ImageIndex {
+        blobs: writer.blobs,
+        root,
+    };
+    let index_bytes = postcard::to_allocvec(&index).expect("Failed to encode image index");
+
+    let mut image = Vec::with_capacity(HEADER_LEN + index_bytes.len() + writer.blob_region.len());
+    image.extend_from_slice(&IMAGE_MAGIC);
+    image.extend_from_slice(&IMAGE_VERSION.to_le_bytes());
+    image.extend_from_slice(&(index_bytes.len() as u32).to_le_bytes());
+    image.extend_from_slice(&sha256(&index_bytes));
+    // Filled in once the rest of the image is in place
+    image.extend_from_slice(&[0; SHA256_DIGEST_LEN]);
+    image.extend_from_slice(&index_bytes);
+    image.extend_from_slice(&writer.blob_region);
+
+    let image_digest = sha256(&image[HEADER_LEN..]);
+    image[IMAGE_DIGEST_OFFSET..HEADER_LEN].copy_from_slice(&image_digest);
+    image
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn load_directory<'a>(
+    dir: IndexDirectory,
+    blobs: &[FileContents<'a>],
+) -> Result<DirectoryImage<'a>, ImageError> {
+    let mut loaded = DirectoryImage::new(&dir.name);
+    for (name, file) in dir.files.into_iter() {
+        let contents = blobs
+            .get(file.blob as usize)
+            .ok_or(ImageError::MissingBlob(file.blob))?;
+        loaded.files.insert(name.clone(), contents.clone());
+        loaded.file_metadata.insert(name, file.metadata);
+    }
+    for (name, subdir) in dir.subdirectories.into_iter() {
+        loaded
+            .subdirectories
+            .insert(name, load_directory(subdir, blobs)?);
+    }
+    Ok(loaded)
+}
+
+/// Parses an image's index. File contents are left in `image` until they're first read.
+pub fn decode_image(image: &[u8]) -> Result<DirectoryImage<'_>, ImageError> {
+    if image.len() < HEADER_LEN {
+        return Err(ImageError::Truncated);
+    }
+    if image[..4] != IMAGE_MAGIC {
+        return Err(ImageError::BadMagic);
+    }
+    let version = read_u32(image, 4);
+    if version != IMAGE_VERSION {
+        return Err(ImageError::UnsupportedVersion(version));
+    }
+    let index_len = read_u32(image, 8) as usize;
+    let index_bytes = image
+        .get(HEADER_LEN..HEADER_LEN + index_len)
+        .ok_or(ImageError::Truncated)?;
+    if sha256(index_bytes) != image[INDEX_DIGEST_OFFSET..IMAGE_DIGEST_OFFSET] {
+        return Err(ImageError::IndexDigestMismatch);
+    }
+    let index: ImageIndex =
+        postcard::from_bytes(index_bytes).map_err(|_| ImageError::MalformedIndex)?;
+    let blob_region = &image[HEADER_LEN + index_len..];
+
+    let mut blobs = Vec::with_capacity(index.blobs.len());
+    for (i, blob) in index.blobs.iter().enumerate() {
+        let stored = usize::try_from(blob.offset)
+            .ok()
+            .zip(usize::try_from(blob.offset + blob.stored_len).ok())
+            .and_then(|(start, end)| blob_region.get(start..end))
+            .ok_or(ImageError::BlobOutOfBounds(i as u32))?;
+        blobs.push(FileContents::packed(PackedBlob {
+            compression: blob.compression,
+            stored,
+            decoded_len: blob.decoded_len as usize,
+            digest: blob.digest,
+        }));
+    }
+    load_directory(index.root, &blobs)
+}
+
+/// Checks the digest covering the whole image.
+/// This reads every byte of the image, so it's not done as part of `decode_image`.
+pub fn verify_image(image: &[u8]) -> Result<(), ImageError> {
+    if image.len() < HEADER_LEN {
+        return Err(ImageError::Truncated);
+    }
+    if sha256(&image[HEADER_LEN..]) != image[IMAGE_DIGEST_OFFSET..HEADER_LEN] {
+        return Err(ImageError::ImageDigestMismatch);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs_entry_find;
+
+    fn sample_tree() -> DirectoryImage<'static> {
+        let mut root = DirectoryImage::new("/");
+        root.create_directory("/usr").unwrap();
+        root.create_directory("/usr/fonts").unwrap();
+        let repetitive: Vec<u8> = b"axle ".iter().cycle().take(4096).copied().collect();
+        root.write_file("/usr/fonts/a.ttf", &repetitive).unwrap();
+        root.write_file("/usr/fonts/copy.ttf", &repetitive).unwrap();
+        root.write_file("/usr/tiny.txt", b"hi").unwrap();
+        root.create_file("/empty").unwrap();
+        root
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        // Given a directory tree
+        let root = sample_tree();
+
+        // When I encode it into an image and load it back
+        let image = encode_image(&root);
+        let loaded = decode_image(&image).unwrap();
+
+        // Then every file has the same contents and metadata
+        for path in [
+            "/usr/fonts/a.ttf",
+            "/usr/fonts/copy.ttf",
+            "/usr/tiny.txt",
+            "/empty",
+        ] {
+            let original = fs_entry_find(&root, path).unwrap();
+            let found = fs_entry_find(&loaded, path).unwrap();
+            assert_eq!(found.file_data(), original.file_data(), "{path}");
+            assert_eq!(found.metadata, original.metadata, "{path}");
+        }
+        assert_eq!(
+            fs_entry_find(&loaded, "/usr/fonts")
+                .unwrap()
+                .dir_image
+                .unwrap()
+                .name,
+            "fonts"
+        );
+    }
+
+    #[test]
+    fn test_compression_and_deduplication() {
+        // Given a tree containing two copies of a large, repetitive file
+        let root = sample_tree();
+
+        // When I encode it
+        let image = encode_image(&root);
+
+        // Then the image is much smaller than even a single copy of the file
+        assert!(image.len() < 4096 / 2);
+    }
+
+    #[test]
+    fn test_files_are_decoded_lazily() {
+        // Given a loaded image
+        let image = encode_image(&sample_tree());
+        let loaded = decode_image(&image).unwrap();
+        let font_dir = &loaded.subdirectories["usr"].subdirectories["fonts"];
+        let (a, copy) = (&font_dir.files["a.ttf"], &font_dir.files["copy.ttf"]);
+
+        // Then nothing is decoded until it's read
+        assert!(!a.is_decoded());
+        assert_eq!(a.len(), 4096);
+        assert!(!a.is_decoded());
+
+        // And reading one file decodes the blob it shares with its duplicate
+        fs_entry_find(&loaded, "/usr/fonts/a.ttf")
+            .unwrap()
+            .file_data()
+            .unwrap();
+        assert!(a.is_decoded());
+        assert!(copy.is_decoded());
+    }
+
+    #[test]
+    fn test_invalid_images() {
+        let image = encode_image(&sample_tree());
+
+        assert_eq!(
+            decode_image(&image[..4]).unwrap_err(),
+            ImageError::Truncated
+        );
+
+        let mut bad_magic = image.clone();
+        bad_magic[0] = b'X';
+        assert_eq!(decode_image(&bad_magic).unwrap_err(), ImageError::BadMagic);
+
+        let mut future_version = image.clone();
+        future_version[4..8].copy_from_slice(&3_u32.to_le_bytes());
+        assert_eq!(
+            decode_image(&future_version).unwrap_err(),
+            ImageError::UnsupportedVersion(3)
+        );
+
+        // An image cut off partway through the blob region
+        let index_len = read_u32(&image, 8) as usize;
+        assert!(matches!(
+            decode_image(&image[..HEADER_LEN + index_len + 1]).unwrap_err(),
+            ImageError::BlobOutOfBounds(_)
+        ));
+
+        assert_eq!(decode_image(&[0; 3]).unwrap_err(), ImageError::Truncated);
+    }
+
+    #[test]
+    fn test_corrupt_index_is_rejected() {
+        // Given an image whose index has been tampered with
+        let mut image = encode_image(&sample_tree());
+        image[HEADER_LEN] ^= 0xff;
+
+        // Then it's rejected when it's loaded
+        assert_eq!(
+            decode_image(&image).unwrap_err(),
+            ImageError::IndexDigestMismatch
+        );
+    }
+
+    #[test]
+    fn test_corrupt_blob_is_detected_when_read() {
+        // Given an image whose last blob has been tampered with
+        let image = encode_image(&sample_tree());
+        assert_eq!(verify_image(&image), Ok(()));
+        let mut image = image;
+        let last = image.len() - 1;
+        image[last] ^= 0xff;
+
+        // Then the image still loads, but the whole-image digest no longer matches
+        let mut loaded = decode_image(&image).unwrap();
+        assert_eq!(verify_image(&image), Err(ImageError::ImageDigestMismatch));
+
+        // And reading the tampered file fails, while other files are still readable
+        let corrupt_files: Vec<_> = ["/usr/fonts/a.ttf", "/usr/tiny.txt", "/empty"]
+            .into_iter()
+            .filter(|path| {
+                fs_entry_find(&loaded, path)
+                    .unwrap()
+                    .try_file_data()
+                    .is_err()
+            })
+            .collect();
+        assert_eq!(corrupt_files.len(), 1);
+        assert_eq!(
+            fs_entry_find(&loaded, corrupt_files[0])
+                .unwrap()
+                .try_file_data(),
+            Err(FsError::Corrupt)
+        );
+        // And modifying the tampered file reports the corruption too
+        assert_eq!(
+            loaded.write_file_part(corrupt_files[0], 0, b"a"),
+            Err(FsError::Corrupt)
+        );
+    }
+}
@@ -1,4 +1,5 @@
 #![no_std]
+#![feature(once_cell)]
 extern crate alloc;
 #[cfg(test)]
 extern crate std;
@@ -7,47 +8,173 @@ use std::format;
 
 use alloc::borrow::ToOwned;
 use alloc::collections::BTreeMap;
+use alloc::rc::Rc;
 use alloc::string::String;
 use alloc::vec::Vec;
-use serde::{Deserialize, Serialize};
+use core::cell::OnceCell;
+use core::fmt;
+use file_manager_messages::{FileMetadata, FileOperationStatus, FileType};
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct DirectoryImage {
+pub mod image;
+mod lz4;
+pub mod query;
+pub mod sha256;
+
+use crate::image::PackedBlob;
+
+/// Files are held in memory, so writes may not grow a file past this size
+pub const MAX_FILE_SIZE: usize = 64 * 1024 * 1024;
+
+#[derive(Debug)]
+struct Blob<'a> {
+    // Set if the contents came from an image and haven't been modified since
+    packed: Option<PackedBlob<'a>>,
+    decoded: OnceCell<Vec<u8>>,
+}
+
+/// The contents of a file. Contents loaded from an image are decoded on first access.
+/// Files with identical contents in an image share a single decoded copy.
+#[derive(Clone)]
+pub struct FileContents<'a>(Rc<Blob<'a>>);
+
+impl<'a> FileContents<'a> {
+    pub fn new(data: Vec<u8>) -> Self {
+        Self(Rc::new(Blob {
+            packed: None,
+            decoded: OnceCell::from(data),
+        }))
+    }
+
+    pub(crate) fn packed(packed: PackedBlob<'a>) -> Self {
+        Self(Rc::new(Blob {
+            packed: Some(packed),
+            decoded: OnceCell::new(),
+        }))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn len(&self) -> usize {
+        match (self.0.decoded.get(), &self.0.packed) {
+            (Some(data), _) => data.len(),
+            (None, Some(packed)) => packed.decoded_len,
+            (None, None) => unreachable!("File contents are neither decoded nor packed"),
+        }
+    }
+
+    pub fn is_decoded(&self) -> bool {
+        self.0.decoded.get().is_some()
+    }
+
+    /// Fails with `FsError::Corrupt` if the contents don't match the digest recorded in the image.
+    /// Contents that fail to decode are not cached, so each access checks them again.
+    pub fn try_data(&self) -> Result<&Vec<u8>, FsError> {
+        if let Some(data) = self.0.decoded.get() {
+            return Ok(data);
+        }
+        let packed = self.0.packed.as_ref().unwrap();
+        let _ = self.0.decoded.set(packed.decode()?);
+        Ok(self.0.decoded.get().unwrap())
+    }
+
+    pub fn data(&self) -> &Vec<u8> {
+        self.try_data()
+            .unwrap_or_else(|e| panic!("Failed to decode file contents: {e:?}"))
+    }
+
+    /// Detaches these contents from any other files sharing them, and allows them to be modified.
+    /// Fails with `FsError::Corrupt` like `try_data`.
+    pub fn try_data_mut(&mut self) -> Result<&mut Vec<u8>, FsError> {
+        if Rc::get_mut(&mut self.0).is_none() {
+            *self = FileContents::new(self.try_data()?.clone());
+        }
+        self.try_data()?;
+        let blob = Rc::get_mut(&mut self.0).unwrap();
+        blob.packed = None;
+        Ok(blob.decoded.get_mut().unwrap())
+    }
+}
+
+impl<'a> fmt::Debug for FileContents<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FileContents")
+            .field("len", &self.len())
+            .field("is_decoded", &self.is_decoded())
+            .finish()
+    }
+}
+
+#[derive(Debug)]
+pub struct DirectoryImage<'a> {
     pub name: String,
-    pub files: BTreeMap<String, Vec<u8>>,
-    pub subdirectories: BTreeMap<String, DirectoryImage>,
+    pub files: BTreeMap<String, FileContents<'a>>,
+    /// Keyed by file name, like `files`
+    pub file_metadata: BTreeMap<String, FileMetadata>,
+    pub subdirectories: BTreeMap<String, DirectoryImage<'a>>,
+}
+
+impl<'a> DirectoryImage<'a> {
+    /// Loads an image produced by `image::encode_image`
+    pub fn from_image(image: &'a [u8]) -> Result<Self, image::ImageError> {
+        image::decode_image(image)
+    }
 }
 
 #[derive(Debug)]
 pub struct FsEntry<'a> {
     pub path: String,
     pub is_dir: bool,
-    pub dir_image: Option<&'a DirectoryImage>,
-    pub file_data: Option<&'a Vec<u8>>,
+    pub dir_image: Option<&'a DirectoryImage<'a>>,
+    pub file: Option<&'a FileContents<'a>>,
+    pub metadata: FileMetadata,
+}
+
+impl<'a> FsEntry<'a> {
+    /// Decodes the file's contents if this is their first access
+    pub fn file_data(&self) -> Option<&'a Vec<u8>> {
+        self.file.map(|file| file.data())
+    }
+
+    /// Like `file_data`, but reports corrupt contents rather than panicking
+    pub fn try_file_data(&self) -> Result<&'a Vec<u8>, FsError> {
+        self.file.ok_or(FsError::IsDirectory)?.try_data()
+    }
 }
 
-pub fn fs_entry_find<'a>(root_dir: &'a DirectoryImage, path: &str) -> Option<FsEntry<'a>> {
+const ELF_MAGIC: &[u8] = b"\x7fELF";
+
+/// Builds the metadata for a file that has just been added to the image
+pub fn file_metadata_for(name: &str, data: &[u8], modified: u64) -> FileMetadata {
+    let is_executable = data.starts_with(ELF_MAGIC);
+    let file_type = match is_executable {
+        true => FileType::Executable,
+        false => FileType::from_name(name),
+    };
+    FileMetadata::new(data.len() as u64, modified, is_executable, file_type)
+}
+
+/// Resolves a single path. Use the `query` module to match several entries at once.
+pub fn fs_entry_find<'a>(root_dir: &'a DirectoryImage<'a>, path: &str) -> Option<FsEntry<'a>> {
+    let components = path_components(path);
     // Start off at the root directory
     let mut dir_iter = root_dir;
-    for component in path.split("/") {
-        if component.len() == 0 {
-            // Infix or trailing slash - skip it
-            continue;
-        }
-
-        match dir_iter.subdirectories.get(component) {
+    for (i, component) in components.iter().enumerate() {
+        match dir_iter.subdirectories.get(*component) {
             // There's no subdirectory with this name, but is there a file?
-            None => match dir_iter.files.get(component) {
+            None => match dir_iter.files.get(*component) {
                 // Path doesn't exist
                 None => return None,
-                // We encountered a file in the traversal
-                // TODO(PT): We want to ensure that this is the end of the provided path
+                // We encountered a file in the traversal, but there's more path left to follow
+                Some(_) if i != components.len() - 1 => return None,
                 Some(file) => {
                     return Some(FsEntry {
                         path: path.to_owned(),
                         is_dir: false,
                         dir_image: None,
-                        file_data: Some(file),
+                        file: Some(file),
+                        metadata: dir_iter.metadata_for_file(component, file),
                     })
                 }
             },
@@ -60,12 +187,252 @@ pub fn fs_entry_find<'a>(root_dir: &'a DirectoryImage, path: &str) -> Option<FsE
         path: path.to_owned(),
         is_dir: true,
         dir_image: Some(dir_iter),
-        file_data: None,
+        file: None,
+        metadata: FileMetadata::directory(),
     })
 }
 
+/// Reasons an access or modification to a `DirectoryImage` can fail
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FsError {
+    NotFound,
+    AlreadyExists,
+    IsDirectory,
+    NotADirectory,
+    DirectoryNotEmpty,
+    InvalidPath,
+    /// A file's contents don't match the digest recorded for them in the image
+    Corrupt,
+    /// The write would grow the file past `MAX_FILE_SIZE`
+    NoSpace,
+}
+
+impl From<FsError> for FileOperationStatus {
+    fn from(e: FsError) -> Self {
+        match e {
+            FsError::NotFound => FileOperationStatus::NotFound,
+            FsError::AlreadyExists => FileOperationStatus::AlreadyExists,
+            FsError::IsDirectory => FileOperationStatus::IsDirectory,
+            FsError::NotADirectory => FileOperationStatus::NotADirectory,
+            FsError::DirectoryNotEmpty => FileOperationStatus::DirectoryNotEmpty,
+            FsError::InvalidPath => FileOperationStatus::InvalidPath,
+            FsError::Corrupt => FileOperationStatus::IoError,
+            FsError::NoSpace => FileOperationStatus::NoSpace,
+        }
+    }
+}
+
+/// Splits a path into the names to follow from the root.
+/// Infix or trailing slashes are skipped, and `.` and `..` are resolved.
+/// `..` at the root stays at the root.
+fn path_components(path: &str) -> Vec<&str> {
+    let mut components = Vec::new();
+    for component in path.split("/") {
+        match component {
+            "" | "." => continue,
+            ".." => {
+                components.pop();
+            }
+            _ => components.push(component),
+        }
+    }
+    components
+}
+
+impl<'a> DirectoryImage<'a> {
+    pub fn new(name: &str) -> Self {
+        DirectoryImage {
+            name: name.to_owned(),
+            files: BTreeMap::new(),
+            file_metadata: BTreeMap::new(),
+            subdirectories: BTreeMap::new(),
+        }
+    }
+
+    /// Falls back to metadata derived from the file itself if none was recorded.
+    /// If the contents are corrupt, the fallback is derived from the name alone, and the
+    /// corruption is reported once the contents are read.
+    pub fn metadata_for_file(&self, name: &str, contents: &FileContents) -> FileMetadata {
+        self.try_metadata_for_file(name, contents)
+            .unwrap_or_else(|_| {
+                FileMetadata::new(contents.len() as u64, 0, false, FileType::from_name(name))
+            })
+    }
+
+    /// Like `metadata_for_file`, but fails with `FsError::Corrupt` if the fallback can't be
+    /// derived from the contents
+    pub fn try_metadata_for_file(
+        &self,
+        name: &str,
+        contents: &FileContents,
+    ) -> Result<FileMetadata, FsError> {
+        match self.file_metadata.get(name) {
+            Some(metadata) => Ok(FileMetadata {
+                size: contents.len() as u64,
+                ..*metadata
+            }),
+            None => Ok(file_metadata_for(name, contents.try_data()?, 0)),
+        }
+    }
+
+    fn has_entry(&self, name: &str) -> bool {
+        self.files.contains_key(name) || self.subdirectories.contains_key(name)
+    }
+
+    fn dir_mut(&mut self, components: &[&str]) -> Result<&mut DirectoryImage<'a>, FsError> {
+        let mut dir_iter = self;
+        for component in components {
+            if dir_iter.files.contains_key(*component) {
+                return Err(FsError::NotADirectory);
+            }
+            dir_iter = dir_iter
+                .subdirectories
+                .get_mut(*component)
+                .ok_or(FsError::NotFound)?;
+        }
+        Ok(dir_iter)
+    }
+
+    /// Returns the directory containing the final path component, along with the component
+    fn parent_dir_mut<'b>(
+        &mut self,
+        path: &'b str,
+    ) -> Result<(&mut DirectoryImage<'a>, &'b str), FsError> {
+        let components = path_components(path);
+        let (name, parent_components) = components.split_last().ok_or(FsError::InvalidPath)?;
+        if *name == "." || *name == ".." {
+            return Err(FsError::InvalidPath);
+        }
+        Ok((self.dir_mut(parent_components)?, name))
+    }
+
+    /// Creates the file if it doesn't exist, and replaces its contents with `data`
+    pub fn write_file(&mut self, path: &str, data: &[u8]) -> Result<(), FsError> {
+        if data.len() > MAX_FILE_SIZE {
+            return Err(FsError::NoSpace);
+        }
+        let (parent, name) = self.parent_dir_mut(path)?;
+        if parent.subdirectories.contains_key(name) {
+            return Err(FsError::IsDirectory);
+        }
+        let contents = FileContents::new(data.to_vec());
+        let metadata = parent.try_metadata_for_file(name, &contents)?;
+        parent.files.insert(name.to_owned(), contents);
+        parent.file_metadata.insert(name.to_owned(), metadata);
+        Ok(())
+    }
+
+    /// Overwrites part of an existing file, extending it with zeroes if `offset` is past its end
+    pub fn write_file_part(
+        &mut self,
+        path: &str,
+        offset: usize,
+        data: &[u8],
+    ) -> Result<(), FsError> {
+        let end = offset
+            .checked_add(data.len())
+            .filter(|end| *end <= MAX_FILE_SIZE)
+            .ok_or(FsError::NoSpace)?;
+        let (parent, name) = self.parent_dir_mut(path)?;
+        if parent.subdirectories.contains_key(name) {
+            return Err(FsError::IsDirectory);
+        }
+        let file = parent
+            .files
+            .get_mut(name)
+            .ok_or(FsError::NotFound)?
+            .try_data_mut()?;
+        if file.len() < end {
+            file.resize(end, 0);
+        }
+        file[offset..end].copy_from_slice(data);
+        Ok(())
+    }
+
+    pub fn create_file(&mut self, path: &str) -> Result<(), FsError> {
+        let (parent, name) = self.parent_dir_mut(path)?;
+        if parent.has_entry(name) {
+            return Err(FsError::AlreadyExists);
+        }
+        parent
+            .files
+            .insert(name.to_owned(), FileContents::new(Vec::new()));
+        parent
+            .file_metadata
+            .insert(name.to_owned(), file_metadata_for(name, &[], 0));
+        Ok(())
+    }
+
+    pub fn create_directory(&mut self, path: &str) -> Result<(), FsError> {
+        let (parent, name) = self.parent_dir_mut(path)?;
+        if parent.has_entry(name) {
+            return Err(FsError::AlreadyExists);
+        }
+        parent
+            .subdirectories
+            .insert(name.to_owned(), DirectoryImage::new(name));
+        Ok(())
+    }
+
+    /// Deletes a file, or an empty directory
+    pub fn delete(&mut self, path: &str) -> Result<(), FsError> {
+        let (parent, name) = self.parent_dir_mut(path)?;
+        if parent.files.remove(name).is_some() {
+            parent.file_metadata.remove(name);
+            return Ok(());
+        }
+        let dir = parent.subdirectories.get(name).ok_or(FsError::NotFound)?;
+        if dir.has_any_entries() {
+            return Err(FsError::DirectoryNotEmpty);
+        }
+        parent.subdirectories.remove(name);
+        Ok(())
+    }
+
+    fn has_any_entries(&self) -> bool {
+        !self.files.is_empty() || !self.subdirectories.is_empty()
+    }
+
+    /// Renames or moves a file or directory. The destination must not already exist.
+    pub fn rename(&mut self, from_path: &str, to_path: &str) -> Result<(), FsError> {
+        let from_components = path_components(from_path);
+        let to_components = path_components(to_path);
+        // A directory can't be moved inside itself
+        if to_components.len() > from_components.len()
+            && to_components.starts_with(&from_components)
+        {
+            return Err(FsError::InvalidPath);
+        }
+
+        // Validate the destination before detaching anything from the source
+        let (to_parent, to_name) = self.parent_dir_mut(to_path)?;
+        if to_parent.has_entry(to_name) {
+            return Err(FsError::AlreadyExists);
+        }
+
+        let (from_parent, from_name) = self.parent_dir_mut(from_path)?;
+        if let Some(file) = from_parent.files.remove(from_name) {
+            let metadata = from_parent.file_metadata.remove(from_name);
+            let (to_parent, to_name) = self.parent_dir_mut(to_path)?;
+            to_parent.files.insert(to_name.to_owned(), file);
+            if let Some(metadata) = metadata {
+                to_parent.file_metadata.insert(to_name.to_owned(), metadata);
+            }
+        } else {
+            let mut dir = from_parent
+                .subdirectories
+                .remove(from_name)
+                .ok_or(FsError::NotFound)?;
+            dir.name = to_name.to_owned();
+            let (to_parent, to_name) = self.parent_dir_mut(to_path)?;
+            to_parent.subdirectories.insert(to_name.to_owned(), dir);
+        }
+        Ok(())
+    }
+}
+
 #[cfg(test)]
-fn get_root_directory_from_image() -> DirectoryImage {
+fn get_root_directory_from_image() -> DirectoryImage<'static> {
     let current_dir = std::env::current_dir().unwrap();
     let image_path = current_dir
         .ancestors()
@@ -79,7 +446,8 @@ fn get_root_directory_from_image() -> DirectoryImage {
         .join("mkinitrd")
         .join("output.img");
 
-    postcard::from_bytes(std::fs::read(image_path).unwrap().as_slice()).unwrap()
+    let image = std::fs::read(image_path).unwrap().leak();
+    DirectoryImage::from_image(image).unwrap()
 }
 
 #[test]
@@ -217,7 +585,7 @@ fn test_find_file() {
     let root = get_root_directory_from_image();
     let usr = root.subdirectories.get("usr").unwrap();
     let applications = usr.subdirectories.get("applications").unwrap();
-    let initrd_fs = applications.files.get("initrd_fs").unwrap();
+    let initrd_fs = applications.files.get("initrd_fs").unwrap().data();
 
     let entry_name = "initrd_fs";
     let path = format!("/usr/applications/{}", entry_name);
@@ -228,7 +596,7 @@ fn test_find_file() {
     // Then the correct directory is returned
     // Then the file is returned
     assert!(!found_entry.is_dir);
-    let found_file_data = found_entry.file_data.unwrap();
+    let found_file_data = found_entry.file_data().unwrap();
     assert_eq!(found_file_data, initrd_fs);
 }
 
@@ -240,7 +608,7 @@ fn test_find_file_with_extension() {
     let usr = root.subdirectories.get("usr").unwrap();
     let include = usr.subdirectories.get("include").unwrap();
     let sys = include.subdirectories.get("sys").unwrap();
-    let dirent_h = sys.files.get("dirent.h").unwrap();
+    let dirent_h = sys.files.get("dirent.h").unwrap().data();
 
     let entry_name = "dirent.h";
 
@@ -250,13 +618,213 @@ fn test_find_file_with_extension() {
     // Then the correct directory is returned
     // Then the file is returned
     assert!(!found_entry.is_dir);
-    let found_file_data = found_entry.file_data.unwrap();
+    let found_file_data = found_entry.file_data().unwrap();
     assert_eq!(found_file_data, dirent_h);
 }
 
+#[test]
+fn test_write_and_read_file() {
+    // Given an empty directory tree
+    let mut root = DirectoryImage::new("/");
+    root.create_directory("/config").unwrap();
+
+    // When I write a file
+    root.write_file("/config/preferences.txt", b"theme=dark")
+        .unwrap();
+
+    // Then it can be found with its contents
+    let found_entry = fs_entry_find(&root, "/config/preferences.txt").unwrap();
+    assert!(!found_entry.is_dir);
+    assert_eq!(found_entry.file_data().unwrap(), b"theme=dark");
+
+    // And writing it again replaces its contents
+    root.write_file("/config/preferences.txt", b"theme=light")
+        .unwrap();
+    let found_entry = fs_entry_find(&root, "/config/preferences.txt").unwrap();
+    assert_eq!(found_entry.file_data().unwrap(), b"theme=light");
+}
+
+#[test]
+fn test_write_file_part() {
+    // Given an empty file
+    let mut root = DirectoryImage::new("/");
+    root.create_file("/save.sav").unwrap();
+    assert_eq!(root.create_file("/save.sav"), Err(FsError::AlreadyExists));
+
+    // When I write past the end of the file
+    root.write_file_part("/save.sav", 4, b"abc").unwrap();
+    // Then the gap is zero-filled
+    assert_eq!(
+        root.files.get("save.sav").unwrap().data(),
+        &[0, 0, 0, 0, b'a', b'b', b'c']
+    );
+
+    // And writes within the file overwrite its contents in place
+    root.write_file_part("/save.sav", 1, b"xy").unwrap();
+    assert_eq!(
+        root.files.get("save.sav").unwrap().data(),
+        &[0, b'x', b'y', 0, b'a', b'b', b'c']
+    );
+
+    // And partial writes don't create files
+    assert_eq!(
+        root.write_file_part("/missing", 0, b"a"),
+        Err(FsError::NotFound)
+    );
+
+    // And writes can't grow the file past the size limit, or wrap around the address space
+    assert_eq!(
+        root.write_file_part("/save.sav", MAX_FILE_SIZE, b"a"),
+        Err(FsError::NoSpace)
+    );
+    assert_eq!(
+        root.write_file_part("/save.sav", usize::MAX, b"a"),
+        Err(FsError::NoSpace)
+    );
+    assert_eq!(root.files.get("save.sav").unwrap().len(), 7);
+    root.write_file_part("/save.sav", MAX_FILE_SIZE - 1, b"a")
+        .unwrap();
+    assert_eq!(root.files.get("save.sav").unwrap().len(), MAX_FILE_SIZE);
+}
+
+#[test]
+fn test_modifications_report_errors() {
+    let mut root = DirectoryImage::new("/");
+    root.create_directory("/usr").unwrap();
+    root.write_file("/usr/file.txt", b"abc").unwrap();
+
+    assert_eq!(root.create_directory("/usr"), Err(FsError::AlreadyExists));
+    assert_eq!(root.write_file("/usr", b""), Err(FsError::IsDirectory));
+    assert_eq!(
+        root.write_file("/usr/file.txt/inner", b""),
+        Err(FsError::NotADirectory)
+    );
+    assert_eq!(
+        root.write_file("/missing/file.txt", b""),
+        Err(FsError::NotFound)
+    );
+    assert_eq!(root.write_file("/", b""), Err(FsError::InvalidPath));
+    assert_eq!(root.delete("/usr"), Err(FsError::DirectoryNotEmpty));
+}
+
+#[test]
+fn test_delete() {
+    // Given a directory containing a file
+    let mut root = DirectoryImage::new("/");
+    root.create_directory("/tmp").unwrap();
+    root.write_file("/tmp/scratch", b"abc").unwrap();
+
+    // When I delete the file, then the directory
+    root.delete("/tmp/scratch").unwrap();
+    root.delete("/tmp").unwrap();
+
+    // Then neither can be found
+    assert!(fs_entry_find(&root, "/tmp").is_none());
+    assert_eq!(root.delete("/tmp"), Err(FsError::NotFound));
+}
+
+#[test]
+fn test_rename_and_move() {
+    // Given a nested directory containing a file
+    let mut root = DirectoryImage::new("/");
+    root.create_directory("/a").unwrap();
+    root.create_directory("/a/inner").unwrap();
+    root.create_directory("/b").unwrap();
+    root.write_file("/a/inner/file.txt", b"contents").unwrap();
+
+    // When I rename the file
+    root.rename("/a/inner/file.txt", "/a/inner/renamed.txt")
+        .unwrap();
+    // And move its directory elsewhere
+    root.rename("/a/inner", "/b/moved").unwrap();
+
+    // Then the file is found at its new path
+    let found_entry = fs_entry_find(&root, "/b/moved/renamed.txt").unwrap();
+    assert_eq!(found_entry.file_data().unwrap(), b"contents");
+    assert_eq!(
+        fs_entry_find(&root, "/b/moved")
+            .unwrap()
+            .dir_image
+            .unwrap()
+            .name,
+        "moved"
+    );
+    assert!(fs_entry_find(&root, "/a/inner").is_none());
+
+    // And a directory can't be moved inside itself, or over an existing entry
+    assert_eq!(root.rename("/b", "/b/c"), Err(FsError::InvalidPath));
+    assert_eq!(root.rename("/a", "/b"), Err(FsError::AlreadyExists));
+}
+
+#[test]
+fn test_file_metadata() {
+    // Given a file whose metadata was recorded when the image was built
+    let mut root = DirectoryImage::new("/");
+    root.write_file("/notes.txt", b"abc").unwrap();
+    root.file_metadata.insert(
+        "notes.txt".to_owned(),
+        FileMetadata::new(3, 1234, false, FileType::Text),
+    );
+
+    // When the file grows
+    root.write_file_part("/notes.txt", 3, b"def").unwrap();
+
+    // Then its reported size follows its contents, and the rest of the metadata is kept
+    let found_entry = fs_entry_find(&root, "/notes.txt").unwrap();
+    assert_eq!(
+        found_entry.metadata,
+        FileMetadata::new(6, 1234, false, FileType::Text)
+    );
+
+    // And the metadata moves with the file
+    root.rename("/notes.txt", "/renamed.txt").unwrap();
+    let found_entry = fs_entry_find(&root, "/renamed.txt").unwrap();
+    assert_eq!(found_entry.metadata.modified, 1234);
+
+    // And new files have their type inferred from their contents and name
+    root.write_file("/program", b"\x7fELF\x02\x01").unwrap();
+    let program = fs_entry_find(&root, "/program").unwrap().metadata;
+    assert!(program.is_executable);
+    assert_eq!(program.file_type, FileType::Executable);
+    root.create_file("/image.bmp").unwrap();
+    let image = fs_entry_find(&root, "/image.bmp").unwrap().metadata;
+    assert_eq!(image.file_type, FileType::Image);
+    assert!(fs_entry_find(&root, "/").unwrap().metadata.is_directory());
+}
+
 #[test]
 fn test_traverse_past_file() {
-    // Should not match abc.txt
-    "/usr/include/abc.txt/more";
-    //todo!();
+    // Given a file nested in a directory
+    let mut root = DirectoryImage::new("/");
+    root.create_directory("/usr").unwrap();
+    root.create_directory("/usr/include").unwrap();
+    root.write_file("/usr/include/abc.txt", b"abc").unwrap();
+
+    // When I request a path that continues past the file
+    // Then the file isn't matched
+    assert!(fs_entry_find(&root, "/usr/include/abc.txt/more").is_none());
+    assert!(fs_entry_find(&root, "/usr/include/abc.txt/").is_some());
+}
+
+#[test]
+fn test_find_with_relative_components() {
+    // Given a couple of nested directories
+    let mut root = DirectoryImage::new("/");
+    root.create_directory("/usr").unwrap();
+    root.create_directory("/usr/include").unwrap();
+    root.write_file("/usr/abc.txt", b"abc").unwrap();
+
+    // When I request paths containing `.` and `..`
+    // Then they're resolved before the lookup
+    let found = fs_entry_find(&root, "/usr/include/../abc.txt").unwrap();
+    assert_eq!(found.file_data().unwrap(), b"abc");
+    let found = fs_entry_find(&root, "/./usr/./include/.").unwrap();
+    assert_eq!(found.dir_image.unwrap().name, "include");
+    let found = fs_entry_find(&root, "/../..").unwrap();
+    assert_eq!(found.dir_image.unwrap() as *const _, &root as *const _);
+    assert!(fs_entry_find(&root, "/usr/include/../../include").is_none());
+
+    // And modifications resolve them the same way
+    root.write_file("/usr/include/../def.txt", b"def").unwrap();
+    assert!(fs_entry_find(&root, "/usr/def.txt").is_some());
 }
@@ -0,0 +1,236 @@
+//! The LZ4 block format, as described in
+//! https://github.com/lz4/lz4/blob/dev/doc/lz4_Block_format.md
+//! Only single blocks are supported, without the framing format, as the image records each
+//! blob's decoded size itself.
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+const MIN_MATCH: usize = 4;
+// The last match must start at least this many bytes before the end of the block
+const MF_LIMIT: usize = 12;
+// The last bytes of a block are always literals
+const LAST_LITERALS: usize = 5;
+const MAX_OFFSET: usize = u16::MAX as usize;
+
+const HASH_LOG: usize = 12;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Lz4Error {
+    /// The block ended partway through a sequence
+    Truncated,
+    /// A match referred to data before the start of the block
+    InvalidOffset,
+    /// The block didn't decode to the expected size
+    SizeMismatch,
+}
+
+fn read_u32(input: &[u8], idx: usize) -> u32 {
+    u32::from_le_bytes(input[idx..idx + 4].try_into().unwrap())
+}
+
+fn hash(sequence: u32) -> usize {
+    (sequence.wrapping_mul(2654435761) >> (32 - HASH_LOG)) as usize
+}
+
+fn push_length_extension(out: &mut Vec<u8>, mut len: usize) {
+    while len >= 255 {
+        out.push(255);
+        len -= 255;
+    }
+    out.push(len as u8);
+}
+
+fn push_sequence(out: &mut Vec<u8>, literals: &[u8], match_info: Option<(usize, usize)>) {
+    let literal_nibble = core::cmp::min(literals.len(), 15);
+    let match_nibble = match match_info {
+        Some((_, match_len)) => core::cmp::min(match_len - MIN_MATCH, 15),
+        None => 0,
+    };
+    out.push(((literal_nibble as u8) << 4) | match_nibble as u8);
+    if literals.len() >= 15 {
+        push_length_extension(out, literals.len() - 15);
+    }
+    out.extend_from_slice(literals);
+
+    if let Some((offset, match_len)) = match_info {
+        out.extend_from_slice(&(offset as u16).to_le_bytes());
+        if match_len - MIN_MATCH >= 15 {
+            push_length_extension(out, match_len - MIN_MATCH - 15);
+        }
+    }
+}
+
+pub fn compress(input: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(input.len() / 2);
+    // Positions are stored off by one so that 0 can mean "no entry"
+    let mut table = vec![0_usize; 1 << HASH_LOG];
+    let mut anchor = 0;
+    let mut i = 0;
+
+    if input.len() > MF_LIMIT {
+        while i < input.len() - MF_LIMIT {
+            let sequence = read_u32(input, i);
+            let slot = &mut table[hash(sequence)];
+            let candidate = *slot;
+            *slot = i + 1;
+
+            if candidate == 0 || i - (candidate - 1) > MAX_OFFSET {
+                i += 1;
+                continue;
+            }
+            let candidate = candidate - 1;
+            if read_u32(input, candidate) != sequence {
+                i += 1;
+                continue;
+            }
+
+            let mut match_len = MIN_MATCH;
+            while i + match_len < input.len() - LAST_LITERALS
+                && input[candidate + match_len] == input[i + match_len]
+            {
+                match_len += 1;
+            }
+            push_sequence(
+                &mut out,
+                &input[anchor..i],
+                Some((i - candidate, match_len)),
+            );
+            i += match_len;
+            anchor = i;
+        }
+    }
+
+    push_sequence(&mut out, &input[anchor..], None);
+    out
+}
+
+fn read_length_extension(input: &[u8], idx: &mut usize) -> Result<usize, Lz4Error> {
+    let mut len = 0;
+    loop {
+        let byte = *input.get(*idx).ok_or(Lz4Error::Truncated)?;
+        *idx += 1;
+        len += byte as usize;
+        if byte != 255 {
+            return Ok(len);
+        }
+    }
+}
+
+pub fn decompress(input: &[u8], decoded_len: usize) -> Result<Vec<u8>, Lz4Error> {
+    let mut out = Vec::with_capacity(decoded_len);
+    let mut i = 0;
+    loop {
+        let token = *input.get(i).ok_or(Lz4Error::Truncated)?;
+        i += 1;
+
+        let mut literal_len = (token >> 4) as usize;
+        if literal_len == 15 {
+            literal_len += read_length_extension(input, &mut i)?;
+        }
+        let literals = input.get(i..i + literal_len).ok_or(Lz4Error::Truncated)?;
+        if out.len() + literals.len() > decoded_len {
+            return Err(Lz4Error::SizeMismatch);
+        }
+        out.extend_from_slice(literals);
+        i += literal_len;
+
+        // The final sequence has no match
+        if i == input.len() {
+            break;
+        }
+
+        let offset_bytes = input.get(i..i + 2).ok_or(Lz4Error::Truncated)?;
+        let offset = u16::from_le_bytes([offset_bytes[0], offset_bytes[1]]) as usize;
+        i += 2;
+        if offset == 0 || offset > out.len() {
+            return Err(Lz4Error::InvalidOffset);
+        }
+
+        let mut match_len = (token & 0xf) as usize;
+        if match_len == 15 {
+            match_len += read_length_extension(input, &mut i)?;
+        }
+        match_len += MIN_MATCH;
+        if out.len() + match_len > decoded_len {
+            return Err(Lz4Error::SizeMismatch);
+        }
+        // Matches may overlap the bytes they produce, so copy one byte at a time
+        let match_start = out.len() - offset;
+        for j in 0..match_len {
+            out.push(out[match_start + j]);
+        }
+    }
+
+    if out.len() != decoded_len {
+        return Err(Lz4Error::SizeMismatch);
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    fn roundtrip(data: &[u8]) -> Vec<u8> {
+        let compressed = compress(data);
+        assert_eq!(decompress(&compressed, data.len()).unwrap(), data);
+        compressed
+    }
+
+    #[test]
+    fn test_roundtrip_small_inputs() {
+        roundtrip(b"");
+        roundtrip(b"a");
+        roundtrip(b"abcdefghijklm");
+    }
+
+    #[test]
+    fn test_repetitive_data_shrinks() {
+        // Given data with lots of repetition, including runs longer than the length nibbles
+        let mut data = Vec::new();
+        for i in 0..2000 {
+            data.extend_from_slice(b"axle axle axle ");
+            data.push((i % 7) as u8);
+        }
+        data.extend_from_slice(&[0; 1000]);
+
+        // Then it compresses well, and decompresses to the original
+        let compressed = roundtrip(&data);
+        assert!(compressed.len() < data.len() / 4);
+    }
+
+    #[test]
+    fn test_incompressible_data() {
+        // Given data with no repetition
+        let mut state = 0x12345678_u32;
+        let data: Vec<u8> = (0..5000)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect();
+        // Then it still survives the roundtrip
+        roundtrip(&data);
+    }
+
+    #[test]
+    fn test_corrupt_input() {
+        let compressed = compress(b"hello hello hello hello hello");
+        // A block that stops partway through is rejected
+        assert_eq!(
+            decompress(&compressed[..compressed.len() - 3], 29),
+            Err(Lz4Error::Truncated)
+        );
+        // As is a block that doesn't decode to the expected size
+        assert_eq!(decompress(&compressed, 10), Err(Lz4Error::SizeMismatch));
+        // And a match referring to before the start of the block
+        assert_eq!(
+            decompress(&[0x10, b'a', 0x05, 0x00, 0x00], 10),
+            Err(Lz4Error::InvalidOffset)
+        );
+    }
+}
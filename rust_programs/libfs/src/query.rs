@@ -0,0 +1,339 @@
+//! Queries that match more than a single exact path: glob patterns and recursive walks
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use file_manager_messages::FileMetadata;
+
+use crate::{path_components, DirectoryImage, FsEntry};
+
+/// Resolves `.` and `..` components and collapses repeated slashes.
+/// This is purely lexical, and `..` at the root stays at the root.
+pub fn normalize_path(path: &str) -> String {
+    format!("/{}", path_components(path).join("/"))
+}
+
+pub fn is_glob_pattern(path: &str) -> bool {
+    path.contains(['*', '?'])
+}
+
+/// Matches a single path component against a pattern, where `*` matches any run of characters
+/// and `?` matches any single character
+pub fn glob_matches(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // Where to resume if the most recent `*` needs to consume another character
+    let mut backtrack = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, n));
+                p += 1;
+            }
+            Some(&ch) if ch == '?' || ch == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((star_p, star_n)) => {
+                    p = star_p + 1;
+                    n = star_n + 1;
+                    backtrack = Some((star_p, star_n + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&ch| ch == '*')
+}
+
+fn join_path(dir_path: &str, name: &str) -> String {
+    match dir_path.ends_with('/') {
+        true => format!("{dir_path}{name}"),
+        false => format!("{dir_path}/{name}"),
+    }
+}
+
+fn dir_entry<'a>(path: String, dir: &'a DirectoryImage<'a>) -> FsEntry<'a> {
+    FsEntry {
+        path,
+        is_dir: true,
+        dir_image: Some(dir),
+        file: None,
+        metadata: FileMetadata::directory(),
+    }
+}
+
+/// The entries directly within a directory, in name order
+fn children<'a>(dir: &'a DirectoryImage<'a>, dir_path: &str) -> Vec<FsEntry<'a>> {
+    let mut entries: Vec<FsEntry<'a>> = dir
+        .subdirectories
+        .iter()
+        .map(|(name, subdir)| dir_entry(join_path(dir_path, name), subdir))
+        .chain(dir.files.iter().map(|(name, file)| FsEntry {
+            path: join_path(dir_path, name),
+            is_dir: false,
+            dir_image: None,
+            file: Some(file),
+            metadata: dir.metadata_for_file(name, file),
+        }))
+        .collect();
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+    entries
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum WalkAction {
+    Continue,
+    /// Don't descend into the directory that was just visited
+    SkipDirectory,
+    /// End the walk without visiting anything else
+    Stop,
+}
+
+/// Visits everything beneath `dir` depth-first and in name order.
+/// Each directory is visited before its contents, and `dir` itself isn't visited.
+pub fn fs_walk<'a, F>(dir: &FsEntry<'a>, visitor: &mut F)
+where
+    F: FnMut(&FsEntry<'a>) -> WalkAction,
+{
+    if let Some(dir_image) = dir.dir_image {
+        walk_dir(dir_image, &dir.path, visitor);
+    }
+}
+
+// Returns false once the visitor has asked to stop
+fn walk_dir<'a, F>(dir: &'a DirectoryImage<'a>, dir_path: &str, visitor: &mut F) -> bool
+where
+    F: FnMut(&FsEntry<'a>) -> WalkAction,
+{
+    for entry in children(dir, dir_path) {
+        match visitor(&entry) {
+            WalkAction::Stop => return false,
+            WalkAction::SkipDirectory => continue,
+            WalkAction::Continue => {
+                if let Some(subdir) = entry.dir_image {
+                    if !walk_dir(subdir, &entry.path, visitor) {
+                        return false;
+                    }
+                }
+            }
+        }
+    }
+    true
+}
+
+/// Finds every entry matching `pattern`, an absolute path whose components may use the
+/// wildcards accepted by `glob_matches`. A `**` component matches any number of nested
+/// directories, including none. The pattern is normalized first, and results are in path order.
+pub fn fs_find_glob<'a>(root_dir: &'a DirectoryImage<'a>, pattern: &str) -> Vec<FsEntry<'a>> {
+    let components = path_components(pattern);
+    let mut matches = Vec::new();
+    find_glob_in_dir(root_dir, "/", &components, &mut matches);
+    matches.sort_by(|a, b| a.path.cmp(&b.path));
+    // Consecutive `**` components can reach the same entry more than once
+    matches.dedup_by(|a, b| a.path == b.path);
+    matches
+}
+
+fn find_glob_in_dir<'a>(
+    dir: &'a DirectoryImage<'a>,
+    dir_path: &str,
+    pattern: &[&str],
+    matches: &mut Vec<FsEntry<'a>>,
+) {
+    let (component, rest) = match pattern.split_first() {
+        Some(split) => split,
+        None => {
+            matches.push(dir_entry(dir_path.into(), dir));
+            return;
+        }
+    };
+
+    if *component == "**" {
+        // Match zero directories, then one or more
+        find_glob_in_dir(dir, dir_path, rest, matches);
+        for (name, subdir) in dir.subdirectories.iter() {
+            find_glob_in_dir(subdir, &join_path(dir_path, name), pattern, matches);
+        }
+        return;
+    }
+
+    for entry in children(dir, dir_path) {
+        let name = entry.path.rsplit('/').next().unwrap();
+        if !glob_matches(component, name) {
+            continue;
+        }
+        match (entry.dir_image, rest.is_empty()) {
+            (_, true) => matches.push(entry),
+            (Some(subdir), false) => find_glob_in_dir(subdir, &entry.path, rest, matches),
+            // Files can only match the last component
+            (None, false) => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs_entry_find;
+    use alloc::string::ToString;
+    use alloc::vec;
+
+    fn sample_tree() -> DirectoryImage<'static> {
+        let mut root = DirectoryImage::new("/");
+        root.create_directory("/usr").unwrap();
+        root.create_directory("/usr/applications").unwrap();
+        root.create_directory("/usr/applications/games").unwrap();
+        root.create_directory("/config").unwrap();
+        root.write_file("/usr/applications/ide", b"\x7fELF")
+            .unwrap();
+        root.write_file("/usr/applications/file_browser", b"\x7fELF")
+            .unwrap();
+        root.write_file("/usr/applications/games/gb_emu", b"\x7fELF")
+            .unwrap();
+        root.write_file("/usr/notes.txt", b"notes").unwrap();
+        root.write_file("/config/run_on_startup.txt", b"/usr/applications/ide")
+            .unwrap();
+        root
+    }
+
+    fn paths(entries: &[FsEntry]) -> Vec<String> {
+        entries.iter().map(|e| e.path.clone()).collect()
+    }
+
+    #[test]
+    fn test_normalize_path() {
+        assert_eq!(normalize_path("/"), "/");
+        assert_eq!(normalize_path(""), "/");
+        assert_eq!(normalize_path("//usr///applications/"), "/usr/applications");
+        assert_eq!(normalize_path("/usr/./applications/."), "/usr/applications");
+        assert_eq!(normalize_path("/usr/applications/../fonts"), "/usr/fonts");
+        assert_eq!(normalize_path("/../../usr"), "/usr");
+    }
+
+    #[test]
+    fn test_glob_matches() {
+        assert!(glob_matches("*", "anything"));
+        assert!(glob_matches("*", ""));
+        assert!(glob_matches("*.txt", "notes.txt"));
+        assert!(!glob_matches("*.txt", "notes.txt.bak"));
+        assert!(glob_matches("gb_*", "gb_emu"));
+        assert!(glob_matches("?de", "ide"));
+        assert!(!glob_matches("?de", "de"));
+        assert!(glob_matches("*_*_*", "a_b_c"));
+        assert!(glob_matches("a*b*c", "aXbYbZc"));
+        assert!(!glob_matches("a*b*c", "aXbYbZ"));
+        assert!(glob_matches("exact", "exact"));
+        assert!(!glob_matches("exact", "exactly"));
+    }
+
+    #[test]
+    fn test_find_glob_single_directory() {
+        // Given a directory with a mix of files and subdirectories
+        let root = sample_tree();
+
+        // When I search for everything within it
+        let found = fs_find_glob(&root, "/usr/applications/*");
+
+        // Then its direct children are returned, in path order
+        assert_eq!(
+            paths(&found),
+            vec![
+                "/usr/applications/file_browser",
+                "/usr/applications/games",
+                "/usr/applications/ide",
+            ]
+        );
+        assert!(found[1].is_dir);
+        assert!(found[0].metadata.is_executable);
+    }
+
+    #[test]
+    fn test_find_glob_recursive() {
+        let root = sample_tree();
+
+        // `**` matches any depth, including directly within the starting directory
+        assert_eq!(
+            paths(&fs_find_glob(&root, "/**/*.txt")),
+            vec!["/config/run_on_startup.txt", "/usr/notes.txt"]
+        );
+        assert_eq!(
+            paths(&fs_find_glob(&root, "/usr/**/gb_*")),
+            vec!["/usr/applications/games/gb_emu"]
+        );
+        // Repeated `**` components don't produce duplicate results
+        assert_eq!(
+            paths(&fs_find_glob(&root, "/**/**/ide")),
+            vec!["/usr/applications/ide"]
+        );
+    }
+
+    #[test]
+    fn test_find_glob_edge_cases() {
+        let root = sample_tree();
+
+        // A pattern without wildcards finds at most the exact path
+        assert_eq!(
+            paths(&fs_find_glob(&root, "/usr/notes.txt")),
+            vec!["/usr/notes.txt"]
+        );
+        // Patterns are normalized before they're matched
+        assert_eq!(
+            paths(&fs_find_glob(&root, "/config/../usr/./*.txt")),
+            vec!["/usr/notes.txt"]
+        );
+        // Files never match a component that has more after it
+        assert!(fs_find_glob(&root, "/usr/*/*/gb_emu/*").is_empty());
+        assert!(fs_find_glob(&root, "/nothing/*").is_empty());
+        assert_eq!(paths(&fs_find_glob(&root, "/")), vec!["/"]);
+    }
+
+    #[test]
+    fn test_walk() {
+        let root = sample_tree();
+        let usr = fs_entry_find(&root, "/usr").unwrap();
+
+        // Walking visits each directory before its contents
+        let mut visited = Vec::new();
+        fs_walk(&usr, &mut |entry| {
+            visited.push(entry.path.clone());
+            WalkAction::Continue
+        });
+        assert_eq!(
+            visited,
+            vec![
+                "/usr/applications",
+                "/usr/applications/file_browser",
+                "/usr/applications/games",
+                "/usr/applications/games/gb_emu",
+                "/usr/applications/ide",
+                "/usr/notes.txt",
+            ]
+        );
+
+        // Directories can be skipped
+        let mut visited = Vec::new();
+        fs_walk(&usr, &mut |entry| {
+            visited.push(entry.path.clone());
+            match entry.path.ends_with("games") {
+                true => WalkAction::SkipDirectory,
+                false => WalkAction::Continue,
+            }
+        });
+        assert!(!visited.contains(&"/usr/applications/games/gb_emu".to_string()));
+        assert!(visited.contains(&"/usr/notes.txt".to_string()));
+
+        // And the walk can end early
+        let mut visited = 0;
+        fs_walk(&usr, &mut |_| {
+            visited += 1;
+            match visited {
+                2 => WalkAction::Stop,
+                _ => WalkAction::Continue,
+            }
+        });
+        assert_eq!(visited, 2);
+    }
+}
@@ -1,18 +1,15 @@
 use crate::text_view::DrawnCharacter;
 use agx_definitions::{Color, LikeLayerSlice, Point, Polygon, PolygonStack, Rect, Size};
+use alloc::borrow::Cow;
 use alloc::boxed::Box;
 use alloc::format;
-use alloc::vec::Vec;
-use axle_rt::AmcMessage;
-use core::ptr;
-use file_manager_messages::{ReadFile, ReadFileResponse, FILE_SERVER_SERVICE_NAME};
 use ttf_renderer::{
     render_char_onto, Codepoint, Font, GlyphMetrics, GlyphRenderDescription,
     GlyphRenderInstructions,
 };
 
 #[cfg(target_os = "axle")]
-use axle_rt::{amc_message_await__u32_event, amc_message_send};
+use file_manager_messages::map_file;
 #[cfg(not(any(target_os = "axle", feature = "run_in_uefi")))]
 use std::fs;
 
@@ -194,22 +191,13 @@ pub fn draw_char_with_font_onto(
 }
 
 pub fn load_font(path: &str) -> Font {
-    let font_bytes: Vec<u8> = {
+    let font_bytes: Cow<'static, [u8]> = {
         #[cfg(target_os = "axle")]
         {
-            let file_read_request = ReadFile::new(path);
-            amc_message_send(FILE_SERVER_SERVICE_NAME, file_read_request);
-            let file_data_msg: AmcMessage<ReadFileResponse> =
-                amc_message_await__u32_event(FILE_SERVER_SERVICE_NAME);
-            let file_data_body = file_data_msg.body();
-            unsafe {
-                let data_slice = ptr::slice_from_raw_parts(
-                    (&file_data_body.data) as *const u8,
-                    file_data_body.len,
-                );
-                let data: &[u8] = &*(data_slice as *const [u8]);
-                data.to_vec()
-            }
+            // Fonts can be several megabytes, so map them rather than copying them through a message
+            Cow::Borrowed(
+                map_file(path).unwrap_or_else(|e| panic!("Failed to read font {path}: {e:?}")),
+            )
         }
         #[cfg(feature = "run_in_uefi")]
         {
@@ -217,7 +205,7 @@ pub fn load_font(path: &str) -> Font {
         }
         #[cfg(not(any(target_os = "axle", feature = "run_in_uefi")))]
         {
-            fs::read(path).unwrap()
+            Cow::Owned(fs::read(path).unwrap())
         }
     };
     ttf_renderer::parse(&font_bytes)
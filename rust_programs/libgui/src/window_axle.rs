@@ -4,7 +4,10 @@ use alloc::{rc::Weak, vec::Vec};
 
 use core::cell::RefCell;
 
-use axle_rt::core_commands::{AmcSleepUntilDelayOrMessage, AMC_CORE_SERVICE_NAME};
+use axle_rt::core_commands::{
+    amc_configure_inbox, AmcInboxOverflowPolicy, AmcSleepUntilDelayOrMessage,
+    AMC_CORE_SERVICE_NAME, AMC_INBOX_DEFAULT_LIMIT,
+};
 use axle_rt::libc::ms_since_boot;
 use axle_rt::AmcMessage;
 use axle_rt::{amc_message_await__u32_event, printf, println};
@@ -83,6 +86,13 @@ impl AwmWindow {
     pub const AWM_SERVICE_NAME: &'static str = "com.axle.awm";
 
     pub fn new(title: &str, size: Size) -> Self {
+        // If we fall behind, only the latest mouse position matters
+        amc_configure_inbox(
+            AMC_INBOX_DEFAULT_LIMIT,
+            AmcInboxOverflowPolicy::Coalesce,
+            &[MouseMoved::EXPECTED_EVENT, MouseDragged::EXPECTED_EVENT],
+        );
+
         // Start off by getting a window from awm
         amc_message_send(AwmWindow::AWM_SERVICE_NAME, AwmCreateWindow::new(size));
         // awm should send back info about the window that was created
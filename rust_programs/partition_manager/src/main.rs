@@ -0,0 +1,327 @@
+#![no_std]
+#![feature(start)]
+#![feature(default_alloc_error_handler)]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use core::mem::size_of;
+
+use axle_rt::{
+    amc_message_await_untyped, amc_message_send, amc_register_service, printf, AmcMessage,
+    ExpectsEventField,
+};
+use block_cache_messages::cached_disk;
+use gpt_helper::{Partition, PartitionTable, SectorReader};
+use partition_manager_messages::{
+    device_for_partition_entry, ListPartitions, PartitionList, PARTITION_MANAGER_SERVICE_NAME,
+};
+use sata_driver_messages::{
+    BlockDeviceStatus, DeviceInfo, Flush, FlushResponse, GetDeviceInfo, ReadSectors,
+    ReadSectorsResponse, RemoteBlockDevice, WriteSectors, WriteSectorsResponse,
+    MAX_SECTORS_PER_REQUEST, SATA_SECTOR_SIZE, WHOLE_DISK_DEVICE,
+};
+
+/// Lets gpt_helper read the partition table through the block cache
+struct DiskReader<'a> {
+    disk: &'a RemoteBlockDevice,
+    sector_count: u64,
+}
+
+impl SectorReader for DiskReader<'_> {
+    type Error = BlockDeviceStatus;
+
+    fn sector_size(&self) -> usize {
+        SATA_SECTOR_SIZE
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.sector_count
+    }
+
+    fn read_sectors(&mut self, lba: u64, buf: &mut [u8]) -> Result<(), BlockDeviceStatus> {
+        let data = self
+            .disk
+            .read_sectors(lba, (buf.len() / SATA_SECTOR_SIZE) as u64)?;
+        buf.copy_from_slice(&data);
+        Ok(())
+    }
+}
+
+struct PartitionManager {
+    disk: RemoteBlockDevice,
+    disk_info: DeviceInfo,
+    partitions: Vec<Partition>,
+}
+
+impl PartitionManager {
+    fn new(disk: RemoteBlockDevice, disk_info: DeviceInfo) -> Self {
+        let mut reader = DiskReader {
+            disk: &disk,
+            sector_count: disk_info.sector_count,
+        };
+        let partitions = match PartitionTable::read(&mut reader) {
+            Ok(table) => {
+                printf!(
+                    "Read partition table from the {:?} header\n",
+                    table.header_location
+                );
+                table.partitions()
+            }
+            Err(e) => {
+                printf!("No usable partition table, serving the whole disk only: {e:?}\n");
+                Vec::new()
+            }
+        };
+
+        // Don't expose partitions that claim sectors past the end of the disk
+        let partitions: Vec<Partition> = partitions
+            .into_iter()
+            .filter(|p| {
+                let fits = p.first_lba <= p.last_lba && p.last_lba < disk_info.sector_count;
+                if !fits {
+                    printf!("Ignoring partition #{} with an invalid range\n", p.index);
+                }
+                fits
+            })
+            .collect();
+        for p in partitions.iter() {
+            printf!(
+                "Partition #{} \"{}\": type {}, sectors [{} - {}]\n",
+                p.index,
+                p.name,
+                p.type_guid,
+                p.first_lba,
+                p.last_lba
+            );
+        }
+
+        Self {
+            disk,
+            disk_info,
+            partitions,
+        }
+    }
+
+    /// The first sector and the sector count of a device
+    fn device_extent(&self, device: u32) -> Option<(u64, u64)> {
+        if device == WHOLE_DISK_DEVICE {
+            return Some((0, self.disk_info.sector_count));
+        }
+        self.partitions
+            .iter()
+            .find(|p| device_for_partition_entry(p.index) == device)
+            .map(|p| (p.first_lba, p.sector_count()))
+    }
+
+    /// Translates a device-relative range into a range of disk sectors
+    fn translate(
+        &self,
+        device: u32,
+        start_sector: u64,
+        sector_count: u64,
+    ) -> Result<u64, BlockDeviceStatus> {
+        let (first_lba, device_sector_count) = self
+            .device_extent(device)
+            .ok_or(BlockDeviceStatus::NoSuchDevice)?;
+        if sector_count == 0 || sector_count > MAX_SECTORS_PER_REQUEST {
+            return Err(BlockDeviceStatus::InvalidRequest);
+        }
+        match start_sector.checked_add(sector_count) {
+            Some(end_sector) if end_sector <= device_sector_count => Ok(first_lba + start_sector),
+            _ => Err(BlockDeviceStatus::OutOfRange),
+        }
+    }
+
+    fn get_device_info(&self, sender: &str, request: &GetDeviceInfo) {
+        let response = match self.device_extent(request.device) {
+            Some((_, sector_count)) => DeviceInfo::new(
+                request.request_id,
+                BlockDeviceStatus::Success,
+                sector_count,
+                self.disk_info.model(),
+                self.disk_info.serial_number(),
+            ),
+            None => DeviceInfo::new(
+                request.request_id,
+                BlockDeviceStatus::NoSuchDevice,
+                0,
+                "",
+                "",
+            ),
+        };
+        amc_message_send(sender, response);
+    }
+
+    fn read_sectors(&self, sender: &str, request: &ReadSectors) {
+        let result = self
+            .translate(request.device, request.start_sector, request.sector_count)
+            .and_then(|disk_sector| self.disk.read_sectors(disk_sector, request.sector_count));
+        match result {
+            Ok(data) => {
+                ReadSectorsResponse::send(sender, request.request_id, request.start_sector, &data)
+            }
+            Err(status) => ReadSectorsResponse::send_error(
+                sender,
+                request.request_id,
+                request.start_sector,
+                request.sector_count,
+                status,
+            ),
+        }
+    }
+
+    /// `message_len` is the size of the delivered message, including the trailing data
+    fn write_sectors(&self, sender: &str, request: &WriteSectors, message_len: usize) {
+        let expected_len = (request.sector_count as usize).checked_mul(SATA_SECTOR_SIZE);
+        let result = match unsafe { request.data(message_len) } {
+            Some(data) if Some(data.len()) == expected_len => self
+                .translate(request.device, request.start_sector, request.sector_count)
+                .and_then(|disk_sector| self.disk.write_sectors(disk_sector, data)),
+            _ => Err(BlockDeviceStatus::InvalidRequest),
+        };
+        WriteSectorsResponse::send(
+            sender,
+            request.request_id,
+            request.start_sector,
+            request.sector_count,
+            result.err().unwrap_or(BlockDeviceStatus::Success),
+        );
+    }
+
+    fn flush(&self, sender: &str, request: &Flush) {
+        let status = match self.device_extent(request.device) {
+            // The drive's write cache is shared by every partition
+            Some(_) => self
+                .disk
+                .flush()
+                .err()
+                .unwrap_or(BlockDeviceStatus::Success),
+            None => BlockDeviceStatus::NoSuchDevice,
+        };
+        amc_message_send(sender, FlushResponse::new(request.request_id, status));
+    }
+
+    fn list_partitions(&self, sender: &str, request: &ListPartitions) {
+        amc_message_send(
+            sender,
+            PartitionList::new(
+                request.request_id,
+                BlockDeviceStatus::Success,
+                &self.partitions,
+            ),
+        );
+    }
+}
+
+unsafe fn body_as_type_unchecked<T>(body: &[u8]) -> &T {
+    &*(body.as_ptr() as *const T)
+}
+
+/// Rejects a request whose body is too short to hold it. The request ID is echoed if the body
+/// is long enough to hold one.
+fn reply_truncated_request(sender: &str, event: u32, raw_body: &[u8]) {
+    let request_id = raw_body
+        .get(size_of::<u32>()..size_of::<u32>() * 2)
+        .map_or(0, |request_id| {
+            u32::from_ne_bytes(request_id.try_into().unwrap())
+        });
+    let status = BlockDeviceStatus::InvalidRequest;
+    match event {
+        GetDeviceInfo::EXPECTED_EVENT => {
+            amc_message_send(sender, DeviceInfo::new(request_id, status, 0, "", ""))
+        }
+        ReadSectors::EXPECTED_EVENT => {
+            ReadSectorsResponse::send_error(sender, request_id, 0, 0, status)
+        }
+        WriteSectors::EXPECTED_EVENT => {
+            WriteSectorsResponse::send(sender, request_id, 0, 0, status)
+        }
+        Flush::EXPECTED_EVENT => amc_message_send(sender, FlushResponse::new(request_id, status)),
+        ListPartitions::EXPECTED_EVENT => {
+            amc_message_send(sender, PartitionList::new(request_id, status, &[]))
+        }
+        _ => unreachable!(),
+    }
+}
+
+/// Parses the first bytes of the message as a u32 event field, and checks that the body is long
+/// enough to be cast to the request that the event names. Requests that are too short are
+/// rejected here, and None is returned.
+fn request_event(sender: &str, raw_body: &[u8]) -> Option<u32> {
+    let event = match raw_body.get(..size_of::<u32>()) {
+        Some(event) => u32::from_ne_bytes(event.try_into().unwrap()),
+        None => {
+            printf!("Dropping message from {sender} that's too short to hold an event\n");
+            return None;
+        }
+    };
+    let request_len = match event {
+        GetDeviceInfo::EXPECTED_EVENT => size_of::<GetDeviceInfo>(),
+        ReadSectors::EXPECTED_EVENT => size_of::<ReadSectors>(),
+        WriteSectors::EXPECTED_EVENT => size_of::<WriteSectors>(),
+        Flush::EXPECTED_EVENT => size_of::<Flush>(),
+        ListPartitions::EXPECTED_EVENT => size_of::<ListPartitions>(),
+        // Left for the caller to report
+        _ => return Some(event),
+    };
+    if raw_body.len() < request_len {
+        printf!("Rejecting truncated request {event} from {sender}\n");
+        reply_truncated_request(sender, event, raw_body);
+        return None;
+    }
+    Some(event)
+}
+
+#[start]
+#[allow(unreachable_code)]
+fn start(_argc: isize, _argv: *const *const u8) -> isize {
+    amc_register_service(PARTITION_MANAGER_SERVICE_NAME);
+
+    // Partitions share the cache with anything else that accesses the whole disk, so both see
+    // the same data
+    let disk = cached_disk();
+    let disk_info = match disk.device_info() {
+        Ok(info) => info,
+        Err(e) => {
+            printf!("Failed to query the disk: {e:?}\n");
+            return 1;
+        }
+    };
+    let manager = PartitionManager::new(disk, disk_info);
+
+    loop {
+        let msg_unparsed: AmcMessage<[u8]> = unsafe { amc_message_await_untyped(None).unwrap() };
+
+        let raw_body = msg_unparsed.body();
+        let event = match request_event(msg_unparsed.source(), raw_body) {
+            Some(event) => event,
+            None => continue,
+        };
+
+        // Each inner call to body_as_type_unchecked is unsafe because we must be
+        // sure we're casting to the right type.
+        // Since we verify the type on the LHS, and request_event checked that the body is long
+        // enough to hold it, each usage is safe.
+        unsafe {
+            let sender = msg_unparsed.source();
+            match event {
+                GetDeviceInfo::EXPECTED_EVENT => {
+                    manager.get_device_info(sender, body_as_type_unchecked(raw_body))
+                }
+                ReadSectors::EXPECTED_EVENT => {
+                    manager.read_sectors(sender, body_as_type_unchecked(raw_body))
+                }
+                WriteSectors::EXPECTED_EVENT => {
+                    manager.write_sectors(sender, body_as_type_unchecked(raw_body), raw_body.len())
+                }
+                Flush::EXPECTED_EVENT => manager.flush(sender, body_as_type_unchecked(raw_body)),
+                ListPartitions::EXPECTED_EVENT => {
+                    manager.list_partitions(sender, body_as_type_unchecked(raw_body))
+                }
+                _ => printf!("Unknown event from {sender}: {event}\n"),
+            }
+        }
+    }
+    0
+}
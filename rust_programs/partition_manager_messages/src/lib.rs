@@ -0,0 +1,162 @@
+#![no_std]
+
+//! The partition manager serves the block device protocol from `sata_driver_messages` for each
+//! GPT partition on the disk. The whole disk is `WHOLE_DISK_DEVICE`, and the partition in entry
+//! N of the partition entry array is device N + 1, so device numbers stay stable as other
+//! partitions are added or removed.
+
+extern crate alloc;
+
+#[cfg(target_os = "axle")]
+mod conditional_imports {
+    pub use alloc::vec::Vec;
+    pub use axle_rt::{amc_message_await__u32_event, amc_message_send, AmcMessage};
+    pub use sata_driver_messages::RemoteBlockDevice;
+}
+#[cfg(not(target_os = "axle"))]
+mod conditional_imports {}
+
+use crate::conditional_imports::*;
+
+use axle_rt::{copy_str_into_sized_slice, ContainsEventField, ExpectsEventField};
+use axle_rt_derive::ContainsEventField;
+use gpt_helper::{Guid, Partition};
+use sata_driver_messages::{str_from_u8_nul_utf8_unchecked, BlockDeviceStatus};
+
+pub const PARTITION_MANAGER_SERVICE_NAME: &'static str = "com.axle.partition_manager";
+/// Partitions past this many are not reported
+pub const MAX_PARTITIONS: usize = 16;
+
+pub fn device_for_partition_entry(entry_index: u32) -> u32 {
+    entry_index + 1
+}
+
+#[repr(C)]
+#[derive(Debug, ContainsEventField)]
+pub struct ListPartitions {
+    event: u32,
+    pub request_id: u32,
+}
+
+impl ListPartitions {
+    pub fn new(request_id: u32) -> Self {
+        Self {
+            event: Self::EXPECTED_EVENT,
+            request_id,
+        }
+    }
+}
+
+impl ExpectsEventField for ListPartitions {
+    const EXPECTED_EVENT: u32 = 204;
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct PartitionDescription {
+    /// The device to address in block device requests for this partition
+    pub device: u32,
+    type_guid: [u8; 16],
+    unique_guid: [u8; 16],
+    pub first_lba: u64,
+    pub sector_count: u64,
+    pub attributes: u64,
+    /// UTF-8, NUL-terminated
+    name: [u8; 72],
+}
+
+impl PartitionDescription {
+    fn empty() -> Self {
+        Self {
+            device: 0,
+            type_guid: [0; 16],
+            unique_guid: [0; 16],
+            first_lba: 0,
+            sector_count: 0,
+            attributes: 0,
+            name: [0; 72],
+        }
+    }
+
+    pub fn new(partition: &Partition) -> Self {
+        let mut ret = Self {
+            device: device_for_partition_entry(partition.index),
+            type_guid: partition.type_guid.0,
+            unique_guid: partition.unique_guid.0,
+            first_lba: partition.first_lba,
+            sector_count: partition.sector_count(),
+            attributes: partition.attributes,
+            ..Self::empty()
+        };
+        // Don't split a multi-byte character when truncating
+        let mut name_len = core::cmp::min(partition.name.len(), ret.name.len() - 1);
+        while !partition.name.is_char_boundary(name_len) {
+            name_len -= 1;
+        }
+        copy_str_into_sized_slice(&mut ret.name, &partition.name[..name_len]);
+        ret
+    }
+
+    pub fn type_guid(&self) -> Guid {
+        Guid(self.type_guid)
+    }
+
+    pub fn unique_guid(&self) -> Guid {
+        Guid(self.unique_guid)
+    }
+
+    pub fn name(&self) -> &str {
+        str_from_u8_nul_utf8_unchecked(&self.name)
+    }
+
+    #[cfg(target_os = "axle")]
+    pub fn block_device(&self) -> RemoteBlockDevice {
+        RemoteBlockDevice::new(PARTITION_MANAGER_SERVICE_NAME, self.device)
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, ContainsEventField)]
+pub struct PartitionList {
+    event: u32,
+    pub request_id: u32,
+    pub status: BlockDeviceStatus,
+    pub partition_count: u32,
+    pub partitions: [PartitionDescription; MAX_PARTITIONS],
+}
+
+impl PartitionList {
+    pub fn new(request_id: u32, status: BlockDeviceStatus, partitions: &[Partition]) -> Self {
+        let mut ret = Self {
+            event: Self::EXPECTED_EVENT,
+            request_id,
+            status,
+            partition_count: 0,
+            partitions: [PartitionDescription::empty(); MAX_PARTITIONS],
+        };
+        for (slot, partition) in ret.partitions.iter_mut().zip(partitions.iter()) {
+            *slot = PartitionDescription::new(partition);
+            ret.partition_count += 1;
+        }
+        ret
+    }
+
+    pub fn partitions(&self) -> &[PartitionDescription] {
+        &self.partitions[..self.partition_count as usize]
+    }
+}
+
+impl ExpectsEventField for PartitionList {
+    const EXPECTED_EVENT: u32 = 204;
+}
+
+/// Blocks until the partition manager has responded
+#[cfg(target_os = "axle")]
+pub fn list_partitions() -> Result<Vec<PartitionDescription>, BlockDeviceStatus> {
+    amc_message_send(PARTITION_MANAGER_SERVICE_NAME, ListPartitions::new(0));
+    let response: AmcMessage<PartitionList> =
+        amc_message_await__u32_event(PARTITION_MANAGER_SERVICE_NAME);
+    let response = response.body();
+    response.status.into_result()?;
+    Ok(response.partitions().to_vec())
+}
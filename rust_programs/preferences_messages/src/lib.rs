@@ -1,8 +1,7 @@
 #![no_std]
 
 use agx_definitions::Color;
-use axle_rt::{ContainsEventField, ExpectsEventField};
-use axle_rt_derive::ContainsEventField;
+use axle_rt_derive::amc_message;
 
 pub const PREFERENCES_SERVICE_NAME: &'static str = "com.axle.preferences";
 
@@ -17,14 +16,9 @@ impl From<ColorRGBA> for Color {
     }
 }
 
-#[repr(C)]
-#[derive(Debug, ContainsEventField)]
+#[amc_message(event = 812)]
+#[derive(Debug)]
 pub struct PreferencesUpdated {
-    event: u32,
     pub from: ColorRGBA,
     pub to: ColorRGBA,
 }
-
-impl ExpectsEventField for PreferencesUpdated {
-    const EXPECTED_EVENT: u32 = 812;
-}
@@ -0,0 +1,64 @@
+//! The port logic reaches the HBA through these traits, so that it can drive the memory-mapped
+//! registers of a real controller, or a simulated HBA in tests.
+
+use core::mem::size_of;
+use core::ptr::{read_volatile, write_volatile};
+
+use axle_rt::core_commands::PhysRangeMapping;
+
+use crate::sata_definitions::AhciPortBlock;
+
+/// The port registers used by the driver, valued by their offset within the port's register
+/// block. See AHCI 1.3.1, §3.3
+#[repr(usize)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PortRegister {
+    CommandListBase = 0x00,
+    CommandListBaseUpper = 0x04,
+    FrameInfoStructBase = 0x08,
+    FrameInfoStructBaseUpper = 0x0c,
+    InterruptStatus = 0x10,
+    InterruptEnable = 0x14,
+    CommandAndStatus = 0x18,
+    TaskFileData = 0x20,
+    Signature = 0x24,
+    SataStatus = 0x28,
+    SataControl = 0x2c,
+    SataError = 0x30,
+    SataActive = 0x34,
+    CommandIssue = 0x38,
+}
+
+/// Writes have the same side effects as on hardware. For example, PxIS and PxSERR are
+/// write-1-to-clear, and PxCI and PxSACT are write-1-to-set.
+pub trait PortRegisters {
+    fn read(&self, register: PortRegister) -> u32;
+    fn write(&mut self, register: PortRegister, value: u32);
+}
+
+impl PortRegisters for AhciPortBlock {
+    fn read(&self, register: PortRegister) -> u32 {
+        let base = self as *const AhciPortBlock as *const u32;
+        unsafe { read_volatile(base.add(register as usize / size_of::<u32>())) }
+    }
+
+    fn write(&mut self, register: PortRegister, value: u32) {
+        let base = self as *mut AhciPortBlock as *mut u32;
+        unsafe { write_volatile(base.add(register as usize / size_of::<u32>()), value) }
+    }
+}
+
+impl<T: PortRegisters + ?Sized> PortRegisters for &mut T {
+    fn read(&self, register: PortRegister) -> u32 {
+        (**self).read(register)
+    }
+
+    fn write(&mut self, register: PortRegister, value: u32) {
+        (**self).write(register, value)
+    }
+}
+
+/// Hands out physically contiguous buffers that the HBA can access
+pub trait DmaAllocator {
+    fn alloc(&self, size: usize) -> PhysRangeMapping;
+}
@@ -0,0 +1,15 @@
+#![no_std]
+#![feature(raw_ref_op)]
+#![feature(format_args_nl)]
+
+//! The AHCI port logic behind the SATA driver. The hardware is reached through the traits in
+//! `hba`, so the logic can be tested on the host against a simulated HBA.
+
+extern crate alloc;
+
+pub mod hba;
+pub mod port;
+pub mod sata_definitions;
+
+#[cfg(test)]
+mod sim;
@@ -1,540 +1,336 @@
 #![no_std]
 #![feature(start)]
-#![feature(raw_ref_op)]
 #![feature(slice_ptr_get)]
 #![feature(format_args_nl)]
 #![feature(default_alloc_error_handler)]
-#![feature(core_intrinsics)]
 
 mod pci_messages;
-mod sata_definitions;
 
 extern crate alloc;
 extern crate libc;
 
-use alloc::{boxed::Box, collections::BTreeMap, format, rc::Weak, vec, vec::Vec};
-use alloc::{
-    rc::Rc,
-    string::{String, ToString},
-};
+use alloc::{collections::BTreeMap, rc::Rc, vec::Vec};
 
 use bitvec::prelude::*;
 
 use axle_rt::{
-    adi_event_await, adi_register_driver, adi_send_eoi, amc_has_message, amc_message_await_untyped,
-    core_commands::{
-        amc_alloc_physical_range, amc_map_physical_range, AmcMapPhysicalRangeRequest,
-        PhysRangeMapping, PhysVirtPair,
-    },
-    print, ContainsEventField, ExpectsEventField,
+    adi_register_driver, adi_send_eoi,
+    core_commands::{amc_alloc_physical_range, amc_map_physical_range, PhysRangeMapping},
+    executor::Executor,
+    rpc::OwnedAmcMessage,
+    ExpectsEventField,
 };
-use axle_rt_derive::ContainsEventField;
-use core::{cell::RefCell, cmp, intrinsics::ceilf64, mem};
-use sata_definitions::{AhciCommandHeader, AhciPortBlock, RawPhysRegionDescriptor};
+use core::{cell::RefCell, mem};
 
-use axle_rt::{
-    amc_message_await, amc_message_send, amc_register_service, printf, println, AmcMessage,
-};
+use axle_rt::{amc_message_send, amc_register_service, println};
 
-use crate::{
-    pci_messages::{pci_config_word_read, pci_config_word_write, AHCI_INTERRUPT_VECTOR},
-    sata_definitions::{
-        AhciCommandHeaderWord0, AhciCommandHeaderWord0Bits2, AhciGenericHostControlBlock,
-        CommandOpcode, HostToDeviceFIS, IdentifyDeviceData,
-    },
+use crate::pci_messages::{pci_config_word_read, pci_config_word_write, AHCI_INTERRUPT_VECTOR};
+use sata_driver::{
+    hba::DmaAllocator,
+    port::{AhciPortDescription, CommandRequest, Completion, DiskSectorRange, Requester},
+    sata_definitions::{AhciGenericHostControlBlock, AhciPortBlock},
+};
+use sata_driver_messages::{
+    BlockDeviceStatus, DeviceInfo, Flush, FlushResponse, GetDeviceInfo, ReadSectors,
+    ReadSectorsResponse, WriteSectors, WriteSectorsResponse, SATA_DRIVER_SERVICE_NAME,
+    WHOLE_DISK_DEVICE,
 };
 
-#[derive(Debug, Clone)]
-struct DiskSectorRange {
-    start_sector: usize,
-    sector_count: usize,
-}
-
-impl DiskSectorRange {
-    const SECTOR_SIZE: usize = 512;
+/// DMA buffers come from the kernel
+struct AxleDmaAllocator;
 
-    fn new(start_sector: usize, sector_count: usize) -> Self {
-        println!("new {start_sector} {sector_count}");
-        Self {
-            start_sector,
-            sector_count,
-        }
-    }
-
-    fn from_addr_range(start_address: usize, size: usize) -> Self {
-        println!("start_address {start_address} size {size}");
-        // Start address and size must be sector-aligned
-        assert!(
-            start_address % Self::SECTOR_SIZE == 0,
-            "Start address is not sector-aligned"
-        );
-        assert!(size % Self::SECTOR_SIZE == 0, "Size is not sector-aligned");
-        Self::new(start_address / Self::SECTOR_SIZE, size / Self::SECTOR_SIZE)
+impl DmaAllocator for AxleDmaAllocator {
+    fn alloc(&self, size: usize) -> PhysRangeMapping {
+        amc_alloc_physical_range(size).expect("Failed to allocate DMA buffer")
     }
+}
 
-    fn zero() -> Self {
-        Self {
-            start_sector: 0,
-            sector_count: 0,
+type AxlePort = AhciPortDescription<&'static mut AhciPortBlock, AxleDmaAllocator>;
+
+/// Informs the requester that a read, write, flush or device info request has finished
+fn send_completion(completion: Completion) {
+    match completion {
+        Completion::Read {
+            requester,
+            sector_range,
+            status,
+            data,
+        } => {
+            if status != BlockDeviceStatus::Success {
+                ReadSectorsResponse::send_error(
+                    &requester.service,
+                    requester.request_id,
+                    sector_range.start_sector as u64,
+                    sector_range.sector_count as u64,
+                    status,
+                );
+                return;
+            }
+            ReadSectorsResponse::send(
+                &requester.service,
+                requester.request_id,
+                sector_range.start_sector as u64,
+                &data,
+            );
         }
-    }
-
-    fn size(&self) -> usize {
-        DiskSectorRange::SECTOR_SIZE * self.sector_count
-    }
-
-    fn base_address(&self) -> usize {
-        DiskSectorRange::SECTOR_SIZE * self.start_sector
+        Completion::Write {
+            requester,
+            sector_range,
+            status,
+        } => WriteSectorsResponse::send(
+            &requester.service,
+            requester.request_id,
+            sector_range.start_sector as u64,
+            sector_range.sector_count as u64,
+            status,
+        ),
+        Completion::Flush { requester, status } => amc_message_send(
+            &requester.service,
+            FlushResponse::new(requester.request_id, status),
+        ),
+        Completion::DeviceInfo { requester, drive } => amc_message_send(
+            &requester.service,
+            DeviceInfo::new(
+                requester.request_id,
+                BlockDeviceStatus::Success,
+                drive.sector_count,
+                &drive.model,
+                &drive.serial_number,
+            ),
+        ),
     }
 }
 
-#[derive(Debug)]
-struct CommandRequest {
-    opcode: CommandOpcode,
-    cmd_data: CommandData,
+unsafe fn body_as_type_unchecked<T>(body: &[u8]) -> &T {
+    &*(body.as_ptr() as *const T)
 }
 
-#[derive(Debug, Clone)]
-enum CommandData {
-    IdentifyDevice,
-    WriteDmaExt {
-        sector_range: DiskSectorRange,
-        data: Vec<u8>,
-    },
-    ReadDmaExt {
-        sector_range: DiskSectorRange,
-    },
-}
-
-impl CommandRequest {
-    fn new_read_command(sector_range: DiskSectorRange) -> Self {
-        Self {
-            opcode: CommandOpcode::ReadDmaExt,
-            cmd_data: CommandData::ReadDmaExt { sector_range },
+/// Rejects a request whose body is too short to hold it. The request ID is echoed if the body
+/// is long enough to hold one.
+fn reply_truncated_request(sender: &str, event: u32, raw_body: &[u8]) {
+    let request_id = raw_body
+        .get(mem::size_of::<u32>()..mem::size_of::<u32>() * 2)
+        .map_or(0, |request_id| {
+            u32::from_ne_bytes(request_id.try_into().unwrap())
+        });
+    let status = BlockDeviceStatus::InvalidRequest;
+    match event {
+        GetDeviceInfo::EXPECTED_EVENT => {
+            amc_message_send(sender, DeviceInfo::new(request_id, status, 0, "", ""))
         }
-    }
-
-    fn new_write_command(sector_range: DiskSectorRange, data: &[u8]) -> Self {
-        Self {
-            opcode: CommandOpcode::WriteDmaExt,
-            cmd_data: CommandData::WriteDmaExt {
-                sector_range,
-                data: data.to_vec(),
-            },
+        ReadSectors::EXPECTED_EVENT => {
+            ReadSectorsResponse::send_error(sender, request_id, 0, 0, status)
         }
-    }
-
-    fn new_identify_command() -> Self {
-        Self {
-            opcode: CommandOpcode::IdentifyDevice,
-            cmd_data: CommandData::IdentifyDevice,
+        WriteSectors::EXPECTED_EVENT => {
+            WriteSectorsResponse::send(sender, request_id, 0, 0, status)
         }
+        Flush::EXPECTED_EVENT => amc_message_send(sender, FlushResponse::new(request_id, status)),
+        _ => unreachable!(),
     }
 }
 
-#[derive(Debug)]
-struct ActiveCommand {
-    command_slot: usize,
-    command_data: CommandData,
-    command_type: CommandOpcode,
-    command_table_buf: PhysRangeMapping,
-    phys_region_descriptors: Vec<PhysRegionDescriptor>,
-}
-
-impl ActiveCommand {
-    fn new(command_slot: usize, command_type: CommandOpcode, command_data: CommandData) -> Self {
-        let command_table_buf = amc_alloc_physical_range(0x1000);
-        Self {
-            command_slot,
-            command_data,
-            command_type,
-            command_table_buf,
-            phys_region_descriptors: vec![],
+/// Parses the first bytes of the message as a u32 event field, and checks that the body is long
+/// enough to be cast to the request that the event names. Requests that are too short are
+/// rejected here, and None is returned.
+fn request_event(msg_unparsed: &OwnedAmcMessage) -> Option<u32> {
+    let raw_body = &msg_unparsed.body;
+    let event = match raw_body.get(..mem::size_of::<u32>()) {
+        Some(event) => u32::from_ne_bytes(event.try_into().unwrap()),
+        None => {
+            println!(
+                "Dropping message from {} that's too short to hold an event",
+                msg_unparsed.source
+            );
+            return None;
         }
+    };
+    let request_len = match event {
+        GetDeviceInfo::EXPECTED_EVENT => mem::size_of::<GetDeviceInfo>(),
+        ReadSectors::EXPECTED_EVENT => mem::size_of::<ReadSectors>(),
+        WriteSectors::EXPECTED_EVENT => mem::size_of::<WriteSectors>(),
+        Flush::EXPECTED_EVENT => mem::size_of::<Flush>(),
+        // Left for the caller to report
+        _ => return Some(event),
+    };
+    if raw_body.len() < request_len {
+        println!(
+            "Rejecting truncated request {event} from {}",
+            msg_unparsed.source
+        );
+        reply_truncated_request(&msg_unparsed.source, event, raw_body);
+        return None;
     }
+    Some(event)
+}
 
-    fn complete(&self) {
-        match self.command_type {
-            CommandOpcode::ReadDmaExt => {
-                println!("Read DMA ext completed from drive");
-                let sector_data = {
-                    let phys_region = &self.phys_region_descriptors[0].phys_region_buf;
-                    let region_base = phys_region.addr.virt;
-                    let slice =
-                        core::ptr::slice_from_raw_parts(region_base as *const u8, phys_region.size);
-                    unsafe { &*(slice as *const [u8]) }
-                };
-                let chunk_size = 64;
-                let base_address = match &self.command_data {
-                    CommandData::ReadDmaExt { sector_range } => sector_range.base_address(),
-                    _ => panic!("Expected ReadDmaExt command data"),
-                };
-                for (offset, line) in sector_data.chunks(chunk_size).enumerate() {
-                    print!("{:016x}: ", base_address + (offset * chunk_size));
-                    // TODO(PT): Fixup endianness?
-                    for word in line.chunks(4) {
-                        for byte in word {
-                            print!("{}", *byte as char);
-                        }
-                        print!(" ");
-                    }
-                    println!();
+fn handle_message(port_desc: &mut AxlePort, msg_unparsed: OwnedAmcMessage) {
+    let event = match request_event(&msg_unparsed) {
+        Some(event) => event,
+        None => return,
+    };
+    let raw_body = &msg_unparsed.body;
+    let sender = &msg_unparsed.source;
+
+    // Each inner call to body_as_type_unchecked is unsafe because we must be
+    // sure we're casting to the right type.
+    // Since we verify the type on the LHS, and request_event checked that the body is long
+    // enough to hold it, each usage is safe.
+    unsafe {
+        match event {
+            GetDeviceInfo::EXPECTED_EVENT => {
+                let request: &GetDeviceInfo = body_as_type_unchecked(raw_body);
+                if request.device != WHOLE_DISK_DEVICE {
+                    amc_message_send(
+                        sender,
+                        DeviceInfo::new(
+                            request.request_id,
+                            BlockDeviceStatus::NoSuchDevice,
+                            0,
+                            "",
+                            "",
+                        ),
+                    );
+                    return;
+                }
+                let requester = Requester::new(sender, request.request_id);
+                if let Some(completion) = port_desc.request_device_info(requester) {
+                    send_completion(completion);
                 }
             }
-            CommandOpcode::WriteDmaExt => {
-                println!("Write DMA ext completed from drive");
+            ReadSectors::EXPECTED_EVENT => {
+                let request: &ReadSectors = body_as_type_unchecked(raw_body);
+                let status = port_desc.validate_sector_range(
+                    request.device,
+                    request.start_sector,
+                    request.sector_count,
+                );
+                if status != BlockDeviceStatus::Success {
+                    ReadSectorsResponse::send_error(
+                        sender,
+                        request.request_id,
+                        request.start_sector,
+                        request.sector_count,
+                        status,
+                    );
+                    return;
+                }
+                port_desc.submit(CommandRequest::new_read_command(
+                    DiskSectorRange::new(
+                        request.start_sector as usize,
+                        request.sector_count as usize,
+                    ),
+                    Requester::new(sender, request.request_id),
+                ));
             }
-            CommandOpcode::IdentifyDevice => {
-                println!("Interpreting results of IDENTIFY DEVICE...");
-                let identify_block = {
-                    let region = &self.phys_region_descriptors[0].phys_region_buf;
-                    let ptr = region.addr.virt;
-                    unsafe { &*(ptr as *const IdentifyDeviceData) }
+            WriteSectors::EXPECTED_EVENT => {
+                let request: &WriteSectors = body_as_type_unchecked(raw_body);
+                let mut status = port_desc.validate_sector_range(
+                    request.device,
+                    request.start_sector,
+                    request.sector_count,
+                );
+                let expected_len =
+                    (request.sector_count as usize).checked_mul(DiskSectorRange::SECTOR_SIZE);
+                let data = match request.data(raw_body.len()) {
+                    Some(data) if Some(data.len()) == expected_len => data,
+                    _ => {
+                        status = BlockDeviceStatus::InvalidRequest;
+                        &[]
+                    }
                 };
-                println!("Drive serial number: {:?}", &identify_block.serial_number());
+                if status != BlockDeviceStatus::Success {
+                    WriteSectorsResponse::send(
+                        sender,
+                        request.request_id,
+                        request.start_sector,
+                        request.sector_count,
+                        status,
+                    );
+                    return;
+                }
+                port_desc.submit(CommandRequest::new_write_command(
+                    DiskSectorRange::new(
+                        request.start_sector as usize,
+                        request.sector_count as usize,
+                    ),
+                    data,
+                    Requester::new(sender, request.request_id),
+                ));
             }
-        }
-    }
-}
-
-#[derive(Debug)]
-pub struct PhysRegionDescriptor {
-    raw_descriptor: &'static mut RawPhysRegionDescriptor,
-    pub phys_region_buf: PhysRangeMapping,
-}
-
-impl PhysRegionDescriptor {
-    pub fn from_virt_addr(addr: usize, underlying_buffer_size: usize) -> Self {
-        let ptr = addr as *mut RawPhysRegionDescriptor;
-        let raw_desc = unsafe { &mut *ptr };
-
-        raw_desc.set_interrupt_on_completion(true);
-
-        println!("Allocating PhysRegionDescriptor buf");
-        let phys_region_buf = amc_alloc_physical_range(underlying_buffer_size);
-
-        raw_desc.set_byte_count(underlying_buffer_size as u32);
-        raw_desc.set_data_base_address(phys_region_buf.addr.phys as u64);
-        println!("Phys region data base: {:016x}", phys_region_buf.addr.phys);
-
-        PhysRegionDescriptor {
-            raw_descriptor: raw_desc,
-            phys_region_buf,
-        }
-    }
-}
-
-struct AhciPortDescription {
-    port_index: u8,
-    port_block: &'static mut AhciPortBlock,
-    command_list_region: PhysRangeMapping,
-    command_list: &'static mut [AhciCommandHeader],
-    frame_info_struct_recv_region: PhysRangeMapping,
-
-    active_commands: Vec<ActiveCommand>,
-}
-
-impl AhciPortDescription {
-    fn new(port_index: u8, port_block: &'static mut AhciPortBlock) -> Self {
-        // Initialize the device
-        //
-        // First, print out some debug info
-        println!("\tPort {}", port_index);
-        println!(
-            "\t\tCommand list base 0x{:08x}:{:08x}",
-            port_block.command_list_base_upper, port_block.command_list_base
-        );
-        println!(
-            "\t\tFIS base 0x{:08x}:{:08x}",
-            port_block.frame_info_struct_base_upper, port_block.frame_info_struct_base
-        );
-        println!("\t\tSignature: 0x{:08x}", port_block.signature);
-        println!("\t\tActive: 0x{:08x}", port_block.sata_active);
-        println!("\t\tStatus: 0x{:08x}", port_block.sata_status);
-
-        println!(
-            "\t\tCommand and start bit? {}",
-            port_block.command_and_status.view_bits::<Lsb0>()[0]
-        );
-
-        // Allocate the command-list and FIS-receive buffers which we'll give to the device
-        let command_list_region = amc_alloc_physical_range(0x1000);
-        let frame_info_struct_recv_region = amc_alloc_physical_range(0x1000);
-        // TODO(PT): Support 64-bit physical addresses here
-        // Need to use the CommandListBaseUpper / FISBaseUpper fields
-        // And detect if the HW supports 64 bit addressing too
-        // For now, error out if axle gives us a too-high phys addr
-        assert!(
-            command_list_region.addr.phys < (u32::MAX as usize),
-            "Kernel handed out a too-big address",
-        );
-        assert!(
-            frame_info_struct_recv_region.addr.phys < (u32::MAX as usize),
-            "Kernel handed out a too-big address",
-        );
-
-        let command_list = {
-            let ptr = command_list_region.addr.virt as *mut AhciCommandHeader;
-            let slice = core::ptr::slice_from_raw_parts_mut(ptr, 32);
-            unsafe { &mut *slice }
-        };
-
-        let mut this = Self {
-            port_index,
-            port_block,
-            command_list_region,
-            command_list,
-            frame_info_struct_recv_region,
-            active_commands: vec![],
-        };
-
-        // Now, initialize the command-list and FIS-receive buffers
-        // First, request the HBA stop processing the command-list, since we're going to modify it
-        this.stop_processing_command_list();
-        // Also, request the HBA stops delivering FIS
-        this.stop_receiving_frame_info_structs();
-
-        // Wait for any ongoing access to the command list to complete
-        this.wait_until_command_list_use_completes();
-        // From the spec on FIS Receive enable bit:
-        // > If software wishes to move the base, this bit must first be cleared,
-        // > and software must wait for the FR bit in this register to be cleared.
-        this.wait_until_fis_receive_completes();
-
-        this.port_block.command_list_base = this.command_list_region.addr.phys as u32;
-        this.port_block.command_list_base_upper = 0;
-        this.port_block.frame_info_struct_base =
-            this.frame_info_struct_recv_region.addr.phys as u32;
-        this.port_block.frame_info_struct_base_upper = 0;
-
-        println!(
-            "FIS at virt 0x{:16x}",
-            this.frame_info_struct_recv_region.addr.virt
-        );
-
-        println!(
-            "Port interrupt status: {}",
-            this.port_block.interrupt_status
-        );
-
-        // Clear pending interrupts with a write-clear
-        this.port_block.interrupt_status = 0xffffffff;
-
-        // Wait for any ongoing access to the command list to complete
-        this.wait_until_command_list_use_completes();
-
-        // Ready to receive FIS
-        // 10.3.2: FIS enable must be before CL enable
-        this.start_receiving_frame_info_structs();
-        this.start_processing_command_list();
-
-        println!(
-            "End of port init interrupt status: {}",
-            this.port_block.interrupt_status
-        );
-
-        this
-    }
-
-    fn find_free_command_slot(&self) -> usize {
-        for (bit_idx, bit) in self
-            .port_block
-            .command_issue
-            .view_bits::<Lsb0>()
-            .iter()
-            .enumerate()
-        {
-            if *bit == false {
-                // Found a free command slot!
-                return bit_idx;
+            Flush::EXPECTED_EVENT => {
+                let request: &Flush = body_as_type_unchecked(raw_body);
+                if request.device != WHOLE_DISK_DEVICE {
+                    amc_message_send(
+                        sender,
+                        FlushResponse::new(request.request_id, BlockDeviceStatus::NoSuchDevice),
+                    );
+                    return;
+                }
+                port_desc.submit(CommandRequest::new_flush_command(Requester::new(
+                    sender,
+                    request.request_id,
+                )));
             }
+            _ => println!("Unknown event from {}: {event}", msg_unparsed.source),
         }
-        // TODO(PT): Option<CommandSlot> instead of panic on failure?
-        panic!("Failed to find a free command slot!");
     }
+}
 
-    fn send_command_req(&mut self, cmd_request: &CommandRequest) {
-        let free_command_slot_idx = self.find_free_command_slot();
-        let command_header = &mut self.command_list[free_command_slot_idx];
-        let mut active_command = ActiveCommand::new(
-            free_command_slot_idx,
-            cmd_request.opcode,
-            cmd_request.cmd_data.clone(),
-        );
-        command_header
-            .set_command_table_desc_base(active_command.command_table_buf.addr.phys as u64);
-
-        // Configure the command header
-        let mut word0 = &mut command_header.word0;
-
-        // Command FIS length (in sizeof(u32) increments)
-        assert_eq!(mem::size_of::<HostToDeviceFIS>() / mem::size_of::<u32>(), 5);
-        word0.set_command_fis_len(
-            (mem::size_of::<HostToDeviceFIS>() / mem::size_of::<u32>()) as u32,
-        );
-
-        match cmd_request.opcode {
-            CommandOpcode::WriteDmaExt => word0.set_write(true),
-            _ => word0.set_write(false),
-        }
-
-        word0.set_clear_busy_upon_r_ok(true);
-
-        // Set up the DMA regions that will send or receive the data via the HBA
-        let required_data_buffer_size = match &cmd_request.cmd_data {
-            CommandData::IdentifyDevice => 0x1000,
-            CommandData::ReadDmaExt { sector_range } => sector_range.size(),
-            CommandData::WriteDmaExt { sector_range, data } => sector_range.size(),
-        };
-
-        let phys_region_size = 0x1000;
-        // Round up so we always get at least 1 region
-        let phys_region_count =
-            unsafe { ceilf64(required_data_buffer_size as f64 / phys_region_size as f64) as usize };
-        println!("Phys region count {phys_region_count}");
-        word0.set_phys_region_desc_table_len(phys_region_count as u32);
-
-        // Allocate each DMA region
-        // The struct describing each of these is placed a fixed location after the command header,
-        // and is initialised in-place
-        for region_idx in 0..phys_region_count {
-            let phys_region_descriptor = PhysRegionDescriptor::from_virt_addr(
-                active_command.command_table_buf.addr.virt
-                    + 0x80
-                    + (mem::size_of::<PhysRegionDescriptor>() * region_idx),
-                phys_region_size,
-            );
-
-            // If this is a write command, fill the region with the data we've been asked to write to disk
-            if let CommandData::WriteDmaExt {
-                sector_range: _,
-                data: write_data,
-            } = &cmd_request.cmd_data
-            {
-                let prd_data_buf = {
-                    let region_size = phys_region_descriptor.phys_region_buf.size;
-                    let region_base = phys_region_descriptor.phys_region_buf.addr.virt;
-                    let slice =
-                        core::ptr::slice_from_raw_parts(region_base as *mut u8, region_size);
-                    unsafe { &mut *(slice as *mut [u8]) }
-                };
-                println!("Filling PRD {region_idx}");
-                let offset_into_write_data = phys_region_size * region_idx;
-                for (place, data) in prd_data_buf.iter_mut().zip(write_data.iter()) {
-                    *place = *data
-                }
+/// Fails every request when no drive is attached
+fn reply_no_such_device(msg_unparsed: OwnedAmcMessage) {
+    let event = match request_event(&msg_unparsed) {
+        Some(event) => event,
+        None => return,
+    };
+    let raw_body = &msg_unparsed.body;
+    let sender = &msg_unparsed.source;
+    let status = BlockDeviceStatus::NoSuchDevice;
+
+    // Each inner call to body_as_type_unchecked is unsafe because we must be
+    // sure we're casting to the right type.
+    // Since we verify the type on the LHS, and request_event checked that the body is long
+    // enough to hold it, each usage is safe.
+    unsafe {
+        match event {
+            GetDeviceInfo::EXPECTED_EVENT => {
+                let request: &GetDeviceInfo = body_as_type_unchecked(raw_body);
+                amc_message_send(
+                    sender,
+                    DeviceInfo::new(request.request_id, status, 0, "", ""),
+                );
             }
-
-            active_command
-                .phys_region_descriptors
-                .push(phys_region_descriptor);
-        }
-
-        // Set up the FIS containing the command for the drive
-        // TODO(PT) Can this use BitArray::new() instead of unsafe?
-        let h2d_fis = HostToDeviceFIS::from_virt_addr(active_command.command_table_buf.addr.virt);
-        h2d_fis.set_command(active_command.command_type);
-        h2d_fis.set_is_command(true);
-
-        match &cmd_request.cmd_data {
-            CommandData::ReadDmaExt { sector_range } => {
-                h2d_fis.set_start_sector(sector_range.start_sector);
-                h2d_fis.set_sector_count(sector_range.sector_count.try_into().unwrap());
-                println!("sector range {sector_range:?}");
+            ReadSectors::EXPECTED_EVENT => {
+                let request: &ReadSectors = body_as_type_unchecked(raw_body);
+                ReadSectorsResponse::send_error(
+                    sender,
+                    request.request_id,
+                    request.start_sector,
+                    request.sector_count,
+                    status,
+                );
             }
-            CommandData::WriteDmaExt { sector_range, data } => {
-                h2d_fis.set_start_sector(sector_range.start_sector);
-                h2d_fis.set_sector_count(sector_range.sector_count.try_into().unwrap());
-                println!("sector range {sector_range:?}");
+            WriteSectors::EXPECTED_EVENT => {
+                let request: &WriteSectors = body_as_type_unchecked(raw_body);
+                WriteSectorsResponse::send(
+                    sender,
+                    request.request_id,
+                    request.start_sector,
+                    request.sector_count,
+                    status,
+                );
             }
-            _ => (),
-        }
-
-        self.active_commands.push(active_command);
-
-        // Issue the command
-        println!("Issuing command...");
-        self.port_block.command_issue |= (1 << free_command_slot_idx);
-    }
-
-    fn _set_command_and_status_bit(&mut self, bit_idx: usize, enabled: bool) {
-        self.port_block
-            .command_and_status
-            .view_bits_mut::<Lsb0>()
-            .set(bit_idx, enabled);
-    }
-
-    fn _get_command_and_status_bit(&self, bit_idx: usize) -> bool {
-        self.port_block.command_and_status.view_bits::<Lsb0>()[bit_idx]
-    }
-
-    fn stop_processing_command_list(&mut self) {
-        self._set_command_and_status_bit(0, false);
-    }
-
-    fn start_processing_command_list(&mut self) {
-        self._set_command_and_status_bit(0, true);
-    }
-
-    fn wait_until_command_list_use_completes(&self) {
-        while self._get_command_and_status_bit(15) {
-            println!("Waiting for Command List Running bit to clear...");
-            //unsafe { libc::usleep(1) };
-        }
-    }
-
-    fn stop_receiving_frame_info_structs(&mut self) {
-        self._set_command_and_status_bit(4, false);
-    }
-
-    fn start_receiving_frame_info_structs(&mut self) {
-        self._set_command_and_status_bit(4, true);
-    }
-
-    fn wait_until_fis_receive_completes(&self) {
-        while self._get_command_and_status_bit(14) {
-            println!("Waiting for FIS Receive Running bit to clear...");
-            //unsafe { libc::usleep(1) };
-        }
-    }
-
-    fn handle_interrupt(&mut self) {
-        let port_interrupt_status = self.port_block.interrupt_status;
-        println!("Device interrupt status: {:032b}", port_interrupt_status);
-        self.port_block.clear_interrupt_status_mask();
-
-        // Any error bits set?
-        let error_bits = [04, 23, 24, 26, 27, 28, 29, 30];
-        for error_bit in error_bits {
-            if *port_interrupt_status
-                .view_bits::<Lsb0>()
-                .get(error_bit)
-                .unwrap()
-            {
-                todo!("Handle port error");
+            Flush::EXPECTED_EVENT => {
+                let request: &Flush = body_as_type_unchecked(raw_body);
+                amc_message_send(sender, FlushResponse::new(request.request_id, status));
             }
+            _ => println!("Unknown event from {}: {event}", msg_unparsed.source),
         }
-
-        self.active_commands.retain(|active_cmd| {
-            // Is the associated 'command running' bit now unset?
-            let active_command_completed = self
-                .port_block
-                .command_issue
-                .view_bits::<Lsb0>()
-                .get(active_cmd.command_slot)
-                .unwrap()
-                == false;
-
-            active_cmd.complete();
-
-            !active_command_completed
-        });
     }
 }
 
 fn handle_interrupt(
     generic_host_control_block: &mut AhciGenericHostControlBlock,
-    active_ports: &mut BTreeMap<usize, AhciPortDescription>,
+    active_ports: &mut BTreeMap<usize, AxlePort>,
 ) {
     println!("AHCI interrupt");
 
@@ -551,7 +347,9 @@ fn handle_interrupt(
 
     for port_idx_with_interrupt in &port_indexes_with_interrupt {
         let port_desc_with_interrupt = active_ports.get_mut(&port_idx_with_interrupt).unwrap();
-        port_desc_with_interrupt.handle_interrupt();
+        for completion in port_desc_with_interrupt.handle_interrupt() {
+            send_completion(completion);
+        }
     }
 
     adi_send_eoi(AHCI_INTERRUPT_VECTOR);
@@ -560,8 +358,11 @@ fn handle_interrupt(
 #[start]
 #[allow(unreachable_code)]
 fn start(_argc: isize, _argv: *const *const u8) -> isize {
-    amc_register_service("com.axle.sata_driver");
-    adi_register_driver("com.axle.sata_driver", AHCI_INTERRUPT_VECTOR);
+    amc_register_service(SATA_DRIVER_SERVICE_NAME);
+    if !adi_register_driver(SATA_DRIVER_SERVICE_NAME, AHCI_INTERRUPT_VECTOR) {
+        println!("Failed to register as the AHCI interrupt handler");
+        return 1;
+    }
 
     println!("SATA driver running!");
 
@@ -598,7 +399,8 @@ fn start(_argc: isize, _argv: *const *const u8) -> isize {
     // Map in the physical AHCI range
     let ahci_base_address = {
         // TODO(PT): Read the range's size from PCI
-        let virt_addr = amc_map_physical_range(ahci_phys_base_address, 0x1000);
+        let virt_addr = amc_map_physical_range(ahci_phys_base_address, 0x1000)
+            .expect("Failed to map AHCI range");
         virt_addr as *mut u8
     };
     println!("Mapped AHCI range to virt {ahci_base_address:p}");
@@ -619,7 +421,7 @@ fn start(_argc: isize, _argv: *const *const u8) -> isize {
     // Detect in-use AHCI ports. From the spec:
     // > Port Implemented (PI): This register is bit significant.
     // If a bit is set to '1', the corresponding port is available for software to use.
-    let mut active_ports: BTreeMap<usize, AhciPortDescription> = BTreeMap::new();
+    let mut active_ports: BTreeMap<usize, AxlePort> = BTreeMap::new();
     for bit_idx in 0..32 {
         if generic_host_control_block
             .ports_implemented
@@ -658,8 +460,16 @@ fn start(_argc: isize, _argv: *const *const u8) -> isize {
                         );
                         // Write-clear active interrupts
                         // Construct a description of this port
-                        active_ports
-                            .insert(bit_idx, AhciPortDescription::new(bit_idx as u8, port_block));
+                        active_ports.insert(
+                            bit_idx,
+                            AhciPortDescription::new(
+                                bit_idx as u8,
+                                port_block,
+                                AxleDmaAllocator,
+                                generic_host_control_block.command_slot_count(),
+                                generic_host_control_block.supports_native_command_queuing(),
+                            ),
+                        );
                     }
                 }
             }
@@ -671,7 +481,7 @@ fn start(_argc: isize, _argv: *const *const u8) -> isize {
 
     // Enable interrupts in each port
     for (_, port) in &mut active_ports {
-        port.port_block.interrupt_enable = 0xffffffff;
+        port.enable_interrupts();
     }
 
     // Set global Interrupt Enable bit
@@ -684,27 +494,38 @@ fn start(_argc: isize, _argv: *const *const u8) -> isize {
 
     // To start off, send an IDENTIFY for each connected drive
     for (port_idx, port_desc) in active_ports.iter_mut() {
-        port_desc.send_command_req(&CommandRequest::new_identify_command());
+        port_desc.submit(CommandRequest::new_identify_command());
     }
 
-    loop {
-        let awoke_for_interrupt = adi_event_await(AHCI_INTERRUPT_VECTOR);
-        if awoke_for_interrupt {
-            handle_interrupt(generic_host_control_block, &mut active_ports);
-        } else {
-            while amc_has_message(None) {
-                println!("Consuming message...");
-                unsafe {
-                    amc_message_await_untyped(None);
-                }
+    let active_ports = Rc::new(RefCell::new(active_ports));
+    let mut executor = Executor::new();
+
+    let events = executor.events();
+    let interrupt_ports = Rc::clone(&active_ports);
+    executor.spawn(async move {
+        loop {
+            events.next_irq(AHCI_INTERRUPT_VECTOR).await;
+            handle_interrupt(
+                generic_host_control_block,
+                &mut interrupt_ports.borrow_mut(),
+            );
+        }
+    });
+
+    let events = executor.events();
+    executor.spawn(async move {
+        loop {
+            let msg_unparsed = events.next_message(None).await;
+            // TODO(PT): Allow the requester to select a port
+            let mut active_ports = active_ports.borrow_mut();
+            match active_ports.values_mut().next() {
+                Some(port_desc) => handle_message(port_desc, msg_unparsed),
+                None => reply_no_such_device(msg_unparsed),
             }
-            println!("Sending AHCI command FIS...");
-            let port_desc = active_ports.get_mut(&0).unwrap();
-            port_desc.send_command_req(&CommandRequest::new_read_command(
-                DiskSectorRange::from_addr_range(0xe00, 1024),
-            ));
         }
-    }
+    });
+
+    executor.run();
 
     0
 }
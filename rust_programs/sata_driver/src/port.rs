@@ -0,0 +1,1161 @@
+use alloc::{
+    collections::VecDeque,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+use core::{cmp, mem};
+
+use axle_rt::{core_commands::PhysRangeMapping, println};
+use bitvec::prelude::*;
+use sata_driver_messages::{BlockDeviceStatus, MAX_SECTORS_PER_REQUEST, WHOLE_DISK_DEVICE};
+
+use crate::hba::{DmaAllocator, PortRegister, PortRegisters};
+use crate::sata_definitions::{
+    AhciCommandHeader, CommandOpcode, HostToDeviceFIS, IdentifyDeviceData, RawPhysRegionDescriptor,
+};
+
+/// Command tables and DMA regions are each a page
+const DMA_PAGE_SIZE: usize = 0x1000;
+/// How many pages from finished commands are kept around for reuse. This covers a few full-size
+/// requests in flight at once.
+const MAX_FREE_DMA_PAGES: usize = 128;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiskSectorRange {
+    pub start_sector: usize,
+    pub sector_count: usize,
+}
+
+impl DiskSectorRange {
+    pub const SECTOR_SIZE: usize = 512;
+
+    pub fn new(start_sector: usize, sector_count: usize) -> Self {
+        println!("new {start_sector} {sector_count}");
+        Self {
+            start_sector,
+            sector_count,
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        DiskSectorRange::SECTOR_SIZE * self.sector_count
+    }
+
+    fn overlaps(&self, other: &DiskSectorRange) -> bool {
+        self.start_sector < other.start_sector + other.sector_count
+            && other.start_sector < self.start_sector + self.sector_count
+    }
+}
+
+/// The client to send a completion message to, and the ID it gave the request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Requester {
+    pub service: String,
+    pub request_id: u32,
+}
+
+impl Requester {
+    pub fn new(service: &str, request_id: u32) -> Self {
+        Self {
+            service: service.to_string(),
+            request_id,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct CommandRequest {
+    opcode: CommandOpcode,
+    cmd_data: CommandData,
+    // The client to inform once the command completes, if any
+    requester: Option<Requester>,
+}
+
+#[derive(Debug, Clone)]
+enum CommandData {
+    IdentifyDevice,
+    WriteDmaExt {
+        sector_range: DiskSectorRange,
+        data: Vec<u8>,
+    },
+    ReadDmaExt {
+        sector_range: DiskSectorRange,
+    },
+    FlushCacheExt,
+}
+
+impl CommandData {
+    fn sector_range(&self) -> Option<&DiskSectorRange> {
+        match self {
+            CommandData::ReadDmaExt { sector_range } => Some(sector_range),
+            CommandData::WriteDmaExt { sector_range, .. } => Some(sector_range),
+            _ => None,
+        }
+    }
+
+    /// Reads and writes can be queued with NCQ, and can run alongside each other
+    fn is_data_transfer(&self) -> bool {
+        self.sector_range().is_some()
+    }
+
+    /// Whether the command must wait for (or hold back) another command that's in flight.
+    /// The drive and the HBA are free to reorder commands, so anything that isn't a read or
+    /// write runs alone, and a write can't run alongside another access to the same sectors.
+    fn conflicts_with(&self, other: &CommandData) -> bool {
+        match (self.sector_range(), other.sector_range()) {
+            (Some(range), Some(other_range)) => {
+                let either_writes = matches!(self, CommandData::WriteDmaExt { .. })
+                    || matches!(other, CommandData::WriteDmaExt { .. });
+                either_writes && range.overlaps(other_range)
+            }
+            _ => true,
+        }
+    }
+}
+
+impl CommandRequest {
+    pub fn new_read_command(sector_range: DiskSectorRange, requester: Requester) -> Self {
+        Self {
+            opcode: CommandOpcode::ReadDmaExt,
+            cmd_data: CommandData::ReadDmaExt { sector_range },
+            requester: Some(requester),
+        }
+    }
+
+    pub fn new_write_command(
+        sector_range: DiskSectorRange,
+        data: &[u8],
+        requester: Requester,
+    ) -> Self {
+        Self {
+            opcode: CommandOpcode::WriteDmaExt,
+            cmd_data: CommandData::WriteDmaExt {
+                sector_range,
+                data: data.to_vec(),
+            },
+            requester: Some(requester),
+        }
+    }
+
+    pub fn new_flush_command(requester: Requester) -> Self {
+        Self {
+            opcode: CommandOpcode::FlushCacheExt,
+            cmd_data: CommandData::FlushCacheExt,
+            requester: Some(requester),
+        }
+    }
+
+    pub fn new_identify_command() -> Self {
+        Self {
+            opcode: CommandOpcode::IdentifyDevice,
+            cmd_data: CommandData::IdentifyDevice,
+            requester: None,
+        }
+    }
+}
+
+/// A finished request, to be reported to the client that made it
+#[derive(Debug)]
+pub enum Completion {
+    Read {
+        requester: Requester,
+        sector_range: DiskSectorRange,
+        status: BlockDeviceStatus,
+        // Empty unless the read succeeded
+        data: Vec<u8>,
+    },
+    Write {
+        requester: Requester,
+        sector_range: DiskSectorRange,
+        status: BlockDeviceStatus,
+    },
+    Flush {
+        requester: Requester,
+        status: BlockDeviceStatus,
+    },
+    DeviceInfo {
+        requester: Requester,
+        drive: IdentifiedDrive,
+    },
+}
+
+#[derive(Debug)]
+struct ActiveCommand {
+    command_slot: usize,
+    command_data: CommandData,
+    command_type: CommandOpcode,
+    // Issued as a native command queuing (FPDMA QUEUED) command
+    queued: bool,
+    command_table_buf: PhysRangeMapping,
+    phys_region_descriptors: Vec<PhysRegionDescriptor>,
+    requester: Option<Requester>,
+}
+
+impl ActiveCommand {
+    fn new(
+        command_table_buf: PhysRangeMapping,
+        command_slot: usize,
+        command_type: CommandOpcode,
+        queued: bool,
+        command_data: CommandData,
+        requester: Option<Requester>,
+    ) -> Self {
+        Self {
+            command_slot,
+            command_data,
+            command_type,
+            queued,
+            command_table_buf,
+            phys_region_descriptors: vec![],
+            requester,
+        }
+    }
+
+    /// Describes how a read, write or flush finished, if anyone asked for it
+    fn take_completion(&mut self, status: BlockDeviceStatus) -> Option<Completion> {
+        let requester = self.requester.take()?;
+        match &self.command_data {
+            CommandData::ReadDmaExt { sector_range } => {
+                let sector_range = sector_range.clone();
+                println!("Read DMA ext completed from drive");
+                if status != BlockDeviceStatus::Success {
+                    return Some(Completion::Read {
+                        requester,
+                        sector_range,
+                        status,
+                        data: vec![],
+                    });
+                }
+                // Gather the sector data from each DMA region, in order
+                let mut sector_data = Vec::with_capacity(sector_range.size());
+                for prd in self.phys_region_descriptors.iter() {
+                    let phys_region = &prd.phys_region_buf;
+                    let slice = core::ptr::slice_from_raw_parts(
+                        phys_region.addr.virt as *const u8,
+                        phys_region.size,
+                    );
+                    let region_data = unsafe { &*(slice as *const [u8]) };
+                    let remaining = sector_range.size() - sector_data.len();
+                    sector_data
+                        .extend_from_slice(&region_data[..cmp::min(remaining, region_data.len())]);
+                }
+                Some(Completion::Read {
+                    requester,
+                    sector_range,
+                    status,
+                    data: sector_data,
+                })
+            }
+            CommandData::WriteDmaExt { sector_range, .. } => {
+                println!("Write DMA ext completed from drive");
+                Some(Completion::Write {
+                    requester,
+                    sector_range: sector_range.clone(),
+                    status,
+                })
+            }
+            CommandData::FlushCacheExt => {
+                println!("Flush cache ext completed from drive");
+                Some(Completion::Flush { requester, status })
+            }
+            CommandData::IdentifyDevice => panic!("IDENTIFY DEVICE is handled by the port"),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct PhysRegionDescriptor {
+    phys_region_buf: PhysRangeMapping,
+}
+
+impl PhysRegionDescriptor {
+    fn from_virt_addr(addr: usize, phys_region_buf: PhysRangeMapping) -> Self {
+        let ptr = addr as *mut RawPhysRegionDescriptor;
+        let raw_desc = unsafe { &mut *ptr };
+
+        raw_desc.set_interrupt_on_completion(true);
+
+        raw_desc.set_byte_count(phys_region_buf.size as u32);
+        raw_desc.set_data_base_address(phys_region_buf.addr.phys as u64);
+        println!("Phys region data base: {:016x}", phys_region_buf.addr.phys);
+
+        PhysRegionDescriptor { phys_region_buf }
+    }
+}
+
+/// What IDENTIFY DEVICE reported about the drive
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IdentifiedDrive {
+    pub sector_count: u64,
+    pub model: String,
+    pub serial_number: String,
+}
+
+pub struct AhciPortDescription<R: PortRegisters, D: DmaAllocator> {
+    port_index: u8,
+    registers: R,
+    dma: D,
+    command_list_region: PhysRangeMapping,
+    command_list: &'static mut [AhciCommandHeader],
+    frame_info_struct_recv_region: PhysRangeMapping,
+    // How many command slots the HBA implements
+    command_slot_count: usize,
+    hba_supports_ncq: bool,
+    // How many NCQ commands can be in flight, once IDENTIFY DEVICE reports that both the HBA and
+    // the drive support NCQ
+    ncq_queue_depth: Option<usize>,
+
+    // Command tables and data buffers from finished commands, ready to be handed to the next
+    // commands instead of allocating fresh physical memory for every request
+    free_dma_pages: Vec<PhysRangeMapping>,
+
+    // Commands waiting for a free command slot, in submission order
+    submission_queue: VecDeque<CommandRequest>,
+    active_commands: Vec<ActiveCommand>,
+    // Populated once IDENTIFY DEVICE completes
+    drive: Option<IdentifiedDrive>,
+    // Device info requests that arrived before IDENTIFY DEVICE completed
+    pending_device_info_requests: Vec<Requester>,
+}
+
+impl<R: PortRegisters, D: DmaAllocator> AhciPortDescription<R, D> {
+    pub fn new(
+        port_index: u8,
+        registers: R,
+        dma: D,
+        command_slot_count: usize,
+        hba_supports_ncq: bool,
+    ) -> Self {
+        // Initialize the device
+        //
+        // First, print out some debug info
+        println!("\tPort {}", port_index);
+        println!(
+            "\t\tCommand list base 0x{:08x}:{:08x}",
+            registers.read(PortRegister::CommandListBaseUpper),
+            registers.read(PortRegister::CommandListBase)
+        );
+        println!(
+            "\t\tFIS base 0x{:08x}:{:08x}",
+            registers.read(PortRegister::FrameInfoStructBaseUpper),
+            registers.read(PortRegister::FrameInfoStructBase)
+        );
+        println!(
+            "\t\tSignature: 0x{:08x}",
+            registers.read(PortRegister::Signature)
+        );
+        println!(
+            "\t\tActive: 0x{:08x}",
+            registers.read(PortRegister::SataActive)
+        );
+        println!(
+            "\t\tStatus: 0x{:08x}",
+            registers.read(PortRegister::SataStatus)
+        );
+
+        println!(
+            "\t\tCommand and start bit? {}",
+            registers
+                .read(PortRegister::CommandAndStatus)
+                .view_bits::<Lsb0>()[0]
+        );
+
+        // Allocate the command-list and FIS-receive buffers which we'll give to the device
+        let command_list_region = dma.alloc(0x1000);
+        let frame_info_struct_recv_region = dma.alloc(0x1000);
+        // TODO(PT): Support 64-bit physical addresses here
+        // Need to use the CommandListBaseUpper / FISBaseUpper fields
+        // And detect if the HW supports 64 bit addressing too
+        // For now, error out if axle gives us a too-high phys addr
+        assert!(
+            command_list_region.addr.phys < (u32::MAX as usize),
+            "Kernel handed out a too-big address",
+        );
+        assert!(
+            frame_info_struct_recv_region.addr.phys < (u32::MAX as usize),
+            "Kernel handed out a too-big address",
+        );
+
+        let command_list = {
+            let ptr = command_list_region.addr.virt as *mut AhciCommandHeader;
+            let slice = core::ptr::slice_from_raw_parts_mut(ptr, 32);
+            unsafe { &mut *slice }
+        };
+
+        let mut this = Self {
+            port_index,
+            registers,
+            dma,
+            command_list_region,
+            command_list,
+            frame_info_struct_recv_region,
+            command_slot_count,
+            hba_supports_ncq,
+            ncq_queue_depth: None,
+            free_dma_pages: vec![],
+            submission_queue: VecDeque::new(),
+            active_commands: vec![],
+            drive: None,
+            pending_device_info_requests: vec![],
+        };
+
+        // Now, initialize the command-list and FIS-receive buffers
+        // First, request the HBA stop processing the command-list, since we're going to modify it
+        this.stop_processing_command_list();
+        // Also, request the HBA stops delivering FIS
+        this.stop_receiving_frame_info_structs();
+
+        // Wait for any ongoing access to the command list to complete
+        this.wait_until_command_list_use_completes();
+        // From the spec on FIS Receive enable bit:
+        // > If software wishes to move the base, this bit must first be cleared,
+        // > and software must wait for the FR bit in this register to be cleared.
+        this.wait_until_fis_receive_completes();
+
+        let command_list_base = this.command_list_region.addr.phys as u32;
+        let frame_info_struct_base = this.frame_info_struct_recv_region.addr.phys as u32;
+        this.registers
+            .write(PortRegister::CommandListBase, command_list_base);
+        this.registers.write(PortRegister::CommandListBaseUpper, 0);
+        this.registers
+            .write(PortRegister::FrameInfoStructBase, frame_info_struct_base);
+        this.registers
+            .write(PortRegister::FrameInfoStructBaseUpper, 0);
+
+        println!(
+            "FIS at virt 0x{:16x}",
+            this.frame_info_struct_recv_region.addr.virt
+        );
+
+        println!(
+            "Port interrupt status: {}",
+            this.registers.read(PortRegister::InterruptStatus)
+        );
+
+        // Clear pending interrupts with a write-clear
+        this.registers
+            .write(PortRegister::InterruptStatus, 0xffffffff);
+
+        // Wait for any ongoing access to the command list to complete
+        this.wait_until_command_list_use_completes();
+
+        // Ready to receive FIS
+        // 10.3.2: FIS enable must be before CL enable
+        this.start_receiving_frame_info_structs();
+        this.start_processing_command_list();
+
+        println!(
+            "End of port init interrupt status: {}",
+            this.registers.read(PortRegister::InterruptStatus)
+        );
+
+        this
+    }
+
+    pub fn enable_interrupts(&mut self) {
+        self.registers
+            .write(PortRegister::InterruptEnable, 0xffffffff);
+    }
+
+    pub fn drive(&self) -> Option<&IdentifiedDrive> {
+        self.drive.as_ref()
+    }
+
+    /// Slots whose command hasn't completed yet. A queued command stays set in PxSACT until
+    /// the drive reports its completion, even after the HBA clears its PxCI bit.
+    fn outstanding_command_slots(&self) -> u32 {
+        self.registers.read(PortRegister::CommandIssue)
+            | self.registers.read(PortRegister::SataActive)
+    }
+
+    fn find_free_command_slot(&self) -> Option<usize> {
+        // A slot stays reserved until its completion has been processed, even if the HBA has
+        // already cleared its bits
+        let outstanding = self.outstanding_command_slots();
+        (0..self.command_slot_count).find(|slot| {
+            !outstanding.view_bits::<Lsb0>()[*slot]
+                && !self
+                    .active_commands
+                    .iter()
+                    .any(|active_cmd| active_cmd.command_slot == *slot)
+        })
+    }
+
+    /// Queues a command, which is issued as soon as it can run
+    pub fn submit(&mut self, cmd_request: CommandRequest) {
+        self.submission_queue.push_back(cmd_request);
+        self.issue_queued_commands();
+    }
+
+    /// Answers straight away if the drive has been identified, and otherwise once it has
+    pub fn request_device_info(&mut self, requester: Requester) -> Option<Completion> {
+        match &self.drive {
+            Some(drive) => Some(Completion::DeviceInfo {
+                requester,
+                drive: drive.clone(),
+            }),
+            None => {
+                self.pending_device_info_requests.push(requester);
+                None
+            }
+        }
+    }
+
+    /// Whether the command should be issued as an NCQ command
+    fn should_queue(&self, cmd_data: &CommandData) -> bool {
+        self.ncq_queue_depth.is_some() && cmd_data.is_data_transfer()
+    }
+
+    /// Issues commands from the front of the submission queue until the command slots are all
+    /// busy, or the next command has to wait for in-flight commands to complete.
+    /// Commands are issued in submission order, so a flush still covers every write that was
+    /// submitted before it.
+    fn issue_queued_commands(&mut self) {
+        while let Some(cmd_request) = self.submission_queue.front() {
+            let queued = self.should_queue(&cmd_request.cmd_data);
+            // NCQ and non-NCQ commands can't be outstanding at the same time
+            let blocked = self.active_commands.iter().any(|active_cmd| {
+                active_cmd.queued != queued
+                    || cmd_request
+                        .cmd_data
+                        .conflicts_with(&active_cmd.command_data)
+            });
+            if blocked {
+                return;
+            }
+            if let Some(queue_depth) = self.ncq_queue_depth {
+                if queued && self.active_commands.len() >= queue_depth {
+                    return;
+                }
+            }
+            let command_slot = match self.find_free_command_slot() {
+                Some(command_slot) => command_slot,
+                None => return,
+            };
+            let cmd_request = self.submission_queue.pop_front().unwrap();
+            self.send_command_req(command_slot, &cmd_request, queued);
+        }
+    }
+
+    fn send_command_req(
+        &mut self,
+        command_slot: usize,
+        cmd_request: &CommandRequest,
+        queued: bool,
+    ) {
+        let opcode = match (cmd_request.opcode, queued) {
+            (CommandOpcode::ReadDmaExt, true) => CommandOpcode::ReadFpdmaQueued,
+            (CommandOpcode::WriteDmaExt, true) => CommandOpcode::WriteFpdmaQueued,
+            (opcode, _) => opcode,
+        };
+        let command_table_buf = self.alloc_dma_page();
+        let command_header = &mut self.command_list[command_slot];
+        let mut active_command = ActiveCommand::new(
+            command_table_buf,
+            command_slot,
+            opcode,
+            queued,
+            cmd_request.cmd_data.clone(),
+            cmd_request.requester.clone(),
+        );
+        command_header
+            .set_command_table_desc_base(active_command.command_table_buf.addr.phys as u64);
+
+        // Configure the command header
+        let word0 = &mut command_header.word0;
+
+        // Command FIS length (in sizeof(u32) increments)
+        assert_eq!(mem::size_of::<HostToDeviceFIS>() / mem::size_of::<u32>(), 5);
+        word0.set_command_fis_len(
+            (mem::size_of::<HostToDeviceFIS>() / mem::size_of::<u32>()) as u32,
+        );
+
+        match opcode {
+            CommandOpcode::WriteDmaExt | CommandOpcode::WriteFpdmaQueued => word0.set_write(true),
+            _ => word0.set_write(false),
+        }
+
+        word0.set_clear_busy_upon_r_ok(true);
+
+        // Set up the DMA regions that will send or receive the data via the HBA
+        let required_data_buffer_size = match &cmd_request.cmd_data {
+            CommandData::IdentifyDevice => 0x1000,
+            CommandData::ReadDmaExt { sector_range } => sector_range.size(),
+            CommandData::WriteDmaExt { sector_range, .. } => sector_range.size(),
+            // Non-data command
+            CommandData::FlushCacheExt => 0,
+        };
+
+        let phys_region_size = DMA_PAGE_SIZE;
+        // Round up so that any data gets a region
+        let phys_region_count = required_data_buffer_size.div_ceil(phys_region_size);
+        println!("Phys region count {phys_region_count}");
+        word0.set_phys_region_desc_table_len(phys_region_count as u32);
+
+        // Allocate each DMA region
+        // The struct describing each of these is placed a fixed location after the command header,
+        // and is initialised in-place
+        for region_idx in 0..phys_region_count {
+            let phys_region_descriptor = PhysRegionDescriptor::from_virt_addr(
+                active_command.command_table_buf.addr.virt
+                    + 0x80
+                    + (mem::size_of::<RawPhysRegionDescriptor>() * region_idx),
+                self.alloc_dma_page(),
+            );
+
+            // If this is a write command, fill the region with the data we've been asked to write to disk
+            if let CommandData::WriteDmaExt {
+                sector_range: _,
+                data: write_data,
+            } = &cmd_request.cmd_data
+            {
+                let prd_data_buf = {
+                    let region_size = phys_region_descriptor.phys_region_buf.size;
+                    let region_base = phys_region_descriptor.phys_region_buf.addr.virt;
+                    let slice =
+                        core::ptr::slice_from_raw_parts(region_base as *mut u8, region_size);
+                    unsafe { &mut *(slice as *mut [u8]) }
+                };
+                println!("Filling PRD {region_idx}");
+                let offset_into_write_data = phys_region_size * region_idx;
+                for (place, data) in prd_data_buf
+                    .iter_mut()
+                    .zip(write_data.iter().skip(offset_into_write_data))
+                {
+                    *place = *data
+                }
+            }
+
+            active_command
+                .phys_region_descriptors
+                .push(phys_region_descriptor);
+        }
+
+        // Set up the FIS containing the command for the drive
+        // TODO(PT) Can this use BitArray::new() instead of unsafe?
+        let h2d_fis = HostToDeviceFIS::from_virt_addr(active_command.command_table_buf.addr.virt);
+        h2d_fis.set_command(active_command.command_type);
+        h2d_fis.set_is_command(true);
+
+        if let Some(sector_range) = cmd_request.cmd_data.sector_range() {
+            h2d_fis.set_start_sector(sector_range.start_sector);
+            let sector_count = sector_range.sector_count.try_into().unwrap();
+            if queued {
+                // The drive reports completions by tag, which we keep identical to the slot
+                h2d_fis.set_queued_sector_count(sector_count);
+                h2d_fis.set_queued_tag(command_slot as u8);
+            } else {
+                h2d_fis.set_sector_count(sector_count);
+            }
+            println!("sector range {sector_range:?}");
+        }
+
+        self.active_commands.push(active_command);
+
+        // Issue the command
+        // Queued (NCQ) commands must also be marked in PxSACT before they're issued.
+        // Both registers are write-1-to-set, so this leaves the other slots alone.
+        println!("Issuing command in slot {command_slot} (queued: {queued})...");
+        if queued {
+            self.registers
+                .write(PortRegister::SataActive, 1 << command_slot);
+        }
+        self.registers
+            .write(PortRegister::CommandIssue, 1 << command_slot);
+    }
+
+    /// Hands out a zeroed page for a command table or a DMA region, reusing one from a finished
+    /// command if possible
+    fn alloc_dma_page(&mut self) -> PhysRangeMapping {
+        match self.free_dma_pages.pop() {
+            Some(page) => {
+                // Stale FIS fields or PRD entries would otherwise leak into the next command
+                unsafe { core::ptr::write_bytes(page.addr.virt as *mut u8, 0, page.size) };
+                page
+            }
+            None => self.dma.alloc(DMA_PAGE_SIZE),
+        }
+    }
+
+    fn recycle_dma_pages(&mut self, active_cmd: ActiveCommand) {
+        let pages = core::iter::once(active_cmd.command_table_buf).chain(
+            active_cmd
+                .phys_region_descriptors
+                .into_iter()
+                .map(|prd| prd.phys_region_buf),
+        );
+        for page in pages {
+            // Beyond the cap, the page is dropped, which hands it back to the kernel
+            if self.free_dma_pages.len() < MAX_FREE_DMA_PAGES {
+                self.free_dma_pages.push(page);
+            }
+        }
+    }
+
+    fn _set_command_and_status_bit(&mut self, bit_idx: usize, enabled: bool) {
+        let mut command_and_status = self.registers.read(PortRegister::CommandAndStatus);
+        command_and_status
+            .view_bits_mut::<Lsb0>()
+            .set(bit_idx, enabled);
+        self.registers
+            .write(PortRegister::CommandAndStatus, command_and_status);
+    }
+
+    fn _get_command_and_status_bit(&self, bit_idx: usize) -> bool {
+        self.registers
+            .read(PortRegister::CommandAndStatus)
+            .view_bits::<Lsb0>()[bit_idx]
+    }
+
+    fn stop_processing_command_list(&mut self) {
+        self._set_command_and_status_bit(0, false);
+    }
+
+    fn start_processing_command_list(&mut self) {
+        self._set_command_and_status_bit(0, true);
+    }
+
+    fn wait_until_command_list_use_completes(&self) {
+        while self._get_command_and_status_bit(15) {
+            println!("Waiting for Command List Running bit to clear...");
+            //unsafe { libc::usleep(1) };
+        }
+    }
+
+    fn stop_receiving_frame_info_structs(&mut self) {
+        self._set_command_and_status_bit(4, false);
+    }
+
+    fn start_receiving_frame_info_structs(&mut self) {
+        self._set_command_and_status_bit(4, true);
+    }
+
+    fn wait_until_fis_receive_completes(&self) {
+        while self._get_command_and_status_bit(14) {
+            println!("Waiting for FIS Receive Running bit to clear...");
+            //unsafe { libc::usleep(1) };
+        }
+    }
+
+    /// Returns the requests that finished
+    pub fn handle_interrupt(&mut self) -> Vec<Completion> {
+        let port_interrupt_status = self.registers.read(PortRegister::InterruptStatus);
+        println!("Device interrupt status: {:032b}", port_interrupt_status);
+        // Clear the port's interrupts-to-service mask
+        self.registers
+            .write(PortRegister::InterruptStatus, port_interrupt_status);
+
+        // Any error bits set?
+        let error_bits = [04, 23, 24, 26, 27, 28, 29, 30];
+        let had_error = error_bits.iter().any(|error_bit| {
+            *port_interrupt_status
+                .view_bits::<Lsb0>()
+                .get(*error_bit)
+                .unwrap()
+        });
+        if had_error {
+            return self.recover_from_error();
+        }
+
+        // Commands whose slot is no longer outstanding have completed.
+        // NCQ commands can complete in any order, and each one is reported to its own requester.
+        let outstanding = self.outstanding_command_slots();
+        let (completed, still_active): (Vec<ActiveCommand>, Vec<ActiveCommand>) =
+            mem::take(&mut self.active_commands)
+                .into_iter()
+                .partition(|active_cmd| !outstanding.view_bits::<Lsb0>()[active_cmd.command_slot]);
+        self.active_commands = still_active;
+
+        let mut completions = vec![];
+        for mut active_cmd in completed {
+            match active_cmd.command_data {
+                CommandData::IdentifyDevice => {
+                    completions.extend(self.complete_identify(&active_cmd))
+                }
+                _ => completions.extend(active_cmd.take_completion(BlockDeviceStatus::Success)),
+            }
+            self.recycle_dma_pages(active_cmd);
+        }
+
+        // Refill the slots that just freed up
+        self.issue_queued_commands();
+        completions
+    }
+
+    /// The drive stops processing commands after an error, so fail everything that was in
+    /// flight and restart the command engine. An NCQ error aborts every queued command, so
+    /// there's no need to find out which command failed.
+    /// Commands that hadn't been issued yet stay in the submission queue.
+    fn recover_from_error(&mut self) -> Vec<Completion> {
+        println!(
+            "Port {} error, SATA error register: {:08x}",
+            self.port_index,
+            self.registers.read(PortRegister::SataError)
+        );
+        self.stop_processing_command_list();
+        self.wait_until_command_list_use_completes();
+        // Write-clear the error and interrupt registers
+        self.registers.write(PortRegister::SataError, 0xffffffff);
+        let port_interrupt_status = self.registers.read(PortRegister::InterruptStatus);
+        self.registers
+            .write(PortRegister::InterruptStatus, port_interrupt_status);
+        self.start_processing_command_list();
+
+        let mut completions = vec![];
+        for mut active_cmd in mem::take(&mut self.active_commands) {
+            match active_cmd.command_data {
+                CommandData::IdentifyDevice => {
+                    println!("IDENTIFY DEVICE failed on port {}", self.port_index)
+                }
+                _ => completions.extend(active_cmd.take_completion(BlockDeviceStatus::IoError)),
+            }
+            self.recycle_dma_pages(active_cmd);
+        }
+        self.issue_queued_commands();
+        completions
+    }
+
+    fn complete_identify(&mut self, active_cmd: &ActiveCommand) -> Vec<Completion> {
+        println!("Interpreting results of IDENTIFY DEVICE...");
+        let identify_block = {
+            let region = &active_cmd.phys_region_descriptors[0].phys_region_buf;
+            let ptr = region.addr.virt;
+            unsafe { &*(ptr as *const IdentifyDeviceData) }
+        };
+        let drive = IdentifiedDrive {
+            sector_count: identify_block.user_addressable_sectors(),
+            model: identify_block.model_number(),
+            serial_number: identify_block.serial_number(),
+        };
+        println!("Identified drive: {drive:?}");
+        self.drive = Some(drive.clone());
+
+        // Only use NCQ if both ends support it
+        self.ncq_queue_depth = match (self.hba_supports_ncq, identify_block.ncq_queue_depth()) {
+            (true, Some(drive_queue_depth)) => {
+                Some(cmp::min(drive_queue_depth, self.command_slot_count))
+            }
+            _ => None,
+        };
+        println!(
+            "Port {} NCQ queue depth: {:?}",
+            self.port_index, self.ncq_queue_depth
+        );
+
+        mem::take(&mut self.pending_device_info_requests)
+            .into_iter()
+            .map(|requester| Completion::DeviceInfo {
+                requester,
+                drive: drive.clone(),
+            })
+            .collect()
+    }
+
+    /// Checks a read or write against the drive's bounds, and the size of a command table
+    pub fn validate_sector_range(
+        &self,
+        device: u32,
+        start_sector: u64,
+        sector_count: u64,
+    ) -> BlockDeviceStatus {
+        if device != WHOLE_DISK_DEVICE {
+            return BlockDeviceStatus::NoSuchDevice;
+        }
+        if sector_count == 0 || sector_count > MAX_SECTORS_PER_REQUEST {
+            return BlockDeviceStatus::InvalidRequest;
+        }
+        match &self.drive {
+            Some(drive) => match start_sector.checked_add(sector_count) {
+                Some(end_sector) if end_sector <= drive.sector_count => BlockDeviceStatus::Success,
+                _ => BlockDeviceStatus::OutOfRange,
+            },
+            // Without IDENTIFY data the bounds are unknown, so let the drive reject it
+            None => BlockDeviceStatus::Success,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    use sata_driver_messages::{BlockDeviceStatus, MAX_SECTORS_PER_REQUEST};
+
+    use crate::port::{
+        AhciPortDescription, CommandRequest, Completion, DiskSectorRange, IdentifiedDrive,
+        Requester,
+    };
+    use crate::sata_definitions::CommandOpcode;
+    use crate::sim::{SimulatedHba, SIM_MODEL, SIM_SECTOR_SIZE, SIM_SERIAL_NUMBER};
+
+    const SECTOR_COUNT: usize = 2048;
+
+    type SimulatedPort = AhciPortDescription<SimulatedHba, SimulatedHba>;
+
+    fn run_interrupts(port: &mut SimulatedPort, hba: &SimulatedHba) -> Vec<Completion> {
+        let mut completions = vec![];
+        while hba.interrupt_pending() {
+            completions.extend(port.handle_interrupt());
+        }
+        completions
+    }
+
+    /// Sets up a port and lets IDENTIFY DEVICE complete
+    fn identified_port(ncq_queue_depth: Option<usize>) -> (SimulatedPort, SimulatedHba) {
+        let hba = SimulatedHba::new(SECTOR_COUNT, ncq_queue_depth);
+        let mut port = AhciPortDescription::new(0, hba.clone(), hba.clone(), 32, true);
+        port.submit(CommandRequest::new_identify_command());
+        assert!(run_interrupts(&mut port, &hba).is_empty());
+        (port, hba)
+    }
+
+    fn read(id: u32, start_sector: usize, sector_count: usize) -> CommandRequest {
+        CommandRequest::new_read_command(
+            DiskSectorRange::new(start_sector, sector_count),
+            Requester::new("test", id),
+        )
+    }
+
+    fn write(id: u32, start_sector: usize, data: &[u8]) -> CommandRequest {
+        CommandRequest::new_write_command(
+            DiskSectorRange::new(start_sector, data.len() / SIM_SECTOR_SIZE),
+            data,
+            Requester::new("test", id),
+        )
+    }
+
+    fn completion_ids(completions: &[Completion]) -> Vec<u32> {
+        completions
+            .iter()
+            .map(|completion| match completion {
+                Completion::Read { requester, .. }
+                | Completion::Write { requester, .. }
+                | Completion::Flush { requester, .. }
+                | Completion::DeviceInfo { requester, .. } => requester.request_id,
+            })
+            .collect()
+    }
+
+    fn pattern(sector_count: usize, seed: u8) -> Vec<u8> {
+        (0..sector_count * SIM_SECTOR_SIZE)
+            .map(|i| (i as u8).wrapping_mul(7).wrapping_add(seed))
+            .collect()
+    }
+
+    #[test]
+    fn test_identify() {
+        let hba = SimulatedHba::new(SECTOR_COUNT, None);
+        let mut port = AhciPortDescription::new(0, hba.clone(), hba.clone(), 32, true);
+        // Device info requests wait for IDENTIFY DEVICE
+        assert!(port
+            .request_device_info(Requester::new("early", 1))
+            .is_none());
+        port.submit(CommandRequest::new_identify_command());
+
+        let expected_drive = IdentifiedDrive {
+            sector_count: SECTOR_COUNT as u64,
+            model: SIM_MODEL.into(),
+            serial_number: SIM_SERIAL_NUMBER.into(),
+        };
+        let completions = run_interrupts(&mut port, &hba);
+        match &completions[..] {
+            [Completion::DeviceInfo { requester, drive }] => {
+                assert_eq!(requester, &Requester::new("early", 1));
+                assert_eq!(drive, &expected_drive);
+            }
+            _ => panic!("Unexpected completions {completions:?}"),
+        }
+        assert_eq!(port.drive(), Some(&expected_drive));
+        assert!(matches!(
+            port.request_device_info(Requester::new("late", 2)),
+            Some(Completion::DeviceInfo { .. })
+        ));
+        assert_eq!(
+            port.validate_sector_range(0, SECTOR_COUNT as u64 - 1, 2),
+            BlockDeviceStatus::OutOfRange
+        );
+    }
+
+    #[test]
+    fn test_write_then_read() {
+        let (mut port, hba) = identified_port(None);
+        // Spans several PRDs, with a partially-filled final region
+        let data = pattern(17, 3);
+        port.submit(write(1, 100, &data));
+        let completions = run_interrupts(&mut port, &hba);
+        assert!(matches!(
+            &completions[..],
+            [Completion::Write {
+                status: BlockDeviceStatus::Success,
+                ..
+            }]
+        ));
+        assert_eq!(hba.sectors(100, 17), data);
+
+        hba.write_sectors(200, &pattern(2, 9));
+        port.submit(read(2, 200, 2));
+        match &run_interrupts(&mut port, &hba)[..] {
+            [Completion::Read {
+                requester,
+                sector_range,
+                status: BlockDeviceStatus::Success,
+                data,
+            }] => {
+                assert_eq!(requester.request_id, 2);
+                assert_eq!(sector_range, &DiskSectorRange::new(200, 2));
+                assert_eq!(data, &pattern(2, 9));
+            }
+            completions => panic!("Unexpected completions {completions:?}"),
+        }
+        assert_eq!(
+            hba.issued_opcodes()[1..],
+            [
+                CommandOpcode::WriteDmaExt as u8,
+                CommandOpcode::ReadDmaExt as u8
+            ]
+        );
+    }
+
+    #[test]
+    fn test_error_fails_in_flight_commands() {
+        let (mut port, hba) = identified_port(None);
+        hba.fail_sector(Some(50));
+        hba.set_defer_completions(true);
+        port.submit(read(1, 40, 8));
+        port.submit(read(2, 48, 8));
+        // Both reads run at once, and the failure aborts everything in flight
+        assert_eq!(hba.deferred_slots().len(), 2);
+        hba.complete_command(hba.deferred_slots()[1]);
+        let completions = run_interrupts(&mut port, &hba);
+        assert_eq!(completion_ids(&completions), [1, 2]);
+        for completion in completions.iter() {
+            assert!(matches!(
+                completion,
+                Completion::Read {
+                    status: BlockDeviceStatus::IoError,
+                    ..
+                }
+            ));
+        }
+
+        // The port recovers and serves later requests
+        hba.fail_sector(None);
+        hba.set_defer_completions(false);
+        port.submit(read(3, 48, 8));
+        assert!(matches!(
+            &run_interrupts(&mut port, &hba)[..],
+            [Completion::Read {
+                status: BlockDeviceStatus::Success,
+                ..
+            }]
+        ));
+    }
+
+    #[test]
+    fn test_ncq_completes_out_of_order() {
+        let (mut port, hba) = identified_port(Some(32));
+        hba.set_defer_completions(true);
+        for i in 0..40 {
+            port.submit(read(i, i as usize * 4, 4));
+        }
+        // Every slot is busy, and the rest wait their turn
+        let slots = hba.deferred_slots();
+        assert_eq!(slots.len(), 32);
+        assert!(hba.issued_opcodes()[1..]
+            .iter()
+            .all(|opcode| *opcode == CommandOpcode::ReadFpdmaQueued as u8));
+
+        for slot in slots.iter().rev().take(4) {
+            hba.complete_command(*slot);
+        }
+        let completions = run_interrupts(&mut port, &hba);
+        let mut ids = completion_ids(&completions);
+        ids.sort();
+        assert_eq!(ids, [28, 29, 30, 31]);
+        // The freed slots were refilled
+        assert_eq!(hba.deferred_slots().len(), 32);
+
+        while let Some(slot) = hba.deferred_slots().pop() {
+            hba.complete_command(slot);
+            run_interrupts(&mut port, &hba);
+        }
+        assert_eq!(hba.issued_opcodes().len(), 41);
+    }
+
+    #[test]
+    fn test_ordering_constraints() {
+        let (mut port, hba) = identified_port(Some(32));
+        hba.set_defer_completions(true);
+        let data = pattern(1, 1);
+        port.submit(write(1, 10, &data));
+        port.submit(write(2, 20, &data));
+        port.submit(CommandRequest::new_flush_command(Requester::new("test", 3)));
+        port.submit(read(4, 10, 1));
+        // The flush waits for the writes, and the read waits for the flush
+        let writes = hba.deferred_slots();
+        assert_eq!(writes.len(), 2);
+        for slot in writes {
+            hba.complete_command(slot);
+        }
+        assert_eq!(completion_ids(&run_interrupts(&mut port, &hba)), [1, 2]);
+        assert_eq!(hba.deferred_slots().len(), 1);
+        assert_eq!(
+            hba.issued_opcodes().last(),
+            Some(&(CommandOpcode::FlushCacheExt as u8))
+        );
+
+        hba.complete_command(hba.deferred_slots()[0]);
+        assert_eq!(completion_ids(&run_interrupts(&mut port, &hba)), [3]);
+        assert_eq!(hba.flush_count(), 1);
+        hba.complete_command(hba.deferred_slots()[0]);
+        assert_eq!(completion_ids(&run_interrupts(&mut port, &hba)), [4]);
+
+        // A read of sectors that are being written waits for the write
+        port.submit(read(5, 40, 1));
+        port.submit(write(6, 30, &data));
+        port.submit(read(7, 30, 1));
+        let slots = hba.deferred_slots();
+        assert_eq!(slots.len(), 2);
+        hba.complete_command(slots[1]);
+        assert_eq!(completion_ids(&run_interrupts(&mut port, &hba)), [6]);
+        // The read is issued once the write is on disk
+        let slots = hba.deferred_slots();
+        assert_eq!(slots.len(), 2);
+        for slot in slots.iter().rev() {
+            hba.complete_command(*slot);
+        }
+        let completions = run_interrupts(&mut port, &hba);
+        assert_eq!(completion_ids(&completions), [5, 7]);
+        match &completions[1] {
+            Completion::Read {
+                data: read_data, ..
+            } => assert_eq!(read_data, &data),
+            completion => panic!("Unexpected completion {completion:?}"),
+        }
+    }
+
+    #[test]
+    fn test_dma_pages_are_reused() {
+        let (mut port, hba) = identified_port(Some(32));
+        let data = pattern(MAX_SECTORS_PER_REQUEST as usize, 5);
+        hba.write_sectors(0, &data);
+        let read_full_request = |port: &mut SimulatedPort| {
+            port.submit(read(1, 0, MAX_SECTORS_PER_REQUEST as usize));
+            match &run_interrupts(port, &hba)[..] {
+                [Completion::Read {
+                    data: read_data, ..
+                }] => assert_eq!(read_data, &data),
+                completions => panic!("Unexpected completions {completions:?}"),
+            }
+        };
+        read_full_request(&mut port);
+        let allocated = hba.dma_bytes_allocated();
+        // Later requests are served from the pages of the first one
+        for _ in 0..8 {
+            read_full_request(&mut port);
+        }
+        assert_eq!(hba.dma_bytes_allocated(), allocated);
+    }
+}
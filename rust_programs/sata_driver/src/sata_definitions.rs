@@ -1,10 +1,8 @@
 use core::ptr::write_volatile;
 
-use alloc::vec;
-use alloc::{format, string::String, vec::Vec};
-use axle_rt::{
-    core_commands::{amc_alloc_physical_range, PhysRangeMapping},
-    println,
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
 };
 use bitvec::prelude::*;
 
@@ -27,6 +25,16 @@ pub struct AhciGenericHostControlBlock {
 }
 
 impl AhciGenericHostControlBlock {
+    /// CAP.NCS is zero-based
+    pub fn command_slot_count(&self) -> usize {
+        (((self.host_capabilities >> 8) & 0x1f) + 1) as usize
+    }
+
+    /// CAP.SNCQ
+    pub fn supports_native_command_queuing(&self) -> bool {
+        self.host_capabilities & (1 << 30) != 0
+    }
+
     pub fn clear_interrupt_status_mask(&mut self) {
         // Clear the top level ports-with-interrupts-to-service mask
         unsafe {
@@ -59,15 +67,6 @@ pub struct AhciPortBlock {
     // Reserved & vendor specific fields here
 }
 
-impl AhciPortBlock {
-    pub fn clear_interrupt_status_mask(&mut self) {
-        // Clear the port's interrupts-to-service mask
-        unsafe {
-            write_volatile(&raw mut (*self).interrupt_status, self.interrupt_status);
-        }
-    }
-}
-
 pub type AhciCommandHeaderWord0Bits2 = BitArray<u32, Lsb0>;
 
 #[derive(Debug)]
@@ -179,6 +178,9 @@ impl AhciCommandHeader {
 pub enum CommandOpcode {
     ReadDmaExt = 0x25,
     WriteDmaExt = 0x35,
+    ReadFpdmaQueued = 0x60,
+    WriteFpdmaQueued = 0x61,
+    FlushCacheExt = 0xEA,
     IdentifyDevice = 0xEC,
 }
 
@@ -227,10 +229,22 @@ impl HostToDeviceFIS {
 
     pub fn set_start_sector(&mut self, start_sector: usize) {
         // Low 24 bits
-        self.0[32..54].store::<u32>(start_sector as u32);
+        self.0[32..56].store::<u32>((start_sector & 0xffffff) as u32);
         // High 24 bits
         self.0[64..88].store::<u32>((start_sector >> 24) as u32);
     }
+
+    /// FPDMA QUEUED commands carry their sector count in the features registers
+    pub fn set_queued_sector_count(&mut self, sector_count: u16) {
+        self.0[24..32].store::<u8>((sector_count & 0xff) as u8);
+        self.0[88..96].store::<u8>(((sector_count >> 8) & 0xff) as u8);
+    }
+
+    /// FPDMA QUEUED commands carry their tag in bits 7:3 of the sector count register.
+    /// The tag is the command slot.
+    pub fn set_queued_tag(&mut self, tag: u8) {
+        self.0[99..104].store::<u8>(tag);
+    }
 }
 
 #[repr(C)]
@@ -282,19 +296,52 @@ impl RawPhysRegionDescriptor {
 pub struct IdentifyDeviceData(BitArray<[u16; 256], Lsb0>);
 
 impl IdentifyDeviceData {
+    /// ATA strings store two characters per word, with the first in the high byte,
+    /// and are padded with trailing spaces
+    fn ata_string(&self, words: core::ops::Range<usize>) -> String {
+        let bytes: Vec<u8> = self.0.data[words]
+            .iter()
+            .flat_map(|word| word.to_be_bytes())
+            .collect();
+        String::from_utf8_lossy(&bytes).trim().to_string()
+    }
+
     pub fn serial_number(&self) -> String {
-        // Words 10-19, LSB
-        let mut out = vec![];
-        'outer: for word in self.0.data[10..19].iter() {
-            let bytes = word.to_be_bytes();
-            for byte in bytes {
-                // Stop parsing once we hit a space character
-                if byte == 0x20 {
-                    break 'outer;
-                }
-                out.push(byte as char);
-            }
+        // Words 10-19
+        self.ata_string(10..20)
+    }
+
+    pub fn model_number(&self) -> String {
+        // Words 27-46
+        self.ata_string(27..47)
+    }
+
+    /// The number of commands the drive can queue, if it supports NCQ
+    pub fn ncq_queue_depth(&self) -> Option<usize> {
+        // Word 76, bit 8
+        match self.0.data[76] & (1 << 8) {
+            0 => None,
+            // Word 75, bits 4:0 are zero-based
+            _ => Some(((self.0.data[75] & 0x1f) + 1) as usize),
+        }
+    }
+
+    pub fn supports_lba48(&self) -> bool {
+        // Word 83, bit 10
+        self.0.data[83] & (1 << 10) != 0
+    }
+
+    pub fn user_addressable_sectors(&self) -> u64 {
+        let words = &self.0.data;
+        if self.supports_lba48() {
+            // Words 100-103
+            (words[100] as u64)
+                | ((words[101] as u64) << 16)
+                | ((words[102] as u64) << 32)
+                | ((words[103] as u64) << 48)
+        } else {
+            // Words 60-61
+            (words[60] as u64) | ((words[61] as u64) << 16)
         }
-        out.into_iter().collect()
     }
 }
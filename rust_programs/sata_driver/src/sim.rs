@@ -0,0 +1,412 @@
+//! A model of an AHCI HBA with a single port and an in-memory drive, for testing the port logic
+//! on the host.
+//!
+//! DMA buffers are carved out of an arena that stands in for physical memory, so the model can
+//! follow the command list, command tables and PRDTs that the driver hands it. Commands run as
+//! soon as they're issued, unless completions are deferred so that a test can finish queued
+//! commands in whatever order it likes.
+
+use alloc::{
+    alloc::{alloc_zeroed, dealloc, Layout},
+    rc::Rc,
+    vec,
+    vec::Vec,
+};
+use core::cell::RefCell;
+
+use axle_rt::core_commands::{PhysRangeMapping, PhysVirtPair};
+
+use crate::hba::{DmaAllocator, PortRegister, PortRegisters};
+use crate::sata_definitions::CommandOpcode;
+
+pub const SIM_SECTOR_SIZE: usize = 512;
+pub const SIM_MODEL: &str = "axle simulated disk";
+pub const SIM_SERIAL_NUMBER: &str = "SIM-0001";
+
+// Where the arena appears in the simulated physical address space
+const ARENA_PHYS_BASE: usize = 0x100000;
+const ARENA_SIZE: usize = 16 * 1024 * 1024;
+
+// PxIS bits
+const INTERRUPT_DEVICE_TO_HOST_REGISTER_FIS: u32 = 1 << 0;
+const INTERRUPT_SET_DEVICE_BITS_FIS: u32 = 1 << 3;
+const INTERRUPT_TASK_FILE_ERROR: u32 = 1 << 30;
+
+// PxCMD bits
+const COMMAND_START: u32 = 1 << 0;
+const COMMAND_FIS_RECEIVE_ENABLE: u32 = 1 << 4;
+const COMMAND_FIS_RECEIVE_RUNNING: u32 = 1 << 14;
+const COMMAND_LIST_RUNNING: u32 = 1 << 15;
+
+/// A command the model has read out of the command list
+#[derive(Debug, Clone, Copy)]
+struct SimCommand {
+    opcode: u8,
+    start_sector: u64,
+    sector_count: u64,
+    command_table: usize,
+    prd_count: usize,
+    queued: bool,
+}
+
+struct HbaState {
+    registers: [u32; 0x40 / 4],
+    arena: *mut u8,
+    arena_used: usize,
+    disk: Vec<u8>,
+    ncq_queue_depth: Option<usize>,
+    defer_completions: bool,
+    // Issued commands that haven't run yet, by slot
+    deferred: Vec<(usize, SimCommand)>,
+    failing_sector: Option<u64>,
+    issued_opcodes: Vec<u8>,
+    flush_count: usize,
+}
+
+impl HbaState {
+    fn arena_layout() -> Layout {
+        Layout::from_size_align(ARENA_SIZE, 0x1000).unwrap()
+    }
+
+    fn register(&mut self, register: PortRegister) -> &mut u32 {
+        &mut self.registers[register as usize / 4]
+    }
+
+    /// Returns the arena memory backing a range of simulated physical memory
+    fn phys_slice(&self, phys_addr: usize, len: usize) -> &'static mut [u8] {
+        assert!(
+            phys_addr >= ARENA_PHYS_BASE && phys_addr + len <= ARENA_PHYS_BASE + self.arena_used,
+            "HBA accessed unallocated memory at {phys_addr:#x}",
+        );
+        unsafe { core::slice::from_raw_parts_mut(self.arena.add(phys_addr - ARENA_PHYS_BASE), len) }
+    }
+
+    fn read_u32(&self, phys_addr: usize) -> u32 {
+        u32::from_le_bytes(self.phys_slice(phys_addr, 4).try_into().unwrap())
+    }
+
+    fn read_u64(&self, phys_addr: usize) -> u64 {
+        (self.read_u32(phys_addr) as u64) | ((self.read_u32(phys_addr + 4) as u64) << 32)
+    }
+
+    fn parse_command(&self, slot: usize) -> SimCommand {
+        let command_list_base = self.registers[PortRegister::CommandListBase as usize / 4];
+        let header = command_list_base as usize + (slot * 32);
+        let word0 = self.read_u32(header);
+        let command_table = self.read_u64(header + 8) as usize;
+        let fis = self.phys_slice(command_table, 20);
+        assert_eq!(fis[0], 0x27, "Expected a host-to-device register FIS");
+        assert!(
+            fis[1] & (1 << 7) != 0,
+            "Expected the FIS to carry a command"
+        );
+
+        let opcode = fis[2];
+        let queued = opcode == CommandOpcode::ReadFpdmaQueued as u8
+            || opcode == CommandOpcode::WriteFpdmaQueued as u8;
+        let start_sector = [fis[4], fis[5], fis[6], fis[8], fis[9], fis[10]]
+            .iter()
+            .rev()
+            .fold(0, |acc, byte| (acc << 8) | (*byte as u64));
+        let sector_count = match queued {
+            true => {
+                assert_eq!(fis[12] >> 3, slot as u8, "NCQ tag must match the slot");
+                (fis[3] as u64) | ((fis[11] as u64) << 8)
+            }
+            false => (fis[12] as u64) | ((fis[13] as u64) << 8),
+        };
+        let is_write = word0 & (1 << 6) != 0;
+        assert_eq!(
+            is_write,
+            opcode == CommandOpcode::WriteDmaExt as u8
+                || opcode == CommandOpcode::WriteFpdmaQueued as u8,
+            "Command header direction doesn't match the command",
+        );
+        SimCommand {
+            opcode,
+            start_sector,
+            sector_count,
+            command_table,
+            prd_count: (word0 >> 16) as usize,
+            queued,
+        }
+    }
+
+    /// The DMA regions described by a command's PRDT
+    fn phys_regions(&self, command: &SimCommand) -> Vec<&'static mut [u8]> {
+        (0..command.prd_count)
+            .map(|i| {
+                let prd = command.command_table + 0x80 + (i * 16);
+                let byte_count = (self.read_u32(prd + 12) & 0x3fffff) as usize + 1;
+                self.phys_slice(self.read_u64(prd) as usize, byte_count)
+            })
+            .collect()
+    }
+
+    fn identify_data(&self) -> Vec<u8> {
+        // Two characters per word, with the first in the high byte, padded with spaces
+        fn ata_string(words: &mut [u16], s: &str) {
+            let mut bytes = s.as_bytes().to_vec();
+            bytes.resize(words.len() * 2, b' ');
+            for (word, chars) in words.iter_mut().zip(bytes.chunks(2)) {
+                *word = u16::from_be_bytes([chars[0], chars[1]]);
+            }
+        }
+
+        let mut words = [0_u16; 256];
+        ata_string(&mut words[10..20], SIM_SERIAL_NUMBER);
+        ata_string(&mut words[27..47], SIM_MODEL);
+        if let Some(queue_depth) = self.ncq_queue_depth {
+            words[75] = (queue_depth - 1) as u16;
+            words[76] = 1 << 8;
+        }
+        // LBA48
+        words[83] = 1 << 10;
+        let sector_count = (self.disk.len() / SIM_SECTOR_SIZE) as u64;
+        for (i, word) in words[100..104].iter_mut().enumerate() {
+            *word = (sector_count >> (i * 16)) as u16;
+        }
+        words.iter().flat_map(|word| word.to_le_bytes()).collect()
+    }
+
+    /// Moves data between the disk and the command's DMA regions
+    fn execute(&mut self, command: &SimCommand) -> Result<(), ()> {
+        let disk_range = || {
+            let start = command.start_sector as usize * SIM_SECTOR_SIZE;
+            start..start + (command.sector_count as usize * SIM_SECTOR_SIZE)
+        };
+        if let Some(failing_sector) = self.failing_sector {
+            let end_sector = command.start_sector + command.sector_count;
+            if (command.start_sector..end_sector).contains(&failing_sector) {
+                return Err(());
+            }
+        }
+        let opcode = command.opcode;
+        if opcode == CommandOpcode::IdentifyDevice as u8 {
+            copy_into_regions(&self.identify_data(), self.phys_regions(command));
+        } else if opcode == CommandOpcode::FlushCacheExt as u8 {
+            self.flush_count += 1;
+        } else if opcode == CommandOpcode::ReadDmaExt as u8
+            || opcode == CommandOpcode::ReadFpdmaQueued as u8
+        {
+            let range = disk_range();
+            if range.end > self.disk.len() {
+                return Err(());
+            }
+            copy_into_regions(&self.disk[range], self.phys_regions(command));
+        } else if opcode == CommandOpcode::WriteDmaExt as u8
+            || opcode == CommandOpcode::WriteFpdmaQueued as u8
+        {
+            let range = disk_range();
+            if range.end > self.disk.len() {
+                return Err(());
+            }
+            let mut written = Vec::new();
+            for region in self.phys_regions(command) {
+                written.extend_from_slice(region);
+            }
+            let len = range.len();
+            self.disk[range].copy_from_slice(&written[..len]);
+        } else {
+            return Err(());
+        }
+        Ok(())
+    }
+
+    fn complete(&mut self, slot: usize, command: SimCommand) {
+        match self.execute(&command) {
+            Ok(()) if command.queued => {
+                // The drive reports NCQ completions with a Set Device Bits FIS
+                *self.register(PortRegister::SataActive) &= !(1 << slot);
+                *self.register(PortRegister::InterruptStatus) |= INTERRUPT_SET_DEVICE_BITS_FIS;
+            }
+            Ok(()) => {
+                *self.register(PortRegister::CommandIssue) &= !(1 << slot);
+                *self.register(PortRegister::InterruptStatus) |=
+                    INTERRUPT_DEVICE_TO_HOST_REGISTER_FIS;
+            }
+            Err(()) => {
+                // Abort, with the error bit set in the status register. The command stays
+                // outstanding until software restarts the port.
+                *self.register(PortRegister::TaskFileData) = (0x04 << 8) | 0x41;
+                *self.register(PortRegister::InterruptStatus) |= INTERRUPT_TASK_FILE_ERROR;
+            }
+        }
+    }
+
+    fn issue(&mut self, slot: usize) {
+        let command = self.parse_command(slot);
+        self.issued_opcodes.push(command.opcode);
+        if command.queued {
+            assert!(
+                self.registers[PortRegister::SataActive as usize / 4] & (1 << slot) != 0,
+                "NCQ command issued without its PxSACT bit"
+            );
+            // The drive accepts the command straight away, and completes it later
+            *self.register(PortRegister::CommandIssue) &= !(1 << slot);
+        }
+        match self.defer_completions {
+            true => self.deferred.push((slot, command)),
+            false => self.complete(slot, command),
+        }
+    }
+
+    fn write_command_and_status(&mut self, value: u32) {
+        let mut value = value & !(COMMAND_LIST_RUNNING | COMMAND_FIS_RECEIVE_RUNNING);
+        if value & COMMAND_START != 0 {
+            value |= COMMAND_LIST_RUNNING;
+        } else {
+            // Stopping the command engine clears every outstanding command
+            *self.register(PortRegister::CommandIssue) = 0;
+            *self.register(PortRegister::SataActive) = 0;
+            self.deferred.clear();
+        }
+        if value & COMMAND_FIS_RECEIVE_ENABLE != 0 {
+            value |= COMMAND_FIS_RECEIVE_RUNNING;
+        }
+        *self.register(PortRegister::CommandAndStatus) = value;
+    }
+}
+
+impl Drop for HbaState {
+    fn drop(&mut self) {
+        unsafe { dealloc(self.arena, Self::arena_layout()) };
+    }
+}
+
+fn copy_into_regions(data: &[u8], regions: Vec<&mut [u8]>) {
+    let mut remaining = data;
+    for region in regions {
+        let len = core::cmp::min(region.len(), remaining.len());
+        region[..len].copy_from_slice(&remaining[..len]);
+        remaining = &remaining[len..];
+    }
+}
+
+/// A handle to the simulated HBA. Clones share the same HBA, so one handle can be given to
+/// the port as its registers and DMA allocator while the test inspects the drive.
+#[derive(Clone)]
+pub struct SimulatedHba(Rc<RefCell<HbaState>>);
+
+impl SimulatedHba {
+    pub fn new(sector_count: usize, ncq_queue_depth: Option<usize>) -> Self {
+        let arena = unsafe { alloc_zeroed(HbaState::arena_layout()) };
+        assert!(!arena.is_null(), "Failed to allocate the simulated RAM");
+        Self(Rc::new(RefCell::new(HbaState {
+            registers: [0; 0x40 / 4],
+            arena,
+            arena_used: 0,
+            disk: vec![0; sector_count * SIM_SECTOR_SIZE],
+            ncq_queue_depth,
+            defer_completions: false,
+            deferred: Vec::new(),
+            failing_sector: None,
+            issued_opcodes: Vec::new(),
+            flush_count: 0,
+        })))
+    }
+
+    pub fn interrupt_pending(&self) -> bool {
+        self.read(PortRegister::InterruptStatus) != 0
+    }
+
+    /// Holds issued commands until `complete_command` is called for their slot
+    pub fn set_defer_completions(&self, defer_completions: bool) {
+        self.0.borrow_mut().defer_completions = defer_completions;
+    }
+
+    /// The slots of issued commands that haven't run yet, in the order they were issued
+    pub fn deferred_slots(&self) -> Vec<usize> {
+        self.0
+            .borrow()
+            .deferred
+            .iter()
+            .map(|(slot, _)| *slot)
+            .collect()
+    }
+
+    pub fn complete_command(&self, slot: usize) {
+        let mut state = self.0.borrow_mut();
+        let index = state
+            .deferred
+            .iter()
+            .position(|(deferred_slot, _)| *deferred_slot == slot)
+            .expect("No deferred command in this slot");
+        let (_, command) = state.deferred.remove(index);
+        state.complete(slot, command);
+    }
+
+    /// Commands that touch this sector fail with a task file error
+    pub fn fail_sector(&self, sector: Option<u64>) {
+        self.0.borrow_mut().failing_sector = sector;
+    }
+
+    pub fn issued_opcodes(&self) -> Vec<u8> {
+        self.0.borrow().issued_opcodes.clone()
+    }
+
+    pub fn flush_count(&self) -> usize {
+        self.0.borrow().flush_count
+    }
+
+    /// How much simulated RAM has been handed out through DmaAllocator
+    pub fn dma_bytes_allocated(&self) -> usize {
+        self.0.borrow().arena_used
+    }
+
+    pub fn sectors(&self, start_sector: usize, sector_count: usize) -> Vec<u8> {
+        let start = start_sector * SIM_SECTOR_SIZE;
+        self.0.borrow().disk[start..start + (sector_count * SIM_SECTOR_SIZE)].to_vec()
+    }
+
+    pub fn write_sectors(&self, start_sector: usize, data: &[u8]) {
+        let start = start_sector * SIM_SECTOR_SIZE;
+        self.0.borrow_mut().disk[start..start + data.len()].copy_from_slice(data);
+    }
+}
+
+impl PortRegisters for SimulatedHba {
+    fn read(&self, register: PortRegister) -> u32 {
+        self.0.borrow().registers[register as usize / 4]
+    }
+
+    fn write(&mut self, register: PortRegister, value: u32) {
+        let mut state = self.0.borrow_mut();
+        match register {
+            PortRegister::InterruptStatus | PortRegister::SataError => {
+                *state.register(register) &= !value
+            }
+            PortRegister::SataActive => *state.register(register) |= value,
+            PortRegister::CommandIssue => {
+                let newly_issued = value & !state.registers[register as usize / 4];
+                *state.register(register) |= value;
+                for slot in (0..32).filter(|slot| newly_issued & (1 << slot) != 0) {
+                    state.issue(slot);
+                }
+            }
+            PortRegister::CommandAndStatus => state.write_command_and_status(value),
+            _ => *state.register(register) = value,
+        }
+    }
+}
+
+impl DmaAllocator for SimulatedHba {
+    fn alloc(&self, size: usize) -> PhysRangeMapping {
+        let mut state = self.0.borrow_mut();
+        // Hand out page-aligned buffers, like axle does
+        let offset = state.arena_used;
+        let size_in_pages = (size + 0xfff) & !0xfff;
+        assert!(
+            offset + size_in_pages <= ARENA_SIZE,
+            "Simulated RAM exhausted"
+        );
+        state.arena_used += size_in_pages;
+        PhysRangeMapping {
+            addr: PhysVirtPair {
+                phys: ARENA_PHYS_BASE + offset,
+                virt: state.arena as usize + offset,
+            },
+            size,
+        }
+    }
+}
@@ -1,20 +1,42 @@
 #![no_std]
 
+//! The block device protocol served by the SATA driver.
+//!
+//! Each request carries a `request_id` chosen by the client, which the driver echoes in the
+//! completion message it sends once the drive has finished the request. Requests may be
+//! pipelined, and each completion reports its own `BlockDeviceStatus`.
+//!
+//! Requests also name the device they're addressed to. The SATA driver serves the whole disk as
+//! `WHOLE_DISK_DEVICE`, and other servers (such as the partition manager) may speak the same
+//! protocol on behalf of further devices.
+
 extern crate alloc;
-use core::{
-    alloc::Layout,
-    intrinsics::copy_nonoverlapping,
-    mem::{align_of, size_of},
-};
-
-use alloc::alloc::alloc;
-use alloc::vec::Vec;
-#[cfg(target_os = "axle")]
-use axle_rt::amc_message_send_untyped;
+
+#[cfg(any(target_os = "axle", feature = "amc_sim"))]
+mod conditional_imports {
+    pub use alloc::alloc::{alloc, dealloc};
+    pub use alloc::vec::Vec;
+    pub use axle_rt::{
+        amc_message_await__u32_event_untyped, amc_message_send, amc_message_send_untyped,
+    };
+    pub use core::alloc::Layout;
+    pub use core::intrinsics::copy_nonoverlapping;
+    pub use core::mem::{align_of, size_of};
+    pub use core::sync::atomic::{AtomicU32, Ordering};
+}
+#[cfg(not(any(target_os = "axle", feature = "amc_sim")))]
+mod conditional_imports {}
+
+use crate::conditional_imports::*;
+
 use axle_rt::{copy_str_into_sized_slice, ContainsEventField, ExpectsEventField};
 use axle_rt_derive::ContainsEventField;
 
-pub const FILE_SERVER_SERVICE_NAME: &'static str = "com.axle.file_server";
+pub const SATA_DRIVER_SERVICE_NAME: &'static str = "com.axle.sata_driver";
+pub const SATA_SECTOR_SIZE: usize = 512;
+/// Larger transfers must be split across several requests
+pub const MAX_SECTORS_PER_REQUEST: u64 = 128;
+pub const WHOLE_DISK_DEVICE: u32 = 0;
 
 pub fn str_from_u8_nul_utf8_unchecked(utf8_src: &[u8]) -> &str {
     let nul_range_end = utf8_src
@@ -24,132 +46,484 @@ pub fn str_from_u8_nul_utf8_unchecked(utf8_src: &[u8]) -> &str {
     unsafe { core::str::from_utf8_unchecked(&utf8_src[0..nul_range_end]) }
 }
 
+#[repr(u32)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BlockDeviceStatus {
+    Success,
+    /// The request extended past the last sector of the device
+    OutOfRange,
+    /// The request was malformed, or exceeded `MAX_SECTORS_PER_REQUEST`
+    InvalidRequest,
+    /// The drive reported an error while carrying out the request
+    IoError,
+    /// The server doesn't provide the requested device
+    NoSuchDevice,
+}
+
+impl BlockDeviceStatus {
+    pub fn into_result(self) -> Result<(), BlockDeviceStatus> {
+        match self {
+            BlockDeviceStatus::Success => Ok(()),
+            e => Err(e),
+        }
+    }
+}
+
+// Device info
+
 #[repr(C)]
 #[derive(Debug, ContainsEventField)]
-pub struct ReadFile {
-    pub event: u32,
-    pub path: [u8; 64],
+pub struct GetDeviceInfo {
+    event: u32,
+    pub request_id: u32,
+    pub device: u32,
 }
 
-impl ReadFile {
-    pub fn new(path: &str) -> Self {
-        let mut s = ReadFile {
+impl GetDeviceInfo {
+    pub fn new(request_id: u32, device: u32) -> Self {
+        Self {
             event: Self::EXPECTED_EVENT,
-            path: [0; 64],
-        };
-        copy_str_into_sized_slice(&mut s.path, path);
-        s
+            request_id,
+            device,
+        }
     }
 }
 
-impl ExpectsEventField for ReadFile {
-    const EXPECTED_EVENT: u32 = 100;
+impl ExpectsEventField for GetDeviceInfo {
+    const EXPECTED_EVENT: u32 = 202;
 }
 
 #[repr(C)]
 #[derive(Debug, Copy, Clone, ContainsEventField)]
-pub struct ReadFileResponse {
-    pub event: u32,
-    pub path: [u8; 64],
-    pub len: usize,
+pub struct DeviceInfo {
+    event: u32,
+    pub request_id: u32,
+    pub status: BlockDeviceStatus,
+    pub sector_size: u32,
+    pub sector_count: u64,
+    model: [u8; 64],
+    serial_number: [u8; 32],
+}
+
+impl DeviceInfo {
+    pub fn new(
+        request_id: u32,
+        status: BlockDeviceStatus,
+        sector_count: u64,
+        model: &str,
+        serial_number: &str,
+    ) -> Self {
+        let mut ret = Self {
+            event: Self::EXPECTED_EVENT,
+            request_id,
+            status,
+            sector_size: SATA_SECTOR_SIZE as u32,
+            sector_count,
+            model: [0; 64],
+            serial_number: [0; 32],
+        };
+        copy_str_into_sized_slice(&mut ret.model, model);
+        copy_str_into_sized_slice(&mut ret.serial_number, serial_number);
+        ret
+    }
+
+    pub fn model(&self) -> &str {
+        str_from_u8_nul_utf8_unchecked(&self.model)
+    }
+
+    pub fn serial_number(&self) -> &str {
+        str_from_u8_nul_utf8_unchecked(&self.serial_number)
+    }
+}
+
+impl ExpectsEventField for DeviceInfo {
+    const EXPECTED_EVENT: u32 = 202;
+}
+
+// Sector reads and writes
+
+#[repr(C)]
+#[derive(Debug, ContainsEventField)]
+pub struct ReadSectors {
+    event: u32,
+    pub request_id: u32,
+    pub device: u32,
+    pub start_sector: u64,
+    pub sector_count: u64,
+}
+
+impl ReadSectors {
+    pub fn new(request_id: u32, device: u32, start_sector: u64, sector_count: u64) -> Self {
+        Self {
+            event: Self::EXPECTED_EVENT,
+            request_id,
+            device,
+            start_sector,
+            sector_count,
+        }
+    }
+}
+
+impl ExpectsEventField for ReadSectors {
+    const EXPECTED_EVENT: u32 = 200;
+}
+
+#[repr(C)]
+#[derive(Debug, ContainsEventField)]
+pub struct ReadSectorsResponse {
+    event: u32,
+    pub request_id: u32,
+    pub status: BlockDeviceStatus,
+    pub start_sector: u64,
+    pub sector_count: u64,
+    pub data_len: usize,
     pub data: [u8; 0],
 }
 
-#[cfg(target_os = "axle")]
-impl ReadFileResponse {
-    pub fn send(service: &str, path: &str, data: &Vec<u8>) {
-        let total_size = size_of::<ReadFileResponse>() + data.len();
+#[cfg(any(target_os = "axle", feature = "amc_sim"))]
+impl ReadSectorsResponse {
+    pub fn send(service: &str, request_id: u32, start_sector: u64, data: &[u8]) {
+        let total_size = size_of::<ReadSectorsResponse>() + data.len();
         let layout = Layout::from_size_align(total_size, align_of::<usize>()).unwrap();
         unsafe {
-            let mut s = alloc(layout) as *mut ReadFileResponse;
+            let s = alloc(layout) as *mut ReadSectorsResponse;
             (*s).event = Self::EXPECTED_EVENT;
-            copy_str_into_sized_slice(&mut (*s).path, path);
-            (*s).len = data.len();
+            (*s).request_id = request_id;
+            (*s).status = BlockDeviceStatus::Success;
+            (*s).start_sector = start_sector;
+            (*s).sector_count = (data.len() / SATA_SECTOR_SIZE) as u64;
+            (*s).data_len = data.len();
             copy_nonoverlapping(data.as_ptr(), (*s).data.as_mut_ptr(), data.len());
             amc_message_send_untyped(service, s as *const u8, total_size);
+            dealloc(s as *mut u8, layout);
         }
     }
+
+    pub fn send_error(
+        service: &str,
+        request_id: u32,
+        start_sector: u64,
+        sector_count: u64,
+        status: BlockDeviceStatus,
+    ) {
+        amc_message_send(
+            service,
+            ReadSectorsResponse {
+                event: Self::EXPECTED_EVENT,
+                request_id,
+                status,
+                start_sector,
+                sector_count,
+                data_len: 0,
+                data: [],
+            },
+        );
+    }
 }
 
-impl ExpectsEventField for ReadFileResponse {
-    const EXPECTED_EVENT: u32 = 100;
+impl ReadSectorsResponse {
+    /// Returns None if `data_len` claims more data than the message was delivered with
+    ///
+    /// # Safety
+    ///
+    /// `self` must point to the start of a delivered message body that is `message_len` bytes
+    /// long.
+    pub unsafe fn data(&self, message_len: usize) -> Option<&[u8]> {
+        let end = core::mem::size_of::<Self>().checked_add(self.data_len)?;
+        if end > message_len {
+            return None;
+        }
+        Some(&*core::ptr::slice_from_raw_parts(
+            self.data.as_ptr(),
+            self.data_len,
+        ))
+    }
 }
 
-// Reading directories
+impl ExpectsEventField for ReadSectorsResponse {
+    const EXPECTED_EVENT: u32 = 200;
+}
 
 #[repr(C)]
 #[derive(Debug, ContainsEventField)]
-pub struct ReadDirectory {
-    pub event: u32,
-    pub dir: [u8; 64],
+pub struct WriteSectors {
+    event: u32,
+    pub request_id: u32,
+    pub device: u32,
+    pub start_sector: u64,
+    pub sector_count: u64,
+    pub data_len: usize,
+    pub data: [u8; 0],
 }
 
-impl ReadDirectory {
-    pub fn new(dir: &str) -> Self {
-        let mut s = ReadDirectory {
-            event: Self::EXPECTED_EVENT,
-            dir: [0; 64],
-        };
-        copy_str_into_sized_slice(&mut s.dir, dir);
-        s
+#[cfg(any(target_os = "axle", feature = "amc_sim"))]
+impl WriteSectors {
+    pub fn send(service: &str, request_id: u32, device: u32, start_sector: u64, data: &[u8]) {
+        assert!(
+            data.len() % SATA_SECTOR_SIZE == 0,
+            "Sector writes must be sector-aligned"
+        );
+        let total_size = size_of::<WriteSectors>() + data.len();
+        let layout = Layout::from_size_align(total_size, align_of::<usize>()).unwrap();
+        unsafe {
+            let s = alloc(layout) as *mut WriteSectors;
+            (*s).event = Self::EXPECTED_EVENT;
+            (*s).request_id = request_id;
+            (*s).device = device;
+            (*s).start_sector = start_sector;
+            (*s).sector_count = (data.len() / SATA_SECTOR_SIZE) as u64;
+            (*s).data_len = data.len();
+            copy_nonoverlapping(data.as_ptr(), (*s).data.as_mut_ptr(), data.len());
+            amc_message_send_untyped(service, s as *const u8, total_size);
+            dealloc(s as *mut u8, layout);
+        }
     }
 }
 
-impl ExpectsEventField for ReadDirectory {
-    const EXPECTED_EVENT: u32 = 101;
+impl WriteSectors {
+    /// Returns None if `data_len` claims more data than the message was delivered with
+    ///
+    /// # Safety
+    ///
+    /// `self` must point to the start of a delivered message body that is `message_len` bytes
+    /// long.
+    pub unsafe fn data(&self, message_len: usize) -> Option<&[u8]> {
+        let end = core::mem::size_of::<Self>().checked_add(self.data_len)?;
+        if end > message_len {
+            return None;
+        }
+        Some(&*core::ptr::slice_from_raw_parts(
+            self.data.as_ptr(),
+            self.data_len,
+        ))
+    }
+}
+
+impl ExpectsEventField for WriteSectors {
+    const EXPECTED_EVENT: u32 = 201;
 }
 
 #[repr(C)]
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
-pub struct DirectoryEntry {
-    pub name: [u8; 64],
-    pub is_directory: bool,
+#[derive(Debug, Copy, Clone, ContainsEventField)]
+pub struct WriteSectorsResponse {
+    event: u32,
+    pub request_id: u32,
+    pub status: BlockDeviceStatus,
+    pub start_sector: u64,
+    pub sector_count: u64,
 }
 
-impl DirectoryEntry {
-    pub fn new(name: &str, is_directory: bool) -> Self {
-        let mut ret = DirectoryEntry {
-            name: [0; 64],
-            is_directory,
-        };
-        copy_str_into_sized_slice(&mut ret.name, name);
-        ret
+#[cfg(any(target_os = "axle", feature = "amc_sim"))]
+impl WriteSectorsResponse {
+    pub fn send(
+        service: &str,
+        request_id: u32,
+        start_sector: u64,
+        sector_count: u64,
+        status: BlockDeviceStatus,
+    ) {
+        amc_message_send(
+            service,
+            WriteSectorsResponse {
+                event: Self::EXPECTED_EVENT,
+                request_id,
+                status,
+                start_sector,
+                sector_count,
+            },
+        );
     }
 }
 
-// TODO(PT): Variable-length array at the end of this structure
-// Ref: https://www.reddit.com/r/rust/comments/75pnn2/ffi_and_variable_size_data_structure/
+impl ExpectsEventField for WriteSectorsResponse {
+    const EXPECTED_EVENT: u32 = 201;
+}
+
+// Flushing the drive's write cache
+
 #[repr(C)]
 #[derive(Debug, ContainsEventField)]
-pub struct DirectoryContents {
-    pub event: u32,
-    pub entries: [Option<DirectoryEntry>; 128],
+pub struct Flush {
+    event: u32,
+    pub request_id: u32,
+    pub device: u32,
 }
 
-impl ExpectsEventField for DirectoryContents {
-    const EXPECTED_EVENT: u32 = 101;
+impl Flush {
+    pub fn new(request_id: u32, device: u32) -> Self {
+        Self {
+            event: Self::EXPECTED_EVENT,
+            request_id,
+            device,
+        }
+    }
 }
 
-// Launching files
+impl ExpectsEventField for Flush {
+    const EXPECTED_EVENT: u32 = 203;
+}
 
 #[repr(C)]
-#[derive(Debug, ContainsEventField)]
-pub struct LaunchProgram {
-    pub event: u32,
-    pub path: [u8; 64],
+#[derive(Debug, Copy, Clone, ContainsEventField)]
+pub struct FlushResponse {
+    event: u32,
+    pub request_id: u32,
+    pub status: BlockDeviceStatus,
 }
 
-impl LaunchProgram {
-    pub fn new(path: &str) -> Self {
-        let mut s = Self {
+impl FlushResponse {
+    pub fn new(request_id: u32, status: BlockDeviceStatus) -> Self {
+        Self {
             event: Self::EXPECTED_EVENT,
-            path: [0; 64],
-        };
-        copy_str_into_sized_slice(&mut s.path, path);
-        s
+            request_id,
+            status,
+        }
+    }
+}
+
+impl ExpectsEventField for FlushResponse {
+    const EXPECTED_EVENT: u32 = 203;
+}
+
+// Client helpers
+
+#[cfg(any(target_os = "axle", feature = "amc_sim"))]
+static NEXT_REQUEST_ID: AtomicU32 = AtomicU32::new(1);
+
+#[cfg(any(target_os = "axle", feature = "amc_sim"))]
+fn next_request_id() -> u32 {
+    NEXT_REQUEST_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Implemented by each completion message, which echoes the `request_id` of its request
+#[cfg(any(target_os = "axle", feature = "amc_sim"))]
+trait Completion: ExpectsEventField + ContainsEventField {
+    fn request_id(&self) -> u32;
+}
+
+#[cfg(any(target_os = "axle", feature = "amc_sim"))]
+impl Completion for DeviceInfo {
+    fn request_id(&self) -> u32 {
+        self.request_id
+    }
+}
+
+#[cfg(any(target_os = "axle", feature = "amc_sim"))]
+impl Completion for ReadSectorsResponse {
+    fn request_id(&self) -> u32 {
+        self.request_id
+    }
+}
+
+#[cfg(any(target_os = "axle", feature = "amc_sim"))]
+impl Completion for WriteSectorsResponse {
+    fn request_id(&self) -> u32 {
+        self.request_id
     }
 }
 
-impl ExpectsEventField for LaunchProgram {
-    const EXPECTED_EVENT: u32 = 102;
+#[cfg(any(target_os = "axle", feature = "amc_sim"))]
+impl Completion for FlushResponse {
+    fn request_id(&self) -> u32 {
+        self.request_id
+    }
+}
+
+/// A device served over the block device protocol.
+/// Each method sends a request to the server and blocks until its completion arrives.
+#[cfg(any(target_os = "axle", feature = "amc_sim"))]
+#[derive(Debug, Copy, Clone)]
+pub struct RemoteBlockDevice {
+    pub service: &'static str,
+    pub device: u32,
+}
+
+#[cfg(any(target_os = "axle", feature = "amc_sim"))]
+impl RemoteBlockDevice {
+    pub const fn new(service: &'static str, device: u32) -> Self {
+        Self { service, device }
+    }
+
+    /// The whole disk, as served by the SATA driver
+    pub const fn sata_disk() -> Self {
+        Self::new(SATA_DRIVER_SERVICE_NAME, WHOLE_DISK_DEVICE)
+    }
+
+    /// Completions that don't match `request_id` belong to requests that were abandoned, and are
+    /// dropped. Returns the completion along with the length it was delivered with.
+    fn await_completion<T: Completion>(
+        &self,
+        request_id: u32,
+    ) -> Result<(&'static T, usize), BlockDeviceStatus> {
+        loop {
+            let msg =
+                unsafe { amc_message_await__u32_event_untyped(self.service, T::EXPECTED_EVENT) }
+                    .map_err(|_| BlockDeviceStatus::IoError)?;
+            if msg.body.len() < size_of::<T>() {
+                return Err(BlockDeviceStatus::IoError);
+            }
+            let response = unsafe { &*(msg.body.as_ptr() as *const T) };
+            if response.request_id() == request_id {
+                return Ok((response, msg.body.len()));
+            }
+        }
+    }
+
+    pub fn device_info(&self) -> Result<DeviceInfo, BlockDeviceStatus> {
+        let request_id = next_request_id();
+        amc_message_send(self.service, GetDeviceInfo::new(request_id, self.device));
+        let (response, _): (&DeviceInfo, _) = self.await_completion(request_id)?;
+        response.status.into_result()?;
+        Ok(*response)
+    }
+
+    /// `sector_count` may exceed `MAX_SECTORS_PER_REQUEST`, in which case several requests are made
+    pub fn read_sectors(
+        &self,
+        start_sector: u64,
+        sector_count: u64,
+    ) -> Result<Vec<u8>, BlockDeviceStatus> {
+        let mut data = Vec::with_capacity(sector_count as usize * SATA_SECTOR_SIZE);
+        let end_sector = start_sector + sector_count;
+        let mut sector = start_sector;
+        while sector < end_sector {
+            let request_id = next_request_id();
+            let chunk_sectors = core::cmp::min(MAX_SECTORS_PER_REQUEST, end_sector - sector);
+            amc_message_send(
+                self.service,
+                ReadSectors::new(request_id, self.device, sector, chunk_sectors),
+            );
+            let (response, message_len): (&ReadSectorsResponse, _) =
+                self.await_completion(request_id)?;
+            response.status.into_result()?;
+            let chunk = unsafe { response.data(message_len) }.ok_or(BlockDeviceStatus::IoError)?;
+            if chunk.len() != chunk_sectors as usize * SATA_SECTOR_SIZE {
+                return Err(BlockDeviceStatus::IoError);
+            }
+            data.extend_from_slice(chunk);
+            sector += chunk_sectors;
+        }
+        Ok(data)
+    }
+
+    /// `data` must be sector-aligned, and may span more than `MAX_SECTORS_PER_REQUEST`
+    pub fn write_sectors(&self, start_sector: u64, data: &[u8]) -> Result<(), BlockDeviceStatus> {
+        let mut sector = start_sector;
+        for chunk in data.chunks(MAX_SECTORS_PER_REQUEST as usize * SATA_SECTOR_SIZE) {
+            let request_id = next_request_id();
+            WriteSectors::send(self.service, request_id, self.device, sector, chunk);
+            let (response, _): (&WriteSectorsResponse, _) = self.await_completion(request_id)?;
+            response.status.into_result()?;
+            sector += (chunk.len() / SATA_SECTOR_SIZE) as u64;
+        }
+        Ok(())
+    }
+
+    /// Returns once everything written so far has reached the disk
+    pub fn flush(&self) -> Result<(), BlockDeviceStatus> {
+        let request_id = next_request_id();
+        amc_message_send(self.service, Flush::new(request_id, self.device));
+        let (response, _): (&FlushResponse, _) = self.await_completion(request_id)?;
+        response.status.into_result()
+    }
 }
@@ -6,13 +6,84 @@
 // PT: Add more definitions here as C clients need them
 
 #define SATA_DRIVER_SERVICE_NAME "com.axle.sata_driver"
+#define SATA_DRIVER_MAX_SECTORS_PER_REQUEST 128
+#define SATA_DRIVER_WHOLE_DISK_DEVICE 0
 
-// TODO(PT): Flesh this out
+// Each request carries a request_id that's echoed in its completion
+typedef enum sata_driver_status {
+    SATA_DRIVER_STATUS_SUCCESS = 0,
+    SATA_DRIVER_STATUS_OUT_OF_RANGE = 1,
+    SATA_DRIVER_STATUS_INVALID_REQUEST = 2,
+    SATA_DRIVER_STATUS_IO_ERROR = 3,
+    SATA_DRIVER_STATUS_NO_SUCH_DEVICE = 4,
+} sata_driver_status_t;
 
-#define SATA_DRIVER_READ_SECTOR_EVENT 100
-typedef struct sata_driver_read {
+#define SATA_DRIVER_READ_SECTORS_EVENT 200
+typedef struct sata_driver_read_sectors {
+    uint32_t event;
+    uint32_t request_id;
+    uint32_t device;
     uint64_t start_sector;
     uint64_t sector_count;
-} sata_driver_read_t;
+} sata_driver_read_sectors_t;
+
+typedef struct sata_driver_read_sectors_response {
+    uint32_t event;
+    uint32_t request_id;
+    uint32_t status;
+    uint64_t start_sector;
+    uint64_t sector_count;
+    uintptr_t data_len;
+    uint8_t data[];
+} sata_driver_read_sectors_response_t;
+
+#define SATA_DRIVER_WRITE_SECTORS_EVENT 201
+typedef struct sata_driver_write_sectors {
+    uint32_t event;
+    uint32_t request_id;
+    uint32_t device;
+    uint64_t start_sector;
+    uint64_t sector_count;
+    uintptr_t data_len;
+    uint8_t data[];
+} sata_driver_write_sectors_t;
+
+typedef struct sata_driver_write_sectors_response {
+    uint32_t event;
+    uint32_t request_id;
+    uint32_t status;
+    uint64_t start_sector;
+    uint64_t sector_count;
+} sata_driver_write_sectors_response_t;
+
+#define SATA_DRIVER_GET_DEVICE_INFO_EVENT 202
+typedef struct sata_driver_get_device_info {
+    uint32_t event;
+    uint32_t request_id;
+    uint32_t device;
+} sata_driver_get_device_info_t;
+
+typedef struct sata_driver_device_info {
+    uint32_t event;
+    uint32_t request_id;
+    uint32_t status;
+    uint32_t sector_size;
+    uint64_t sector_count;
+    char model[64];
+    char serial_number[32];
+} sata_driver_device_info_t;
+
+#define SATA_DRIVER_FLUSH_EVENT 203
+typedef struct sata_driver_flush {
+    uint32_t event;
+    uint32_t request_id;
+    uint32_t device;
+} sata_driver_flush_t;
+
+typedef struct sata_driver_flush_response {
+    uint32_t event;
+    uint32_t request_id;
+    uint32_t status;
+} sata_driver_flush_response_t;
 
 #endif
@@ -0,0 +1,533 @@
+//! The startup manifest describes the services launched at boot.
+//!
+//! Each section is named by the AMC service that the program registers, and holds
+//! `key = value` lines:
+//!
+//! ```text
+//! # Comments start with a hash
+//! [com.axle.awm_dock]
+//! path = /usr/applications/dock
+//! args = --verbose
+//! depends_on = com.axle.awm
+//! restart = always
+//!
+//! [com.axle.ide]
+//! path = /usr/applications/ide
+//! launch = on_demand
+//! capabilities = exec_buffer
+//! ```
+//!
+//! - `path` (required): where the program lives in the initrd
+//! - `args`: space-separated arguments
+//! - `depends_on`: comma-separated services that must be running before this one is launched.
+//!   Only services launched at boot may have dependencies.
+//! - `restart`: `never` (the default) or `always`
+//! - `launch`: `boot` (the default) or `on_demand`, for programs that are only launched when
+//!   they're asked for. These aren't started at boot, but are still granted their capabilities.
+//! - `capabilities`: comma-separated privileged core commands that the program may use, out of
+//!   `map_physical`, `alloc_physical`, `exec_buffer`, `register_driver` and `trace_messages`
+
+#![no_std]
+extern crate alloc;
+
+use alloc::borrow::ToOwned;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use axle_rt::core_commands::AmcCapabilities;
+
+pub mod restart;
+
+pub const STARTUP_MANIFEST_PATH: &str = "/config/startup_manifest.txt";
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RestartPolicy {
+    Never,
+    Always,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LaunchPolicy {
+    AtBoot,
+    OnDemand,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServiceDescription {
+    pub service_name: String,
+    pub path: String,
+    pub args: Vec<String>,
+    pub depends_on: Vec<String>,
+    pub restart: RestartPolicy,
+    pub launch: LaunchPolicy,
+    pub capabilities: AmcCapabilities,
+}
+
+impl ServiceDescription {
+    fn new(service_name: &str) -> Self {
+        Self {
+            service_name: service_name.to_owned(),
+            path: String::new(),
+            args: Vec::new(),
+            depends_on: Vec::new(),
+            restart: RestartPolicy::Never,
+            launch: LaunchPolicy::AtBoot,
+            capabilities: AmcCapabilities::NONE,
+        }
+    }
+}
+
+/// Line numbers start from 1
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ManifestError {
+    MalformedLine(usize),
+    /// A `key = value` line before the first section
+    EntryOutsideSection(usize),
+    UnknownKey {
+        line: usize,
+        key: String,
+    },
+    InvalidRestartPolicy {
+        line: usize,
+        value: String,
+    },
+    InvalidLaunchPolicy {
+        line: usize,
+        value: String,
+    },
+    UnknownCapability {
+        line: usize,
+        capability: String,
+    },
+    DuplicateService(String),
+    MissingPath(String),
+    UnknownDependency {
+        service: String,
+        dependency: String,
+    },
+    /// Programs launched on demand start as soon as they're asked for, so nothing would wait for
+    /// their dependencies
+    OnDemandServiceHasDependencies(String),
+    /// Services launched at boot can't wait on services that are only launched on demand
+    DependsOnOnDemandService {
+        service: String,
+        dependency: String,
+    },
+    /// Lists the services that depend on each other, directly or indirectly
+    DependencyCycle(Vec<String>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StartupManifest {
+    /// Each service comes after everything it depends on, and otherwise keeps its manifest order
+    pub services: Vec<ServiceDescription>,
+}
+
+impl StartupManifest {
+    pub fn parse(text: &str) -> Result<Self, ManifestError> {
+        let mut services: Vec<ServiceDescription> = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let line_number = i + 1;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if let Some(header) = line.strip_prefix('[') {
+                let service_name = header
+                    .strip_suffix(']')
+                    .map(str::trim)
+                    .filter(|name| !name.is_empty())
+                    .ok_or(ManifestError::MalformedLine(line_number))?;
+                if services.iter().any(|s| s.service_name == service_name) {
+                    return Err(ManifestError::DuplicateService(service_name.to_string()));
+                }
+                services.push(ServiceDescription::new(service_name));
+                continue;
+            }
+
+            let (key, value) = line
+                .split_once('=')
+                .ok_or(ManifestError::MalformedLine(line_number))?;
+            let (key, value) = (key.trim(), value.trim());
+            let service = services
+                .last_mut()
+                .ok_or(ManifestError::EntryOutsideSection(line_number))?;
+            match key {
+                "path" => service.path = value.to_string(),
+                "args" => service.args = value.split_whitespace().map(String::from).collect(),
+                "depends_on" => {
+                    service.depends_on = value
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|dependency| !dependency.is_empty())
+                        .map(String::from)
+                        .collect()
+                }
+                "restart" => {
+                    service.restart = match value {
+                        "never" => RestartPolicy::Never,
+                        "always" => RestartPolicy::Always,
+                        _ => {
+                            return Err(ManifestError::InvalidRestartPolicy {
+                                line: line_number,
+                                value: value.to_string(),
+                            })
+                        }
+                    }
+                }
+                "launch" => {
+                    service.launch = match value {
+                        "boot" => LaunchPolicy::AtBoot,
+                        "on_demand" => LaunchPolicy::OnDemand,
+                        _ => {
+                            return Err(ManifestError::InvalidLaunchPolicy {
+                                line: line_number,
+                                value: value.to_string(),
+                            })
+                        }
+                    }
+                }
+                "capabilities" => {
+                    service.capabilities = AmcCapabilities::NONE;
+                    for name in value.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+                        service.capabilities |=
+                            AmcCapabilities::from_name(name).ok_or_else(|| {
+                                ManifestError::UnknownCapability {
+                                    line: line_number,
+                                    capability: name.to_string(),
+                                }
+                            })?;
+                    }
+                }
+                _ => {
+                    return Err(ManifestError::UnknownKey {
+                        line: line_number,
+                        key: key.to_string(),
+                    })
+                }
+            }
+        }
+
+        for service in services.iter() {
+            if service.path.is_empty() {
+                return Err(ManifestError::MissingPath(service.service_name.clone()));
+            }
+            if service.launch == LaunchPolicy::OnDemand && !service.depends_on.is_empty() {
+                return Err(ManifestError::OnDemandServiceHasDependencies(
+                    service.service_name.clone(),
+                ));
+            }
+            for dependency in service.depends_on.iter() {
+                let dependency_description = services
+                    .iter()
+                    .find(|s| &s.service_name == dependency)
+                    .ok_or_else(|| ManifestError::UnknownDependency {
+                        service: service.service_name.clone(),
+                        dependency: dependency.clone(),
+                    })?;
+                if service.launch == LaunchPolicy::AtBoot
+                    && dependency_description.launch == LaunchPolicy::OnDemand
+                {
+                    return Err(ManifestError::DependsOnOnDemandService {
+                        service: service.service_name.clone(),
+                        dependency: dependency.clone(),
+                    });
+                }
+            }
+        }
+
+        Ok(Self {
+            services: dependency_order(services)?,
+        })
+    }
+
+    pub fn service(&self, service_name: &str) -> Option<&ServiceDescription> {
+        self.services
+            .iter()
+            .find(|s| s.service_name == service_name)
+    }
+
+    /// The capabilities granted to the program at `path`, whichever service it's listed under.
+    /// Programs that aren't in the manifest get none.
+    pub fn capabilities_for_path(&self, path: &str) -> AmcCapabilities {
+        self.services
+            .iter()
+            .filter(|s| s.path == path)
+            .fold(AmcCapabilities::NONE, |capabilities, s| {
+                capabilities | s.capabilities
+            })
+    }
+}
+
+fn dependency_order(
+    mut remaining: Vec<ServiceDescription>,
+) -> Result<Vec<ServiceDescription>, ManifestError> {
+    let mut ordered: Vec<ServiceDescription> = Vec::new();
+    while !remaining.is_empty() {
+        // Take the earliest service whose dependencies have all been placed
+        let next = remaining.iter().position(|service| {
+            service
+                .depends_on
+                .iter()
+                .all(|dependency| ordered.iter().any(|s| &s.service_name == dependency))
+        });
+        match next {
+            Some(idx) => ordered.push(remaining.remove(idx)),
+            // Everything left is waiting on something else that's left
+            None => {
+                return Err(ManifestError::DependencyCycle(
+                    remaining.into_iter().map(|s| s.service_name).collect(),
+                ))
+            }
+        }
+    }
+    Ok(ordered)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    fn service_names(manifest: &StartupManifest) -> Vec<&str> {
+        manifest
+            .services
+            .iter()
+            .map(|s| s.service_name.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn test_parse() {
+        let manifest = StartupManifest::parse(
+            "
+            # The window manager
+            [com.axle.awm]
+            path = /usr/applications/awm2
+            restart = always
+
+            [com.axle.awm_dock]
+            path=/usr/applications/dock
+            args = --one   --two
+            depends_on = com.axle.awm
+            ",
+        )
+        .unwrap();
+
+        assert_eq!(
+            manifest.services,
+            vec![
+                ServiceDescription {
+                    service_name: "com.axle.awm".to_string(),
+                    path: "/usr/applications/awm2".to_string(),
+                    args: vec![],
+                    depends_on: vec![],
+                    restart: RestartPolicy::Always,
+                    launch: LaunchPolicy::AtBoot,
+                    capabilities: AmcCapabilities::NONE,
+                },
+                ServiceDescription {
+                    service_name: "com.axle.awm_dock".to_string(),
+                    path: "/usr/applications/dock".to_string(),
+                    args: vec!["--one".to_string(), "--two".to_string()],
+                    depends_on: vec!["com.axle.awm".to_string()],
+                    restart: RestartPolicy::Never,
+                    launch: LaunchPolicy::AtBoot,
+                    capabilities: AmcCapabilities::NONE,
+                },
+            ]
+        );
+        assert_eq!(
+            manifest.service("com.axle.awm_dock").unwrap().path,
+            "/usr/applications/dock"
+        );
+        assert!(manifest.service("com.axle.net").is_none());
+    }
+
+    #[test]
+    fn test_capabilities() {
+        let manifest = StartupManifest::parse(
+            "
+            [com.axle.realtek_8139_driver]
+            path = /usr/applications/realtek_8139_driver
+            capabilities = register_driver, alloc_physical
+            [com.axle.ide]
+            path = /usr/applications/ide
+            launch = on_demand
+            capabilities = exec_buffer
+            [com.axle.awm]
+            path = /usr/applications/awm2
+            ",
+        )
+        .unwrap();
+
+        let driver = manifest.service("com.axle.realtek_8139_driver").unwrap();
+        assert_eq!(driver.launch, LaunchPolicy::AtBoot);
+        assert_eq!(
+            driver.capabilities,
+            AmcCapabilities::REGISTER_DRIVER | AmcCapabilities::ALLOC_PHYSICAL
+        );
+        assert!(!driver.capabilities.contains(AmcCapabilities::MAP_PHYSICAL));
+        assert_eq!(
+            manifest.service("com.axle.ide").unwrap().launch,
+            LaunchPolicy::OnDemand
+        );
+
+        // Launches by path are granted the capabilities of the matching entry
+        assert_eq!(
+            manifest.capabilities_for_path("/usr/applications/ide"),
+            AmcCapabilities::EXEC_BUFFER
+        );
+        assert_eq!(
+            manifest.capabilities_for_path("/usr/applications/awm2"),
+            AmcCapabilities::NONE
+        );
+        assert_eq!(
+            manifest.capabilities_for_path("/usr/applications/paintbrush"),
+            AmcCapabilities::NONE
+        );
+    }
+
+    #[test]
+    fn test_dependency_order() {
+        // Given services listed before the services they depend on
+        let manifest = StartupManifest::parse(
+            "
+            [com.axle.net]
+            path = /usr/applications/net
+            depends_on = com.axle.realtek_8139_driver
+            [com.axle.file_browser]
+            path = /usr/applications/file_browser
+            depends_on = com.axle.awm
+            [com.axle.realtek_8139_driver]
+            path = /usr/applications/realtek_8139_driver
+            depends_on = com.axle.pci_driver
+            [com.axle.awm]
+            path = /usr/applications/awm2
+            [com.axle.pci_driver]
+            path = /usr/applications/pci_driver
+            ",
+        )
+        .unwrap();
+
+        // Then each service comes after its dependencies, and otherwise keeps its order
+        assert_eq!(
+            service_names(&manifest),
+            vec![
+                "com.axle.awm",
+                "com.axle.file_browser",
+                "com.axle.pci_driver",
+                "com.axle.realtek_8139_driver",
+                "com.axle.net",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dependency_cycle() {
+        let result = StartupManifest::parse(
+            "
+            [a]
+            path = /a
+            depends_on = c
+            [b]
+            path = /b
+            [c]
+            path = /c
+            depends_on = a, b
+            ",
+        );
+        assert_eq!(
+            result,
+            Err(ManifestError::DependencyCycle(vec![
+                "a".to_string(),
+                "c".to_string()
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_parse_startup_manifest() {
+        // The manifest shipped in the OS image must always parse
+        let manifest =
+            StartupManifest::parse(include_str!("../../../os_dist/config/startup_manifest.txt"))
+                .unwrap();
+        assert_eq!(manifest.services[0].service_name, "com.axle.awm");
+    }
+
+    #[test]
+    fn test_invalid_manifests() {
+        assert_eq!(
+            StartupManifest::parse("path = /a"),
+            Err(ManifestError::EntryOutsideSection(1))
+        );
+        assert_eq!(
+            StartupManifest::parse("[a]\npath = /a\nthis line has no value"),
+            Err(ManifestError::MalformedLine(3))
+        );
+        assert_eq!(
+            StartupManifest::parse("[a\npath = /a"),
+            Err(ManifestError::MalformedLine(1))
+        );
+        assert_eq!(
+            StartupManifest::parse("[a]\npath = /a\ncolour = blue"),
+            Err(ManifestError::UnknownKey {
+                line: 3,
+                key: "colour".to_string()
+            })
+        );
+        assert_eq!(
+            StartupManifest::parse("[a]\npath = /a\nrestart = sometimes"),
+            Err(ManifestError::InvalidRestartPolicy {
+                line: 3,
+                value: "sometimes".to_string()
+            })
+        );
+        assert_eq!(
+            StartupManifest::parse("[a]\npath = /a\nlaunch = later"),
+            Err(ManifestError::InvalidLaunchPolicy {
+                line: 3,
+                value: "later".to_string()
+            })
+        );
+        assert_eq!(
+            StartupManifest::parse("[a]\npath = /a\ncapabilities = exec_buffer, root"),
+            Err(ManifestError::UnknownCapability {
+                line: 3,
+                capability: "root".to_string()
+            })
+        );
+        assert_eq!(
+            StartupManifest::parse("[a]\npath = /a\n[a]\npath = /b"),
+            Err(ManifestError::DuplicateService("a".to_string()))
+        );
+        assert_eq!(
+            StartupManifest::parse("[a]\nargs = 1"),
+            Err(ManifestError::MissingPath("a".to_string()))
+        );
+        assert_eq!(
+            StartupManifest::parse("[a]\npath = /a\ndepends_on = b"),
+            Err(ManifestError::UnknownDependency {
+                service: "a".to_string(),
+                dependency: "b".to_string()
+            })
+        );
+        assert_eq!(
+            StartupManifest::parse(
+                "[a]\npath = /a\ndepends_on = b\n[b]\npath = /b\nlaunch = on_demand"
+            ),
+            Err(ManifestError::DependsOnOnDemandService {
+                service: "a".to_string(),
+                dependency: "b".to_string()
+            })
+        );
+        assert_eq!(
+            StartupManifest::parse(
+                "[a]\npath = /a\n[b]\npath = /b\ndepends_on = a\nlaunch = on_demand"
+            ),
+            Err(ManifestError::OnDemandServiceHasDependencies(
+                "b".to_string()
+            ))
+        );
+    }
+}
@@ -0,0 +1,121 @@
+//! Decides when a service that died should be restarted.
+//!
+//! Restarts back off exponentially while a service keeps dying soon after it starts, and a
+//! service that dies too often within a short window is reported as crash looping.
+
+use alloc::vec::Vec;
+
+/// The delay before the first restart of a service that died
+pub const INITIAL_RESTART_DELAY_MS: u64 = 250;
+/// Restart delays stop growing here
+pub const MAX_RESTART_DELAY_MS: u64 = 30_000;
+/// A service that stays up this long is considered healthy again, and its back-off resets
+pub const STABLE_UPTIME_MS: u64 = 10_000;
+/// This many deaths within `CRASH_LOOP_WINDOW_MS` is a crash loop
+pub const CRASH_LOOP_DEATHS: usize = 5;
+pub const CRASH_LOOP_WINDOW_MS: u64 = 60_000;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct RestartDecision {
+    pub delay_ms: u64,
+    /// Set when the service has died at least `CRASH_LOOP_DEATHS` times within the window
+    pub crash_looping: bool,
+}
+
+/// Tracks the recent deaths of a single service
+#[derive(Debug, Clone, Default)]
+pub struct RestartTracker {
+    /// Times of death within the crash loop window, oldest first
+    recent_deaths: Vec<u64>,
+    /// Deaths since the service last stayed up for `STABLE_UPTIME_MS`
+    consecutive_failures: u32,
+}
+
+impl RestartTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records that the service died at `now` after running for `uptime_ms`, and decides how
+    /// long to wait before restarting it. Times are in milliseconds since boot.
+    pub fn record_death(&mut self, now: u64, uptime_ms: u64) -> RestartDecision {
+        if uptime_ms >= STABLE_UPTIME_MS {
+            self.consecutive_failures = 0;
+        }
+        // Saturate rather than shift, as a large shift wraps the delay around to 0
+        let delay_ms = INITIAL_RESTART_DELAY_MS
+            .saturating_mul(1 << self.consecutive_failures.min(u64::BITS - 1))
+            .min(MAX_RESTART_DELAY_MS);
+        self.consecutive_failures = self.consecutive_failures.saturating_add(1);
+
+        self.recent_deaths
+            .retain(|&died_at| now.saturating_sub(died_at) < CRASH_LOOP_WINDOW_MS);
+        self.recent_deaths.push(now);
+
+        RestartDecision {
+            delay_ms,
+            crash_looping: self.recent_deaths.len() >= CRASH_LOOP_DEATHS,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff() {
+        let mut tracker = RestartTracker::new();
+        let mut now = 0;
+        // Given a service that dies as soon as it starts
+        let mut delays = Vec::new();
+        for _ in 0..10 {
+            let decision = tracker.record_death(now, 0);
+            delays.push(decision.delay_ms);
+            now += decision.delay_ms;
+        }
+        // Then each restart waits twice as long as the last, up to the limit
+        assert_eq!(
+            delays,
+            [250, 500, 1000, 2000, 4000, 8000, 16000, 30000, 30000, 30000]
+        );
+
+        // And once the service stays up for a while, the back-off resets
+        let decision = tracker.record_death(now + STABLE_UPTIME_MS, STABLE_UPTIME_MS);
+        assert_eq!(decision.delay_ms, INITIAL_RESTART_DELAY_MS);
+    }
+
+    #[test]
+    fn test_backoff_after_many_failures() {
+        // Given a service that has failed more times than the delay has bits
+        let mut tracker = RestartTracker::new();
+        for i in 0..200 {
+            let decision = tracker.record_death(i * MAX_RESTART_DELAY_MS, 0);
+            // Then the delay stays at the limit, rather than wrapping around to nothing
+            if i >= 7 {
+                assert_eq!(decision.delay_ms, MAX_RESTART_DELAY_MS);
+            }
+        }
+    }
+
+    #[test]
+    fn test_crash_loop() {
+        let mut tracker = RestartTracker::new();
+        // Deaths spread further apart than the window never count as a loop
+        for i in 0..CRASH_LOOP_DEATHS as u64 * 2 {
+            let decision = tracker.record_death(i * CRASH_LOOP_WINDOW_MS, STABLE_UPTIME_MS);
+            assert!(!decision.crash_looping);
+        }
+
+        // Deaths in quick succession do
+        let mut tracker = RestartTracker::new();
+        let decisions: Vec<bool> = (0..CRASH_LOOP_DEATHS as u64)
+            .map(|i| tracker.record_death(i * 1000, 1000).crash_looping)
+            .collect();
+        assert_eq!(decisions, [false, false, false, false, true]);
+
+        // And the loop ends once older deaths fall out of the window
+        let decision = tracker.record_death(CRASH_LOOP_WINDOW_MS + 3500, STABLE_UPTIME_MS);
+        assert!(!decision.crash_looping);
+    }
+}
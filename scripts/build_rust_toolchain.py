@@ -120,6 +120,9 @@ def test_rust_programs() -> None:
     programs_with_tests = [
         "libfs",
         "agx_definitions",
+        "gpt_helper",
+        "sata_driver",
+        "block_cache",
     ]
 
     for program_dir_name in programs_with_tests:
@@ -1,9 +1,32 @@
 #!/usr/local/bin/python3
+import argparse
+from pathlib import Path
+
 from build_utils import run_and_check
 
+_GPT_HELPER_DIR = Path(__file__).parents[1] / "rust_programs" / "gpt_helper"
+
+
+def _run_gpt_helper(image_path: Path, *args: str) -> None:
+    run_and_check(
+        ["cargo", "run", "--release", "--features", "use_std", "--", image_path.as_posix(), *args],
+        cwd=_GPT_HELPER_DIR,
+    )
+
 
 def main() -> None:
-    run_and_check(["qemu-img", "create", "-f", "raw", "axle-hdd.img", "64M"])
+    parser = argparse.ArgumentParser()
+    parser.add_argument("--gpt", action="store_true", help="Partition the image with a single data partition")
+    args = parser.parse_args()
+
+    image_path = Path("axle-hdd.img").resolve()
+    if not args.gpt:
+        run_and_check(["qemu-img", "create", "-f", "raw", image_path.as_posix(), "64M"])
+        return
+
+    _run_gpt_helper(image_path, "create", "64")
+    _run_gpt_helper(image_path, "add", "basic", "rest", "axle")
+    _run_gpt_helper(image_path, "list")
 
 
 if __name__ == "__main__":
@@ -1,6 +1,9 @@
-use libfs::DirectoryImage;
+use libfs::image::encode_image;
+use libfs::sha256::sha256;
+use libfs::{file_metadata_for, DirectoryImage, FileContents};
 use std::collections::BTreeMap;
 use std::io::Write;
+use std::time::UNIX_EPOCH;
 use std::{env, error, fs, io, path};
 
 fn path_relative_to(path: &path::Path, pivot: &path::Path) -> String {
@@ -25,7 +28,7 @@ where
     filename(path).unwrap_or("<Unknown>".to_string())
 }
 
-fn traverse<P1, P2>(sysroot: &P1, dir: &P2) -> io::Result<DirectoryImage>
+fn traverse<P1, P2>(sysroot: &P1, dir: &P2) -> io::Result<DirectoryImage<'static>>
 where
     P1: AsRef<path::Path> + std::fmt::Debug + std::cmp::PartialEq,
     P2: AsRef<path::Path> + std::fmt::Debug + std::cmp::PartialEq,
@@ -34,6 +37,7 @@ where
     let mut dir_contents = DirectoryImage {
         name: filename_with_fallback(dir),
         files: BTreeMap::new(),
+        file_metadata: BTreeMap::new(),
         subdirectories: BTreeMap::new(),
     };
 
@@ -54,9 +58,18 @@ where
                 if entry.path().ends_with(".DS_Store") {
                     continue;
                 }
+                let name = filename_with_fallback(&entry.path());
+                let modified = attributes
+                    .modified()
+                    .ok()
+                    .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                    .map_or(0, |duration| duration.as_secs());
+                dir_contents
+                    .file_metadata
+                    .insert(name.clone(), file_metadata_for(&name, &file_data, modified));
                 dir_contents
                     .files
-                    .insert(filename_with_fallback(&entry.path()), file_data);
+                    .insert(name, FileContents::new(file_data));
             } else {
                 eprintln!("Failed to read contents of file {:?}", entry);
                 continue;
@@ -96,7 +109,10 @@ fn main() -> Result<(), Box<dyn error::Error>> {
     println!("Finished generating directory image");
 
     let mut file = io::BufWriter::new(std::fs::File::create("./output.img").unwrap());
-    let v = postcard::to_allocvec(&fs_image).expect("Failed to encode");
+    let v = encode_image(&fs_image);
+    println!("Encoded image is {} bytes", v.len());
+    let digest: String = sha256(&v).iter().map(|b| format!("{b:02x}")).collect();
+    println!("Image SHA-256: {digest}");
     file.write(&v).expect("Failed to write to file");
 
     //print_tree(0, &fs_image);